@@ -117,6 +117,9 @@ pub enum Body {
     /// Zero sized response body. `Content-Length` header is set to `0`.
     Empty,
     /// Specific response body.
+    ///
+    /// `Bytes` buffer is reference counted, the same body could be sent
+    /// multiple times by cloning `Bytes` without copying the buffer.
     Bytes(Bytes),
     /// Generic message body.
     Message(Box<dyn MessageBody>),
@@ -382,6 +385,90 @@ where
     }
 }
 
+/// Item of `FlushStream` body
+#[derive(Debug, Clone, PartialEq)]
+pub enum BodyChunk {
+    /// Body data
+    Data(Bytes),
+    /// Flush point, body data that precedes it is flushed to the peer
+    Flush,
+}
+
+/// Streaming body with explicit flush points.
+///
+/// Data chunks that are ready are merged together and sent as a single
+/// body chunk, `BodyChunk::Flush` ends current chunk so data that
+/// precedes it is sent to the peer before next chunk is read. Data is
+/// sent as well once stream is not ready.
+pub struct FlushStream<S, E> {
+    stream: S,
+    buf: BytesMut,
+    eof: bool,
+    _t: PhantomData<E>,
+}
+
+impl<S, E> FlushStream<S, E>
+where
+    S: Stream<Item = BodyChunk, Error = E>,
+    E: Into<Error>,
+{
+    /// Create body from stream of data chunks and flush points.
+    pub fn new(stream: S) -> Self {
+        FlushStream {
+            stream,
+            buf: BytesMut::new(),
+            eof: false,
+            _t: PhantomData,
+        }
+    }
+
+    fn take_chunk(&mut self) -> Option<Bytes> {
+        if self.buf.is_empty() {
+            None
+        } else {
+            Some(self.buf.take().freeze())
+        }
+    }
+}
+
+impl<S, E> MessageBody for FlushStream<S, E>
+where
+    S: Stream<Item = BodyChunk, Error = E>,
+    E: Into<Error>,
+{
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(&mut self) -> Poll<Option<Bytes>, Error> {
+        if self.eof {
+            return Ok(Async::Ready(self.take_chunk()));
+        }
+        loop {
+            match self.stream.poll().map_err(std::convert::Into::into)? {
+                Async::Ready(Some(BodyChunk::Data(chunk))) => {
+                    self.buf.extend_from_slice(&chunk)
+                }
+                Async::Ready(Some(BodyChunk::Flush)) => {
+                    if let Some(chunk) = self.take_chunk() {
+                        return Ok(Async::Ready(Some(chunk)));
+                    }
+                }
+                Async::Ready(None) => {
+                    self.eof = true;
+                    return Ok(Async::Ready(self.take_chunk()));
+                }
+                Async::NotReady => {
+                    return match self.take_chunk() {
+                        Some(chunk) => Ok(Async::Ready(Some(chunk))),
+                        None => Ok(Async::NotReady),
+                    };
+                }
+            }
+        }
+    }
+}
+
 /// Type represent streaming body. This body implementation should be used
 /// if total size of stream is known. Data get sent as is without using transfer encoding.
 pub struct SizedStream<S> {
@@ -488,6 +575,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_bytes_shared() {
+        let b = Bytes::from(vec![0u8; 64 * 1024]);
+        let body1 = Body::from(b.clone());
+        let body2 = Body::from(b.clone());
+        assert_eq!(body1.get_ref().as_ptr(), b.as_ptr());
+        assert_eq!(body2.get_ref().as_ptr(), b.as_ptr());
+    }
+
     #[test]
     fn test_bytes_mut() {
         let mut b = BytesMut::from("test");
@@ -529,6 +625,30 @@ mod tests {
         assert_eq!(val.poll_next().unwrap(), Async::Ready(None));
     }
 
+    #[test]
+    fn test_flush_stream() {
+        let data = |chunk: &'static str| BodyChunk::Data(Bytes::from(chunk));
+        let chunks = vec![
+            BodyChunk::Flush,
+            data("a"),
+            data("b"),
+            BodyChunk::Flush,
+            data("c"),
+        ];
+        let mut body = FlushStream::new(futures::stream::iter_ok::<_, Error>(chunks));
+        assert_eq!(body.size(), BodySize::Stream);
+        assert_eq!(
+            body.poll_next().ok(),
+            Some(Async::Ready(Some(Bytes::from("ab"))))
+        );
+        assert_eq!(
+            body.poll_next().ok(),
+            Some(Async::Ready(Some(Bytes::from("c"))))
+        );
+        assert_eq!(body.poll_next().ok(), Some(Async::Ready(None)));
+        assert_eq!(body.poll_next().ok(), Some(Async::Ready(None)));
+    }
+
     #[test]
     fn test_body_eq() {
         assert!(Body::None == Body::None);
@@ -0,0 +1,672 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_connect::{
+    Connect as TcpConnect, ConnectError, Connection as TcpConnection, Resolver,
+};
+use actix_service::Service;
+use futures::future::{ok, Either};
+use futures::{Async, Future, Poll};
+use http::Uri;
+use net2::TcpBuilder;
+use tokio_reactor::Handle;
+use tokio_tcp::{ConnectFuture, TcpStream};
+
+use super::pool::{ConnectAttempt, PoolObserver};
+
+/// Observer of connect attempts, shared with connector configuration
+pub(crate) type ObserverSlot = Rc<RefCell<Option<Rc<dyn PoolObserver>>>>;
+
+/// Socket options, shared with connector configuration
+pub(crate) type BindSlot = Rc<RefCell<BindConfig>>;
+
+/// Selects local address of outbound socket by destination address
+pub(crate) type LocalAddressFor = Arc<dyn Fn(&SocketAddr) -> Option<IpAddr>>;
+
+/// Socket options applied to outbound sockets before connect
+#[derive(Clone, Default)]
+pub(crate) struct BindConfig {
+    pub(crate) local_address: Option<SocketAddr>,
+    pub(crate) local_address_for: Option<LocalAddressFor>,
+    pub(crate) reuse_address: bool,
+    pub(crate) reuse_port: bool,
+    pub(crate) adaptive_order: bool,
+    pub(crate) dns_cache_ttl: Option<Duration>,
+    pub(crate) stale_while_revalidate: bool,
+    /// Resolver used instead of default async resolver
+    pub(crate) resolver: Option<Resolver<Uri>>,
+    /// Connect to host addresses one by one without socket options,
+    /// set for default tcp connector
+    pub(crate) multi_address: bool,
+}
+
+impl BindConfig {
+    /// Check if any option that requires custom connect is set
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.local_address.is_some()
+            || self.local_address_for.is_some()
+            || self.reuse_address
+            || self.reuse_port
+            || self.adaptive_order
+            || self.dns_cache_ttl.is_some()
+            || self.resolver.is_some()
+            || self.multi_address
+    }
+}
+
+/// Recent connect latency of host addresses
+#[derive(Clone, Default)]
+struct AddressLatency(Rc<RefCell<HashMap<String, HashMap<SocketAddr, Duration>>>>);
+
+impl AddressLatency {
+    /// Record latency of successful connect, smoothed with previous value
+    fn record(&self, host: &str, addr: SocketAddr, latency: Duration) {
+        let mut hosts = self.0.borrow_mut();
+        if !hosts.contains_key(host) {
+            hosts.insert(host.to_owned(), HashMap::new());
+        }
+        let addrs = hosts.get_mut(host).unwrap();
+        let latency = match addrs.get(&addr) {
+            Some(prev) => (*prev + latency) / 2,
+            None => latency,
+        };
+        addrs.insert(addr, latency);
+    }
+
+    /// Forget address that failed to connect
+    fn remove(&self, host: &str, addr: &SocketAddr) {
+        if let Some(addrs) = self.0.borrow_mut().get_mut(host) {
+            addrs.remove(addr);
+        }
+    }
+
+    /// Order addresses by latency, addresses without measured
+    /// latency keep resolved order after measured ones
+    fn sort(&self, host: &str, addrs: &mut VecDeque<SocketAddr>) {
+        if let Some(latency) = self.0.borrow().get(host) {
+            let mut sorted: Vec<_> = addrs.drain(..).collect();
+            sorted.sort_by_key(|addr| match latency.get(addr) {
+                Some(latency) => (0, *latency),
+                None => (1, Duration::from_secs(0)),
+            });
+            addrs.extend(sorted);
+        }
+    }
+}
+
+/// Resolved host addresses
+struct DnsEntry {
+    addrs: VecDeque<SocketAddr>,
+    resolved: Instant,
+    refreshing: bool,
+}
+
+/// Cache of resolved host addresses
+#[derive(Clone, Default)]
+struct DnsCache {
+    entries: Rc<RefCell<HashMap<(String, u16), DnsEntry>>>,
+}
+
+impl DnsCache {
+    /// Resolve host addresses, entries younger than ttl are served from cache.
+    ///
+    /// With stale-while-revalidate expired entry is served as well, while
+    /// `resolve` refreshes it in background.
+    fn resolve<F, R>(
+        &self,
+        host: &str,
+        port: u16,
+        ttl: Duration,
+        stale_while_revalidate: bool,
+        resolve: F,
+    ) -> Box<dyn Future<Item = VecDeque<SocketAddr>, Error = ConnectError>>
+    where
+        F: FnOnce() -> R,
+        R: Future<Item = VecDeque<SocketAddr>, Error = ConnectError> + 'static,
+    {
+        let key = (host.to_owned(), port);
+        if let Some(entry) = self.entries.borrow_mut().get_mut(&key) {
+            if entry.resolved.elapsed() < ttl {
+                return Box::new(ok(entry.addrs.clone()));
+            }
+            if stale_while_revalidate {
+                if !entry.refreshing {
+                    entry.refreshing = true;
+                    let cache = self.clone();
+                    tokio_current_thread::spawn(resolve().then(move |res| {
+                        let mut entries = cache.entries.borrow_mut();
+                        match res {
+                            Ok(addrs) => cache.insert(&mut entries, key, addrs),
+                            Err(_) => {
+                                // keep serving stale entry, next request retries
+                                if let Some(entry) = entries.get_mut(&key) {
+                                    entry.refreshing = false;
+                                }
+                            }
+                        }
+                        Ok(())
+                    }));
+                }
+                return Box::new(ok(entry.addrs.clone()));
+            }
+        }
+
+        let cache = self.clone();
+        Box::new(resolve().map(move |addrs| {
+            cache.insert(&mut cache.entries.borrow_mut(), key, addrs.clone());
+            addrs
+        }))
+    }
+
+    fn insert(
+        &self,
+        entries: &mut HashMap<(String, u16), DnsEntry>,
+        key: (String, u16),
+        addrs: VecDeque<SocketAddr>,
+    ) {
+        let entry = DnsEntry {
+            addrs,
+            resolved: Instant::now(),
+            refreshing: false,
+        };
+        entries.insert(key, entry);
+    }
+}
+
+/// Tcp connector that configures and binds socket before connecting.
+///
+/// Host name is resolved with configured resolver or with default async
+/// resolver, preresolved address of connect request is used as is. Options are read from shared slot on
+/// every connect, if no option is set, connects are handled by wrapped
+/// connector. Default tcp connector of `Connector` is this connector with
+/// `multi_address` option.
+#[derive(Clone)]
+pub(crate) struct BindConnector<T> {
+    connector: T,
+    config: BindSlot,
+    resolver: Resolver<Uri>,
+    latency: AddressLatency,
+    dns_cache: DnsCache,
+    observer: ObserverSlot,
+}
+
+impl<T> BindConnector<T> {
+    pub(crate) fn new(connector: T, config: BindSlot, observer: ObserverSlot) -> Self {
+        BindConnector {
+            connector,
+            config,
+            resolver: Resolver::default(),
+            latency: AddressLatency::default(),
+            dns_cache: DnsCache::default(),
+            observer,
+        }
+    }
+}
+
+impl<T> Service for BindConnector<T>
+where
+    T: Service<
+        Request = TcpConnect<Uri>,
+        Response = TcpConnection<Uri, TcpStream>,
+        Error = ConnectError,
+    >,
+{
+    type Request = TcpConnect<Uri>;
+    type Response = TcpConnection<Uri, TcpStream>;
+    type Error = ConnectError;
+    type Future =
+        Either<T::Future, Box<dyn Future<Item = Self::Response, Error = Self::Error>>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if self.config.borrow().is_enabled() {
+            Ok(Async::Ready(()))
+        } else {
+            self.connector.poll_ready()
+        }
+    }
+
+    fn call(&mut self, req: TcpConnect<Uri>) -> Self::Future {
+        let config = self.config.borrow().clone();
+        if !config.is_enabled() {
+            return Either::A(self.connector.call(req));
+        }
+
+        let latency = if config.adaptive_order {
+            Some(self.latency.clone())
+        } else {
+            None
+        };
+        let observer = self.observer.borrow().clone();
+        let host = req.host().to_owned();
+        let port = req.port();
+        // connection pool does not use request uri of tcp connection
+        let uri = format!("{}:{}", host, port).parse().unwrap_or_default();
+
+        // preresolved addresses are not cached
+        let mut resolver = match config.resolver {
+            Some(ref resolver) => resolver.clone(),
+            None => self.resolver.clone(),
+        };
+        let addrs: Box<dyn Future<Item = _, Error = _>> = match config.dns_cache_ttl {
+            Some(ttl) if req.addrs().next().is_none() => self.dns_cache.resolve(
+                &host,
+                port,
+                ttl,
+                config.stale_while_revalidate,
+                move || resolve(&mut resolver, req),
+            ),
+            _ => Box::new(resolve(&mut resolver, req)),
+        };
+        Either::B(Box::new(addrs.and_then(move |mut addrs| {
+            if let Some(ref latency) = latency {
+                latency.sort(&host, &mut addrs);
+            }
+            BindConnect {
+                config,
+                uri: Some(uri),
+                addrs,
+                stream: None,
+                error: None,
+                host,
+                latency,
+                observer,
+                addr: None,
+                started: Instant::now(),
+            }
+        })))
+    }
+}
+
+/// Resolve addresses of connect request
+fn resolve(
+    resolver: &mut Resolver<Uri>,
+    req: TcpConnect<Uri>,
+) -> impl Future<Item = VecDeque<SocketAddr>, Error = ConnectError> {
+    resolver.call(req).and_then(|mut req| {
+        let addrs: VecDeque<_> = req.take_addrs().collect();
+        if addrs.is_empty() {
+            return Err(ConnectError::NoRecords);
+        }
+        Ok(addrs)
+    })
+}
+
+struct BindConnect {
+    config: BindConfig,
+    uri: Option<Uri>,
+    addrs: VecDeque<SocketAddr>,
+    stream: Option<ConnectFuture>,
+    error: Option<io::Error>,
+    host: String,
+    latency: Option<AddressLatency>,
+    observer: Option<Rc<dyn PoolObserver>>,
+    addr: Option<SocketAddr>,
+    started: Instant,
+}
+
+impl BindConnect {
+    /// Report connect attempt of current address
+    fn attempt(&self, attempt: ConnectAttempt) {
+        if let (Some(observer), Some(addr)) = (&self.observer, self.addr) {
+            let elapsed = self.started.elapsed();
+            observer.on_connect_attempt(&self.host, addr, elapsed, attempt);
+        }
+    }
+
+    /// Record failed connect, returns most informative error
+    /// if there are no more addresses to try.
+    fn failed(&mut self, err: io::Error) -> Option<ConnectError> {
+        if let (Some(latency), Some(addr)) = (&self.latency, &self.addr) {
+            latency.remove(&self.host, addr);
+        }
+        self.error = match self.error.take() {
+            Some(prev) if error_rank(&prev) >= error_rank(&err) => Some(prev),
+            _ => Some(err),
+        };
+        if self.addrs.is_empty() {
+            self.error.take().map(ConnectError::Io)
+        } else {
+            None
+        }
+    }
+}
+
+impl Future for BindConnect {
+    type Item = TcpConnection<Uri, TcpStream>;
+    type Error = ConnectError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if let Some(ref mut fut) = self.stream {
+                match fut.poll() {
+                    Ok(Async::Ready(io)) => {
+                        self.stream = None;
+                        self.attempt(ConnectAttempt::Connected);
+                        if let (Some(latency), Some(addr)) = (&self.latency, self.addr) {
+                            latency.record(&self.host, addr, self.started.elapsed());
+                        }
+                        let uri = self.uri.take().unwrap();
+                        return Ok(Async::Ready(TcpConnection::new(io, uri)));
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        trace!("TCP connector - failed to connect: {:?}", e);
+                        self.stream = None;
+                        self.attempt(ConnectAttempt::Failed);
+                        if let Some(err) = self.failed(e) {
+                            return Err(err);
+                        }
+                    }
+                }
+            }
+
+            // try next address
+            let addr = self.addrs.pop_front().unwrap();
+            self.addr = Some(addr);
+            self.started = Instant::now();
+            match connect(&addr, &self.config) {
+                Ok(fut) => self.stream = Some(fut),
+                Err(e) => {
+                    self.stream = None;
+                    self.attempt(ConnectAttempt::Failed);
+                    if let Some(err) = self.failed(e) {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for BindConnect {
+    fn drop(&mut self) {
+        // connect is abandoned, i.e. on connect timeout
+        if self.stream.is_some() {
+            self.attempt(ConnectAttempt::Cancelled);
+        }
+    }
+}
+
+/// Rank of failed connect error.
+///
+/// If connects to all addresses of a host fail (i.e. both ipv4 and ipv6
+/// addresses), error of reached peer is reported rather than failure to
+/// reach the network. On equal rank the first error is reported.
+fn error_rank(err: &io::Error) -> u8 {
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused => 3,
+        io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::TimedOut => 2,
+        _ => 1,
+    }
+}
+
+fn connect(addr: &SocketAddr, config: &BindConfig) -> io::Result<ConnectFuture> {
+    let builder = match addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+    };
+    if config.reuse_address {
+        builder.reuse_address(true)?;
+    }
+    #[cfg(unix)]
+    {
+        if config.reuse_port {
+            use net2::unix::UnixTcpBuilderExt;
+            builder.reuse_port(true)?;
+        }
+    }
+    let local = match config.local_address_for {
+        Some(ref select) => select(addr).map(|ip| SocketAddr::new(ip, 0)),
+        None => config.local_address,
+    };
+    if let Some(ref local) = local {
+        builder.bind(local)?;
+    }
+    let stream = builder.to_tcp_stream()?;
+    Ok(TcpStream::connect_std(stream, addr, &Handle::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Connect future with pending addresses, io is not started
+    fn pending_connect(addrs: usize) -> BindConnect {
+        BindConnect {
+            config: BindConfig::default(),
+            uri: None,
+            addrs: (0..addrs)
+                .map(|port| SocketAddr::from(([127, 0, 0, 1], port as u16)))
+                .collect(),
+            stream: None,
+            error: None,
+            host: "localhost".to_owned(),
+            latency: None,
+            observer: None,
+            addr: None,
+            started: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_dual_stack_error() {
+        let refused = || io::Error::from(io::ErrorKind::ConnectionRefused);
+        let timed_out = || io::Error::from(io::ErrorKind::TimedOut);
+        let unreachable = || io::Error::new(io::ErrorKind::Other, "unreachable");
+        assert!(error_rank(&refused()) > error_rank(&timed_out()));
+        assert!(error_rank(&timed_out()) > error_rank(&unreachable()));
+
+        // error of reached peer is reported regardless of address order
+        let cases = vec![
+            (vec![unreachable(), refused()], io::ErrorKind::ConnectionRefused),
+            (vec![refused(), unreachable()], io::ErrorKind::ConnectionRefused),
+            (vec![timed_out(), refused()], io::ErrorKind::ConnectionRefused),
+            (vec![unreachable(), timed_out()], io::ErrorKind::TimedOut),
+        ];
+        for (errors, kind) in cases {
+            let mut connect = pending_connect(errors.len() - 1);
+            let mut res = None;
+            for err in errors {
+                assert!(res.is_none());
+                res = connect.failed(err);
+                connect.addrs.pop_front();
+            }
+            match res {
+                Some(ConnectError::Io(e)) => assert_eq!(e.kind(), kind),
+                _ => panic!("connect error is expected"),
+            }
+        }
+
+        // first error is reported on equal rank
+        let mut connect = pending_connect(1);
+        let first = io::Error::new(io::ErrorKind::Other, "first");
+        let second = io::Error::new(io::ErrorKind::Other, "second");
+        assert!(connect.failed(first).is_none());
+        connect.addrs.pop_front();
+        match connect.failed(second) {
+            Some(ConnectError::Io(e)) => assert_eq!(e.to_string(), "first"),
+            _ => panic!("connect error is expected"),
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Attempts(Rc<RefCell<Vec<(SocketAddr, Duration, ConnectAttempt)>>>);
+
+    impl PoolObserver for Attempts {
+        fn on_connect_attempt(
+            &self,
+            _: &str,
+            addr: SocketAddr,
+            elapsed: Duration,
+            attempt: ConnectAttempt,
+        ) {
+            self.0.borrow_mut().push((addr, elapsed, attempt));
+        }
+    }
+
+    #[test]
+    fn test_connect_attempts() {
+        // closed local port is tried first
+        let refused = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let attempts = Attempts::default();
+        let observer: Rc<dyn PoolObserver> = Rc::new(attempts.clone());
+        let mut sys = actix_rt::System::new("test");
+        let conn = sys
+            .block_on(futures::future::lazy(move || BindConnect {
+                config: BindConfig::default(),
+                uri: Some(Uri::default()),
+                addrs: vec![refused, addr].into_iter().collect(),
+                stream: None,
+                error: None,
+                host: "localhost".to_owned(),
+                latency: None,
+                observer: Some(observer),
+                addr: None,
+                started: Instant::now(),
+            }))
+            .unwrap();
+        assert_eq!(conn.get_ref().peer_addr().unwrap(), addr);
+
+        let attempts = attempts.0.borrow();
+        assert_eq!(attempts.len(), 2);
+        assert_eq!((attempts[0].0, attempts[0].2), (refused, ConnectAttempt::Failed));
+        assert_eq!((attempts[1].0, attempts[1].2), (addr, ConnectAttempt::Connected));
+        assert!(attempts.iter().all(|a| a.1 < Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_cancelled_connect_attempt() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let attempts = Attempts::default();
+        let observer: Rc<dyn PoolObserver> = Rc::new(attempts.clone());
+        let mut sys = actix_rt::System::new("test");
+        let ready = sys
+            .block_on(futures::future::lazy(move || {
+                let mut fut = BindConnect {
+                    config: BindConfig::default(),
+                    uri: Some(Uri::default()),
+                    addrs: vec![addr].into_iter().collect(),
+                    stream: None,
+                    error: None,
+                    host: "localhost".to_owned(),
+                    latency: None,
+                    observer: Some(observer),
+                    addr: None,
+                    started: Instant::now(),
+                };
+                // connect is in flight, then abandoned, i.e. by connect timeout
+                fut.poll().map(|res| res.is_ready())
+            }))
+            .unwrap();
+        assert!(!ready);
+
+        let attempts = attempts.0.borrow();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!((attempts[0].0, attempts[0].2), (addr, ConnectAttempt::Cancelled));
+    }
+
+    #[test]
+    fn test_adaptive_address_order() {
+        let slow = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let fast = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let (slow, fast) = (slow.local_addr().unwrap(), fast.local_addr().unwrap());
+
+        // fast address is consistently faster
+        let latency = AddressLatency::default();
+        for _ in 0..3 {
+            latency.record("localhost", slow, Duration::from_millis(50));
+            latency.record("localhost", fast, Duration::from_millis(5));
+        }
+
+        let mut sys = actix_rt::System::new("test");
+        for _ in 0..2 {
+            let latency = latency.clone();
+            let conn = sys
+                .block_on(futures::future::lazy(move || {
+                    let mut addrs: VecDeque<_> = vec![slow, fast].into_iter().collect();
+                    latency.sort("localhost", &mut addrs);
+                    BindConnect {
+                        config: BindConfig::default(),
+                        uri: Some(Uri::default()),
+                        addrs,
+                        stream: None,
+                        error: None,
+                        host: "localhost".to_owned(),
+                        latency: Some(latency),
+                        observer: None,
+                        addr: None,
+                        started: Instant::now(),
+                    }
+                }))
+                .unwrap();
+            // fast address is tried first
+            assert_eq!(conn.get_ref().peer_addr().unwrap(), fast);
+        }
+        // successful connects are measured, slow address is not tried
+        let hosts = latency.0.borrow();
+        assert_ne!(hosts["localhost"][&fast], Duration::from_millis(5));
+        assert_eq!(hosts["localhost"][&slow], Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_dns_stale_while_revalidate() {
+        let cached: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let fresh: SocketAddr = "127.0.0.2:8080".parse().unwrap();
+        let cache = DnsCache::default();
+        let ttl = Duration::from_secs(60);
+        let key = ("localhost".to_owned(), 8080);
+        let entry = DnsEntry {
+            addrs: vec![cached].into_iter().collect(),
+            resolved: Instant::now() - Duration::from_secs(120),
+            refreshing: false,
+        };
+        cache.entries.borrow_mut().insert(key.clone(), entry);
+
+        let calls = Rc::new(std::cell::Cell::new(0));
+        let resolve = |calls: Rc<std::cell::Cell<usize>>| {
+            move || {
+                calls.set(calls.get() + 1);
+                ok::<_, ConnectError>(vec![fresh].into_iter().collect::<VecDeque<_>>())
+            }
+        };
+
+        let mut sys = actix_rt::System::new("test");
+        let (c, r) = (cache.clone(), resolve(calls.clone()));
+        let addrs = sys
+            .block_on(futures::future::lazy(move || {
+                c.resolve("localhost", 8080, ttl, true, r)
+            }))
+            .unwrap();
+        // expired entry is served immediately, resolver refreshes it
+        assert_eq!(addrs, vec![cached]);
+        assert_eq!(calls.get(), 1);
+
+        sys.block_on(tokio_timer::sleep(Duration::from_millis(10))).unwrap();
+        assert_eq!(cache.entries.borrow()[&key].addrs, vec![fresh]);
+
+        // refreshed entry is fresh
+        let (c, r) = (cache.clone(), resolve(calls.clone()));
+        let addrs = sys
+            .block_on(futures::future::lazy(move || {
+                c.resolve("localhost", 8080, ttl, true, r)
+            }))
+            .unwrap();
+        assert_eq!(addrs, vec![fresh]);
+        assert_eq!(calls.get(), 1);
+    }
+}
@@ -0,0 +1,122 @@
+use std::rc::Rc;
+use std::time::Duration;
+
+use http::header::HeaderValue;
+
+use crate::message::ResponseHead;
+
+use super::bind::{BindSlot, ObserverSlot};
+use super::connector::CircuitBreaker;
+use super::pool::{PoolHandle, PoolObserver, QueuePolicy};
+use super::{HostConflictPolicy, Priority};
+
+/// Default max number of interim (1xx) responses before final response
+pub(crate) const MAX_INTERIM_RESPONSES: usize = 10;
+
+/// Default max size of decompressed response payload, 100 times of
+/// default client body size limit (256Kb)
+pub(crate) const MAX_DECOMPRESSED_SIZE: usize = 100 * 256 * 1024;
+
+/// Connector configuration
+#[derive(Clone)]
+pub(crate) struct ConnectorConfig {
+    pub(crate) timeout: Duration,
+    pub(crate) conn_lifetime: Duration,
+    pub(crate) conn_keep_alive: Duration,
+    pub(crate) h2_max_connection_age: Option<Duration>,
+    pub(crate) h2_keepalive_interval: Option<Duration>,
+    pub(crate) h2_keepalive_timeout: Duration,
+    pub(crate) disconnect_timeout: Option<Duration>,
+    pub(crate) limit: usize,
+    pub(crate) priority_limits: Vec<(Priority, usize)>,
+    pub(crate) health_check_reserve: usize,
+    pub(crate) limit_per_group: usize,
+    pub(crate) max_tunnels: Option<usize>,
+    pub(crate) max_idle_per_host: Option<usize>,
+    pub(crate) max_requests: Option<u64>,
+    pub(crate) strict_content_length: bool,
+    pub(crate) drain_on_drop: usize,
+    pub(crate) read_idle_timeout: Option<Duration>,
+    pub(crate) per_read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    pub(crate) expect_continue_threshold: Option<usize>,
+    pub(crate) http10_keep_alive: bool,
+    pub(crate) response_read_ahead: bool,
+    pub(crate) tolerate_missing_last_chunk: bool,
+    pub(crate) drain_rate: Option<(usize, Duration)>,
+    pub(crate) circuit_breaker: CircuitBreaker,
+    pub(crate) coalesce_small_body: usize,
+    pub(crate) flush_each_request: bool,
+    pub(crate) max_interim_responses: usize,
+    pub(crate) max_decompressed_size: usize,
+    pub(crate) max_response_header_size: Option<usize>,
+    pub(crate) max_response_headers: Option<usize>,
+    pub(crate) explicit_zero_content_length: Option<bool>,
+    pub(crate) default_accept: Option<HeaderValue>,
+    pub(crate) queue_policy: QueuePolicy,
+    pub(crate) host_conflict_policy: HostConflictPolicy,
+    pub(crate) observer: Option<Rc<dyn PoolObserver>>,
+    pub(crate) connect_observer: ObserverSlot,
+    pub(crate) should_pool: Option<Rc<dyn Fn(&ResponseHead) -> bool>>,
+    pub(crate) after_receive: Option<Rc<dyn Fn(&mut ResponseHead)>>,
+    pub(crate) pool_handle: PoolHandle,
+    pub(crate) checkout_buckets: Vec<Duration>,
+    pub(crate) bind: BindSlot,
+}
+
+impl Default for ConnectorConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+            conn_lifetime: Duration::from_secs(75),
+            conn_keep_alive: Duration::from_secs(15),
+            h2_max_connection_age: None,
+            h2_keepalive_interval: None,
+            h2_keepalive_timeout: Duration::from_secs(20),
+            disconnect_timeout: Some(Duration::from_millis(3000)),
+            limit: 100,
+            priority_limits: Vec::new(),
+            health_check_reserve: 0,
+            limit_per_group: 0,
+            max_tunnels: None,
+            max_idle_per_host: None,
+            max_requests: None,
+            strict_content_length: false,
+            drain_on_drop: 0,
+            read_idle_timeout: None,
+            per_read_timeout: None,
+            write_timeout: None,
+            expect_continue_threshold: None,
+            http10_keep_alive: false,
+            response_read_ahead: false,
+            tolerate_missing_last_chunk: false,
+            drain_rate: None,
+            circuit_breaker: CircuitBreaker::default(),
+            coalesce_small_body: 0,
+            flush_each_request: false,
+            max_interim_responses: MAX_INTERIM_RESPONSES,
+            max_decompressed_size: MAX_DECOMPRESSED_SIZE,
+            max_response_header_size: None,
+            max_response_headers: None,
+            explicit_zero_content_length: None,
+            default_accept: None,
+            queue_policy: QueuePolicy::Fifo,
+            host_conflict_policy: HostConflictPolicy::Header,
+            observer: None,
+            connect_observer: ObserverSlot::default(),
+            should_pool: None,
+            after_receive: None,
+            pool_handle: PoolHandle::default(),
+            checkout_buckets: Vec::new(),
+            bind: BindSlot::default(),
+        }
+    }
+}
+
+impl ConnectorConfig {
+    pub(crate) fn no_disconnect_timeout(&self) -> Self {
+        let mut res = self.clone();
+        res.disconnect_timeout = None;
+        res
+    }
+}
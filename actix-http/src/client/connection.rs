@@ -1,25 +1,52 @@
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
 use std::{fmt, io, time};
 
 use actix_codec::{AsyncRead, AsyncWrite, Framed};
 use bytes::{Buf, Bytes};
-use futures::future::{err, Either, Future, FutureResult};
+use futures::future::{err, ok, Either, Future, FutureResult};
+use futures::unsync::oneshot;
 use futures::Poll;
-use h2::client::SendRequest;
+use h2::client::{handshake, SendRequest};
 
 use crate::body::MessageBody;
 use crate::h1::ClientCodec;
+use crate::header::HeaderMap;
+use crate::http::header::{HeaderName, HeaderValue, ACCEPT};
 use crate::message::{RequestHeadType, ResponseHead};
 use crate::payload::Payload;
 
-use super::error::SendRequestError;
-use super::pool::{Acquired, Protocol};
+#[cfg(unix)]
+use super::connector::raw_fd;
+use super::error::{ConnectError, SendRequestError};
+use super::h2proto::{H2Connection, Preface, PrefaceIo};
+use super::pool::{Acquired, Protocol, TunnelPermit};
 use super::{h1proto, h2proto};
 
 pub(crate) enum ConnectionType<Io> {
     H1(Io),
-    H2(SendRequest<Bytes>),
+    H2(SendRequest<Bytes>, Preface),
 }
 
+/// Client connection
+///
+/// Http/1 connection can not multiplex requests, it serves single request
+/// at a time. Request methods take connection by value, connection returns
+/// to the pool only after response payload is read, so borrowed connection
+/// can not be used concurrently:
+///
+/// ```rust,compile_fail
+/// use actix_http::client::Connection;
+/// use actix_http::RequestHead;
+///
+/// fn send_twice<C: Connection>(conn: C) {
+///     let _first = conn.send_request(RequestHead::default(), ());
+///     let _second = conn.send_request(RequestHead::default(), ());
+/// }
+/// ```
+///
+/// Io of http/1 connection that is already released to the pool fails
+/// with `io::ErrorKind::NotConnected` error.
 pub trait Connection {
     type Io: AsyncRead + AsyncWrite;
     type Future: Future<Item = (ResponseHead, Payload), Error = SendRequestError>;
@@ -34,12 +61,120 @@ pub trait Connection {
     ) -> Self::Future;
 
     type TunnelFuture: Future<
-        Item = (ResponseHead, Framed<Self::Io, ClientCodec>),
+        Item = (ResponseHead, Framed<Self::Io, ClientCodec>, TunnelPermit),
         Error = SendRequestError,
     >;
 
-    /// Send request, returns Response and Framed
+    /// Send request, returns Response, Framed and tunnel permit
+    ///
+    /// Tunnel is counted against `Connector::max_tunnels()` until
+    /// returned permit is dropped. Non-2xx response to `CONNECT` request
+    /// fails with `ConnectError::ProxyStatus` error.
     fn open_tunnel<H: Into<RequestHeadType>>(self, head: H) -> Self::TunnelFuture;
+
+    /// Send request and body, fail if new stream can not be opened right away
+    ///
+    /// Http/2 connection could be at max concurrent streams limit, in that
+    /// case `send_request()` waits for a free stream while this method fails
+    /// with `SendRequestError::StreamCapacity` error. Http/1 request is
+    /// sent as with `send_request()`.
+    fn try_send_request<B: MessageBody + 'static, H: Into<RequestHeadType>>(
+        self,
+        head: H,
+        body: B,
+    ) -> Self::Future
+    where
+        Self: Sized,
+    {
+        self.send_request(head, body)
+    }
+
+    /// Total number of bytes transferred over this connection
+    ///
+    /// Counters include all requests sent over pooled connection.
+    /// Only http/1 connections are accounted.
+    fn bytes(&self) -> ConnectionBytes {
+        ConnectionBytes::default()
+    }
+
+    /// Write pre-serialized request head and body, read response
+    ///
+    /// Data is written to the connection as is and response is parsed by
+    /// http/1 client, request method is taken from the request line.
+    /// Connection is closed after response. Http/2 connections and
+    /// connections that do not implement this method fail with
+    /// `SendRequestError::RawRequestNotSupported`.
+    fn send_raw_request(
+        self,
+        _: Bytes,
+    ) -> Box<dyn Future<Item = (ResponseHead, Payload), Error = SendRequestError>>
+    where
+        Self: Sized,
+    {
+        Box::new(err(SendRequestError::RawRequestNotSupported))
+    }
+
+    /// Raw file descriptor of http/1 connection socket
+    ///
+    /// Descriptor is available for tcp and tls connections opened by
+    /// default tcp connector, connection keeps ownership of the socket.
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+/// Connection that can be reused without connection pool
+pub trait HandleConnection: Connection + Sized {
+    type HandleFuture: Future<
+        Item = (ResponseHead, Payload, ConnectionHandle<Self>),
+        Error = SendRequestError,
+    >;
+
+    /// Send request and body, returns handle of the same connection
+    ///
+    /// Handle resolves to the connection once response payload is fully
+    /// read, so next request could be sent without connection pool.
+    /// Handle fails if connection can not be reused. Http/2 connection
+    /// is available immediately.
+    fn send_request_with_handle<B: MessageBody + 'static, H: Into<RequestHeadType>>(
+        self,
+        head: H,
+        body: B,
+    ) -> Self::HandleFuture;
+}
+
+/// Number of bytes sent and received over connection
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionBytes {
+    /// Bytes written to the socket
+    pub sent: u64,
+    /// Bytes read from the socket
+    pub received: u64,
+    /// Requests sent over connection
+    pub requests: u64,
+}
+
+/// Future that resolves to connection after response completion
+pub struct ConnectionHandle<C>(Box<dyn Future<Item = C, Error = ConnectError>>);
+
+impl<C> ConnectionHandle<C> {
+    /// Create handle from future that resolves to connection
+    pub fn new<F>(fut: F) -> Self
+    where
+        F: Future<Item = C, Error = ConnectError> + 'static,
+    {
+        ConnectionHandle(Box::new(fut))
+    }
+}
+
+impl<C> Future for ConnectionHandle<C> {
+    type Item = C;
+    type Error = ConnectError;
+
+    fn poll(&mut self) -> Poll<C, ConnectError> {
+        self.0.poll()
+    }
 }
 
 pub(crate) trait ConnectionLifetime: AsyncRead + AsyncWrite + 'static {
@@ -50,11 +185,11 @@ pub(crate) trait ConnectionLifetime: AsyncRead + AsyncWrite + 'static {
     fn release(&mut self);
 }
 
-#[doc(hidden)]
 /// HTTP client connection
 pub struct IoConnection<T> {
     io: Option<ConnectionType<T>>,
     created: time::Instant,
+    bytes: ConnectionBytes,
     pool: Option<Acquired<T>>,
 }
 
@@ -65,7 +200,7 @@ where
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.io {
             Some(ConnectionType::H1(ref io)) => write!(f, "H1Connection({:?})", io),
-            Some(ConnectionType::H2(_)) => write!(f, "H2Connection"),
+            Some(ConnectionType::H2(..)) => write!(f, "H2Connection"),
             None => write!(f, "Connection(Empty)"),
         }
     }
@@ -80,12 +215,51 @@ impl<T: AsyncRead + AsyncWrite> IoConnection<T> {
         IoConnection {
             pool,
             created,
+            bytes: ConnectionBytes::default(),
             io: Some(io),
         }
     }
 
-    pub(crate) fn into_inner(self) -> (ConnectionType<T>, time::Instant) {
-        (self.io.unwrap(), self.created)
+    pub(crate) fn with_bytes(mut self, bytes: ConnectionBytes) -> Self {
+        self.bytes = bytes;
+        self
+    }
+
+    pub(crate) fn into_inner(
+        self,
+    ) -> (ConnectionType<T>, time::Instant, ConnectionBytes) {
+        (self.io.unwrap(), self.created, self.bytes)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + 'static> IoConnection<T> {
+    /// Create connection from already established stream.
+    ///
+    /// Connection does not belong to any connections pool, it gets closed
+    /// after request completion. Http/2 handshake is performed
+    /// for `Protocol::Http2`.
+    pub fn from_stream(
+        io: T,
+        protocol: Protocol,
+    ) -> impl Future<Item = IoConnection<T>, Error = ConnectError> {
+        match protocol {
+            Protocol::Http1 => Either::A(ok(IoConnection::new(
+                ConnectionType::H1(io),
+                time::Instant::now(),
+                None,
+            ))),
+            Protocol::Http2 => {
+                let (io, preface) = PrefaceIo::new(io);
+                Either::B(handshake(io).from_err().map(move |(snd, connection)| {
+                    tokio_current_thread::spawn(H2Connection::new(connection, None));
+                    IoConnection::new(
+                        ConnectionType::H2(snd, preface),
+                        time::Instant::now(),
+                        None,
+                    )
+                }))
+            }
+        }
     }
 }
 
@@ -100,7 +274,7 @@ where
     fn protocol(&self) -> Protocol {
         match self.io {
             Some(ConnectionType::H1(_)) => Protocol::Http1,
-            Some(ConnectionType::H2(_)) => Protocol::Http2,
+            Some(ConnectionType::H2(..)) => Protocol::Http2,
             None => Protocol::Http1,
         }
     }
@@ -110,44 +284,96 @@ where
         head: H,
         body: B,
     ) -> Self::Future {
+        let head = self.request_head(head);
+
         match self.io.take().unwrap() {
             ConnectionType::H1(io) => Box::new(h1proto::send_request(
                 io,
-                head.into(),
+                head,
                 body,
                 self.created,
+                self.bytes,
                 self.pool,
+                None,
             )),
-            ConnectionType::H2(io) => Box::new(h2proto::send_request(
+            ConnectionType::H2(io, preface) => Box::new(h2proto::send_request(
+                io,
+                preface,
+                head,
+                body,
+                self.created,
+                self.pool,
+                false,
+            )),
+        }
+    }
+
+    fn try_send_request<B: MessageBody + 'static, H: Into<RequestHeadType>>(
+        mut self,
+        head: H,
+        body: B,
+    ) -> Self::Future {
+        match self.io {
+            Some(ConnectionType::H2(..)) => (),
+            _ => return self.send_request(head, body),
+        }
+        let head = self.request_head(head);
+
+        match self.io.take().unwrap() {
+            ConnectionType::H2(io, preface) => Box::new(h2proto::send_request(
                 io,
-                head.into(),
+                preface,
+                head,
                 body,
                 self.created,
                 self.pool,
+                true,
             )),
+            ConnectionType::H1(_) => unreachable!(),
         }
     }
 
     type TunnelFuture = Either<
         Box<
             dyn Future<
-                Item = (ResponseHead, Framed<Self::Io, ClientCodec>),
+                Item = (ResponseHead, Framed<Self::Io, ClientCodec>, TunnelPermit),
                 Error = SendRequestError,
             >,
         >,
-        FutureResult<(ResponseHead, Framed<Self::Io, ClientCodec>), SendRequestError>,
+        FutureResult<
+            (ResponseHead, Framed<Self::Io, ClientCodec>, TunnelPermit),
+            SendRequestError,
+        >,
     >;
 
-    /// Send request, returns Response and Framed
+    /// Send request, returns Response, Framed and tunnel permit
     fn open_tunnel<H: Into<RequestHeadType>>(mut self, head: H) -> Self::TunnelFuture {
         match self.io.take().unwrap() {
             ConnectionType::H1(io) => {
-                Either::A(Box::new(h1proto::open_tunnel(io, head.into())))
+                let permit = match self.pool.as_ref().map(|pool| pool.tunnel_permit()) {
+                    Some(Ok(permit)) => permit,
+                    Some(Err(e)) => {
+                        // connection is not used, return it to the pool
+                        let conn = IoConnection::new(
+                            ConnectionType::H1(io),
+                            self.created,
+                            None,
+                        )
+                        .with_bytes(self.bytes);
+                        self.pool.take().unwrap().release(conn);
+                        return Either::B(err(e));
+                    }
+                    None => TunnelPermit::default(),
+                };
+                Either::A(Box::new(
+                    h1proto::open_tunnel(io, head.into())
+                        .map(move |(head, framed)| (head, framed, permit)),
+                ))
             }
-            ConnectionType::H2(io) => {
+            ConnectionType::H2(io, preface) => {
                 if let Some(mut pool) = self.pool.take() {
                     pool.release(IoConnection::new(
-                        ConnectionType::H2(io),
+                        ConnectionType::H2(io, preface),
                         self.created,
                         None,
                     ));
@@ -156,6 +382,125 @@ where
             }
         }
     }
+
+    fn bytes(&self) -> ConnectionBytes {
+        self.bytes
+    }
+
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        match self.io {
+            Some(ConnectionType::H1(ref io)) => raw_fd(io),
+            _ => None,
+        }
+    }
+
+    fn send_raw_request(mut self, data: Bytes) -> Self::Future
+    where
+        Self: Sized,
+    {
+        match self.io.take().unwrap() {
+            ConnectionType::H1(io) => Box::new(h1proto::send_raw_request(
+                io,
+                data,
+                self.created,
+                self.bytes,
+                self.pool,
+            )),
+            ConnectionType::H2(io, preface) => {
+                if let Some(mut pool) = self.pool.take() {
+                    pool.release(IoConnection::new(
+                        ConnectionType::H2(io, preface),
+                        self.created,
+                        None,
+                    ));
+                }
+                Box::new(err(SendRequestError::RawRequestNotSupported))
+            }
+        }
+    }
+}
+
+impl<T> HandleConnection for IoConnection<T>
+where
+    T: AsyncRead + AsyncWrite + 'static,
+{
+    type HandleFuture = Box<
+        dyn Future<
+            Item = (ResponseHead, Payload, ConnectionHandle<Self>),
+            Error = SendRequestError,
+        >,
+    >;
+
+    fn send_request_with_handle<B: MessageBody + 'static, H: Into<RequestHeadType>>(
+        mut self,
+        head: H,
+        body: B,
+    ) -> Self::HandleFuture {
+        let head = self.request_head(head);
+
+        match self.io.take().unwrap() {
+            ConnectionType::H1(io) => {
+                let (tx, rx) = oneshot::channel();
+                let handle =
+                    ConnectionHandle::new(rx.map_err(|_| ConnectError::Disconnected));
+                Box::new(
+                    h1proto::send_request(
+                        io,
+                        head,
+                        body,
+                        self.created,
+                        self.bytes,
+                        self.pool,
+                        Some(tx),
+                    )
+                    .map(move |(head, payload)| (head, payload, handle)),
+                )
+            }
+            ConnectionType::H2(io, preface) => {
+                // streams are multiplexed, connection is available right away
+                let conn = IoConnection::new(
+                    ConnectionType::H2(io.clone(), preface.clone()),
+                    self.created,
+                    None,
+                );
+                Box::new(
+                    h2proto::send_request(
+                        io,
+                        preface,
+                        head,
+                        body,
+                        self.created,
+                        self.pool,
+                        false,
+                    )
+                    .map(move |(head, payload)| {
+                        (head, payload, ConnectionHandle::new(ok(conn)))
+                    }),
+                )
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + 'static> IoConnection<T> {
+    /// Apply connector defaults to request head
+    fn request_head<H: Into<RequestHeadType>>(&self, head: H) -> RequestHeadType {
+        let mut head = head.into();
+        if let Some(config) = self.pool.as_ref().and_then(|pool| pool.config()) {
+            if let Some(ref accept) = config.default_accept {
+                set_header_if_none(&mut head, ACCEPT, accept);
+            }
+        }
+        head
+    }
+
+    /// Return connection to the pool, connection without pool is closed
+    pub(crate) fn release(mut self) {
+        if let Some(mut pool) = self.pool.take() {
+            pool.release(self)
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -180,6 +525,31 @@ where
         }
     }
 
+    fn bytes(&self) -> ConnectionBytes {
+        match self {
+            EitherConnection::A(con) => con.bytes(),
+            EitherConnection::B(con) => con.bytes(),
+        }
+    }
+
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        match self {
+            EitherConnection::A(con) => con.as_raw_fd(),
+            EitherConnection::B(con) => con.as_raw_fd(),
+        }
+    }
+
+    fn send_raw_request(self, data: Bytes) -> Self::Future
+    where
+        Self: Sized,
+    {
+        match self {
+            EitherConnection::A(con) => con.send_raw_request(data),
+            EitherConnection::B(con) => con.send_raw_request(data),
+        }
+    }
+
     fn send_request<RB: MessageBody + 'static, H: Into<RequestHeadType>>(
         self,
         head: H,
@@ -191,23 +561,74 @@ where
         }
     }
 
+    fn try_send_request<RB: MessageBody + 'static, H: Into<RequestHeadType>>(
+        self,
+        head: H,
+        body: RB,
+    ) -> Self::Future {
+        match self {
+            EitherConnection::A(con) => con.try_send_request(head, body),
+            EitherConnection::B(con) => con.try_send_request(head, body),
+        }
+    }
+
     type TunnelFuture = Box<
         dyn Future<
-            Item = (ResponseHead, Framed<Self::Io, ClientCodec>),
+            Item = (ResponseHead, Framed<Self::Io, ClientCodec>, TunnelPermit),
             Error = SendRequestError,
         >,
     >;
 
-    /// Send request, returns Response and Framed
+    /// Send request, returns Response, Framed and tunnel permit
     fn open_tunnel<H: Into<RequestHeadType>>(self, head: H) -> Self::TunnelFuture {
+        match self {
+            EitherConnection::A(con) => {
+                Box::new(con.open_tunnel(head).map(|(head, framed, permit)| {
+                    (head, framed.map_io(EitherIo::A), permit)
+                }))
+            }
+            EitherConnection::B(con) => {
+                Box::new(con.open_tunnel(head).map(|(head, framed, permit)| {
+                    (head, framed.map_io(EitherIo::B), permit)
+                }))
+            }
+        }
+    }
+}
+
+impl<A, B> HandleConnection for EitherConnection<A, B>
+where
+    A: AsyncRead + AsyncWrite + 'static,
+    B: AsyncRead + AsyncWrite + 'static,
+{
+    type HandleFuture = Box<
+        dyn Future<
+            Item = (ResponseHead, Payload, ConnectionHandle<Self>),
+            Error = SendRequestError,
+        >,
+    >;
+
+    fn send_request_with_handle<RB: MessageBody + 'static, H: Into<RequestHeadType>>(
+        self,
+        head: H,
+        body: RB,
+    ) -> Self::HandleFuture {
         match self {
             EitherConnection::A(con) => Box::new(
-                con.open_tunnel(head)
-                    .map(|(head, framed)| (head, framed.map_io(EitherIo::A))),
+                con.send_request_with_handle(head, body)
+                    .map(|(head, payload, handle)| {
+                        let handle =
+                            ConnectionHandle::new(handle.map(EitherConnection::A));
+                        (head, payload, handle)
+                    }),
             ),
             EitherConnection::B(con) => Box::new(
-                con.open_tunnel(head)
-                    .map(|(head, framed)| (head, framed.map_io(EitherIo::B))),
+                con.send_request_with_handle(head, body)
+                    .map(|(head, payload, handle)| {
+                        let handle =
+                            ConnectionHandle::new(handle.map(EitherConnection::B));
+                        (head, payload, handle)
+                    }),
             ),
         }
     }
@@ -286,3 +707,99 @@ where
         }
     }
 }
+
+/// Set request header, unless request or its extra headers already have it
+fn set_header_if_none(head: &mut RequestHeadType, name: HeaderName, value: &HeaderValue) {
+    if head.as_ref().headers.contains_key(&name)
+        || head.extra_headers().iter().any(|h| h.contains_key(&name))
+    {
+        return;
+    }
+    match head {
+        RequestHeadType::Owned(ref mut head) => {
+            head.headers.insert(name, value.clone());
+        }
+        RequestHeadType::Rc(_, ref mut extra_headers) => {
+            extra_headers
+                .get_or_insert(HeaderMap::new())
+                .insert(name, value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use actix_rt::System;
+    use actix_service::{IntoService, Service};
+    use futures::future::lazy;
+    use futures::Stream;
+
+    use super::super::config::ConnectorConfig;
+    use super::super::pool::ConnectionPool;
+    use super::super::Connect;
+    use super::*;
+    use crate::http::{StatusCode, Uri};
+    use crate::message::RequestHead;
+    use crate::test::TestBuffer;
+
+    #[test]
+    fn test_h1_use_after_release() {
+        let mut sys = System::new("test");
+        let mut pool = ConnectionPool::new(
+            (|_: Connect| Ok((TestBuffer::empty(), Protocol::Http1))).into_service(),
+            ConnectorConfig::default(),
+        );
+        let uri: Uri = "http://localhost/".parse().unwrap();
+
+        let mut conn = sys
+            .block_on(lazy(|| pool.call(Connect::new(uri.clone()))))
+            .unwrap();
+        let io = match conn.io.take() {
+            Some(ConnectionType::H1(io)) => io,
+            _ => panic!(),
+        };
+        let pool_ref = conn.pool.take();
+        let mut h1 = h1proto::H1Connection::new(io, conn.created, conn.bytes, pool_ref);
+        h1.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        h1.release();
+
+        // stale holder can not write into pooled connection
+        let err = h1.write(b"GET /other HTTP/1.1\r\n\r\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        let err = h1.flush().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+
+        let mut conn = sys
+            .block_on(lazy(|| pool.call(Connect::new(uri))))
+            .unwrap();
+        match conn.io.take() {
+            Some(ConnectionType::H1(io)) => {
+                assert_eq!(&io.write_buf[..], &b"GET / HTTP/1.1\r\n\r\n"[..]);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_h1_connection_from_stream() {
+        // read side of the buffer holds server response
+        let io = TestBuffer::new("HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello");
+
+        let mut sys = System::new("test");
+        let (head, payload) = sys
+            .block_on(
+                IoConnection::from_stream(io, Protocol::Http1)
+                    .from_err()
+                    .and_then(|conn| {
+                        assert_eq!(conn.protocol(), Protocol::Http1);
+                        conn.send_request(RequestHead::default(), ())
+                    }),
+            )
+            .unwrap();
+        let body = sys.block_on(payload.concat2()).unwrap();
+        assert_eq!(head.status, StatusCode::OK);
+        assert_eq!(&body[..], b"hello");
+    }
+}
@@ -1,36 +1,121 @@
-use std::fmt;
+#[cfg(unix)]
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::marker::PhantomData;
-use std::time::Duration;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{cmp, fmt, io, net};
 
 use actix_codec::{AsyncRead, AsyncWrite};
 use actix_connect::{
-    default_connector, Connect as TcpConnect, Connection as TcpConnection,
+    default_connector, Connect as TcpConnect, Connection as TcpConnection, Resolver,
 };
 use actix_service::{apply_fn, Service, ServiceExt};
-use actix_utils::timeout::{TimeoutError, TimeoutService};
+use futures::future::err;
+use futures::{Future, Poll};
+use http::header::HeaderValue;
 use http::Uri;
 use tokio_tcp::TcpStream;
+use tokio_timer::Timeout;
+use trust_dns_resolver::config::{LookupIpStrategy, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::system_conf::read_system_conf;
+
+use crate::message::ResponseHead;
 
-use super::connection::Connection;
-use super::error::ConnectError;
-use super::pool::{ConnectionPool, Protocol};
-use super::Connect;
+use super::bind::BindConnector;
+use super::config::ConnectorConfig;
+use super::connection::HandleConnection;
+use super::error::{ConnectError, PeerAddressRejected};
+use super::pool::{
+    CircuitState, ConnectionPool, PoolHandle, PoolObserver, Protocol, QueuePolicy,
+};
+use super::{Connect, HostConflictPolicy, Priority};
 
 #[cfg(feature = "ssl")]
 use openssl::ssl::SslConnector as OpensslConnector;
 
 #[cfg(feature = "rust-tls")]
 use rustls::ClientConfig;
-#[cfg(feature = "rust-tls")]
-use std::sync::Arc;
 
+/// Dns record types requested when resolving host name
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DnsQuery {
+    /// Ipv4 addresses only
+    A,
+    /// Ipv6 addresses only
+    AAAA,
+    /// Both ipv4 and ipv6 addresses
+    Both,
+}
+
+/// Tls configuration used for new connections
 #[cfg(any(feature = "ssl", feature = "rust-tls"))]
-enum SslConnector {
+#[derive(Clone)]
+pub enum TlsConfig {
     #[cfg(feature = "ssl")]
     Openssl(OpensslConnector),
     #[cfg(feature = "rust-tls")]
     Rustls(Arc<ClientConfig>),
 }
+
+/// Handle for replacing tls configuration of a running connector.
+///
+/// New tls connections use configuration that is set at the time
+/// of connect, pooled connections are kept until they expire or
+/// the pool is drained.
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+#[derive(Clone)]
+pub struct TlsConfigHandle(Rc<RefCell<TlsConfigs>>, PoolHandle);
+
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+impl TlsConfigHandle {
+    /// Replace tls configuration
+    pub fn set_tls_config(&self, config: TlsConfig) {
+        self.0.borrow_mut().default = config;
+    }
+
+    /// Replace tls configuration and drain connection pool.
+    ///
+    /// Connections opened with previous configuration are closed, see
+    /// `PoolHandle::drain()`. Must be called from within the running system.
+    pub fn set_tls_config_and_drain(&self, config: TlsConfig) {
+        self.set_tls_config(config);
+        self.1.drain();
+    }
+
+    /// Replace tls configuration used for specific host
+    pub fn set_host_tls_config(&self, host: &str, config: TlsConfig) {
+        self.0.borrow_mut().hosts.insert(host.to_owned(), config);
+    }
+}
+
+/// Default tls configuration and per host overrides
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+struct TlsConfigs {
+    default: TlsConfig,
+    hosts: HashMap<String, TlsConfig>,
+}
+
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+impl TlsConfigs {
+    fn new(default: TlsConfig) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(TlsConfigs {
+            default,
+            hosts: HashMap::new(),
+        }))
+    }
+
+    fn get(&self, host: &str) -> &TlsConfig {
+        self.hosts.get(host).unwrap_or(&self.default)
+    }
+}
+
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+type SslConnector = Rc<RefCell<TlsConfigs>>;
 #[cfg(not(any(feature = "ssl", feature = "rust-tls")))]
 type SslConnector = ();
 
@@ -48,18 +133,98 @@ type SslConnector = ();
 /// ```
 pub struct Connector<T, U> {
     connector: T,
-    timeout: Duration,
-    conn_lifetime: Duration,
-    conn_keep_alive: Duration,
-    disconnect_timeout: Duration,
-    limit: usize,
+    config: ConnectorConfig,
     #[allow(dead_code)]
     ssl: SslConnector,
     _t: PhantomData<U>,
 }
 
-trait Io: AsyncRead + AsyncWrite {}
-impl<T: AsyncRead + AsyncWrite> Io for T {}
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+trait Io: AsyncRead + AsyncWrite {
+    /// Raw file descriptor of underlying tcp stream
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd>;
+}
+
+/// Tls stream, keeps raw file descriptor of its tcp stream
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+struct TlsIo<T> {
+    io: T,
+    #[cfg(unix)]
+    fd: Option<RawFd>,
+}
+
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+impl<T: AsyncRead + AsyncWrite + 'static> TlsIo<T> {
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn boxed<F, U>(io: T, tcp: F) -> Box<dyn Io>
+    where
+        F: FnOnce(&T) -> &U,
+        U: 'static,
+    {
+        Box::new(TlsIo {
+            #[cfg(unix)]
+            fd: raw_fd(tcp(&io)),
+            io,
+        })
+    }
+}
+
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+impl<T: AsyncRead + AsyncWrite> Io for TlsIo<T> {
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.fd
+    }
+}
+
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+impl<T: io::Read> io::Read for TlsIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+impl<T: AsyncRead> AsyncRead for TlsIo<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+impl<T: io::Write> io::Write for TlsIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+impl<T: AsyncWrite> AsyncWrite for TlsIo<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+/// Raw file descriptor of connection io opened by default tcp connector
+#[cfg(unix)]
+pub(crate) fn raw_fd<T: 'static>(io: &T) -> Option<RawFd> {
+    let io = io as &dyn Any;
+    if let Some(stream) = io.downcast_ref::<TcpStream>() {
+        return Some(stream.as_raw_fd());
+    }
+    #[cfg(any(feature = "ssl", feature = "rust-tls"))]
+    {
+        if let Some(io) = io.downcast_ref::<Box<dyn Io>>() {
+            return io.raw_fd();
+        }
+    }
+    None
+}
 
 impl Connector<(), ()> {
     #[allow(clippy::new_ret_no_self)]
@@ -80,7 +245,7 @@ impl Connector<(), ()> {
                 let _ = ssl
                     .set_alpn_protos(b"\x02h2\x08http/1.1")
                     .map_err(|e| error!("Can not set alpn protocol: {:?}", e));
-                SslConnector::Openssl(ssl.build())
+                TlsConfigs::new(TlsConfig::Openssl(ssl.build()))
             }
             #[cfg(all(not(feature = "ssl"), feature = "rust-tls"))]
             {
@@ -90,20 +255,27 @@ impl Connector<(), ()> {
                 config
                     .root_store
                     .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
-                SslConnector::Rustls(Arc::new(config))
+                TlsConfigs::new(TlsConfig::Rustls(Arc::new(config)))
             }
             #[cfg(not(any(feature = "ssl", feature = "rust-tls")))]
             {}
         };
 
+        // host addresses are tried one by one, connect attempts are reported
+        // and most informative error is returned if all of them fail. socket
+        // binding options are read from connector configuration on connect
+        let config = ConnectorConfig::default();
+        config.bind.borrow_mut().multi_address = true;
+        let connector = BindConnector::new(
+            default_connector(),
+            config.bind.clone(),
+            config.connect_observer.clone(),
+        );
+
         Connector {
             ssl,
-            connector: default_connector(),
-            timeout: Duration::from_secs(1),
-            conn_lifetime: Duration::from_secs(75),
-            conn_keep_alive: Duration::from_secs(15),
-            disconnect_timeout: Duration::from_millis(3000),
-            limit: 100,
+            connector,
+            config,
             _t: PhantomData,
         }
     }
@@ -122,17 +294,254 @@ impl<T, U> Connector<T, U> {
     {
         Connector {
             connector,
-            timeout: self.timeout,
-            conn_lifetime: self.conn_lifetime,
-            conn_keep_alive: self.conn_keep_alive,
-            disconnect_timeout: self.disconnect_timeout,
-            limit: self.limit,
+            config: self.config,
             ssl: self.ssl,
             _t: PhantomData,
         }
     }
 }
 
+impl<T> Connector<T, TcpStream>
+where
+    T: Service<
+            Request = TcpConnect<Uri>,
+            Response = TcpConnection<Uri, TcpStream>,
+            Error = actix_connect::ConnectError,
+        > + Clone
+        + 'static,
+{
+    /// Bind outbound sockets to specified local address.
+    ///
+    /// Socket binding options apply to default tcp connector, they are
+    /// ignored if custom connector is set with `connector()`.
+    pub fn local_address(self, addr: net::SocketAddr) -> Self {
+        self.config.bind.borrow_mut().local_address = Some(addr);
+        self
+    }
+
+    /// Select local address of outbound sockets by destination address.
+    ///
+    /// Function is called before every connect attempt, socket is bound
+    /// to returned address with port chosen by operating system. If
+    /// function returns `None`, operating system chooses local address.
+    /// Overrides `local_address()`. See `local_address()` for notes.
+    pub fn local_address_for(
+        self,
+        f: Arc<dyn Fn(&net::SocketAddr) -> Option<net::IpAddr>>,
+    ) -> Self {
+        self.config.bind.borrow_mut().local_address_for = Some(f);
+        self
+    }
+
+    /// Set `SO_REUSEADDR` option on outbound sockets before bind.
+    ///
+    /// Allows to bind local address that is still in `TIME_WAIT` state.
+    /// Disabled by default. See `local_address()` for notes.
+    pub fn reuse_address(self, val: bool) -> Self {
+        self.config.bind.borrow_mut().reuse_address = val;
+        self
+    }
+
+    /// Set `SO_REUSEPORT` option on outbound sockets before bind.
+    ///
+    /// Only supported on unix platforms, ignored elsewhere. Disabled by
+    /// default. See `local_address()` for notes.
+    pub fn reuse_port(self, val: bool) -> Self {
+        self.config.bind.borrow_mut().reuse_port = val;
+        self
+    }
+
+    /// Prefer host addresses with lower connect latency.
+    ///
+    /// Connector remembers recent connect latency of host addresses,
+    /// next connects to the host try addresses in order of measured
+    /// latency. Disabled by default. Applies to default tcp connector.
+    pub fn adaptive_address_order(self, val: bool) -> Self {
+        self.config.bind.borrow_mut().adaptive_order = val;
+        self
+    }
+
+    /// Cache resolved host addresses for specified duration.
+    ///
+    /// Host names are resolved again once cached entry expires. Disabled
+    /// by default. Applies to default tcp connector.
+    pub fn dns_cache_ttl(self, ttl: Duration) -> Self {
+        self.config.bind.borrow_mut().dns_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Serve expired dns cache entries while they are refreshed.
+    ///
+    /// Connects to host with expired cache entry use cached addresses
+    /// immediately, host name is resolved in background and cache entry
+    /// is updated once resolution succeeds. Has effect only with
+    /// `dns_cache_ttl()`. Disabled by default.
+    pub fn dns_stale_while_revalidate(self, val: bool) -> Self {
+        self.config.bind.borrow_mut().stale_while_revalidate = val;
+        self
+    }
+
+    /// Set size of the send buffer (`SO_SNDBUF`) of connected sockets.
+    ///
+    /// By default operating system's default value is used.
+    pub fn send_buffer_size(
+        self,
+        size: usize,
+    ) -> Connector<
+        impl Service<
+                Request = TcpConnect<Uri>,
+                Response = TcpConnection<Uri, TcpStream>,
+                Error = actix_connect::ConnectError,
+            > + Clone,
+        TcpStream,
+    > {
+        self.map_stream(move |io| io.set_send_buffer_size(size))
+    }
+
+    /// Set size of the receive buffer (`SO_RCVBUF`) of connected sockets.
+    ///
+    /// By default operating system's default value is used.
+    pub fn recv_buffer_size(
+        self,
+        size: usize,
+    ) -> Connector<
+        impl Service<
+                Request = TcpConnect<Uri>,
+                Response = TcpConnection<Uri, TcpStream>,
+                Error = actix_connect::ConnectError,
+            > + Clone,
+        TcpStream,
+    > {
+        self.map_stream(move |io| io.set_recv_buffer_size(size))
+    }
+
+    /// Send every written request part to the peer immediately.
+    ///
+    /// By default http/1 client keeps writing body chunks into codec write
+    /// buffer while previous chunk is not flushed yet. With this option codec
+    /// write buffer is flushed after request head and after every body chunk
+    /// before next one is written, head is never coalesced with body.
+    /// Nagle's algorithm (`TCP_NODELAY`) is disabled as well, so flushed
+    /// data is not batched by the kernel.
+    pub fn flush_each_request(
+        mut self,
+        val: bool,
+    ) -> Connector<
+        impl Service<
+                Request = TcpConnect<Uri>,
+                Response = TcpConnection<Uri, TcpStream>,
+                Error = actix_connect::ConnectError,
+            > + Clone,
+        TcpStream,
+    > {
+        self.config.flush_each_request = val;
+        self.map_stream(move |io| io.set_nodelay(val))
+    }
+
+    /// Validate peer address of every connected tcp stream.
+    ///
+    /// Filter receives requested host name and peer address, connect fails
+    /// with `ConnectError::PeerAddressRejected` error if filter returns
+    /// `false`. Could be used as a guard against dns rebinding.
+    pub fn peer_address_filter<F>(
+        self,
+        filter: F,
+    ) -> Connector<
+        impl Service<
+                Request = TcpConnect<Uri>,
+                Response = TcpConnection<Uri, TcpStream>,
+                Error = actix_connect::ConnectError,
+            > + Clone,
+        TcpStream,
+    >
+    where
+        F: Fn(&str, &net::SocketAddr) -> bool + 'static,
+    {
+        let filter = Rc::new(filter);
+        let connector = self.connector.clone();
+        self.connector(apply_fn(
+            connector,
+            move |req: TcpConnect<Uri>, srv: &mut T| {
+                let filter = filter.clone();
+                srv.call(req).and_then(move |conn| {
+                    let addr = conn
+                        .get_ref()
+                        .peer_addr()
+                        .map_err(actix_connect::ConnectError::Io)?;
+                    if filter(conn.host(), &addr) {
+                        Ok(conn)
+                    } else {
+                        Err(actix_connect::ConnectError::Io(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            PeerAddressRejected(addr),
+                        )))
+                    }
+                })
+            },
+        ))
+    }
+
+    /// Select dns record types requested by the resolver.
+    ///
+    /// Host names are resolved with dedicated resolver with system
+    /// configuration. By default `AAAA` records are requested only if
+    /// there are no `A` records for the host. See `local_address()`
+    /// for notes.
+    pub fn dns_query(self, query: DnsQuery) -> Self {
+        let (cfg, opts) = read_system_conf().unwrap_or_else(|e| {
+            warn!("Can not read system dns configuration: {:?}", e);
+            (ResolverConfig::default(), ResolverOpts::default())
+        });
+        self.resolver(cfg, opts, query)
+    }
+
+    /// Use dedicated resolver
+    fn resolver(
+        self,
+        cfg: ResolverConfig,
+        mut opts: ResolverOpts,
+        query: DnsQuery,
+    ) -> Self {
+        opts.ip_strategy = match query {
+            DnsQuery::A => LookupIpStrategy::Ipv4Only,
+            DnsQuery::AAAA => LookupIpStrategy::Ipv6Only,
+            DnsQuery::Both => LookupIpStrategy::Ipv4AndIpv6,
+        };
+        let resolver = actix_connect::start_resolver(cfg, opts);
+        self.config.bind.borrow_mut().resolver = Some(Resolver::new(resolver));
+        self
+    }
+
+    /// Apply socket configuration to every connected tcp stream
+    fn map_stream<F>(
+        self,
+        f: F,
+    ) -> Connector<
+        impl Service<
+                Request = TcpConnect<Uri>,
+                Response = TcpConnection<Uri, TcpStream>,
+                Error = actix_connect::ConnectError,
+            > + Clone,
+        TcpStream,
+    >
+    where
+        F: Fn(&TcpStream) -> io::Result<()> + Clone + 'static,
+    {
+        let connector = self.connector.clone();
+        self.connector(apply_fn(
+            connector,
+            move |req: TcpConnect<Uri>, srv: &mut T| {
+                let f = f.clone();
+                srv.call(req).and_then(move |conn| {
+                    f(conn.get_ref())
+                        .map(|_| conn)
+                        .map_err(actix_connect::ConnectError::Io)
+                })
+            },
+        ))
+    }
+}
+
 impl<T, U> Connector<T, U>
 where
     U: AsyncRead + AsyncWrite + fmt::Debug + 'static,
@@ -144,31 +553,118 @@ where
         + 'static,
 {
     /// Connection timeout, i.e. max time to connect to remote host including dns name resolution.
+    /// Could be overridden per request with `ConnectTimeout` request extension.
     /// Set to 1 second by default.
     pub fn timeout(mut self, timeout: Duration) -> Self {
-        self.timeout = timeout;
+        self.config.timeout = timeout;
         self
     }
 
     #[cfg(feature = "ssl")]
     /// Use custom `SslConnector` instance.
-    pub fn ssl(mut self, connector: OpensslConnector) -> Self {
-        self.ssl = SslConnector::Openssl(connector);
+    pub fn ssl(self, connector: OpensslConnector) -> Self {
+        self.ssl.borrow_mut().default = TlsConfig::Openssl(connector);
         self
     }
 
     #[cfg(feature = "rust-tls")]
-    pub fn rustls(mut self, connector: Arc<ClientConfig>) -> Self {
-        self.ssl = SslConnector::Rustls(connector);
+    pub fn rustls(self, connector: Arc<ClientConfig>) -> Self {
+        self.ssl.borrow_mut().default = TlsConfig::Rustls(connector);
         self
     }
 
+    #[cfg(any(feature = "ssl", feature = "rust-tls"))]
+    /// Use separate tls configuration for connections to specific host.
+    ///
+    /// For example, configuration that trusts self-signed certificate of
+    /// an internal service, while other hosts are verified with default
+    /// configuration. Host is matched exactly, without port.
+    pub fn host_tls_config(self, host: &str, config: TlsConfig) -> Self {
+        self.ssl
+            .borrow_mut()
+            .hosts
+            .insert(host.to_owned(), config);
+        self
+    }
+
+    #[cfg(any(feature = "ssl", feature = "rust-tls"))]
+    /// Get handle for replacing tls configuration after connector is created.
+    pub fn tls_config_handle(&self) -> TlsConfigHandle {
+        TlsConfigHandle(self.ssl.clone(), self.config.pool_handle.clone())
+    }
+
     /// Set total number of simultaneous connections per type of scheme.
     ///
     /// If limit is 0, the connector has no limit.
     /// The default limit size is 100.
     pub fn limit(mut self, limit: usize) -> Self {
-        self.limit = limit;
+        self.config.limit = limit;
+        self
+    }
+
+    /// Reserve separate pool segment for requests with specified priority.
+    ///
+    /// Requests with this `Priority` use own idle connections and own
+    /// connections limit, they are not counted against `limit()`. Other
+    /// requests can not exhaust connections of the segment. If limit is 0,
+    /// the segment has no limit.
+    pub fn priority_limit(mut self, priority: Priority, limit: usize) -> Self {
+        self.config.priority_limits.retain(|(p, _)| *p != priority);
+        self.config.priority_limits.push((priority, limit));
+        self
+    }
+
+    /// Reserve connections for health check requests.
+    ///
+    /// Requests marked with `HealthCheck` extension could open `reserve`
+    /// connections over `limit()`, so health checks succeed while the pool
+    /// is saturated by other requests. Reserve does not apply to requests
+    /// of pool segments, see `priority_limit()`. By default there is no
+    /// reserve.
+    pub fn health_check_reserve(mut self, reserve: usize) -> Self {
+        self.config.health_check_reserve = reserve;
+        self
+    }
+
+    /// Set connections limit of every connection group.
+    ///
+    /// Connections of requests with the same `ConnectionGroup` are counted
+    /// across all hosts, requests of saturated group wait for a connection
+    /// while requests of other groups proceed. Idle connections are kept
+    /// separately for every group. If limit is 0, groups are not limited,
+    /// default value is 0.
+    pub fn limit_per_group(mut self, limit: usize) -> Self {
+        self.config.limit_per_group = limit;
+        self
+    }
+
+    /// Set max number of concurrently open tunnels.
+    ///
+    /// Tunnel connection is not returned to the pool, it is counted until
+    /// tunnel permit returned by `Connection::open_tunnel()` is dropped.
+    /// Opening tunnel past this number fails with `SendRequestError::TunnelLimit`
+    /// error, connection that would carry the tunnel is returned to the pool.
+    /// By default number of tunnels is not limited.
+    pub fn max_tunnels(mut self, max: usize) -> Self {
+        self.config.max_tunnels = Some(max);
+        self
+    }
+
+    /// Set max number of idle connections kept in the pool per host.
+    ///
+    /// Connections released above this number are closed.
+    /// By default number of idle connections is not limited.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.config.max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Set max number of requests served by single http/1 connection.
+    ///
+    /// Connection is closed once it served this number of requests.
+    /// By default number of requests is not limited.
+    pub fn pool_max_requests(mut self, max: u64) -> Self {
+        self.config.max_requests = Some(max);
         self
     }
 
@@ -179,7 +675,7 @@ where
     /// exceeds this period, the connection is closed.
     /// Default keep-alive period is 15 seconds.
     pub fn conn_keep_alive(mut self, dur: Duration) -> Self {
-        self.conn_keep_alive = dur;
+        self.config.conn_keep_alive = dur;
         self
     }
 
@@ -189,7 +685,38 @@ where
     /// until it is closed regardless of keep-alive period.
     /// Default lifetime period is 75 seconds.
     pub fn conn_lifetime(mut self, dur: Duration) -> Self {
-        self.conn_lifetime = dur;
+        self.config.conn_lifetime = dur;
+        self
+    }
+
+    /// Set max age of http/2 connection.
+    ///
+    /// Http/2 connection older than this period does not accept new
+    /// streams, in-flight streams are completed and then connection
+    /// is closed. New requests open fresh connection.
+    /// By default only connection lifetime is applied.
+    pub fn h2_max_connection_age(mut self, dur: Duration) -> Self {
+        self.config.h2_max_connection_age = Some(dur);
+        self
+    }
+
+    /// Send http/2 keep-alive pings.
+    ///
+    /// Ping is sent every `interval` over established http/2 connection,
+    /// including idle pooled connections. Connection is closed if peer
+    /// does not acknowledge ping within `h2_keepalive_timeout()`.
+    /// By default pings are not sent.
+    pub fn h2_keepalive_interval(mut self, interval: Duration) -> Self {
+        self.config.h2_keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Set http/2 keep-alive ping timeout.
+    ///
+    /// Connection is closed if ping is not acknowledged within this period,
+    /// applies if `h2_keepalive_interval()` is set. Default is 20 seconds.
+    pub fn h2_keepalive_timeout(mut self, timeout: Duration) -> Self {
+        self.config.h2_keepalive_timeout = timeout;
         self
     }
 
@@ -202,39 +729,335 @@ where
     ///
     /// By default disconnect timeout is set to 3000 milliseconds.
     pub fn disconnect_timeout(mut self, dur: Duration) -> Self {
-        self.disconnect_timeout = dur;
+        self.config.disconnect_timeout = Some(dur);
+        self
+    }
+
+    /// Fail response payload if server sends more data than
+    /// declared by `Content-Length` header.
+    ///
+    /// Such connection is never returned to the pool. By default
+    /// it is silently closed.
+    pub fn strict_content_length(mut self, val: bool) -> Self {
+        self.config.strict_content_length = val;
+        self
+    }
+
+    /// Set max number of bytes to drain from unfinished response payload.
+    ///
+    /// If response is dropped before its payload is fully read, connection
+    /// gets drained in background and returned to the pool, if the rest
+    /// of the payload fits into this limit and arrives within keep-alive
+    /// period. Otherwise connection is closed.
+    ///
+    /// By default drain is disabled and such connection is closed as soon
+    /// as response payload is dropped.
+    pub fn drain_on_drop(mut self, limit: usize) -> Self {
+        self.config.drain_on_drop = limit;
+        self
+    }
+
+    /// Set max period without response payload data.
+    ///
+    /// Period starts when response head is received and restarts after
+    /// each payload chunk, it includes time spent by the consumer between
+    /// reads. Reading payload fails with `TimedOut` io error once period
+    /// elapses. Could be overridden per request with `ReadIdleTimeout`
+    /// request extension. This setting affects only http/1 connections.
+    /// By default timeout is not set.
+    pub fn read_idle_timeout(mut self, dur: Duration) -> Self {
+        self.config.read_idle_timeout = Some(dur);
+        self
+    }
+
+    /// Set max period of single response payload read.
+    ///
+    /// Period starts when consumer polls payload and no data is available.
+    /// Reading payload fails with `TimedOut` io error once period elapses.
+    /// Could be overridden per request with `PerReadTimeout` request
+    /// extension. This setting affects only http/1 connections.
+    /// By default timeout is not set.
+    pub fn per_read_timeout(mut self, dur: Duration) -> Self {
+        self.config.per_read_timeout = Some(dur);
+        self
+    }
+
+    /// Set max duration of sending request body.
+    ///
+    /// Sending fails with `SendRequestError::WriteTimeout` once duration
+    /// elapses, error contains number of body bytes written to the
+    /// connection, so upload could be resumed. Connection is closed.
+    /// This setting affects only http/1 connections. By default timeout
+    /// is not set.
+    pub fn write_timeout(mut self, dur: Duration) -> Self {
+        self.config.write_timeout = Some(dur);
+        self
+    }
+
+    /// Send `Expect: 100-continue` header only for large request bodies.
+    ///
+    /// Header is added to requests with body larger than threshold or with
+    /// body of unknown size, and it is removed from requests with smaller
+    /// body even if it is set explicitly. Request body is sent without
+    /// waiting for `100 Continue` response.
+    ///
+    /// This setting affects only http/1 connections. By default `Expect`
+    /// header is sent as it is set in the request.
+    pub fn expect_continue_threshold(mut self, size: usize) -> Self {
+        self.config.expect_continue_threshold = Some(size);
+        self
+    }
+
+    /// Send `Connection: keep-alive` header with http/1.0 requests.
+    ///
+    /// Http/1.0 connections are not persistent by default, with this option
+    /// enabled connection is pooled if server confirms keep-alive in its
+    /// response. Requests with explicitly set connection type are not
+    /// affected. By default keep-alive is disabled.
+    pub fn http10_keep_alive(mut self, val: bool) -> Self {
+        self.config.http10_keep_alive = val;
+        self
+    }
+
+    /// Read response payload ahead of the consumer.
+    ///
+    /// With read-ahead enabled http/1 payload stream reads next chunk from
+    /// the socket right after returning current one, so it is ready by the
+    /// next poll. Otherwise socket is read only when payload is polled.
+    /// Disabled by default.
+    pub fn response_read_ahead(mut self, val: bool) -> Self {
+        self.config.response_read_ahead = val;
+        self
+    }
+
+    /// Accept chunked response payload without terminal chunk.
+    ///
+    /// If enabled, server closing connection in the middle of chunked
+    /// payload ends the payload, otherwise reading payload fails with
+    /// `PayloadError::Incomplete`. Strict by default.
+    pub fn tolerate_missing_last_chunk(mut self, val: bool) -> Self {
+        self.config.tolerate_missing_last_chunk = val;
+        self
+    }
+
+    /// Limit rate of closing idle connections when pool gets drained.
+    ///
+    /// At most `num` idle connections are closed per `interval`, see
+    /// `PoolHandle::drain()`. By default all idle connections are closed
+    /// at once.
+    pub fn drain_rate(mut self, num: usize, interval: Duration) -> Self {
+        self.config.drain_rate = Some((num.max(1), interval));
+        self
+    }
+
+    /// Set max size of decompressed response payload.
+    ///
+    /// Payload decoder fails with `PayloadError::Overflow` once
+    /// decompressed payload exceeds this limit, limit is checked while
+    /// payload is inflated. Uncompressed payload is not affected.
+    ///
+    /// By default limit is 25Mb, 100 times of default body size limit.
+    pub fn max_decompressed_size(mut self, size: usize) -> Self {
+        self.config.max_decompressed_size = size;
+        self
+    }
+
+    /// Set max size of http/1 response head.
+    ///
+    /// Request fails with `ParseError::TooLarge` if response head exceeds
+    /// this size. Could be overridden per request with
+    /// `MaxResponseHeaderSize` request extension. Default is 128Kb.
+    pub fn max_response_header_size(mut self, size: usize) -> Self {
+        self.config.max_response_header_size = Some(size);
+        self
+    }
+
+    /// Set max number of http/1 response headers.
+    ///
+    /// Request fails with `ParseError::TooLarge` if response contains
+    /// more headers. Could be overridden per request with
+    /// `MaxResponseHeaders` request extension. Default is 96.
+    pub fn max_response_headers(mut self, num: usize) -> Self {
+        self.config.max_response_headers = Some(num);
+        self
+    }
+
+    /// Send request head and small body with a single write.
+    ///
+    /// If request body size is known, does not exceed `size` and body
+    /// is immediately available, body is buffered together with request
+    /// head and written to the socket at once. This setting affects only
+    /// http/1 connections. By default coalescing is disabled.
+    pub fn coalesce_small_body(mut self, size: usize) -> Self {
+        self.config.coalesce_small_body = size;
+        self
+    }
+
+    /// Control `Content-Length: 0` header for `POST` and `PUT` requests
+    /// without body.
+    ///
+    /// If set to `true` bodyless request always gets `Content-Length: 0`
+    /// header, if set to `false` the header is never sent. By default
+    /// the header is sent only for explicitly empty body. Explicitly empty
+    /// body, or no body of `POST` and `PUT` request, with `Content-Type`
+    /// header always gets `Content-Length: 0` header.
+    pub fn explicit_zero_content_length(mut self, val: bool) -> Self {
+        self.config.explicit_zero_content_length = Some(val);
+        self
+    }
+
+    /// Set max number of interim (1xx) responses accepted before
+    /// final response.
+    ///
+    /// Interim responses are skipped by http/1 client, request fails with
+    /// `SendRequestError::TooManyInterimResponses` error if server sends
+    /// more of them. By default limit is set to 10.
+    pub fn max_interim_responses(mut self, max: usize) -> Self {
+        self.config.max_interim_responses = max;
         self
     }
 
+    /// Set default `Accept` header value.
+    ///
+    /// Header is added to requests that do not have `Accept` header.
+    pub fn default_accept(mut self, value: HeaderValue) -> Self {
+        self.config.default_accept = Some(value);
+        self
+    }
+
+    /// Set order in which requests waiting for a connection are served.
+    ///
+    /// Requests wait for a connection when connections limit is reached.
+    /// By default requests are served in order of arrival.
+    pub fn queue_policy(mut self, policy: QueuePolicy) -> Self {
+        self.config.queue_policy = policy;
+        self
+    }
+
+    /// Set handling of `Host` header that does not match request uri.
+    ///
+    /// Header port could be omitted if uri uses default port of its scheme.
+    /// This setting affects only http/1 connections. By default header
+    /// is sent as is.
+    pub fn host_conflict_policy(mut self, policy: HostConflictPolicy) -> Self {
+        self.config.host_conflict_policy = policy;
+        self
+    }
+
+    /// Set connection pool events observer.
+    ///
+    /// Observer gets notified about connections lifecycle events,
+    /// i.e. when a connection get evicted from the pool.
+    pub fn pool_observer<O: PoolObserver + 'static>(mut self, observer: O) -> Self {
+        let observer: Rc<dyn PoolObserver> = Rc::new(observer);
+        *self.config.connect_observer.borrow_mut() = Some(observer.clone());
+        self.config.observer = Some(observer);
+        self
+    }
+
+    /// Decide whether http/1 connection is pooled after response.
+    ///
+    /// Callback receives response head, connection is closed after
+    /// the response if callback returns `false`, i.e. if response
+    /// signals that server is going away. Such connection is reported
+    /// to pool observer with `EvictReason::ErrorStatus`.
+    pub fn should_pool<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&ResponseHead) -> bool + 'static,
+    {
+        self.config.should_pool = Some(Rc::new(f));
+        self
+    }
+
+    /// Inspect or modify response head before it is returned.
+    ///
+    /// Callback is called for every response received over connections
+    /// of this connector, after response head is read.
+    pub fn after_receive<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut ResponseHead) + 'static,
+    {
+        self.config.after_receive = Some(Rc::new(f));
+        self
+    }
+
+    /// Fast-fail connects to hosts with consecutive connect failures.
+    ///
+    /// After `threshold` consecutive failed connects to a host, new
+    /// connects to this host fail with `ConnectError::CircuitOpen` until
+    /// `cooldown` period elapses. After cooldown single probe connect is
+    /// attempted, other connects fail with `ConnectError::CircuitOpen`
+    /// until it completes. Successful connect resets failure counter.
+    /// By default circuit breaker is disabled.
+    pub fn circuit_breaker(mut self, threshold: usize, cooldown: Duration) -> Self {
+        let breaker = CircuitBreaker::new(threshold.max(1), cooldown);
+        breaker.register(&self.config.pool_handle);
+        self.config.circuit_breaker = breaker;
+        self
+    }
+
+    /// State of circuit breaker of the host.
+    ///
+    /// Circuit breaker state is tracked by connector service created by
+    /// `finish()`, use `PoolHandle::circuit_state()` to inspect it after
+    /// `finish()`. Circuit is always closed if circuit breaker is disabled.
+    pub fn circuit_state(&self, host: &str) -> CircuitState {
+        self.config.pool_handle.circuit_state(host)
+    }
+
+    /// Get handle for draining connection pool of this connector.
+    pub fn pool_handle(&self) -> PoolHandle {
+        self.config.pool_handle.clone()
+    }
+
+    /// Accumulate connection checkout latency histogram.
+    ///
+    /// `bounds` are upper bounds of latency buckets, histogram is
+    /// available with `PoolHandle::pool_stats()`. Histogram is not
+    /// collected by default.
+    pub fn checkout_histogram(mut self, bounds: &[Duration]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort();
+        bounds.dedup();
+        self.config.checkout_buckets = bounds;
+        self
+    }
+
+    /// Wait until no connections are checked out of connection pool.
+    ///
+    /// Returned future could be created before `finish()`, it tracks
+    /// connection pool of connector service created by `finish()`.
+    /// See `PoolHandle::wait_idle()`.
+    pub fn wait_idle(&self) -> impl Future<Item = (), Error = ()> {
+        self.config.pool_handle.wait_idle()
+    }
+
     /// Finish configuration process and create connector service.
     /// The Connector builder always concludes by calling `finish()` last in
     /// its combinator chain.
     pub fn finish(
         self,
-    ) -> impl Service<Request = Connect, Response = impl Connection, Error = ConnectError>
-                 + Clone {
+    ) -> impl Service<
+        Request = Connect,
+        Response = impl HandleConnection,
+        Error = ConnectError,
+    > + Clone {
         #[cfg(not(any(feature = "ssl", feature = "rust-tls")))]
         {
-            let connector = TimeoutService::new(
-                self.timeout,
+            let breaker = self.config.circuit_breaker.clone();
+            let connector = TimeoutConnector::new(
+                self.config.timeout,
                 apply_fn(self.connector, |msg: Connect, srv| {
                     srv.call(TcpConnect::new(msg.uri).set_addr(msg.addr))
                 })
                 .map_err(ConnectError::from)
                 .map(|stream| (stream.into_parts().0, Protocol::Http1)),
-            )
-            .map_err(|e| match e {
-                TimeoutError::Service(e) => e,
-                TimeoutError::Timeout => ConnectError::Timeout,
-            });
+            );
 
             connect_impl::InnerConnector {
                 tcp_pool: ConnectionPool::new(
-                    connector,
-                    self.conn_lifetime,
-                    self.conn_keep_alive,
-                    None,
-                    self.limit,
+                    breaker.wrap(connector),
+                    self.config.no_disconnect_timeout(),
                 ),
             }
         }
@@ -245,95 +1068,272 @@ where
             use actix_connect::ssl::OpensslConnector;
             #[cfg(feature = "rust-tls")]
             use actix_connect::ssl::RustlsConnector;
-            use actix_service::boxed::service;
+            use actix_service::service_fn;
             #[cfg(feature = "rust-tls")]
             use rustls::Session;
 
-            let ssl_service = TimeoutService::new(
-                self.timeout,
+            let breaker = self.config.circuit_breaker.clone();
+            let ssl_service = TimeoutConnector::new(
+                self.config.timeout,
                 apply_fn(self.connector.clone(), |msg: Connect, srv| {
                     srv.call(TcpConnect::new(msg.uri).set_addr(msg.addr))
                 })
                 .map_err(ConnectError::from)
-                .and_then(match self.ssl {
-                    #[cfg(feature = "ssl")]
-                    SslConnector::Openssl(ssl) => service(
-                        OpensslConnector::service(ssl)
-                            .map_err(ConnectError::from)
-                            .map(|stream| {
-                                let sock = stream.into_parts().0;
-                                let h2 = sock
-                                    .get_ref()
-                                    .ssl()
-                                    .selected_alpn_protocol()
-                                    .map(|protos| protos.windows(2).any(|w| w == H2))
-                                    .unwrap_or(false);
-                                if h2 {
-                                    (Box::new(sock) as Box<dyn Io>, Protocol::Http2)
-                                } else {
-                                    (Box::new(sock) as Box<dyn Io>, Protocol::Http1)
-                                }
-                            }),
-                    ),
-                    #[cfg(feature = "rust-tls")]
-                    SslConnector::Rustls(ssl) => service(
-                        RustlsConnector::service(ssl)
-                            .map_err(ConnectError::from)
-                            .map(|stream| {
-                                let sock = stream.into_parts().0;
-                                let h2 = sock
-                                    .get_ref()
-                                    .1
-                                    .get_alpn_protocol()
-                                    .map(|protos| protos.windows(2).any(|w| w == H2))
-                                    .unwrap_or(false);
-                                if h2 {
-                                    (Box::new(sock) as Box<dyn Io>, Protocol::Http2)
-                                } else {
-                                    (Box::new(sock) as Box<dyn Io>, Protocol::Http1)
-                                }
-                            }),
-                    ),
+                .and_then({
+                    let tls = self.ssl.clone();
+                    service_fn::<_, _, _, ()>(move |stream: TcpConnection<Uri, U>| {
+                        let fut: Box<
+                            dyn Future<Item = (Box<dyn Io>, Protocol), Error = ConnectError>,
+                        > = match *tls.borrow().get(stream.host()) {
+                            #[cfg(feature = "ssl")]
+                            TlsConfig::Openssl(ref ssl) => Box::new(
+                                OpensslConnector::service(ssl.clone())
+                                    .call(stream)
+                                    .map_err(ConnectError::from)
+                                    .map(|stream| {
+                                        let sock = stream.into_parts().0;
+                                        let h2 = sock
+                                            .get_ref()
+                                            .ssl()
+                                            .selected_alpn_protocol()
+                                            .map(|protos| protos.windows(2).any(|w| w == H2))
+                                            .unwrap_or(false);
+                                        let io = TlsIo::boxed(sock, |sock| {
+                                            sock.get_ref().get_ref()
+                                        });
+                                        if h2 {
+                                            (io, Protocol::Http2)
+                                        } else {
+                                            (io, Protocol::Http1)
+                                        }
+                                    }),
+                            ),
+                            #[cfg(feature = "rust-tls")]
+                            TlsConfig::Rustls(ref ssl) => Box::new(
+                                RustlsConnector::service(ssl.clone())
+                                    .call(stream)
+                                    .map_err(ConnectError::TlsHandshake)
+                                    .map(|stream| {
+                                        let sock = stream.into_parts().0;
+                                        let h2 = sock
+                                            .get_ref()
+                                            .1
+                                            .get_alpn_protocol()
+                                            .map(|protos| protos.windows(2).any(|w| w == H2))
+                                            .unwrap_or(false);
+                                        let io = TlsIo::boxed(sock, |sock| sock.get_ref().0);
+                                        if h2 {
+                                            (io, Protocol::Http2)
+                                        } else {
+                                            (io, Protocol::Http1)
+                                        }
+                                    }),
+                            ),
+                        };
+                        fut
+                    })
                 }),
-            )
-            .map_err(|e| match e {
-                TimeoutError::Service(e) => e,
-                TimeoutError::Timeout => ConnectError::Timeout,
-            });
-
-            let tcp_service = TimeoutService::new(
-                self.timeout,
+            );
+
+            let tcp_service = TimeoutConnector::new(
+                self.config.timeout,
                 apply_fn(self.connector.clone(), |msg: Connect, srv| {
                     srv.call(TcpConnect::new(msg.uri).set_addr(msg.addr))
                 })
                 .map_err(ConnectError::from)
                 .map(|stream| (stream.into_parts().0, Protocol::Http1)),
-            )
-            .map_err(|e| match e {
-                TimeoutError::Service(e) => e,
-                TimeoutError::Timeout => ConnectError::Timeout,
-            });
+            );
 
             connect_impl::InnerConnector {
                 tcp_pool: ConnectionPool::new(
-                    tcp_service,
-                    self.conn_lifetime,
-                    self.conn_keep_alive,
-                    None,
-                    self.limit,
-                ),
-                ssl_pool: ConnectionPool::new(
-                    ssl_service,
-                    self.conn_lifetime,
-                    self.conn_keep_alive,
-                    Some(self.disconnect_timeout),
-                    self.limit,
+                    breaker.wrap(tcp_service),
+                    self.config.no_disconnect_timeout(),
                 ),
+                ssl_pool: ConnectionPool::new(breaker.wrap(ssl_service), self.config),
+            }
+        }
+    }
+}
+
+/// Tracks consecutive connect failures per host
+#[derive(Clone, Default)]
+pub(crate) struct CircuitBreaker(Option<Rc<CircuitBreakerInner>>);
+
+struct CircuitBreakerInner {
+    threshold: usize,
+    cooldown: Duration,
+    hosts: RefCell<HashMap<String, HostCircuit>>,
+}
+
+/// Consecutive connect failures of the host
+struct HostCircuit {
+    failures: usize,
+    opened: Instant,
+    probe: bool,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: usize, cooldown: Duration) -> Self {
+        CircuitBreaker(Some(Rc::new(CircuitBreakerInner {
+            threshold,
+            cooldown,
+            hosts: RefCell::new(HashMap::new()),
+        })))
+    }
+
+    fn state(&self, host: &str) -> CircuitState {
+        let inner = match self.0 {
+            Some(ref inner) => inner,
+            None => return CircuitState::Closed,
+        };
+        match inner.hosts.borrow().get(host) {
+            Some(circuit) if circuit.failures >= inner.threshold => {
+                if circuit.opened.elapsed() < inner.cooldown {
+                    CircuitState::Open(circuit.opened + inner.cooldown)
+                } else {
+                    CircuitState::HalfOpen
+                }
             }
+            _ => CircuitState::Closed,
+        }
+    }
+
+    fn register(&self, handle: &PoolHandle) {
+        let breaker = self.clone();
+        handle.register_circuit_breaker(move |host| breaker.state(host));
+    }
+
+    fn wrap<T>(&self, service: T) -> CircuitBreakerService<T> {
+        CircuitBreakerService {
+            service,
+            breaker: self.clone(),
         }
     }
 }
 
+/// Connect service that fast-fails connects to hosts with open circuit
+#[derive(Clone)]
+struct CircuitBreakerService<T> {
+    service: T,
+    breaker: CircuitBreaker,
+}
+
+impl<T, Io> Service for CircuitBreakerService<T>
+where
+    T: Service<Request = Connect, Response = (Io, Protocol), Error = ConnectError>,
+    T::Future: 'static,
+    Io: 'static,
+{
+    type Request = Connect;
+    type Response = (Io, Protocol);
+    type Error = ConnectError;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: Connect) -> Self::Future {
+        let inner = match self.breaker.0 {
+            Some(ref inner) => inner.clone(),
+            None => return Box::new(self.service.call(req)),
+        };
+        let host = req
+            .uri
+            .authority_part()
+            .map(|authority| authority.as_str().to_owned())
+            .unwrap_or_default();
+
+        let probe = match self.breaker.state(&host) {
+            CircuitState::Open(_) => return Box::new(err(ConnectError::CircuitOpen)),
+            CircuitState::HalfOpen => {
+                // single probe connect decides state of the circuit
+                let mut hosts = inner.hosts.borrow_mut();
+                let circuit = hosts.get_mut(&host).unwrap();
+                if circuit.probe {
+                    return Box::new(err(ConnectError::CircuitOpen));
+                }
+                circuit.probe = true;
+                Some(CircuitProbe(inner.clone(), host.clone()))
+            }
+            CircuitState::Closed => None,
+        };
+
+        Box::new(self.service.call(req).then(move |res| {
+            {
+                let mut hosts = inner.hosts.borrow_mut();
+                match res {
+                    Ok(_) => {
+                        hosts.remove(&host);
+                    }
+                    Err(_) => {
+                        let circuit = hosts.entry(host).or_insert(HostCircuit {
+                            failures: 0,
+                            opened: Instant::now(),
+                            probe: false,
+                        });
+                        circuit.failures += 1;
+                        circuit.opened = Instant::now();
+                    }
+                }
+            }
+            drop(probe);
+            res
+        }))
+    }
+}
+
+/// Probe connect of half-open circuit, next probe is allowed once
+/// it is dropped, i.e. if connect is abandoned before completion
+struct CircuitProbe(Rc<CircuitBreakerInner>, String);
+
+impl Drop for CircuitProbe {
+    fn drop(&mut self) {
+        if let Some(circuit) = self.0.hosts.borrow_mut().get_mut(&self.1) {
+            circuit.probe = false;
+        }
+    }
+}
+
+/// Connect service with deadline, request timeout overrides the default
+#[derive(Clone)]
+struct TimeoutConnector<T> {
+    service: T,
+    timeout: Duration,
+}
+
+impl<T> TimeoutConnector<T> {
+    fn new(timeout: Duration, service: T) -> Self {
+        TimeoutConnector { service, timeout }
+    }
+}
+
+impl<T> Service for TimeoutConnector<T>
+where
+    T: Service<Request = Connect, Error = ConnectError>,
+    T::Future: 'static,
+{
+    type Request = Connect;
+    type Response = T::Response;
+    type Error = ConnectError;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: Connect) -> Self::Future {
+        let mut deadline = Instant::now() + req.timeout.unwrap_or(self.timeout);
+        if let Some(limit) = req.deadline {
+            deadline = cmp::min(deadline, limit);
+        }
+        Box::new(
+            Timeout::new_at(self.service.call(req), deadline)
+                .map_err(|e| e.into_inner().unwrap_or(ConnectError::Timeout)),
+        )
+    }
+}
+
 #[cfg(not(any(feature = "ssl", feature = "rust-tls")))]
 mod connect_impl {
     use futures::future::{err, Either, FutureResult};
@@ -538,3 +1538,371 @@ mod connect_impl {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use actix_service::IntoService;
+    use futures::future::lazy;
+    use trust_dns_resolver::config::NameServerConfigGroup;
+
+    use super::*;
+
+    #[test]
+    fn test_socket_buffer_sizes() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut sys = actix_rt::System::new("test");
+        let conn = sys
+            .block_on(lazy(move || {
+                let mut connector = Connector::new()
+                    .send_buffer_size(128 * 1024)
+                    .recv_buffer_size(128 * 1024)
+                    .connector;
+                let uri: Uri = format!("http://{}/", addr).parse().unwrap();
+                connector.call(TcpConnect::new(uri).set_addr(Some(addr)))
+            }))
+            .unwrap();
+
+        // kernel is allowed to adjust requested values (linux doubles them)
+        let io = conn.get_ref();
+        assert!(io.send_buffer_size().unwrap() >= 128 * 1024);
+        assert!(io.recv_buffer_size().unwrap() >= 128 * 1024);
+    }
+
+    #[test]
+    fn test_local_address_for() {
+        let bound = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let other = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let (bound, other) = (bound.local_addr().unwrap(), other.local_addr().unwrap());
+
+        let local = |addr: net::SocketAddr| {
+            let mut sys = actix_rt::System::new("test");
+            let conn = sys
+                .block_on(lazy(move || {
+                    let mut connector = Connector::new()
+                        .local_address_for(Arc::new(move |dest: &net::SocketAddr| {
+                            if *dest == bound {
+                                Some("127.0.0.2".parse().unwrap())
+                            } else {
+                                None
+                            }
+                        }))
+                        .connector;
+                    let uri: Uri = format!("http://{}/", addr).parse().unwrap();
+                    connector.call(TcpConnect::new(uri))
+                }))
+                .unwrap();
+            conn.get_ref().local_addr().unwrap().ip()
+        };
+
+        // selected address is bound, otherwise operating system chooses
+        assert_eq!(local(bound), "127.0.0.2".parse::<net::IpAddr>().unwrap());
+        assert_eq!(local(other), "127.0.0.1".parse::<net::IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_flush_each_request() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut sys = actix_rt::System::new("test");
+        let conn = sys
+            .block_on(lazy(move || {
+                let mut connector = Connector::new().flush_each_request(true).connector;
+                let uri: Uri = format!("http://{}/", addr).parse().unwrap();
+                connector.call(TcpConnect::new(uri).set_addr(Some(addr)))
+            }))
+            .unwrap();
+        assert!(conn.get_ref().nodelay().unwrap());
+    }
+
+    /// Dns server that resolves every `A` query to 127.0.0.1,
+    /// types of received queries are recorded
+    fn dns_stub() -> (net::SocketAddr, Arc<Mutex<Vec<u16>>>) {
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(Mutex::new(Vec::new()));
+
+        let types = queries.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+                // skip question name
+                let mut pos = 12;
+                while pos < len && buf[pos] != 0 {
+                    pos += buf[pos] as usize + 1;
+                }
+                if pos + 5 > len {
+                    continue;
+                }
+                let qtype = u16::from_be_bytes([buf[pos + 1], buf[pos + 2]]);
+                types.lock().unwrap().push(qtype);
+
+                let answers = if qtype == 1 { 1 } else { 0 };
+                let mut res =
+                    vec![buf[0], buf[1], 0x81, 0x80, 0, 1, 0, answers, 0, 0, 0, 0];
+                res.extend_from_slice(&buf[12..pos + 5]);
+                if answers > 0 {
+                    res.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                    res.extend_from_slice(&[127, 0, 0, 1]);
+                }
+                let _ = socket.send_to(&res, peer);
+            }
+        });
+        (addr, queries)
+    }
+
+    #[test]
+    fn test_dns_query() {
+        let (dns, queries) = dns_stub();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut sys = actix_rt::System::new("test");
+        let conn = sys
+            .block_on(lazy(move || {
+                let servers =
+                    NameServerConfigGroup::from_ips_clear(&[dns.ip()], dns.port());
+                let cfg = ResolverConfig::from_parts(None, vec![], servers);
+                // binding option is kept with dedicated resolver
+                let mut connector = Connector::new()
+                    .local_address("127.0.0.2:0".parse().unwrap())
+                    .resolver(cfg, ResolverOpts::default(), DnsQuery::A)
+                    .connector;
+                let uri: Uri = format!("http://stub.test:{}/", port).parse().unwrap();
+                connector.call(TcpConnect::new(uri))
+            }))
+            .unwrap();
+        assert_eq!(conn.get_ref().peer_addr().unwrap().port(), port);
+        assert_eq!(
+            conn.get_ref().local_addr().unwrap().ip(),
+            "127.0.0.2".parse::<net::IpAddr>().unwrap()
+        );
+
+        // only `A` records are requested
+        let queries = queries.lock().unwrap();
+        assert!(!queries.is_empty());
+        assert!(queries.iter().all(|qtype| *qtype == 1));
+    }
+
+    #[test]
+    fn test_reuse_address() {
+        // reserve free local port
+        let local = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener1 = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener2 = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let mut sys = actix_rt::System::new("test");
+        let mut connector = Connector::new()
+            .local_address(local)
+            .reuse_address(true)
+            .connector;
+
+        for listener in &[listener1, listener2] {
+            let addr = listener.local_addr().unwrap();
+            let conn = sys
+                .block_on(lazy(|| {
+                    let uri: Uri = format!("http://{}/", addr).parse().unwrap();
+                    connector.call(TcpConnect::new(uri))
+                }))
+                .unwrap();
+            assert_eq!(conn.get_ref().local_addr().unwrap(), local);
+        }
+    }
+
+    #[test]
+    fn test_bind_preresolved_address() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut sys = actix_rt::System::new("test");
+        let conn = sys
+            .block_on(lazy(move || {
+                let mut connector = Connector::new()
+                    .local_address("127.0.0.2:0".parse().unwrap())
+                    .connector;
+                // host name is not resolved
+                let uri: Uri = "http://unknown.invalid/".parse().unwrap();
+                connector.call(TcpConnect::new(uri).set_addr(Some(addr)))
+            }))
+            .unwrap();
+        assert_eq!(conn.get_ref().peer_addr().unwrap(), addr);
+        assert_eq!(
+            conn.get_ref().local_addr().unwrap().ip(),
+            "127.0.0.2".parse::<net::IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bind_options_order() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut sys = actix_rt::System::new("test");
+        let conn = sys
+            .block_on(lazy(move || {
+                // socket option is set before binding options
+                let mut connector = Connector::new()
+                    .send_buffer_size(128 * 1024)
+                    .local_address("127.0.0.2:0".parse().unwrap())
+                    .reuse_address(true)
+                    .connector;
+                let uri: Uri = format!("http://{}/", addr).parse().unwrap();
+                connector.call(TcpConnect::new(uri))
+            }))
+            .unwrap();
+
+        let io = conn.get_ref();
+        assert!(io.send_buffer_size().unwrap() >= 128 * 1024);
+        assert_eq!(
+            io.local_addr().unwrap().ip(),
+            "127.0.0.2".parse::<net::IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bind_custom_connector() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let calls = Rc::new(Cell::new(0));
+        let calls2 = calls.clone();
+        let mut sys = actix_rt::System::new("test");
+        sys.block_on(lazy(move || {
+            let tcp = apply_fn(
+                actix_connect::default_connector(),
+                move |req: TcpConnect<Uri>, srv| {
+                    calls2.set(calls2.get() + 1);
+                    srv.call(req)
+                },
+            );
+            let mut connector = Connector::new()
+                .connector(tcp)
+                .reuse_address(true)
+                .reuse_port(true)
+                .connector;
+            let uri: Uri = format!("http://{}/", addr).parse().unwrap();
+            connector.call(TcpConnect::new(uri))
+        }))
+        .unwrap();
+
+        // binding options do not replace custom connector
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn test_circuit_state() {
+        let connector = Connector::new().circuit_breaker(1, Duration::from_secs(10));
+        assert_eq!(connector.circuit_state("localhost:80"), CircuitState::Closed);
+
+        // failures are recorded by the breaker of connector service
+        let failed = |_| err::<((), Protocol), _>(ConnectError::Disconnected);
+        let mut service = connector.config.circuit_breaker.wrap(failed.into_service());
+        let uri = Uri::from_static("http://localhost:80/");
+        assert!(service.call(Connect::new(uri)).wait().is_err());
+        match connector.circuit_state("localhost:80") {
+            CircuitState::Open(until) => assert!(until > Instant::now()),
+            state => panic!("{:?}", state),
+        }
+        assert_eq!(connector.circuit_state("example.com:80"), CircuitState::Closed);
+
+        // circuit breaker is disabled
+        let connector = Connector::new();
+        assert_eq!(connector.circuit_state("localhost:80"), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_circuit_half_open_probe() {
+        use futures::unsync::oneshot;
+
+        // connects complete once their senders are resolved
+        let pending = Rc::new(RefCell::new(VecDeque::new()));
+        let connects = pending.clone();
+        let connect = move |_| {
+            let (tx, rx) = oneshot::channel::<bool>();
+            connects.borrow_mut().push_back(tx);
+            rx.then(|res| match res {
+                Ok(true) => Ok(((), Protocol::Http1)),
+                _ => Err(ConnectError::Disconnected),
+            })
+        };
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(0));
+        let mut service = breaker.wrap(connect.into_service());
+        let uri = Uri::from_static("http://localhost:80/");
+        let mut complete = |ok: bool| {
+            let tx: oneshot::Sender<bool> = pending.borrow_mut().pop_front().unwrap();
+            let _ = tx.send(ok);
+        };
+
+        // failed connect opens the circuit, cooldown elapses immediately
+        let first = service.call(Connect::new(uri.clone()));
+        complete(false);
+        assert!(first.wait().is_err());
+        assert_eq!(breaker.state("localhost:80"), CircuitState::HalfOpen);
+
+        // concurrent connects are rejected while probe is in progress
+        let probe = service.call(Connect::new(uri.clone()));
+        for _ in 0..3 {
+            match service.call(Connect::new(uri.clone())).wait() {
+                Err(ConnectError::CircuitOpen) => (),
+                res => panic!("{:?}", res.map(|_| ())),
+            }
+        }
+        assert_eq!(pending.borrow().len(), 1);
+
+        // failed probe keeps circuit half-open for the next probe
+        complete(false);
+        assert!(probe.wait().is_err());
+        let probe = service.call(Connect::new(uri.clone()));
+        assert_eq!(pending.borrow().len(), 1);
+
+        // abandoned probe allows next probe
+        drop(probe);
+        pending.borrow_mut().clear();
+        let probe = service.call(Connect::new(uri.clone()));
+        assert_eq!(pending.borrow().len(), 1);
+
+        // successful probe closes the circuit
+        complete(true);
+        assert!(probe.wait().is_ok());
+        assert_eq!(breaker.state("localhost:80"), CircuitState::Closed);
+        let _a = service.call(Connect::new(uri.clone()));
+        let _b = service.call(Connect::new(uri));
+        assert_eq!(pending.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_default_connector_error() {
+        let refused = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        // broadcast address can not be reached
+        let unreachable: net::SocketAddr = "255.255.255.255:80".parse().unwrap();
+
+        let mut sys = actix_rt::System::new("test");
+        let res = sys.block_on(lazy(move || {
+            let mut connector = Connector::new().connector;
+            let uri: Uri = "http://unknown.invalid/".parse().unwrap();
+            connector.call(TcpConnect::new(uri).set_addrs(vec![refused, unreachable]))
+        }));
+        // error of reached peer is reported
+        match res {
+            Err(actix_connect::ConnectError::Io(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused)
+            }
+            _ => panic!("connect error is expected"),
+        }
+    }
+}
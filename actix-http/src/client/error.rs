@@ -1,5 +1,6 @@
-use std::io;
+use std::{error, fmt, io, net};
 
+use bytes::Bytes;
 use derive_more::{Display, From};
 use trust_dns_resolver::error::ResolveError;
 
@@ -7,11 +8,11 @@ use trust_dns_resolver::error::ResolveError;
 use openssl::ssl::{Error as SslError, HandshakeError};
 
 use crate::error::{Error, ParseError, ResponseError};
-use crate::http::Error as HttpError;
+use crate::http::{Error as HttpError, StatusCode};
 use crate::response::Response;
 
 /// A set of errors that can occur while connecting to an HTTP host
-#[derive(Debug, Display, From)]
+#[derive(Debug, Display)]
 pub enum ConnectError {
     /// SSL feature is not enabled
     #[display(fmt = "SSL is not supported")]
@@ -22,6 +23,11 @@ pub enum ConnectError {
     #[display(fmt = "{}", _0)]
     SslError(SslError),
 
+    /// Rustls handshake error
+    #[cfg(feature = "rust-tls")]
+    #[display(fmt = "Tls handshake failed: {}", _0)]
+    TlsHandshake(io::Error),
+
     /// Failed to resolve the hostname
     #[display(fmt = "Failed resolving hostname: {}", _0)]
     Resolver(ResolveError),
@@ -38,6 +44,14 @@ pub enum ConnectError {
     #[display(fmt = "Timeout out while establishing connection")]
     Timeout,
 
+    /// Peer refused connection
+    #[display(fmt = "Connection refused: {}", _0)]
+    Refused(io::Error),
+
+    /// Network or host is unreachable
+    #[display(fmt = "Host is unreachable: {}", _0)]
+    Unreachable(io::Error),
+
     /// Connector has been disconnected
     #[display(fmt = "Internal error: connector has been disconnected")]
     Disconnected,
@@ -49,6 +63,18 @@ pub enum ConnectError {
     /// Connection io error
     #[display(fmt = "{}", _0)]
     Io(io::Error),
+
+    /// Peer address is rejected by connector's peer address filter
+    #[display(fmt = "Peer address {} is not allowed", _0)]
+    PeerAddressRejected(net::SocketAddr),
+
+    /// Host had too many consecutive connect failures
+    #[display(fmt = "Circuit breaker is open for the host")]
+    CircuitOpen,
+
+    /// Proxy refused to establish tunnel to the host
+    #[display(fmt = "{}", _0)]
+    ProxyStatus(ProxyError),
 }
 
 impl From<actix_connect::ConnectError> for ConnectError {
@@ -58,11 +84,116 @@ impl From<actix_connect::ConnectError> for ConnectError {
             actix_connect::ConnectError::NoRecords => ConnectError::NoRecords,
             actix_connect::ConnectError::InvalidInput => panic!(),
             actix_connect::ConnectError::Unresolverd => ConnectError::Unresolverd,
-            actix_connect::ConnectError::Io(e) => ConnectError::Io(e),
+            actix_connect::ConnectError::Io(e) => {
+                let inner = e.get_ref();
+                if let Some(rejected) =
+                    inner.and_then(|e| e.downcast_ref::<PeerAddressRejected>())
+                {
+                    ConnectError::PeerAddressRejected(rejected.0)
+                } else {
+                    tcp_connect_error(e)
+                }
+            }
         }
     }
 }
 
+#[cfg(feature = "ssl")]
+impl From<SslError> for ConnectError {
+    fn from(err: SslError) -> ConnectError {
+        ConnectError::SslError(err)
+    }
+}
+
+impl From<ResolveError> for ConnectError {
+    fn from(err: ResolveError) -> ConnectError {
+        ConnectError::Resolver(err)
+    }
+}
+
+impl From<h2::Error> for ConnectError {
+    fn from(err: h2::Error) -> ConnectError {
+        ConnectError::H2(err)
+    }
+}
+
+impl From<io::Error> for ConnectError {
+    fn from(err: io::Error) -> ConnectError {
+        ConnectError::Io(err)
+    }
+}
+
+/// Failed tcp connects are classified by error kind, retry decisions
+/// differ for refused, unreachable and timed out connects.
+fn tcp_connect_error(err: io::Error) -> ConnectError {
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused => ConnectError::Refused(err),
+        io::ErrorKind::TimedOut => ConnectError::Timeout,
+        _ if is_unreachable(&err) => ConnectError::Unreachable(err),
+        _ => ConnectError::Io(err),
+    }
+}
+
+#[cfg(unix)]
+fn is_unreachable(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(libc::ENETUNREACH) | Some(libc::EHOSTUNREACH) => true,
+        _ => false,
+    }
+}
+
+#[cfg(windows)]
+fn is_unreachable(err: &io::Error) -> bool {
+    // WSAENETUNREACH, WSAEHOSTUNREACH
+    match err.raw_os_error() {
+        Some(10051) | Some(10065) => true,
+        _ => false,
+    }
+}
+
+/// Peer address rejection, passed through tcp connector as io error
+#[derive(Debug)]
+pub(crate) struct PeerAddressRejected(pub(crate) net::SocketAddr);
+
+impl fmt::Display for PeerAddressRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Peer address {} is not allowed", self.0)
+    }
+}
+
+impl error::Error for PeerAddressRejected {}
+
+/// Non-2xx response of http proxy to `CONNECT` request
+#[derive(Clone, Debug)]
+pub struct ProxyError {
+    status: StatusCode,
+    body: Bytes,
+}
+
+impl ProxyError {
+    pub(crate) fn new(status: StatusCode, body: Bytes) -> Self {
+        ProxyError { status, body }
+    }
+
+    /// Response status of the proxy
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Beginning of proxy response body, at most 1kb
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Proxy responded with {} to CONNECT request", self.status)
+    }
+}
+
+impl error::Error for ProxyError {}
+
 #[cfg(feature = "ssl")]
 impl<T> From<HandshakeError<T>> for ConnectError {
     fn from(err: HandshakeError<T>) -> ConnectError {
@@ -105,14 +236,46 @@ pub enum SendRequestError {
     /// Http2 error
     #[display(fmt = "{}", _0)]
     H2(h2::Error),
+    /// Server sent too many interim (1xx) responses
+    #[display(fmt = "Too many interim responses")]
+    TooManyInterimResponses,
+    /// Content-Length framing is requested for body of unknown size
+    #[display(fmt = "Body size is unknown, can not send Content-Length")]
+    UnknownBodyLength,
     /// Response took too long
     #[display(fmt = "Timeout out while waiting for response")]
     Timeout,
+    /// Redirect response does not have `Location` header
+    #[display(fmt = "Redirect response without Location header")]
+    RedirectNoLocation,
     /// Tunnels are not supported for http2 connection
     #[display(fmt = "Tunnels are not supported for http2 connection")]
     TunnelNotSupported,
+    /// Raw requests are not supported for http2 connection
+    #[display(fmt = "Raw requests are not supported for http2 connection")]
+    RawRequestNotSupported,
+    /// `Host` header does not match request uri authority
+    #[display(fmt = "Host header does not match request uri")]
+    HostConflict,
+    /// `101 Switching Protocols` response does not switch to requested
+    /// protocol or misses `Connection: upgrade` header
+    #[display(fmt = "Upgrade response does not match requested protocol")]
+    UpgradeMismatch,
+    /// Connector is at max number of open tunnels
+    #[display(fmt = "Max number of open tunnels is reached")]
+    TunnelLimit,
+    /// Http2 connection is at max concurrent streams limit
+    #[display(fmt = "Http2 connection is at max concurrent streams limit")]
+    StreamCapacity,
+    /// Server did not send valid http/2 connection preface
+    #[display(fmt = "Server did not complete http/2 connection preface")]
+    H2Preface,
     /// Error sending request body
     Body(Error),
+    /// Sending request body took too long, contains number of body bytes
+    /// written to the connection
+    #[display(fmt = "Timeout while sending request body, {} bytes sent", _0)]
+    WriteTimeout(u64),
 }
 
 /// Convert `SendRequestError` to a server `Response`
@@ -147,4 +310,52 @@ impl From<FreezeRequestError> for SendRequestError {
             FreezeRequestError::Http(e) => e.into(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp_error(err: io::Error) -> ConnectError {
+        ConnectError::from(actix_connect::ConnectError::Io(err))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tcp_connect_error() {
+        match tcp_error(io::Error::from_raw_os_error(libc::ECONNREFUSED)) {
+            ConnectError::Refused(e) => {
+                assert_eq!(e.raw_os_error(), Some(libc::ECONNREFUSED))
+            }
+            e => panic!("{:?}", e),
+        }
+        for code in &[libc::ENETUNREACH, libc::EHOSTUNREACH] {
+            match tcp_error(io::Error::from_raw_os_error(*code)) {
+                ConnectError::Unreachable(e) => {
+                    assert_eq!(e.raw_os_error(), Some(*code))
+                }
+                e => panic!("{:?}", e),
+            }
+        }
+        match tcp_error(io::Error::from_raw_os_error(libc::ETIMEDOUT)) {
+            ConnectError::Timeout => (),
+            e => panic!("{:?}", e),
+        }
+        match tcp_error(io::Error::from_raw_os_error(libc::ECONNRESET)) {
+            ConnectError::Io(e) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_io_error_is_not_classified() {
+        // io errors outside of tcp connect, i.e. of http/2 handshake
+        for code in &[libc::ECONNREFUSED, libc::ENETUNREACH, libc::ETIMEDOUT] {
+            match ConnectError::from(io::Error::from_raw_os_error(*code)) {
+                ConnectError::Io(e) => assert_eq!(e.raw_os_error(), Some(*code)),
+                e => panic!("{:?}", e),
+            }
+        }
+    }
+}
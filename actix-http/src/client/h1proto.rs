@@ -1,21 +1,38 @@
 use std::io::Write;
+use std::rc::Rc;
 use std::{io, time};
 
-use actix_codec::{AsyncRead, AsyncWrite, Framed};
+use actix_codec::{AsyncRead, AsyncWrite, Framed, FramedParts};
 use bytes::{BufMut, Bytes, BytesMut};
-use futures::future::{ok, Either};
-use futures::{Async, Future, Poll, Sink, Stream};
+use futures::future::{err, ok, Either};
+use futures::unsync::oneshot;
+use futures::{stream, Async, Future, Poll, Sink, Stream};
+use tokio_current_thread::TaskExecutor;
+use tokio_timer::Delay;
 
-use crate::error::PayloadError;
+use crate::error::{ParseError, PayloadError};
 use crate::h1;
-use crate::http::header::{IntoHeaderValue, HOST};
+use crate::http::header::{
+    HeaderName, HeaderValue, IntoHeaderValue, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE,
+    EXPECT, HOST, TRANSFER_ENCODING, UPGRADE,
+};
+use crate::http::uri::Authority;
+use crate::http::{Method, StatusCode, Uri};
 use crate::message::{RequestHeadType, ResponseHead};
 use crate::payload::{Payload, PayloadStream};
 use crate::header::HeaderMap;
 
-use super::connection::{ConnectionLifetime, ConnectionType, IoConnection};
-use super::error::{ConnectError, SendRequestError};
-use super::pool::Acquired;
+use super::config::{ConnectorConfig, MAX_INTERIM_RESPONSES};
+use super::connection::{
+    ConnectionBytes, ConnectionLifetime, ConnectionType, IoConnection,
+};
+use super::error::{ConnectError, ProxyError, SendRequestError};
+use super::pool::{Acquired, EvictReason};
+use super::{
+    CaptureSentHeaders, DecompressLimit, HostConflictPolicy, MaxResponseHeaderSize,
+    MaxResponseHeaders, PerReadTimeout, ReadIdleTimeout, RequestBytesSent, SentHeaders,
+    TransferEncoding,
+};
 use crate::body::{BodySize, MessageBody};
 
 pub(crate) fn send_request<T, B>(
@@ -23,14 +40,35 @@ pub(crate) fn send_request<T, B>(
     mut head: RequestHeadType,
     body: B,
     created: time::Instant,
+    bytes: ConnectionBytes,
     pool: Option<Acquired<T>>,
+    handle: Option<oneshot::Sender<IoConnection<T>>>,
 ) -> impl Future<Item = (ResponseHead, Payload), Error = SendRequestError>
 where
     T: AsyncRead + AsyncWrite + 'static,
-    B: MessageBody,
+    B: MessageBody + 'static,
 {
-    // set request host header
-    if !head.as_ref().headers.contains_key(HOST) && !head.extra_headers().iter().any(|h| h.contains_key(HOST)) {
+    let config = pool.as_ref().and_then(|pool| pool.config());
+
+    // set request host header, existing header could conflict with uri
+    let set_host = match get_header(&head, HOST) {
+        None => true,
+        Some(ref value) if !host_matches(value, &head.as_ref().uri) => {
+            let policy = config
+                .as_ref()
+                .map(|config| config.host_conflict_policy)
+                .unwrap_or(HostConflictPolicy::Header);
+            match policy {
+                HostConflictPolicy::Uri => true,
+                HostConflictPolicy::Header => false,
+                HostConflictPolicy::Error => {
+                    return Either::A(err(SendRequestError::HostConflict))
+                }
+            }
+        }
+        Some(_) => false,
+    };
+    if set_host {
         if let Some(host) = head.as_ref().uri.host() {
             let mut wrt = BytesMut::with_capacity(host.len() + 5).writer();
 
@@ -58,50 +96,485 @@ where
         }
     }
 
-    let io = H1Connection {
-        created,
-        pool,
-        io: Some(io),
+    let te = head.as_ref().extensions().get::<TransferEncoding>().cloned();
+    let len = match te {
+        None => {
+            let zero_length = config
+                .as_ref()
+                .and_then(|config| config.explicit_zero_content_length);
+            match (body.size(), zero_length) {
+                // explicitly empty body with content type keeps its framing
+                (BodySize::Empty, _) if has_header(&head, CONTENT_TYPE) => {
+                    BodySize::Empty
+                }
+                // content type implies body of methods that usually carry one
+                (BodySize::None, _)
+                    if has_payload(&head) && has_header(&head, CONTENT_TYPE) =>
+                {
+                    BodySize::Empty
+                }
+                (BodySize::None, Some(true)) if has_payload(&head) => BodySize::Empty,
+                (BodySize::Empty, Some(false)) if has_payload(&head) => BodySize::None,
+                (size, _) => size,
+            }
+        }
+        Some(TransferEncoding::Chunked) => BodySize::Stream,
+        Some(TransferEncoding::ContentLength) => match body.size() {
+            BodySize::None => BodySize::Empty,
+            BodySize::Stream => {
+                return Either::A(err(SendRequestError::UnknownBodyLength))
+            }
+            size => size,
+        },
     };
 
-    let len = body.size();
+    // `Expect: 100-continue` is sent only for bodies over the threshold
+    let expect = config
+        .as_ref()
+        .and_then(|config| config.expect_continue_threshold)
+        .map(|threshold| match len {
+            BodySize::Sized(size) => size > threshold,
+            BodySize::Sized64(size) => size > threshold as u64,
+            BodySize::Stream => true,
+            BodySize::None | BodySize::Empty => false,
+        });
+    if expect == Some(true) && !has_header(&head, EXPECT) {
+        let value = HeaderValue::from_static("100-continue");
+        match head {
+            RequestHeadType::Owned(ref mut head) => head.headers.insert(EXPECT, value),
+            RequestHeadType::Rc(_, ref mut extra_headers) => extra_headers
+                .get_or_insert(HeaderMap::new())
+                .insert(EXPECT, value),
+        };
+    }
+
+    let max_interim = config
+        .as_ref()
+        .map(|config| config.max_interim_responses)
+        .unwrap_or(MAX_INTERIM_RESPONSES);
+
+    let coalesce = config
+        .as_ref()
+        .map(|config| config.coalesce_small_body as u64)
+        .unwrap_or(0);
+    let flush_each = config
+        .as_ref()
+        .map(|config| config.flush_each_request)
+        .unwrap_or(false);
+    let coalesce = !flush_each
+        && match len {
+            BodySize::Sized(size) => size > 0 && size as u64 <= coalesce,
+            BodySize::Sized64(size) => size > 0 && size <= coalesce,
+            _ => false,
+        };
+
+    // response head limits, request overrides connector defaults
+    let mut codec = h1::ClientCodec::default();
+    codec.set_http10_keep_alive(
+        config
+            .as_ref()
+            .map(|config| config.http10_keep_alive)
+            .unwrap_or(false),
+    );
+    codec.set_tolerate_missing_last_chunk(
+        config
+            .as_ref()
+            .map(|config| config.tolerate_missing_last_chunk)
+            .unwrap_or(false),
+    );
+    let max_head_size = head
+        .as_ref()
+        .extensions()
+        .get::<MaxResponseHeaderSize>()
+        .map(|size| size.0)
+        .or_else(|| config.as_ref().and_then(|c| c.max_response_header_size));
+    if let Some(size) = max_head_size {
+        codec.set_max_head_size(size);
+    }
+    let max_headers = head
+        .as_ref()
+        .extensions()
+        .get::<MaxResponseHeaders>()
+        .map(|num| num.0)
+        .or_else(|| config.as_ref().and_then(|c| c.max_response_headers));
+    if let Some(num) = max_headers {
+        codec.set_max_headers(num);
+    }
+
+    // payload timeouts, request overrides connector defaults
+    let timeouts = ReadTimeouts {
+        idle: head
+            .as_ref()
+            .extensions()
+            .get::<ReadIdleTimeout>()
+            .map(|timeout| timeout.0)
+            .or_else(|| config.as_ref().and_then(|c| c.read_idle_timeout)),
+        read: head
+            .as_ref()
+            .extensions()
+            .get::<PerReadTimeout>()
+            .map(|timeout| timeout.0)
+            .or_else(|| config.as_ref().and_then(|c| c.per_read_timeout)),
+    };
+
+    let write_timeout = config.as_ref().and_then(|config| config.write_timeout);
+
+    let sent_headers = if head.as_ref().extensions().contains::<CaptureSentHeaders>() {
+        let mut headers = sent_headers(&head, len);
+        if expect == Some(false) {
+            headers.remove(EXPECT);
+        }
+        Some(SentHeaders(headers))
+    } else {
+        None
+    };
+
+    // `Expect` header of small body is skipped while head is encoded
+    if expect == Some(false) {
+        head = skip_expect(head);
+    }
+
+    let mut io = H1Connection::new(io, created, bytes, pool);
+    io.handle = handle;
+    io.bytes.requests += 1;
+    let mut framed = Framed::new(io, codec);
 
     // create Framed and send request
-    Framed::new(io, h1::ClientCodec::default())
-        .send((head, len).into())
-        .from_err()
-        // send request body
-        .and_then(move |framed| match body.size() {
-            BodySize::None | BodySize::Empty | BodySize::Sized(0) => {
-                Either::A(ok(framed))
+    let send: Box<
+        dyn Future<
+            Item = (Framed<H1Connection<T>, h1::ClientCodec>, u64),
+            Error = SendRequestError,
+        >,
+    > = if coalesce {
+        // head and available body are flushed together
+        let (body, sent) = match buffer_request(&mut framed, (head, len).into(), body) {
+            Ok(res) => res,
+            Err(e) => return Either::A(err(e)),
+        };
+        Box::new(framed.flush().from_err().and_then(move |framed| match body {
+            Some(body) => {
+                let mut fut = SendBody::new(body, framed, write_timeout, false);
+                fut.sent = sent;
+                Either::A(fut)
             }
-            _ => Either::B(SendBody::new(body, framed)),
-        })
-        // read response and init read body
-        .and_then(|framed| {
+            None => Either::B(ok((framed, sent))),
+        }))
+    } else {
+        Box::new(
             framed
-                .into_future()
-                .map_err(|(e, _)| SendRequestError::from(e))
-                .and_then(|(item, framed)| {
-                    if let Some(res) = item {
-                        match framed.get_codec().message_type() {
-                            h1::MessageType::None => {
-                                let force_close = !framed.get_codec().keepalive();
-                                release_connection(framed, force_close);
-                                Ok((res, Payload::None))
-                            }
-                            _ => {
-                                let pl: PayloadStream = Box::new(PlStream::new(framed));
-                                Ok((res, pl.into()))
-                            }
-                        }
-                    } else {
-                        Err(ConnectError::Disconnected.into())
+                .send((head, len).into())
+                .from_err()
+                // send request body
+                .and_then(move |framed| match len {
+                    BodySize::None | BodySize::Empty | BodySize::Sized(0) => {
+                        Either::A(ok((framed, 0)))
                     }
-                })
+                    _ => Either::B(SendBody::new(
+                        body,
+                        framed,
+                        write_timeout,
+                        flush_each,
+                    )),
+                }),
+        )
+    };
+
+    let fut = send
+        // read response and init read body
+        .and_then(move |(framed, sent)| {
+            read_response(framed, sent, config, timeouts, max_interim)
+        })
+        .map(move |(head, payload)| {
+            if let Some(headers) = sent_headers {
+                head.extensions_mut().insert(headers);
+            }
+            (head, payload)
+        });
+    Either::B(fut)
+}
+
+/// Send pre-serialized request, response is read with regular parser.
+///
+/// Data is written as is, so connection keep-alive is unknown and connection
+/// is closed once response is handled. Request method is taken from the
+/// request line, response to `HEAD` request has no payload.
+pub(crate) fn send_raw_request<T>(
+    io: T,
+    data: Bytes,
+    created: time::Instant,
+    bytes: ConnectionBytes,
+    pool: Option<Acquired<T>>,
+) -> impl Future<Item = (ResponseHead, Payload), Error = SendRequestError>
+where
+    T: AsyncRead + AsyncWrite + 'static,
+{
+    let config = pool.as_ref().and_then(|pool| pool.config());
+    let max_interim = config
+        .as_ref()
+        .map(|config| config.max_interim_responses)
+        .unwrap_or(MAX_INTERIM_RESPONSES);
+    let timeouts = ReadTimeouts {
+        idle: config.as_ref().and_then(|c| c.read_idle_timeout),
+        read: config.as_ref().and_then(|c| c.per_read_timeout),
+    };
+
+    let mut io = H1Connection::new(io, created, bytes, pool);
+    io.reuse = false;
+    let sent = data.len() as u64;
+    let mut codec = h1::ClientCodec::default();
+    let method = data.split(|b| *b == b' ').next();
+    codec.set_head_request(method == Some(&b"HEAD"[..]));
+    let mut parts = FramedParts::new(io, codec);
+    parts.write_buf.extend_from_slice(&data);
+
+    Framed::from_parts(parts)
+        .flush()
+        .from_err()
+        .and_then(move |framed| {
+            read_response(framed, sent, config, timeouts, max_interim)
         })
 }
 
+/// Read response head and init response payload
+fn read_response<T>(
+    framed: Framed<H1Connection<T>, h1::ClientCodec>,
+    sent: u64,
+    config: Option<Rc<ConnectorConfig>>,
+    timeouts: ReadTimeouts,
+    max_interim: usize,
+) -> impl Future<Item = (ResponseHead, Payload), Error = SendRequestError>
+where
+    T: AsyncRead + AsyncWrite + 'static,
+{
+    ReadResponse {
+        framed: Some(framed),
+        remaining: max_interim,
+    }
+    .map(move |(mut res, mut framed)| {
+        if let Some(after_receive) =
+            config.as_ref().and_then(|c| c.after_receive.as_ref())
+        {
+            after_receive(&mut res);
+        }
+        res.extensions_mut().insert(RequestBytesSent(sent));
+        if let Some(ref config) = config {
+            res.extensions_mut()
+                .insert(DecompressLimit(config.max_decompressed_size));
+            if let Some(ref should_pool) = config.should_pool {
+                if framed.get_ref().reuse && !should_pool(&res) {
+                    let io = framed.get_mut();
+                    io.reuse = false;
+                    io.evict = EvictReason::ErrorStatus;
+                }
+            }
+        }
+        if res.status == StatusCode::SWITCHING_PROTOCOLS {
+            // protocol is switched, connection can not be reused
+            framed.into_parts().io.close();
+            log::warn!("Server switched protocols, connection is closed");
+            let pl: PayloadStream =
+                Box::new(stream::once(Err(PayloadError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Response payload is not available after protocol switch",
+                )))));
+            return (res, pl.into());
+        }
+        match framed.get_codec().message_type() {
+            h1::MessageType::None => {
+                // bytes after bodyless response, connection can not be reused
+                let force_close = !framed.get_codec().keepalive();
+                if release_connection(framed, force_close) {
+                    trace!("Unexpected data after bodyless response, closing");
+                }
+                (res, Payload::None)
+            }
+            _ => {
+                let pl: PayloadStream =
+                    Box::new(PlStream::new(framed, config, timeouts));
+                (res, pl.into())
+            }
+        }
+    })
+}
+
+/// Encode request head and immediately available body into write buffer.
+///
+/// Returns unfinished body and number of buffered body bytes.
+fn buffer_request<T, B>(
+    framed: &mut Framed<T, h1::ClientCodec>,
+    msg: h1::Message<(RequestHeadType, BodySize)>,
+    mut body: B,
+) -> Result<(Option<B>, u64), SendRequestError>
+where
+    T: AsyncRead + AsyncWrite,
+    B: MessageBody,
+{
+    framed.force_send(msg)?;
+
+    let mut sent = 0;
+    loop {
+        match body.poll_next()? {
+            Async::Ready(Some(chunk)) => {
+                sent += chunk.len() as u64;
+                framed.force_send(h1::Message::Chunk(Some(chunk)))?;
+            }
+            Async::Ready(None) => {
+                framed.force_send(h1::Message::Chunk(None))?;
+                return Ok((None, sent));
+            }
+            Async::NotReady => return Ok((Some(body), sent)),
+        }
+    }
+}
+
+/// Future that reads final response head, interim (1xx) responses are skipped
+struct ReadResponse<Io> {
+    framed: Option<Framed<Io, h1::ClientCodec>>,
+    remaining: usize,
+}
+
+impl<Io: ConnectionLifetime> Future for ReadResponse<Io> {
+    type Item = (ResponseHead, Framed<Io, h1::ClientCodec>);
+    type Error = SendRequestError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let res = match futures::try_ready!(self.framed.as_mut().unwrap().poll()) {
+                Some(res) => res,
+                None => {
+                    // request is sent already, it is not a connect failure
+                    return Err(SendRequestError::Response(ParseError::Io(
+                        io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "Connection closed before response head",
+                        ),
+                    )));
+                }
+            };
+
+            if res.status.is_informational()
+                && res.status != StatusCode::SWITCHING_PROTOCOLS
+            {
+                if self.remaining == 0 {
+                    return Err(SendRequestError::TooManyInterimResponses);
+                }
+                self.remaining -= 1;
+                continue;
+            }
+            return Ok(Async::Ready((res, self.framed.take().unwrap())));
+        }
+    }
+}
+
+/// Methods that usually carry request body
+fn has_payload(head: &RequestHeadType) -> bool {
+    match head.as_ref().method {
+        Method::POST | Method::PUT => true,
+        _ => false,
+    }
+}
+
+fn get_header(head: &RequestHeadType, name: HeaderName) -> Option<HeaderValue> {
+    match head {
+        RequestHeadType::Owned(head) => head.headers.get(&name).cloned(),
+        RequestHeadType::Rc(head, extra) => extra
+            .as_ref()
+            .and_then(|extra| extra.get(&name))
+            .or_else(|| head.headers.get(&name))
+            .cloned(),
+    }
+}
+
+/// Request headers with framing headers as they are written by encoder
+fn sent_headers(head: &RequestHeadType, len: BodySize) -> HeaderMap {
+    let mut headers = head.as_ref().headers.clone();
+    if let RequestHeadType::Rc(_, Some(ref extra)) = head {
+        for name in extra.keys() {
+            headers.remove(name);
+        }
+        for (name, value) in extra.iter() {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+
+    headers.remove(CONTENT_LENGTH);
+    headers.remove(TRANSFER_ENCODING);
+    match len {
+        BodySize::Stream => headers.insert(
+            TRANSFER_ENCODING,
+            HeaderValue::from_static("chunked"),
+        ),
+        BodySize::Empty => {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"))
+        }
+        BodySize::Sized(len) => headers.insert(CONTENT_LENGTH, len.into()),
+        BodySize::Sized64(len) => headers.insert(CONTENT_LENGTH, len.into()),
+        BodySize::None => (),
+    }
+    headers
+}
+
+/// Check if `Host` header value matches authority of request uri
+fn host_matches(value: &HeaderValue, uri: &Uri) -> bool {
+    let host = match uri.host() {
+        Some(host) => host,
+        None => return true,
+    };
+    let default_port = if uri.scheme_str() == Some("https") {
+        443
+    } else {
+        80
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.parse::<Authority>().ok())
+        .map(|authority| {
+            authority.host().eq_ignore_ascii_case(host)
+                && authority.port_u16().unwrap_or(default_port)
+                    == uri.port_u16().unwrap_or(default_port)
+        })
+        .unwrap_or(false)
+}
+
+/// Mark request head to be encoded without `Expect` header
+///
+/// Shared head is copied only if it contains the header.
+fn skip_expect(head: RequestHeadType) -> RequestHeadType {
+    match head {
+        RequestHeadType::Owned(mut head) => {
+            head.set_skip_expect(true);
+            RequestHeadType::Owned(head)
+        }
+        RequestHeadType::Rc(mut head, mut extra_headers) => {
+            if let Some(ref mut extra) = extra_headers {
+                extra.remove(EXPECT);
+            }
+            if let Some(head) = Rc::get_mut(&mut head) {
+                head.set_skip_expect(true);
+            } else if head.headers.contains_key(EXPECT) {
+                let mut head = head.duplicate(extra_headers.as_ref());
+                head.set_skip_expect(true);
+                return RequestHeadType::Owned(head);
+            }
+            RequestHeadType::Rc(head, extra_headers)
+        }
+    }
+}
+
+fn has_header(head: &RequestHeadType, name: HeaderName) -> bool {
+    match head {
+        RequestHeadType::Owned(head) => head.headers.contains_key(&name),
+        RequestHeadType::Rc(head, extra) => {
+            head.headers.contains_key(&name)
+                || extra
+                    .as_ref()
+                    .map(|extra| extra.contains_key(&name))
+                    .unwrap_or(false)
+        }
+    }
+}
+
 pub(crate) fn open_tunnel<T>(
     io: T,
     head: RequestHeadType,
@@ -109,31 +582,86 @@ pub(crate) fn open_tunnel<T>(
 where
     T: AsyncRead + AsyncWrite + 'static,
 {
+    let upgrade = get_header(&head, UPGRADE);
+    let connect = head.as_ref().method == Method::CONNECT;
+
     // create Framed and send request
     Framed::new(io, h1::ClientCodec::default())
         .send((head, BodySize::None).into())
         .from_err()
         // read response
-        .and_then(|framed| {
+        .and_then(move |framed| {
             framed
                 .into_future()
                 .map_err(|(e, _)| SendRequestError::from(e))
-                .and_then(|(head, framed)| {
-                    if let Some(head) = head {
-                        Ok((head, framed))
-                    } else {
-                        Err(SendRequestError::from(ConnectError::Disconnected))
+                .and_then(move |(head, framed)| {
+                    let head = head.ok_or(ConnectError::Disconnected)?;
+                    if connect && !head.status.is_success() {
+                        return Err(proxy_error(&head, framed).into());
+                    }
+                    if let Some(ref upgrade) = upgrade {
+                        if !upgrade_matches(upgrade, &head) {
+                            return Err(SendRequestError::UpgradeMismatch);
+                        }
                     }
+                    Ok((head, framed))
                 })
         })
 }
 
+/// Max size of proxy response body kept in `ProxyError`
+const MAX_PROXY_BODY: usize = 1024;
+
+/// Proxy refused to establish tunnel, connection is closed
+///
+/// Error keeps beginning of response body that is received with the head.
+fn proxy_error<T>(
+    head: &ResponseHead,
+    framed: Framed<T, h1::ClientCodec>,
+) -> ConnectError {
+    let mut body = framed.into_parts().read_buf;
+    body.truncate(MAX_PROXY_BODY);
+    ConnectError::ProxyStatus(ProxyError::new(head.status, body.freeze()))
+}
+
+/// Check if `101 Switching Protocols` response switches to one of the
+/// requested protocols and confirms upgrade with `Connection` header
+fn upgrade_matches(requested: &HeaderValue, head: &ResponseHead) -> bool {
+    if head.status != StatusCode::SWITCHING_PROTOCOLS {
+        return true;
+    }
+    let has_token = |value: Option<&HeaderValue>, token: &str| {
+        value
+            .and_then(|value| value.to_str().ok())
+            .map(|value| {
+                value
+                    .split(',')
+                    .any(|item| item.trim().eq_ignore_ascii_case(token))
+            })
+            .unwrap_or(false)
+    };
+    let accepted = match head.headers.get(UPGRADE).map(|value| value.to_str()) {
+        Some(Ok(accepted)) => accepted.trim(),
+        _ => return false,
+    };
+    has_token(Some(requested), accepted)
+        && has_token(head.headers.get(CONNECTION), "upgrade")
+}
+
 #[doc(hidden)]
 /// HTTP client connection
+///
+/// Connection serves single request at a time. Io object is given away
+/// once connection is released, any further io fails with `NotConnected`
+/// error instead of writing into a connection that is used by another request.
 pub struct H1Connection<T> {
     io: Option<T>,
     created: time::Instant,
+    bytes: ConnectionBytes,
     pool: Option<Acquired<T>>,
+    handle: Option<oneshot::Sender<IoConnection<T>>>,
+    reuse: bool,
+    evict: EvictReason,
 }
 
 impl<T: AsyncRead + AsyncWrite + 'static> ConnectionLifetime for H1Connection<T> {
@@ -141,32 +669,83 @@ impl<T: AsyncRead + AsyncWrite + 'static> ConnectionLifetime for H1Connection<T>
     fn close(&mut self) {
         if let Some(mut pool) = self.pool.take() {
             if let Some(io) = self.io.take() {
-                pool.close(IoConnection::new(
-                    ConnectionType::H1(io),
-                    self.created,
-                    None,
-                ));
+                pool.close(
+                    IoConnection::new(ConnectionType::H1(io), self.created, None)
+                        .with_bytes(self.bytes),
+                    self.evict,
+                );
             }
         }
     }
 
     /// Release this connection to the connection pool
     fn release(&mut self) {
+        if !self.reuse {
+            return self.close();
+        }
+
+        // connection is requested by handle, it stays checked out
+        if let Some(tx) = self.handle.take() {
+            if let Some(io) = self.io.take() {
+                let conn = IoConnection::new(
+                    ConnectionType::H1(io),
+                    self.created,
+                    self.pool.take(),
+                )
+                .with_bytes(self.bytes);
+                if let Err(conn) = tx.send(conn) {
+                    conn.release();
+                }
+                return;
+            }
+        }
+
         if let Some(mut pool) = self.pool.take() {
             if let Some(io) = self.io.take() {
                 pool.release(IoConnection::new(
                     ConnectionType::H1(io),
                     self.created,
                     None,
-                ));
+                )
+                .with_bytes(self.bytes));
             }
         }
     }
 }
 
+impl<T> H1Connection<T> {
+    pub(crate) fn new(
+        io: T,
+        created: time::Instant,
+        bytes: ConnectionBytes,
+        pool: Option<Acquired<T>>,
+    ) -> Self {
+        H1Connection {
+            io: Some(io),
+            created,
+            bytes,
+            pool,
+            handle: None,
+            reuse: true,
+            evict: EvictReason::NotReusable,
+        }
+    }
+
+    fn io(&mut self) -> io::Result<&mut T> {
+        self.io.as_mut().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Http/1 connection is used after it has been released",
+            )
+        })
+    }
+}
+
 impl<T: AsyncRead + AsyncWrite + 'static> io::Read for H1Connection<T> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.io.as_mut().unwrap().read(buf)
+        let n = self.io()?.read(buf)?;
+        self.bytes.received += n as u64;
+        Ok(n)
     }
 }
 
@@ -174,25 +753,33 @@ impl<T: AsyncRead + AsyncWrite + 'static> AsyncRead for H1Connection<T> {}
 
 impl<T: AsyncRead + AsyncWrite + 'static> io::Write for H1Connection<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.io.as_mut().unwrap().write(buf)
+        let n = self.io()?.write(buf)?;
+        self.bytes.sent += n as u64;
+        Ok(n)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.io.as_mut().unwrap().flush()
+        self.io()?.flush()
     }
 }
 
 impl<T: AsyncRead + AsyncWrite + 'static> AsyncWrite for H1Connection<T> {
     fn shutdown(&mut self) -> Poll<(), io::Error> {
-        self.io.as_mut().unwrap().shutdown()
+        self.io()?.shutdown()
     }
 }
 
 /// Future responsible for sending request body to the peer
+///
+/// Buffer is flushed after every body chunk, next chunk is written to the
+/// buffer while flush is not complete unless `flush_each` is set.
 pub(crate) struct SendBody<I, B> {
     body: Option<B>,
     framed: Option<Framed<I, h1::ClientCodec>>,
     flushed: bool,
+    flush_each: bool,
+    sent: u64,
+    timeout: Option<Delay>,
 }
 
 impl<I, B> SendBody<I, B>
@@ -200,11 +787,42 @@ where
     I: AsyncRead + AsyncWrite + 'static,
     B: MessageBody,
 {
-    pub(crate) fn new(body: B, framed: Framed<I, h1::ClientCodec>) -> Self {
+    pub(crate) fn new(
+        body: B,
+        framed: Framed<I, h1::ClientCodec>,
+        timeout: Option<time::Duration>,
+        flush_each: bool,
+    ) -> Self {
         SendBody {
             body: Some(body),
             framed: Some(framed),
             flushed: true,
+            flush_each,
+            sent: 0,
+            timeout: timeout.map(|timeout| Delay::new(time::Instant::now() + timeout)),
+        }
+    }
+}
+
+impl<I: ConnectionLifetime, B> SendBody<I, B> {
+    /// Check write timeout, body is not sent yet
+    fn poll_timeout(&mut self) -> Result<(), SendRequestError> {
+        let expired = match self.timeout {
+            Some(ref mut delay) => {
+                delay.poll().map(|res| res.is_ready()).unwrap_or(true)
+            }
+            None => false,
+        };
+
+        if expired {
+            // buffered data is not sent, chunked encoding framing is
+            // counted as body data so reported size is a lower bound
+            let mut parts = self.framed.take().unwrap().into_parts();
+            let unsent = parts.write_buf.len() as u64;
+            parts.io.close();
+            Err(SendRequestError::WriteTimeout(self.sent.saturating_sub(unsent)))
+        } else {
+            Ok(())
         }
     }
 }
@@ -214,7 +832,7 @@ where
     I: ConnectionLifetime,
     B: MessageBody,
 {
-    type Item = Framed<I, h1::ClientCodec>;
+    type Item = (Framed<I, h1::ClientCodec>, u64);
     type Error = SendRequestError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -222,13 +840,17 @@ where
         loop {
             while body_ready
                 && self.body.is_some()
+                && (self.flushed || !self.flush_each)
                 && !self.framed.as_ref().unwrap().is_write_buf_full()
             {
                 match self.body.as_mut().unwrap().poll_next()? {
                     Async::Ready(item) => {
                         // check if body is done
-                        if item.is_none() {
-                            let _ = self.body.take();
+                        match item {
+                            Some(ref chunk) => self.sent += chunk.len() as u64,
+                            None => {
+                                let _ = self.body.take();
+                            }
                         }
                         self.flushed = false;
                         self.framed
@@ -247,26 +869,143 @@ where
                         self.flushed = true;
                         continue;
                     }
-                    Async::NotReady => return Ok(Async::NotReady),
+                    Async::NotReady => {
+                        self.poll_timeout()?;
+                        return Ok(Async::NotReady);
+                    }
                 }
             }
 
             if self.body.is_none() {
-                return Ok(Async::Ready(self.framed.take().unwrap()));
+                return Ok(Async::Ready((self.framed.take().unwrap(), self.sent)));
             }
+            self.poll_timeout()?;
             return Ok(Async::NotReady);
         }
     }
 }
 
-pub(crate) struct PlStream<Io> {
+/// Response payload read timeouts
+#[derive(Clone, Copy)]
+struct ReadTimeouts {
+    idle: Option<time::Duration>,
+    read: Option<time::Duration>,
+}
+
+pub(crate) struct PlStream<Io: ConnectionLifetime> {
     framed: Option<Framed<Io, h1::ClientPayloadCodec>>,
+    config: Option<Rc<ConnectorConfig>>,
+    timeouts: ReadTimeouts,
+    idle: Option<Delay>,
+    read: Option<Delay>,
+    /// Payload item read ahead of the consumer
+    ahead: Option<Option<Bytes>>,
+    /// Read error of read ahead, reported by the next poll
+    ahead_err: Option<PayloadError>,
+    /// Connection is closed by read timeout
+    timed_out: bool,
 }
 
 impl<Io: ConnectionLifetime> PlStream<Io> {
-    fn new(framed: Framed<Io, h1::ClientCodec>) -> Self {
+    fn new(
+        framed: Framed<Io, h1::ClientCodec>,
+        config: Option<Rc<ConnectorConfig>>,
+        timeouts: ReadTimeouts,
+    ) -> Self {
         PlStream {
             framed: Some(framed.map_codec(|codec| codec.into_payload_codec())),
+            config,
+            idle: timeouts
+                .idle
+                .map(|timeout| Delay::new(time::Instant::now() + timeout)),
+            read: None,
+            timeouts,
+            ahead: None,
+            ahead_err: None,
+            timed_out: false,
+        }
+    }
+
+    /// Close connection after read error, payload can not be drained
+    fn read_error(&mut self, err: PayloadError) -> PayloadError {
+        if let Some(framed) = self.framed.take() {
+            framed.into_parts().io.close();
+        }
+        err
+    }
+
+    fn read_ahead(&self) -> bool {
+        self.config
+            .as_ref()
+            .map(|config| config.response_read_ahead)
+            .unwrap_or(false)
+    }
+
+    /// Check read timeouts, payload is not ready
+    fn poll_timeouts(&mut self) -> Result<(), PayloadError> {
+        if self.read.is_none() {
+            if let Some(timeout) = self.timeouts.read {
+                self.read = Some(Delay::new(time::Instant::now() + timeout));
+            }
+        }
+
+        let idle = match self.idle {
+            Some(ref mut delay) => {
+                delay.poll().map(|res| res.is_ready()).unwrap_or(true)
+            }
+            None => false,
+        };
+        let read = match self.read {
+            Some(ref mut delay) => {
+                delay.poll().map(|res| res.is_ready()).unwrap_or(true)
+            }
+            None => false,
+        };
+
+        if idle || read {
+            // connection is in unknown state
+            if let Some(framed) = self.framed.take() {
+                framed.into_parts().io.close();
+            }
+            self.timed_out = true;
+            Err(read_timeout_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn read_timeout_error() -> PayloadError {
+    PayloadError::Io(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "Timeout while reading response payload",
+    ))
+}
+
+impl<Io: ConnectionLifetime> Drop for PlStream<Io> {
+    fn drop(&mut self) {
+        // payload is not fully read, drain the rest of it or close connection
+        if let Some(framed) = self.framed.take() {
+            // end of payload is already read ahead
+            if let Some(None) = self.ahead {
+                let force_close = !framed.get_codec().keepalive();
+                let _ = release_connection(framed, force_close);
+                return;
+            }
+            match self.config {
+                Some(ref config) if config.drain_on_drop > 0 => {
+                    // without executor (e.g. after blocking client call) drain
+                    // future is dropped right away and closes connection
+                    let timeout = time::Instant::now() + config.conn_keep_alive;
+                    let drain = DrainPayload {
+                        framed: Some(framed),
+                        remaining: config.drain_on_drop,
+                        timeout: Delay::new(timeout),
+                    };
+                    let _ = TaskExecutor::current().spawn_local(Box::new(drain));
+                }
+                _ => framed.into_parts().io.close(),
+            }
         }
     }
 }
@@ -276,15 +1015,59 @@ impl<Io: ConnectionLifetime> Stream for PlStream<Io> {
     type Error = PayloadError;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match self.framed.as_mut().unwrap().poll()? {
-            Async::NotReady => Ok(Async::NotReady),
+        if let Some(e) = self.ahead_err.take() {
+            return Err(e);
+        }
+        let item = match self.ahead.take() {
+            Some(item) => Async::Ready(Some(item)),
+            None => match self.framed.as_mut().map(|framed| framed.poll()) {
+                Some(Ok(item)) => item,
+                Some(Err(e)) => return Err(self.read_error(e)),
+                None if self.timed_out => return Err(read_timeout_error()),
+                None => return Ok(Async::Ready(None)),
+            },
+        };
+        match item {
+            Async::NotReady => {
+                self.poll_timeouts()?;
+                Ok(Async::NotReady)
+            }
             Async::Ready(Some(chunk)) => {
                 if let Some(chunk) = chunk {
+                    self.read = None;
+                    if let Some(timeout) = self.timeouts.idle {
+                        self.idle = Some(Delay::new(time::Instant::now() + timeout));
+                    }
+                    if self.read_ahead() {
+                        // fill read buffer while consumer handles current chunk,
+                        // read errors are reported by the next poll
+                        match self.framed.as_mut().map(|framed| framed.poll()) {
+                            Some(Ok(Async::Ready(Some(item)))) => {
+                                self.ahead = Some(item)
+                            }
+                            Some(Err(e)) => self.ahead_err = Some(self.read_error(e)),
+                            _ => (),
+                        }
+                    }
                     Ok(Async::Ready(Some(chunk)))
                 } else {
-                    let framed = self.framed.take().unwrap();
+                    let framed = match self.framed.take() {
+                        Some(framed) => framed,
+                        None => return Ok(Async::Ready(None)),
+                    };
                     let force_close = !framed.get_codec().keepalive();
-                    release_connection(framed, force_close);
+                    let extra_data = release_connection(framed, force_close);
+                    let strict = self
+                        .config
+                        .as_ref()
+                        .map(|config| config.strict_content_length)
+                        .unwrap_or(false);
+                    if extra_data && strict {
+                        return Err(PayloadError::Io(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Response contains more data than declared",
+                        )));
+                    }
                     Ok(Async::Ready(None))
                 }
             }
@@ -293,14 +1076,261 @@ impl<Io: ConnectionLifetime> Stream for PlStream<Io> {
     }
 }
 
-fn release_connection<T, U>(framed: Framed<T, U>, force_close: bool)
+/// Reads the rest of unfinished payload, connection is returned to the pool
+/// if payload ends within drain limit, otherwise it gets closed.
+struct DrainPayload<Io: ConnectionLifetime> {
+    framed: Option<Framed<Io, h1::ClientPayloadCodec>>,
+    remaining: usize,
+    timeout: Delay,
+}
+
+impl<Io: ConnectionLifetime> Future for DrainPayload<Io> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            let item = match self.framed {
+                Some(ref mut framed) => framed.poll(),
+                None => return Ok(Async::Ready(())),
+            };
+            match item {
+                Ok(Async::Ready(Some(Some(chunk)))) => {
+                    if chunk.len() > self.remaining {
+                        break;
+                    }
+                    self.remaining -= chunk.len();
+                }
+                Ok(Async::Ready(Some(None))) => {
+                    if let Some(framed) = self.framed.take() {
+                        let force_close = !framed.get_codec().keepalive();
+                        let _ = release_connection(framed, force_close);
+                    }
+                    return Ok(Async::Ready(()));
+                }
+                Ok(Async::NotReady) => match self.timeout.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(_)) | Err(_) => break,
+                },
+                // read error, connection is broken
+                Err(_) => break,
+                Ok(Async::Ready(None)) => break,
+            }
+        }
+
+        // too much data left or connection is broken
+        if let Some(framed) = self.framed.take() {
+            framed.into_parts().io.close();
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<Io: ConnectionLifetime> Drop for DrainPayload<Io> {
+    fn drop(&mut self) {
+        // drain is not finished, connection is in unknown state
+        if let Some(framed) = self.framed.take() {
+            framed.into_parts().io.close();
+        }
+    }
+}
+
+/// Release connection to the pool, connection with unread data is closed.
+///
+/// Returns `true` if peer sent data after the end of the response.
+fn release_connection<T, U>(framed: Framed<T, U>, force_close: bool) -> bool
 where
     T: ConnectionLifetime,
 {
     let mut parts = framed.into_parts();
-    if !force_close && parts.read_buf.is_empty() && parts.write_buf.is_empty() {
+    let extra_data = !parts.read_buf.is_empty();
+    if !force_close && !extra_data && parts.write_buf.is_empty() {
         parts.io.release()
     } else {
         parts.io.close()
     }
+    extra_data
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Read;
+
+    use futures::future::lazy;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct TestIo(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Read for TestIo {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl AsyncRead for TestIo {}
+
+    impl io::Write for TestIo {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncWrite for TestIo {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn test_use_after_release() {
+        let io = TestIo::default();
+        let (tx, rx) = oneshot::channel();
+        let mut conn = H1Connection {
+            io: Some(io.clone()),
+            created: time::Instant::now(),
+            bytes: ConnectionBytes::default(),
+            pool: None,
+            handle: Some(tx),
+            reuse: true,
+            evict: EvictReason::NotReusable,
+        };
+        conn.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        // connection is handed over to the next request
+        conn.release();
+        let next = rx.wait().unwrap();
+
+        // released connection can not be used anymore
+        let mut buf = [0; 8];
+        let err = conn.write(b"GET /other HTTP/1.1\r\n\r\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        let err = conn.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        assert_eq!(&io.0.borrow()[..], &b"GET / HTTP/1.1\r\n\r\n"[..]);
+        drop(next);
+    }
+
+    /// Returns one chunk per read, counts reads
+    #[derive(Clone, Default)]
+    struct ChunkIo {
+        chunks: Rc<RefCell<Vec<&'static [u8]>>>,
+        reads: Rc<RefCell<usize>>,
+    }
+
+    impl io::Read for ChunkIo {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            *self.reads.borrow_mut() += 1;
+            if self.chunks.borrow().is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let chunk = self.chunks.borrow_mut().remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    impl AsyncRead for ChunkIo {}
+
+    impl io::Write for ChunkIo {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncWrite for ChunkIo {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn payload(
+        io: ChunkIo,
+        read_ahead: bool,
+        read: Option<time::Duration>,
+    ) -> PlStream<H1Connection<ChunkIo>> {
+        let conn = H1Connection {
+            io: Some(io),
+            created: time::Instant::now(),
+            bytes: ConnectionBytes::default(),
+            pool: None,
+            handle: None,
+            reuse: true,
+            evict: EvictReason::NotReusable,
+        };
+        let mut framed = Framed::new(conn, h1::ClientCodec::default());
+        match framed.poll() {
+            Ok(Async::Ready(Some(head))) => assert_eq!(head.status, StatusCode::OK),
+            _ => panic!(),
+        }
+
+        let config = ConnectorConfig {
+            response_read_ahead: read_ahead,
+            ..ConnectorConfig::default()
+        };
+        let timeouts = ReadTimeouts { idle: None, read };
+        PlStream::new(framed, Some(Rc::new(config)), timeouts)
+    }
+
+    #[test]
+    fn test_response_read_ahead() {
+        let chunks: Vec<&'static [u8]> = vec![
+            &b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\n"[..],
+            &b"hello"[..],
+            &b"world"[..],
+        ];
+
+        // data is read on demand
+        let io = ChunkIo::default();
+        *io.chunks.borrow_mut() = chunks.clone();
+        let mut pl = payload(io.clone(), false, None);
+        let reads = *io.reads.borrow();
+        assert_eq!(pl.poll().unwrap(), Async::Ready(Some(Bytes::from("hello"))));
+        assert_eq!(*io.reads.borrow(), reads + 1);
+        assert_eq!(pl.poll().unwrap(), Async::Ready(Some(Bytes::from("world"))));
+        assert_eq!(*io.reads.borrow(), reads + 2);
+
+        // next chunk is read while current one is handled
+        let io = ChunkIo::default();
+        *io.chunks.borrow_mut() = chunks;
+        let mut pl = payload(io.clone(), true, None);
+        assert_eq!(pl.poll().unwrap(), Async::Ready(Some(Bytes::from("hello"))));
+        let reads = *io.reads.borrow();
+        assert_eq!(pl.poll().unwrap(), Async::Ready(Some(Bytes::from("world"))));
+        assert_eq!(*io.reads.borrow(), reads);
+        assert_eq!(pl.poll().unwrap(), Async::Ready(None));
+        assert_eq!(*io.reads.borrow(), reads);
+    }
+
+    #[test]
+    fn test_poll_after_timeout() {
+        let mut rt = actix_rt::Runtime::new().unwrap();
+        let io = ChunkIo::default();
+        *io.chunks.borrow_mut() =
+            vec![&b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\n"[..]];
+        let timeout = time::Duration::from_millis(10);
+        let mut pl = payload(io, false, Some(timeout));
+
+        assert_eq!(rt.block_on(lazy(|| pl.poll())).unwrap(), Async::NotReady);
+        rt.block_on(tokio_timer::sleep(timeout * 2)).unwrap();
+        for _ in 0..2 {
+            match rt.block_on(lazy(|| pl.poll())) {
+                Err(PayloadError::Io(ref e)) => {
+                    assert_eq!(e.kind(), io::ErrorKind::TimedOut)
+                }
+                res => panic!("{:?}", res),
+            }
+        }
+    }
 }
@@ -1,28 +1,36 @@
-use std::time;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::{cmp, io, time};
 
 use actix_codec::{AsyncRead, AsyncWrite};
 use bytes::Bytes;
 use futures::future::{err, Either};
 use futures::{Async, Future, Poll};
-use h2::{client::SendRequest, SendStream};
+use h2::client::{Connection, SendRequest};
+use h2::{Ping, PingPong, SendStream};
 use http::header::{HeaderValue, CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING};
 use http::{request::Request, HttpTryFrom, Method, Version};
+use tokio_timer::Delay;
 
 use crate::body::{BodySize, MessageBody};
 use crate::message::{RequestHeadType, ResponseHead};
 use crate::payload::Payload;
 use crate::header::HeaderMap;
 
+use super::config::ConnectorConfig;
 use super::connection::{ConnectionType, IoConnection};
 use super::error::SendRequestError;
-use super::pool::Acquired;
+use super::pool::{Acquired, EvictReason};
+use super::{CaptureSentHeaders, DecompressLimit, RequestBytesSent, SentHeaders};
 
 pub(crate) fn send_request<T, B>(
     io: SendRequest<Bytes>,
+    preface: Preface,
     head: RequestHeadType,
     body: B,
     created: time::Instant,
     pool: Option<Acquired<T>>,
+    fail_fast: bool,
 ) -> impl Future<Item = (ResponseHead, Payload), Error = SendRequestError>
 where
     T: AsyncRead + AsyncWrite + 'static,
@@ -30,12 +38,19 @@ where
 {
     trace!("Sending client request: {:?} {:?}", head, body.size());
     let head_req = head.as_ref().method == Method::HEAD;
+    let capture_headers = head.as_ref().extensions().contains::<CaptureSentHeaders>();
+    let config = pool.as_ref().and_then(|pool| pool.config());
+    let decompress_limit = config
+        .as_ref()
+        .map(|config| DecompressLimit(config.max_decompressed_size));
+    let after_receive = config.and_then(|config| config.after_receive.clone());
     let length = body.size();
     let eof = match length {
         BodySize::None | BodySize::Empty | BodySize::Sized(0) => true,
         _ => false,
     };
 
+    let preface2 = preface.clone();
     io.ready()
         .map_err(SendRequestError::from)
         .and_then(move |mut io| {
@@ -91,9 +106,25 @@ where
                 req.headers_mut().append(key, value.clone());
             }
 
+            let sent_headers = if capture_headers {
+                Some(SentHeaders(req.headers().clone().into()))
+            } else {
+                None
+            };
             match io.send_request(req, eof) {
                 Ok((res, send)) => {
-                    release(io, pool, created, false);
+                    // stream is queued if connection is at max concurrent streams
+                    if fail_fast {
+                        if let Ok(Async::NotReady) = io.poll_ready() {
+                            // queued stream is cancelled with its handle,
+                            // connection itself is still usable
+                            release(io.clone(), preface, pool, created, false);
+                            return Either::A(Either::A(err(
+                                SendRequestError::StreamCapacity,
+                            )));
+                        }
+                    }
+                    release(io, preface, pool, created, false);
 
                     if !eof {
                         Either::A(Either::B(
@@ -101,39 +132,63 @@ where
                                 body,
                                 send,
                                 buf: None,
+                                sent: 0,
                             }
-                            .and_then(move |_| res.map_err(SendRequestError::from)),
+                            .and_then(move |sent| {
+                                res.map_err(SendRequestError::from)
+                                    .map(move |resp| (resp, sent, sent_headers))
+                            }),
                         ))
                     } else {
-                        Either::B(res.map_err(SendRequestError::from))
+                        Either::B(
+                            res.map_err(SendRequestError::from)
+                                .map(move |resp| (resp, 0, sent_headers)),
+                        )
                     }
                 }
                 Err(e) => {
-                    release(io, pool, created, e.is_io());
+                    release(io, preface, pool, created, e.is_io());
                     Either::A(Either::A(err(e.into())))
                 }
             }
         })
-        .and_then(move |resp| {
+        .and_then(move |(resp, sent, sent_headers)| {
             let (parts, body) = resp.into_parts();
             let payload = if head_req { Payload::None } else { body.into() };
 
             let mut head = ResponseHead::new(parts.status);
             head.version = parts.version;
             head.headers = parts.headers.into();
+            if let Some(after_receive) = after_receive {
+                after_receive(&mut head);
+            }
+            head.extensions_mut().insert(RequestBytesSent(sent));
+            if let Some(headers) = sent_headers {
+                head.extensions_mut().insert(headers);
+            }
+            if let Some(limit) = decompress_limit {
+                head.extensions_mut().insert(limit);
+            }
             Ok((head, payload))
         })
-        .from_err()
+        .map_err(move |e| {
+            if preface2.failed() {
+                SendRequestError::H2Preface
+            } else {
+                e
+            }
+        })
 }
 
 struct SendBody<B: MessageBody> {
     body: B,
     send: SendStream<Bytes>,
     buf: Option<Bytes>,
+    sent: u64,
 }
 
 impl<B: MessageBody> Future for SendBody<B> {
-    type Item = ();
+    type Item = u64;
     type Error = SendRequestError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -149,7 +204,7 @@ impl<B: MessageBody> Future for SendBody<B> {
                             return Err(e.into());
                         }
                         self.send.reserve_capacity(0);
-                        return Ok(Async::Ready(()));
+                        return Ok(Async::Ready(self.sent));
                     }
                     Ok(Async::NotReady) => return Ok(Async::NotReady),
                     Err(e) => return Err(e.into()),
@@ -158,11 +213,12 @@ impl<B: MessageBody> Future for SendBody<B> {
 
             match self.send.poll_capacity() {
                 Ok(Async::NotReady) => return Ok(Async::NotReady),
-                Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(self.sent)),
                 Ok(Async::Ready(Some(cap))) => {
                     let mut buf = self.buf.take().unwrap();
                     let len = buf.len();
                     let bytes = buf.split_to(std::cmp::min(cap, len));
+                    self.sent += bytes.len() as u64;
 
                     if let Err(e) = self.send.send_data(bytes, false) {
                         return Err(e.into());
@@ -183,15 +239,299 @@ impl<B: MessageBody> Future for SendBody<B> {
 // release SendRequest object
 fn release<T: AsyncRead + AsyncWrite + 'static>(
     io: SendRequest<Bytes>,
+    preface: Preface,
     pool: Option<Acquired<T>>,
     created: time::Instant,
     close: bool,
 ) {
     if let Some(mut pool) = pool {
-        if close {
-            pool.close(IoConnection::new(ConnectionType::H2(io), created, None));
+        // do not reuse http/2 connection after max age, existing streams
+        // complete and connection gets closed once last stream is done
+        let expired = pool
+            .config()
+            .and_then(|config| config.h2_max_connection_age)
+            .map(|age| created.elapsed() > age)
+            .unwrap_or(false);
+        let failed = preface.failed();
+        let io = ConnectionType::H2(io, preface);
+        if expired {
+            pool.close(IoConnection::new(io, created, None), EvictReason::Lifetime);
+        } else if close || failed {
+            pool.close(IoConnection::new(io, created, None), EvictReason::NotReusable);
+        } else {
+            pool.release(IoConnection::new(io, created, None));
+        }
+    }
+}
+
+/// Drives http/2 connection, sends keep-alive pings if configured
+pub(crate) struct H2Connection<T> {
+    connection: Connection<T, Bytes>,
+    keepalive: Option<KeepAlive>,
+}
+
+impl<T: AsyncRead + AsyncWrite> H2Connection<T> {
+    pub(crate) fn new(
+        mut connection: Connection<T, Bytes>,
+        config: Option<Rc<ConnectorConfig>>,
+    ) -> Self {
+        let keepalive = config.and_then(|config| {
+            let interval = config.h2_keepalive_interval?;
+            let ping_pong = connection.ping_pong()?;
+            Some(KeepAlive {
+                ping_pong,
+                interval,
+                timeout: config.h2_keepalive_timeout,
+                delay: Delay::new(time::Instant::now() + interval),
+                sent: false,
+            })
+        });
+        H2Connection {
+            connection,
+            keepalive,
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Future for H2Connection<T> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut keepalive) = self.keepalive {
+            // dropping connection closes the socket
+            if !keepalive.poll() {
+                trace!("Http/2 keep-alive ping failed, closing connection");
+                return Ok(Async::Ready(()));
+            }
+        }
+        self.connection.poll().map_err(|_| ())
+    }
+}
+
+struct KeepAlive {
+    ping_pong: PingPong,
+    interval: time::Duration,
+    timeout: time::Duration,
+    delay: Delay,
+    sent: bool,
+}
+
+impl KeepAlive {
+    /// Send ping when interval elapses, returns `false` if ping fails
+    fn poll(&mut self) -> bool {
+        loop {
+            if self.sent {
+                match self.ping_pong.poll_pong() {
+                    Ok(Async::Ready(_)) => {
+                        self.sent = false;
+                        self.delay.reset(time::Instant::now() + self.interval);
+                    }
+                    Ok(Async::NotReady) => {
+                        return match self.delay.poll() {
+                            Ok(Async::NotReady) => true,
+                            _ => false,
+                        };
+                    }
+                    Err(_) => return false,
+                }
+            } else {
+                match self.delay.poll() {
+                    Ok(Async::NotReady) => return true,
+                    _ => {
+                        if self.ping_pong.send_ping(Ping::opaque()).is_err() {
+                            return false;
+                        }
+                        self.sent = true;
+                        self.delay.reset(time::Instant::now() + self.timeout);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Server connection preface state, shared by handles of http/2 connection
+#[derive(Clone, Default)]
+pub(crate) struct Preface(Rc<Cell<Option<bool>>>);
+
+impl Preface {
+    /// Server did not send valid connection preface
+    pub(crate) fn failed(&self) -> bool {
+        self.0.get() == Some(false)
+    }
+}
+
+/// Io wrapper that verifies server connection preface.
+///
+/// Server preface must start with `SETTINGS` frame, reading fails if
+/// first frame header is not a `SETTINGS` frame header or if connection
+/// is closed before complete frame header is received.
+pub(crate) struct PrefaceIo<T> {
+    io: T,
+    preface: Preface,
+    header: [u8; 9],
+    received: usize,
+}
+
+impl<T> PrefaceIo<T> {
+    pub(crate) fn new(io: T) -> (Self, Preface) {
+        let preface = Preface::default();
+        let io = PrefaceIo {
+            io,
+            preface: preface.clone(),
+            header: [0; 9],
+            received: 0,
+        };
+        (io, preface)
+    }
+
+    fn check(&mut self, data: &[u8]) -> io::Result<()> {
+        let len = cmp::min(self.header.len() - self.received, data.len());
+        self.header[self.received..self.received + len].copy_from_slice(&data[..len]);
+        self.received += len;
+        if self.received < self.header.len() {
+            return Ok(());
+        }
+
+        // SETTINGS frame of connection stream without ACK flag
+        let h = &self.header;
+        let size = (h[0] as usize) << 16 | (h[1] as usize) << 8 | h[2] as usize;
+        let valid = h[3] == 0x4
+            && h[4] & 0x1 == 0
+            && h[5] & 0x7f == 0
+            && h[6..] == [0, 0, 0]
+            && size % 6 == 0;
+        self.preface.0.set(Some(valid));
+        if valid {
+            Ok(())
         } else {
-            pool.release(IoConnection::new(ConnectionType::H2(io), created, None));
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid http/2 server connection preface",
+            ))
+        }
+    }
+}
+
+impl<T: io::Read> io::Read for PrefaceIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.preface.0.get().is_some() {
+            return self.io.read(buf);
+        }
+        match self.io.read(buf) {
+            Ok(0) => {
+                self.preface.0.set(Some(false));
+                Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed before http/2 server connection preface",
+                ))
+            }
+            Ok(n) => self.check(&buf[..n]).map(|_| n),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::WouldBlock {
+                    self.preface.0.set(Some(false));
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for PrefaceIo<T> {}
+
+impl<T: io::Write> io::Write for PrefaceIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for PrefaceIo<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use h2::client::handshake;
+    use tokio_tcp::TcpStream;
+
+    use super::*;
+
+    #[test]
+    fn test_h2_keepalive() {
+        // server reads client frames and never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = Vec::new();
+            let _ = stream.read_to_end(&mut buf);
+            buf
+        });
+
+        let config = ConnectorConfig {
+            h2_keepalive_interval: Some(Duration::from_millis(50)),
+            h2_keepalive_timeout: Duration::from_millis(100),
+            ..ConnectorConfig::default()
+        };
+        let mut sys = actix_rt::System::new("test");
+        let res = sys.block_on(futures::future::lazy(move || {
+            TcpStream::connect(&addr)
+                .map_err(|_| ())
+                .and_then(|io| handshake(io).map_err(|_| ()))
+                .and_then(move |(snd, connection)| {
+                    // resolves once ping is not acknowledged in time
+                    H2Connection::new(connection, Some(Rc::new(config)))
+                        .map(move |_| drop(snd))
+                })
+        }));
+        assert!(res.is_ok());
+
+        // connection got closed, client preface is followed by single ping
+        let buf = server.join().unwrap();
+        let mut frames = &buf[24..];
+        let mut pings = 0;
+        while frames.len() >= 9 {
+            let size = (frames[0] as usize) << 16
+                | (frames[1] as usize) << 8
+                | frames[2] as usize;
+            if frames[3] == 0x6 && frames[4] & 0x1 == 0 {
+                pings += 1;
+            }
+            frames = &frames[cmp::min(9 + size, frames.len())..];
         }
+        assert_eq!(pings, 1);
+    }
+
+    #[test]
+    fn test_preface_eof() {
+        // connection is closed after partial frame header
+        let data: &[u8] = &[0, 0, 0, 0x4];
+        let (mut io, preface) = PrefaceIo::new(data);
+        let mut buf = [0; 16];
+        assert_eq!(io.read(&mut buf).unwrap(), 4);
+        assert!(!preface.failed());
+
+        let err = io.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(preface.failed());
+
+        // complete settings frame header
+        let data: &[u8] = &[0, 0, 0, 0x4, 0, 0, 0, 0, 0];
+        let (mut io, preface) = PrefaceIo::new(data);
+        assert_eq!(io.read(&mut buf).unwrap(), 9);
+        assert_eq!(io.read(&mut buf).unwrap(), 0);
+        assert!(!preface.failed());
     }
 }
@@ -1,6 +1,12 @@
 //! Http client api
+use std::time::{Duration, Instant};
+
 use http::Uri;
 
+use crate::header::HeaderMap;
+
+mod bind;
+mod config;
 mod connection;
 mod connector;
 mod error;
@@ -8,13 +14,191 @@ mod h1proto;
 mod h2proto;
 mod pool;
 
-pub use self::connection::Connection;
-pub use self::connector::Connector;
-pub use self::error::{ConnectError, InvalidUrl, SendRequestError, FreezeRequestError};
-pub use self::pool::Protocol;
+pub use self::connection::{
+    Connection, ConnectionBytes, ConnectionHandle, HandleConnection, IoConnection,
+};
+pub use self::connector::{Connector, DnsQuery};
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+pub use self::connector::{TlsConfig, TlsConfigHandle};
+pub use self::error::{
+    ConnectError, FreezeRequestError, InvalidUrl, ProxyError, SendRequestError,
+};
+pub use self::pool::{
+    CircuitState, ConnectAttempt, EvictReason, PoolHandle, PoolObserver, PoolStats,
+    Protocol, QueuePolicy, TunnelPermit,
+};
+
+/// Number of request body bytes sent to the peer.
+///
+/// Stored in response head extensions. Transfer encoding
+/// framing is not included, only body bytes are counted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestBytesSent(pub u64);
+
+/// Request headers as they were sent to the peer.
+///
+/// Stored in response head extensions if request is marked with
+/// `CaptureSentHeaders`, includes headers added by the client,
+/// i.e. `Host` and `Content-Length`.
+#[derive(Debug, Clone)]
+pub struct SentHeaders(pub HeaderMap);
+
+/// Enables `SentHeaders` response extension.
+///
+/// Stored in request head extensions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaptureSentHeaders;
+
+/// Max size of decompressed response payload.
+///
+/// Stored in response head extensions of pooled connections, see
+/// `Connector::max_decompressed_size()`. It should be applied by
+/// payload decoder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecompressLimit(pub usize);
+
+/// Max size of http/1 response head for a request.
+///
+/// Stored in request head extensions, overrides connector default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaxResponseHeaderSize(pub usize);
 
+/// Max number of http/1 response headers for a request.
+///
+/// Stored in request head extensions, overrides connector default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaxResponseHeaders(pub usize);
+
+/// Max time to connect to remote host for a request.
+///
+/// Stored in request head extensions, overrides connector default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectTimeout(pub Duration);
+
+/// Marks health check request.
+///
+/// Health check requests could use connections reserved with
+/// `Connector::health_check_reserve()`. Stored in request head extensions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthCheck;
+
+/// Absolute deadline of a request.
+///
+/// Stored in request head extensions. Waiting for connection pool slot
+/// and connecting share the deadline, connect timeout is shortened to
+/// the time left once connection pool slot is acquired.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deadline(pub Instant);
+
+/// Max period without response payload data for a request.
+///
+/// Stored in request head extensions, overrides connector default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadIdleTimeout(pub Duration);
+
+/// Max period of single response payload read for a request.
+///
+/// Stored in request head extensions, overrides connector default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PerReadTimeout(pub Duration);
+
+/// Request body framing for http/1 connections
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferEncoding {
+    /// Send body with `Transfer-Encoding: chunked`, even if body size is known
+    Chunked,
+    /// Send body with `Content-Length`, body size must be known
+    ContentLength,
+}
+
+/// How `Host` header that does not match request uri authority is handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HostConflictPolicy {
+    /// `Host` header is replaced with uri authority
+    Uri,
+    /// `Host` header is sent as is
+    Header,
+    /// Request fails with `SendRequestError::HostConflict`
+    Error,
+}
+
+/// Request priority for connection pool queue.
+///
+/// Used by `QueuePolicy::Priority`, waiting requests with higher
+/// priority get connection first. Also selects pool segment, see
+/// `Connector::priority_limit()`. Stored in request head extensions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(pub u8);
+
+/// Connection group of the request.
+///
+/// Connections of a group are limited across all hosts, see
+/// `Connector::limit_per_group()`. Stored in request head extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionGroup(pub u64);
+
+/// Connect request of the connector service.
+///
+/// Created with `Connect::new()`, connection pool options of the request
+/// are set with builder methods.
 #[derive(Clone)]
 pub struct Connect {
     pub uri: Uri,
     pub addr: Option<std::net::SocketAddr>,
+    priority: Priority,
+    group: Option<ConnectionGroup>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    health_check: bool,
+}
+
+impl Connect {
+    /// Create connect request to the host of `uri`
+    pub fn new(uri: Uri) -> Connect {
+        Connect {
+            uri,
+            addr: None,
+            priority: Priority::default(),
+            group: None,
+            timeout: None,
+            deadline: None,
+            health_check: false,
+        }
+    }
+
+    /// Connect to specified address instead of resolving host name
+    pub fn set_addr(mut self, addr: Option<std::net::SocketAddr>) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    /// Set connection pool priority of the request, see `Priority`
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set connection group of the request, see `ConnectionGroup`
+    pub fn group(mut self, group: Option<ConnectionGroup>) -> Self {
+        self.group = group;
+        self
+    }
+
+    /// Set connect timeout of the request, see `ConnectTimeout`
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set deadline of connection pool wait and connect, see `Deadline`
+    pub fn deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Mark request as health check, see `HealthCheck`
+    pub fn health_check(mut self, health_check: bool) -> Self {
+        self.health_check = health_check;
+        self
+    }
 }
@@ -1,6 +1,7 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
 use std::io;
+use std::net::SocketAddr;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
@@ -8,9 +9,9 @@ use actix_codec::{AsyncRead, AsyncWrite};
 use actix_service::Service;
 use bytes::Bytes;
 use futures::future::{err, ok, Either, FutureResult};
-use futures::task::AtomicTask;
+use futures::task::{self, AtomicTask, Task};
 use futures::unsync::oneshot;
-use futures::{Async, Future, Poll};
+use futures::{try_ready, Async, Future, Poll};
 use h2::client::{handshake, Handshake};
 use hashbrown::HashMap;
 use http::uri::Authority;
@@ -18,25 +19,299 @@ use indexmap::IndexSet;
 use slab::Slab;
 use tokio_timer::{sleep, Delay};
 
-use super::connection::{ConnectionType, IoConnection};
-use super::error::ConnectError;
-use super::Connect;
+use super::config::ConnectorConfig;
+use super::connection::{ConnectionBytes, ConnectionType, IoConnection};
+use super::error::{ConnectError, SendRequestError};
+use super::h2proto::{H2Connection, Preface, PrefaceIo};
+use super::{Connect, ConnectionGroup, Priority};
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 /// Protocol version
 pub enum Protocol {
     Http1,
     Http2,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Order in which requests waiting for a connection are served
+pub enum QueuePolicy {
+    /// Requests are served in order of arrival
+    Fifo,
+    /// Requests with higher `Priority` are served first,
+    /// requests with equal priority are served in order of arrival
+    Priority,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The reason a connection got evicted from the pool
+pub enum EvictReason {
+    /// Connection was idle for longer than keep-alive period
+    IdleTimeout,
+    /// Connection exceeded max lifetime period
+    Lifetime,
+    /// Idle connection got closed by the peer or received unexpected data
+    ServerClose,
+    /// Connection can not be reused after request completion
+    NotReusable,
+    /// Pool already keeps max number of idle connections for the host
+    IdleLimit,
+    /// Connection was opened before pool got drained
+    Drained,
+    /// Connection served max number of requests, see `Connector::pool_max_requests()`
+    MaxRequests,
+    /// Response got rejected by `Connector::should_pool()` callback
+    ErrorStatus,
+}
+
+/// Handle for draining connection pool, waiting for it to become idle
+/// and inspecting its state
+///
+/// Created with `Connector::pool_handle()`.
+#[derive(Clone, Default)]
+pub struct PoolHandle(Rc<RefCell<PoolHandleInner>>);
+
+#[derive(Default)]
+struct PoolHandleInner {
+    drains: Vec<Box<dyn Fn()>>,
+    busy: Vec<Box<dyn Fn() -> bool>>,
+    idle: Vec<Box<dyn Fn() -> Vec<(String, ConnectionBytes)>>>,
+    idle_tasks: Vec<Task>,
+    stats: PoolStats,
+    circuit: Option<Box<dyn Fn(&str) -> CircuitState>>,
+}
+
+/// Connection pool statistics
+///
+/// Returned by `PoolHandle::pool_stats()`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PoolStats {
+    checkout_bounds: Vec<Duration>,
+    checkout_counts: Vec<u64>,
+    idle_connections: Vec<(String, ConnectionBytes)>,
+}
+
+impl PoolStats {
+    /// Upper bounds of connection checkout latency buckets.
+    ///
+    /// Empty unless enabled with `Connector::checkout_histogram()`.
+    pub fn checkout_bounds(&self) -> &[Duration] {
+        &self.checkout_bounds
+    }
+
+    /// Number of connection checkouts in each latency bucket.
+    ///
+    /// Counter `i` holds checkouts that took longer than bound `i - 1`
+    /// and at most bound `i`, extra last counter holds checkouts slower
+    /// than the last bound. Checkout latency is time from request for
+    /// a connection until the connection is handed out, either pooled,
+    /// newly opened or released by another request.
+    pub fn checkout_counts(&self) -> &[u64] {
+        &self.checkout_counts
+    }
+
+    /// Idle pooled connections, authority of the host and number of bytes
+    /// transferred over the connection since it was opened.
+    ///
+    /// Only http/1 connections are accounted, http/2 connections report
+    /// zero bytes.
+    pub fn idle_connections(&self) -> &[(String, ConnectionBytes)] {
+        &self.idle_connections
+    }
+
+    fn record_checkout(&mut self, latency: Duration) {
+        let idx = self
+            .checkout_bounds
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(self.checkout_bounds.len());
+        self.checkout_counts[idx] += 1;
+    }
+}
+
+impl PoolHandle {
+    /// Close connections opened before this call.
+    ///
+    /// Idle connections are closed according to `Connector::drain_rate()`,
+    /// connections that are in use get closed once released. Must be
+    /// called from within the running system.
+    pub fn drain(&self) {
+        for drain in self.0.borrow().drains.iter() {
+            drain()
+        }
+    }
+
+    /// Snapshot of connection pool statistics.
+    pub fn pool_stats(&self) -> PoolStats {
+        let inner = self.0.borrow();
+        let mut stats = inner.stats.clone();
+        stats.idle_connections = inner.idle.iter().flat_map(|idle| idle()).collect();
+        stats
+    }
+
+    /// State of circuit breaker of the host.
+    ///
+    /// `host` is authority of request uri, i.e. `example.com:8080`.
+    /// Circuit is always closed if circuit breaker is disabled or
+    /// connector service is not created yet.
+    pub fn circuit_state(&self, host: &str) -> CircuitState {
+        match self.0.borrow().circuit {
+            Some(ref circuit) => circuit(host),
+            None => CircuitState::Closed,
+        }
+    }
+
+    /// Wait until no connections are checked out of the pool.
+    ///
+    /// Future resolves once every connection acquired from the pool,
+    /// including connections that are being established, is released.
+    pub fn wait_idle(&self) -> impl Future<Item = (), Error = ()> {
+        WaitIdle(self.clone())
+    }
+
+    fn register<F, B, I>(&self, drain: F, busy: B, idle: I)
+    where
+        F: Fn() + 'static,
+        B: Fn() -> bool + 'static,
+        I: Fn() -> Vec<(String, ConnectionBytes)> + 'static,
+    {
+        let mut inner = self.0.borrow_mut();
+        inner.drains.push(Box::new(drain));
+        inner.busy.push(Box::new(busy));
+        inner.idle.push(Box::new(idle));
+    }
+
+    pub(crate) fn register_circuit_breaker<F>(&self, circuit: F)
+    where
+        F: Fn(&str) -> CircuitState + 'static,
+    {
+        self.0.borrow_mut().circuit = Some(Box::new(circuit));
+    }
+
+    /// Start checkout latency histogram, pools of one connector share it
+    fn checkout_histogram(&self, bounds: &[Duration]) {
+        let stats = &mut self.0.borrow_mut().stats;
+        if stats.checkout_bounds.is_empty() {
+            stats.checkout_bounds = bounds.to_vec();
+            stats.checkout_counts = vec![0; bounds.len() + 1];
+        }
+    }
+
+    fn record_checkout(&self, latency: Duration) {
+        self.0.borrow_mut().stats.record_checkout(latency)
+    }
+
+    fn is_idle(&self) -> bool {
+        !self.0.borrow().busy.iter().any(|busy| busy())
+    }
+
+    /// Wake up `wait_idle()` futures
+    fn notify_idle(&self) {
+        for task in self.0.borrow_mut().idle_tasks.drain(..) {
+            task.notify();
+        }
+    }
+}
+
+struct WaitIdle(PoolHandle);
+
+impl Future for WaitIdle {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        if self.0.is_idle() {
+            Ok(Async::Ready(()))
+        } else {
+            (self.0).0.borrow_mut().idle_tasks.push(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Outcome of tcp connect attempt to host address
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectAttempt {
+    /// Connection is established
+    Connected,
+    /// Connect failed, next address of the host is tried if any
+    Failed,
+    /// Connect got abandoned before completion, i.e. by connect timeout
+    Cancelled,
+}
+
+/// State of host circuit breaker, see `Connector::circuit_breaker()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CircuitState {
+    /// Connects to the host are attempted
+    Closed,
+    /// Connects to the host fail with `ConnectError::CircuitOpen` until
+    /// specified instant
+    Open(Instant),
+    /// Cooldown period elapsed, result of single probe connect closes the
+    /// circuit or opens it again, other connects fail with
+    /// `ConnectError::CircuitOpen` while probe is in progress
+    HalfOpen,
+}
+
+/// Connection pool events observer
+///
+/// All methods have default no-op implementations.
+pub trait PoolObserver {
+    /// Connection got evicted from the pool and is going to be closed
+    fn on_evict(&self, _authority: &Authority, _reason: EvictReason) {}
+
+    /// Connection got released after request completion
+    ///
+    /// `reused` is `true` if connection is returned to the pool and
+    /// can be used by next request, otherwise it is going to be closed.
+    fn on_release(&self, _reused: bool, _authority: &Authority, _protocol: Protocol) {}
+
+    /// Connection got closed
+    ///
+    /// Called for every connection closed by the pool, with the time passed
+    /// since connection was opened and total number of bytes transferred
+    /// over it. Http/2 connections report zero bytes.
+    fn on_close(
+        &self,
+        _authority: &Authority,
+        _reason: EvictReason,
+        _lifetime: Duration,
+        _bytes_sent: u64,
+        _bytes_received: u64,
+    ) {
+    }
+
+    /// Tcp connect to host address is finished
+    ///
+    /// Called for every address tried while connecting to the host, with
+    /// time spent on the attempt. Not reported by custom tcp connector,
+    /// see `Connector::connector()`.
+    fn on_connect_attempt(
+        &self,
+        _host: &str,
+        _addr: SocketAddr,
+        _elapsed: Duration,
+        _attempt: ConnectAttempt,
+    ) {
+    }
+}
+
 #[derive(Hash, Eq, PartialEq, Clone, Debug)]
 pub(crate) struct Key {
     authority: Authority,
+    /// Pool segment of requests with reserved connections limit
+    segment: Option<Priority>,
+    /// Connection group with connections limit across hosts
+    group: Option<ConnectionGroup>,
 }
 
 impl From<Authority> for Key {
     fn from(authority: Authority) -> Key {
-        Key { authority }
+        Key {
+            authority,
+            segment: None,
+            group: None,
+        }
     }
 }
 
@@ -53,27 +328,48 @@ where
         + Clone
         + 'static,
 {
-    pub(crate) fn new(
-        connector: T,
-        conn_lifetime: Duration,
-        conn_keep_alive: Duration,
-        disconnect_timeout: Option<Duration>,
-        limit: usize,
-    ) -> Self {
-        ConnectionPool(
-            connector,
-            Rc::new(RefCell::new(Inner {
-                conn_lifetime,
-                conn_keep_alive,
-                disconnect_timeout,
-                limit,
-                acquired: 0,
-                waiters: Slab::new(),
-                waiters_queue: IndexSet::new(),
-                available: HashMap::new(),
-                task: None,
-            })),
-        )
+    pub(crate) fn new(connector: T, config: ConnectorConfig) -> Self {
+        let handle = config.pool_handle.clone();
+        if !config.checkout_buckets.is_empty() {
+            handle.checkout_histogram(&config.checkout_buckets);
+        }
+        let inner = Rc::new(RefCell::new(Inner {
+            config: Rc::new(config),
+            acquired: 0,
+            segments: HashMap::new(),
+            groups: HashMap::new(),
+            tunnels: Rc::new(Cell::new(0)),
+            waiters: Slab::new(),
+            waiters_queue: IndexSet::new(),
+            available: HashMap::new(),
+            drain_before: None,
+            draining: false,
+            task: None,
+        }));
+
+        let weak = Rc::downgrade(&inner);
+        let (weak2, weak3) = (weak.clone(), weak.clone());
+        handle.register(
+            move || {
+                if let Some(inner) = weak.upgrade() {
+                    DrainConnections::start(inner);
+                }
+            },
+            move || {
+                weak2
+                    .upgrade()
+                    .map(|inner| !inner.borrow().is_idle())
+                    .unwrap_or(false)
+            },
+            move || {
+                weak3
+                    .upgrade()
+                    .map(|inner| inner.borrow().idle_connections())
+                    .unwrap_or_default()
+            },
+        );
+
+        ConnectionPool(connector, inner)
     }
 }
 
@@ -97,9 +393,11 @@ where
     type Request = Connect;
     type Response = IoConnection<Io>;
     type Error = ConnectError;
-    type Future = Either<
-        FutureResult<Self::Response, Self::Error>,
-        Either<WaitForConnection<Io>, OpenConnection<T::Future, Io>>,
+    type Future = Checkout<
+        Either<
+            FutureResult<Self::Response, Self::Error>,
+            Either<WaitForConnection<Io>, OpenConnection<T::Future, Io>>,
+        >,
     >;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
@@ -107,21 +405,59 @@ where
     }
 
     fn call(&mut self, req: Connect) -> Self::Future {
+        let start = Instant::now();
+        let handle = {
+            let inner = self.1.as_ref().borrow();
+            if inner.config.checkout_buckets.is_empty() {
+                None
+            } else {
+                Some(inner.config.pool_handle.clone())
+            }
+        };
+        Checkout {
+            fut: self.checkout(req),
+            start,
+            handle,
+        }
+    }
+}
+
+impl<T, Io> ConnectionPool<T, Io>
+where
+    Io: AsyncRead + AsyncWrite + 'static,
+    T: Service<Request = Connect, Response = (Io, Protocol), Error = ConnectError>
+        + Clone
+        + 'static,
+{
+    fn checkout(
+        &mut self,
+        req: Connect,
+    ) -> Either<
+        FutureResult<IoConnection<Io>, ConnectError>,
+        Either<WaitForConnection<Io>, OpenConnection<T::Future, Io>>,
+    > {
         let key = if let Some(authority) = req.uri.authority_part() {
-            authority.clone().into()
+            let mut key: Key = authority.clone().into();
+            let inner = self.1.as_ref().borrow();
+            key.segment = inner.segment(req.priority);
+            if inner.config.limit_per_group > 0 {
+                key.group = req.group;
+            }
+            key
         } else {
             return Either::A(err(ConnectError::Unresolverd));
         };
 
         // acquire connection
-        match self.1.as_ref().borrow_mut().acquire(&key) {
-            Acquire::Acquired(io, created) => {
+        match self.1.as_ref().borrow_mut().acquire(&key, req.health_check) {
+            Acquire::Acquired(io, created, bytes) => {
                 // use existing connection
                 return Either::A(ok(IoConnection::new(
                     io,
                     created,
                     Some(Acquired(key, Some(self.1.clone()))),
-                )));
+                )
+                .with_bytes(bytes)));
             }
             Acquire::Available => {
                 // open new connection
@@ -135,7 +471,9 @@ where
         }
 
         // connection is not available, wait
-        let (rx, token, support) = self.1.as_ref().borrow_mut().wait_for(req);
+        let deadline = req.deadline.map(Delay::new);
+        let (rx, token, support) =
+            self.1.as_ref().borrow_mut().wait_for(key.clone(), req);
 
         // start support future
         if !support {
@@ -150,11 +488,33 @@ where
             rx,
             key,
             token,
+            deadline,
             inner: Some(self.1.clone()),
         }))
     }
 }
 
+/// Records connection checkout latency once connection is handed out
+#[doc(hidden)]
+pub struct Checkout<F> {
+    fut: F,
+    start: Instant,
+    handle: Option<PoolHandle>,
+}
+
+impl<F: Future> Future for Checkout<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let item = try_ready!(self.fut.poll());
+        if let Some(handle) = self.handle.take() {
+            handle.record_checkout(self.start.elapsed());
+        }
+        Ok(Async::Ready(item))
+    }
+}
+
 #[doc(hidden)]
 pub struct WaitForConnection<Io>
 where
@@ -163,6 +523,7 @@ where
     key: Key,
     token: usize,
     rx: oneshot::Receiver<Result<IoConnection<Io>, ConnectError>>,
+    deadline: Option<Delay>,
     inner: Option<Rc<RefCell<Inner<Io>>>>,
 }
 
@@ -195,7 +556,16 @@ where
                     Ok(Async::Ready(conn))
                 }
             },
-            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::NotReady) => {
+                // request deadline passed while waiting for connection
+                if let Some(ref mut delay) = self.deadline {
+                    match delay.poll() {
+                        Ok(Async::NotReady) => (),
+                        _ => return Err(ConnectError::Timeout),
+                    }
+                }
+                Ok(Async::NotReady)
+            }
             Err(_) => {
                 let _ = self.inner.take();
                 Err(ConnectError::Disconnected)
@@ -211,7 +581,7 @@ where
 {
     fut: F,
     key: Key,
-    h2: Option<Handshake<Io, Bytes>>,
+    h2: Option<(Handshake<PrefaceIo<Io>, Bytes>, Preface)>,
     inner: Option<Rc<RefCell<Inner<Io>>>>,
 }
 
@@ -237,7 +607,7 @@ where
     fn drop(&mut self) {
         if let Some(inner) = self.inner.take() {
             let mut inner = inner.as_ref().borrow_mut();
-            inner.release();
+            inner.release(&self.key);
             inner.check_availibility();
         }
     }
@@ -252,12 +622,16 @@ where
     type Error = ConnectError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        if let Some(ref mut h2) = self.h2 {
+        if let Some((ref mut h2, ref preface)) = self.h2 {
             return match h2.poll() {
                 Ok(Async::Ready((snd, connection))) => {
-                    tokio_current_thread::spawn(connection.map_err(|_| ()));
+                    let config = self
+                        .inner
+                        .as_ref()
+                        .map(|inner| inner.as_ref().borrow().config.clone());
+                    tokio_current_thread::spawn(H2Connection::new(connection, config));
                     Ok(Async::Ready(IoConnection::new(
-                        ConnectionType::H2(snd),
+                        ConnectionType::H2(snd, preface.clone()),
                         Instant::now(),
                         Some(Acquired(self.key.clone(), self.inner.take())),
                     )))
@@ -277,7 +651,8 @@ where
                         Some(Acquired(self.key.clone(), self.inner.take())),
                     )))
                 } else {
-                    self.h2 = Some(handshake(io));
+                    let (io, preface) = PrefaceIo::new(io);
+                    self.h2 = Some((handshake(io), preface));
                     self.poll()
                 }
             }
@@ -287,7 +662,7 @@ where
 }
 
 enum Acquire<T> {
-    Acquired(ConnectionType<T>, Instant),
+    Acquired(ConnectionType<T>, Instant, ConnectionBytes),
     Available,
     NotAvailable,
 }
@@ -296,14 +671,15 @@ struct AvailableConnection<Io> {
     io: ConnectionType<Io>,
     used: Instant,
     created: Instant,
+    bytes: ConnectionBytes,
 }
 
 pub(crate) struct Inner<Io> {
-    conn_lifetime: Duration,
-    conn_keep_alive: Duration,
-    disconnect_timeout: Option<Duration>,
-    limit: usize,
+    config: Rc<ConnectorConfig>,
     acquired: usize,
+    segments: HashMap<Priority, usize>,
+    groups: HashMap<ConnectionGroup, usize>,
+    tunnels: Rc<Cell<usize>>,
     available: HashMap<Key, VecDeque<AvailableConnection<Io>>>,
     waiters: Slab<
         Option<(
@@ -312,22 +688,128 @@ pub(crate) struct Inner<Io> {
         )>,
     >,
     waiters_queue: IndexSet<(Key, usize)>,
+    drain_before: Option<Instant>,
+    draining: bool,
     task: Option<AtomicTask>,
 }
 
 impl<Io> Inner<Io> {
-    fn reserve(&mut self) {
-        self.acquired += 1;
+    /// Pool segment of requests with specified priority
+    fn segment(&self, priority: Priority) -> Option<Priority> {
+        self.config
+            .priority_limits
+            .iter()
+            .find(|(p, _)| *p == priority)
+            .map(|_| priority)
+    }
+
+    /// Check connections limit of the pool segment, health checks could
+    /// use reserved connections of the default segment
+    fn has_capacity(&self, key: &Key, health_check: bool) -> bool {
+        let (acquired, limit) = match key.segment {
+            Some(segment) => (
+                self.segments.get(&segment).cloned().unwrap_or(0),
+                self.config
+                    .priority_limits
+                    .iter()
+                    .find(|(p, _)| *p == segment)
+                    .map(|(_, limit)| *limit)
+                    .unwrap_or(0),
+            ),
+            None if health_check && self.config.limit > 0 => (
+                self.acquired,
+                self.config.limit + self.config.health_check_reserve,
+            ),
+            None => (self.acquired, self.config.limit),
+        };
+        if let Some(group) = key.group {
+            let group = self.groups.get(&group).cloned().unwrap_or(0);
+            if group >= self.config.limit_per_group {
+                return false;
+            }
+        }
+        limit == 0 || acquired < limit
+    }
+
+    fn reserve(&mut self, key: &Key) {
+        match key.segment {
+            Some(segment) => *self.segments.entry(segment).or_insert(0) += 1,
+            None => self.acquired += 1,
+        }
+        if let Some(group) = key.group {
+            *self.groups.entry(group).or_insert(0) += 1;
+        }
+    }
+
+    fn release(&mut self, key: &Key) {
+        match key.segment {
+            Some(segment) => {
+                if let Some(acquired) = self.segments.get_mut(&segment) {
+                    *acquired -= 1;
+                }
+            }
+            None => self.acquired -= 1,
+        }
+        if let Some(group) = key.group {
+            if let Some(acquired) = self.groups.get_mut(&group) {
+                *acquired -= 1;
+            }
+        }
+        if self.is_idle() {
+            self.config.pool_handle.notify_idle();
+        }
     }
 
-    fn release(&mut self) {
-        self.acquired -= 1;
+    /// No connections are checked out
+    fn is_idle(&self) -> bool {
+        self.acquired == 0 && self.segments.values().all(|acquired| *acquired == 0)
+    }
+
+    /// Host authority and transferred bytes of available connections
+    fn idle_connections(&self) -> Vec<(String, ConnectionBytes)> {
+        self.available
+            .iter()
+            .flat_map(|(key, conns)| {
+                conns
+                    .iter()
+                    .map(move |conn| (key.authority.to_string(), conn.bytes))
+            })
+            .collect()
     }
 
     fn release_waiter(&mut self, key: &Key, token: usize) {
         self.waiters.remove(token);
         self.waiters_queue.remove(&(key.clone(), token));
     }
+
+    fn evicted(
+        &self,
+        key: &Key,
+        reason: EvictReason,
+        created: Instant,
+        bytes: ConnectionBytes,
+    ) {
+        if let Some(ref observer) = self.config.observer {
+            observer.on_evict(&key.authority, reason);
+            observer.on_close(
+                &key.authority,
+                reason,
+                created.elapsed(),
+                bytes.sent,
+                bytes.received,
+            );
+        }
+    }
+
+    fn released(&self, key: &Key, reused: bool, io: &ConnectionType<Io>) {
+        if let Some(ref observer) = self.config.observer {
+            let protocol = match io {
+                ConnectionType::H1(_) => Protocol::Http1,
+                ConnectionType::H2(..) => Protocol::Http2,
+            };
+            observer.on_release(reused, &key.authority, protocol);
+        }
+    }
 }
 
 impl<Io> Inner<Io>
@@ -337,6 +819,7 @@ where
     /// connection is not available, wait
     fn wait_for(
         &mut self,
+        key: Key,
         connect: Connect,
     ) -> (
         oneshot::Receiver<Result<IoConnection<Io>, ConnectError>>,
@@ -345,7 +828,6 @@ where
     ) {
         let (tx, rx) = oneshot::channel();
 
-        let key: Key = connect.uri.authority_part().unwrap().clone().into();
         let entry = self.waiters.vacant_entry();
         let token = entry.key();
         entry.insert(Some((connect, tx)));
@@ -354,24 +836,78 @@ where
         (rx, token, self.task.is_some())
     }
 
-    fn acquire(&mut self, key: &Key) -> Acquire<Io> {
+    /// Index of next waiter in waiters queue, waiters of pool segments
+    /// without free connections are skipped
+    fn next_waiter(&self) -> Option<usize> {
+        let mut waiters = self
+            .waiters_queue
+            .iter()
+            .enumerate()
+            .filter(|(_, (key, token))| {
+                let health_check = self.waiters[*token]
+                    .as_ref()
+                    .map(|(connect, _)| connect.health_check)
+                    .unwrap_or(false);
+                self.has_capacity(key, health_check)
+            });
+        match self.config.queue_policy {
+            QueuePolicy::Fifo => waiters.next().map(|(idx, _)| idx),
+            QueuePolicy::Priority => {
+                let mut next = None;
+                for (idx, (_, token)) in waiters {
+                    let priority = self.waiters[*token]
+                        .as_ref()
+                        .map(|(connect, _)| connect.priority);
+                    match next {
+                        Some((_, prev)) if priority <= prev => (),
+                        _ => next = Some((idx, priority)),
+                    }
+                }
+                next.map(|(idx, _)| idx)
+            }
+        }
+    }
+
+    fn acquire(&mut self, key: &Key, health_check: bool) -> Acquire<Io> {
         // check limits
-        if self.limit > 0 && self.acquired >= self.limit {
+        if !self.has_capacity(key, health_check) {
             return Acquire::NotAvailable;
         }
 
-        self.reserve();
+        self.reserve(key);
 
         // check if open connection is available
         // cleanup stale connections at the same time
+        let mut evicted = Vec::new();
+        let mut result = Acquire::Available;
         if let Some(ref mut connections) = self.available.get_mut(key) {
             let now = Instant::now();
             while let Some(conn) = connections.pop_back() {
                 // check if it still usable
-                if (now - conn.used) > self.conn_keep_alive
-                    || (now - conn.created) > self.conn_lifetime
+                let (h2_expired, h2_failed) = match conn.io {
+                    ConnectionType::H2(_, ref preface) => (
+                        self.config
+                            .h2_max_connection_age
+                            .map(|age| (now - conn.created) > age)
+                            .unwrap_or(false),
+                        preface.failed(),
+                    ),
+                    ConnectionType::H1(_) => (false, false),
+                };
+                let stale = if h2_failed {
+                    Some(EvictReason::ServerClose)
+                } else if (now - conn.used) > self.config.conn_keep_alive {
+                    Some(EvictReason::IdleTimeout)
+                } else if (now - conn.created) > self.config.conn_lifetime || h2_expired
                 {
-                    if let Some(timeout) = self.disconnect_timeout {
+                    Some(EvictReason::Lifetime)
+                } else {
+                    None
+                };
+
+                if let Some(reason) = stale {
+                    evicted.push((reason, conn.created, conn.bytes));
+                    if let Some(timeout) = self.config.disconnect_timeout {
                         if let ConnectionType::H1(io) = conn.io {
                             tokio_current_thread::spawn(CloseConnection::new(
                                 io, timeout,
@@ -385,7 +921,12 @@ where
                         match s.read(&mut buf) {
                             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
                             Ok(n) if n > 0 => {
-                                if let Some(timeout) = self.disconnect_timeout {
+                                evicted.push((
+                                    EvictReason::ServerClose,
+                                    conn.created,
+                                    conn.bytes,
+                                ));
+                                if let Some(timeout) = self.config.disconnect_timeout {
                                     if let ConnectionType::H1(io) = io {
                                         tokio_current_thread::spawn(
                                             CloseConnection::new(io, timeout),
@@ -394,32 +935,78 @@ where
                                 }
                                 continue;
                             }
-                            Ok(_) | Err(_) => continue,
+                            Ok(_) | Err(_) => {
+                                evicted.push((
+                                    EvictReason::ServerClose,
+                                    conn.created,
+                                    conn.bytes,
+                                ));
+                                continue;
+                            }
                         }
                     }
-                    return Acquire::Acquired(io, conn.created);
+                    result = Acquire::Acquired(io, conn.created, conn.bytes);
+                    break;
                 }
             }
         }
-        Acquire::Available
+
+        for (reason, created, bytes) in evicted {
+            self.evicted(key, reason, created, bytes);
+        }
+        result
     }
 
-    fn release_conn(&mut self, key: &Key, io: ConnectionType<Io>, created: Instant) {
-        self.acquired -= 1;
+    fn release_conn(
+        &mut self,
+        key: &Key,
+        io: ConnectionType<Io>,
+        created: Instant,
+        bytes: ConnectionBytes,
+    ) {
+        if self.drain_before.map(|before| created <= before).unwrap_or(false) {
+            return self.close_conn(key, io, EvictReason::Drained, created, bytes);
+        }
+        if let Some(max) = self.config.max_requests {
+            if bytes.requests >= max {
+                let reason = EvictReason::MaxRequests;
+                return self.close_conn(key, io, reason, created, bytes);
+            }
+        }
+        if let Some(max_idle) = self.config.max_idle_per_host {
+            let idle = self.available.get(key).map(|conns| conns.len()).unwrap_or(0);
+            if idle >= max_idle {
+                return self.close_conn(key, io, EvictReason::IdleLimit, created, bytes);
+            }
+        }
+
+        self.release(key);
+        self.released(key, true, &io);
         self.available
             .entry(key.clone())
             .or_insert_with(VecDeque::new)
             .push_back(AvailableConnection {
                 io,
                 created,
+                bytes,
                 used: Instant::now(),
             });
         self.check_availibility();
     }
 
-    fn release_close(&mut self, io: ConnectionType<Io>) {
-        self.acquired -= 1;
-        if let Some(timeout) = self.disconnect_timeout {
+
+    fn close_conn(
+        &mut self,
+        key: &Key,
+        io: ConnectionType<Io>,
+        reason: EvictReason,
+        created: Instant,
+        bytes: ConnectionBytes,
+    ) {
+        self.release(key);
+        self.released(key, false, &io);
+        self.evicted(key, reason, created, bytes);
+        if let Some(timeout) = self.config.disconnect_timeout {
             if let ConnectionType::H1(io) = io {
                 tokio_current_thread::spawn(CloseConnection::new(io, timeout))
             }
@@ -427,8 +1014,42 @@ where
         self.check_availibility();
     }
 
+    /// Close up to `max` idle connections opened before drain,
+    /// returns true if more such connections left
+    fn drain_idle(&mut self, max: usize) -> bool {
+        let before = match self.drain_before {
+            Some(before) => before,
+            None => return false,
+        };
+
+        let mut drained = Vec::new();
+        for (key, conns) in self.available.iter_mut() {
+            while drained.len() < max {
+                match conns.iter().position(|conn| conn.created <= before) {
+                    Some(idx) => {
+                        drained.push((key.clone(), conns.remove(idx).unwrap()))
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        for (key, conn) in drained {
+            self.evicted(&key, EvictReason::Drained, conn.created, conn.bytes);
+            if let Some(timeout) = self.config.disconnect_timeout {
+                if let ConnectionType::H1(io) = conn.io {
+                    tokio_current_thread::spawn(CloseConnection::new(io, timeout))
+                }
+            }
+        }
+
+        self.available
+            .values()
+            .any(|conns| conns.iter().any(|conn| conn.created <= before))
+    }
+
     fn check_availibility(&self) {
-        if !self.waiters_queue.is_empty() && self.acquired < self.limit {
+        if self.next_waiter().is_some() {
             if let Some(t) = self.task.as_ref() {
                 t.notify()
             }
@@ -471,6 +1092,67 @@ where
     }
 }
 
+/// Closes idle connections opened before drain, with configured rate
+struct DrainConnections<Io> {
+    inner: Rc<RefCell<Inner<Io>>>,
+    delay: Delay,
+}
+
+impl<Io> DrainConnections<Io>
+where
+    Io: AsyncRead + AsyncWrite + 'static,
+{
+    fn start(inner: Rc<RefCell<Inner<Io>>>) {
+        let mut this = inner.borrow_mut();
+        this.drain_before = Some(Instant::now());
+        if this.draining {
+            return;
+        }
+
+        let rate = this.config.drain_rate;
+        match rate {
+            Some((num, interval)) => {
+                if this.drain_idle(num) {
+                    this.draining = true;
+                    drop(this);
+                    tokio_current_thread::spawn(DrainConnections {
+                        inner,
+                        delay: sleep(interval),
+                    });
+                }
+            }
+            None => {
+                this.drain_idle(usize::MAX);
+            }
+        }
+    }
+}
+
+impl<Io> Future for DrainConnections<Io>
+where
+    Io: AsyncRead + AsyncWrite + 'static,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            if let Ok(Async::NotReady) = self.delay.poll() {
+                return Ok(Async::NotReady);
+            }
+
+            let mut inner = self.inner.borrow_mut();
+            let (num, interval) = inner.config.drain_rate.unwrap();
+            if inner.drain_idle(num) {
+                self.delay = sleep(interval);
+            } else {
+                inner.draining = false;
+                return Ok(Async::Ready(()));
+            }
+        }
+    }
+}
+
 struct ConnectorPoolSupport<T, Io>
 where
     Io: AsyncRead + AsyncWrite + 'static,
@@ -493,29 +1175,32 @@ where
         inner.task.as_ref().unwrap().register();
 
         // check waiters
-        loop {
+        while let Some(idx) = inner.next_waiter() {
             let (key, token) = {
-                if let Some((key, token)) = inner.waiters_queue.get_index(0) {
+                if let Some((key, token)) = inner.waiters_queue.get_index(idx) {
                     (key.clone(), *token)
                 } else {
                     break;
                 }
             };
-            if inner.waiters.get(token).unwrap().is_none() {
-                continue;
-            }
+            let health_check = match inner.waiters.get(token).unwrap() {
+                Some((connect, _)) => connect.health_check,
+                None => continue,
+            };
 
-            match inner.acquire(&key) {
+            match inner.acquire(&key, health_check) {
                 Acquire::NotAvailable => break,
-                Acquire::Acquired(io, created) => {
+                Acquire::Acquired(io, created, bytes) => {
                     let tx = inner.waiters.get_mut(token).unwrap().take().unwrap().1;
                     if let Err(conn) = tx.send(Ok(IoConnection::new(
                         io,
                         created,
                         Some(Acquired(key.clone(), Some(self.inner.clone()))),
-                    ))) {
-                        let (io, created) = conn.unwrap().into_inner();
-                        inner.release_conn(&key, io, created);
+                    )
+                    .with_bytes(bytes)))
+                    {
+                        let (io, created, bytes) = conn.unwrap().into_inner();
+                        inner.release_conn(&key, io, created, bytes);
                     }
                 }
                 Acquire::Available => {
@@ -529,7 +1214,7 @@ where
                     );
                 }
             }
-            let _ = inner.waiters_queue.swap_remove_index(0);
+            let _ = inner.waiters_queue.shift_remove_index(idx);
         }
 
         Ok(Async::NotReady)
@@ -542,7 +1227,7 @@ where
 {
     fut: F,
     key: Key,
-    h2: Option<Handshake<Io, Bytes>>,
+    h2: Option<(Handshake<PrefaceIo<Io>, Bytes>, Preface)>,
     rx: Option<oneshot::Sender<Result<IoConnection<Io>, ConnectError>>>,
     inner: Option<Rc<RefCell<Inner<Io>>>>,
 }
@@ -575,7 +1260,7 @@ where
     fn drop(&mut self) {
         if let Some(inner) = self.inner.take() {
             let mut inner = inner.as_ref().borrow_mut();
-            inner.release();
+            inner.release(&self.key);
             inner.check_availibility();
         }
     }
@@ -590,13 +1275,17 @@ where
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        if let Some(ref mut h2) = self.h2 {
+        if let Some((ref mut h2, ref preface)) = self.h2 {
             return match h2.poll() {
                 Ok(Async::Ready((snd, connection))) => {
-                    tokio_current_thread::spawn(connection.map_err(|_| ()));
+                    let config = self
+                        .inner
+                        .as_ref()
+                        .map(|inner| inner.as_ref().borrow().config.clone());
+                    tokio_current_thread::spawn(H2Connection::new(connection, config));
                     let rx = self.rx.take().unwrap();
                     let _ = rx.send(Ok(IoConnection::new(
-                        ConnectionType::H2(snd),
+                        ConnectionType::H2(snd, preface.clone()),
                         Instant::now(),
                         Some(Acquired(self.key.clone(), self.inner.take())),
                     )));
@@ -631,7 +1320,8 @@ where
                     )));
                     Ok(Async::Ready(()))
                 } else {
-                    self.h2 = Some(handshake(io));
+                    let (io, preface) = PrefaceIo::new(io);
+                    self.h2 = Some((handshake(io), preface));
                     self.poll()
                 }
             }
@@ -646,19 +1336,61 @@ impl<T> Acquired<T>
 where
     T: AsyncRead + AsyncWrite + 'static,
 {
-    pub(crate) fn close(&mut self, conn: IoConnection<T>) {
+    pub(crate) fn close(&mut self, conn: IoConnection<T>, reason: EvictReason) {
         if let Some(inner) = self.1.take() {
-            let (io, _) = conn.into_inner();
-            inner.as_ref().borrow_mut().release_close(io);
+            let (io, created, bytes) = conn.into_inner();
+            inner
+                .as_ref()
+                .borrow_mut()
+                .close_conn(&self.0, io, reason, created, bytes);
         }
     }
+    pub(crate) fn config(&self) -> Option<Rc<ConnectorConfig>> {
+        self.1
+            .as_ref()
+            .map(|inner| inner.as_ref().borrow().config.clone())
+    }
+
+    /// Count new tunnel, fails if connector is at max number of tunnels
+    pub(crate) fn tunnel_permit(&self) -> Result<TunnelPermit, SendRequestError> {
+        if let Some(ref inner) = self.1 {
+            let inner = inner.as_ref().borrow();
+            let tunnels = inner.tunnels.clone();
+            if let Some(max) = inner.config.max_tunnels {
+                if tunnels.get() >= max {
+                    return Err(SendRequestError::TunnelLimit);
+                }
+            }
+            tunnels.set(tunnels.get() + 1);
+            Ok(TunnelPermit(Some(tunnels)))
+        } else {
+            Ok(TunnelPermit::default())
+        }
+    }
+
     pub(crate) fn release(&mut self, conn: IoConnection<T>) {
         if let Some(inner) = self.1.take() {
-            let (io, created) = conn.into_inner();
+            let (io, created, bytes) = conn.into_inner();
             inner
                 .as_ref()
                 .borrow_mut()
-                .release_conn(&self.0, io, created);
+                .release_conn(&self.0, io, created, bytes);
+        }
+    }
+}
+
+/// Open tunnel of the connection pool.
+///
+/// Returned by `Connection::open_tunnel()`, tunnel is counted against
+/// `Connector::max_tunnels()` until permit is dropped. Permit should be
+/// kept as long as tunnel io is used.
+#[derive(Debug, Default)]
+pub struct TunnelPermit(Option<Rc<Cell<usize>>>);
+
+impl Drop for TunnelPermit {
+    fn drop(&mut self) {
+        if let Some(ref tunnels) = self.0 {
+            tunnels.set(tunnels.get() - 1);
         }
     }
 }
@@ -666,7 +1398,7 @@ where
 impl<T> Drop for Acquired<T> {
     fn drop(&mut self) {
         if let Some(inner) = self.1.take() {
-            inner.as_ref().borrow_mut().release();
+            inner.as_ref().borrow_mut().release(&self.0);
         }
     }
 }
@@ -1,14 +1,14 @@
 use std::io::{self, Write};
 
-use actix_threadpool::{run, CpuFuture};
+use actix_threadpool::{run, BlockingError, CpuFuture};
 #[cfg(feature = "brotli")]
 use brotli2::write::BrotliDecoder;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 #[cfg(any(feature = "flate2-zlib", feature = "flate2-rust"))]
 use flate2::write::{GzDecoder, ZlibDecoder};
 use futures::{try_ready, Async, Future, Poll, Stream};
 
-use super::Writer;
+use super::{Overflow, Writer};
 use crate::error::PayloadError;
 use crate::http::header::{ContentEncoding, HeaderMap, CONTENT_ENCODING};
 
@@ -18,6 +18,7 @@ pub struct Decoder<S> {
     decoder: Option<ContentDecoder>,
     stream: S,
     eof: bool,
+    unsupported: bool,
     fut: Option<CpuFuture<(Option<Bytes>, ContentDecoder), io::Error>>,
 }
 
@@ -28,44 +29,67 @@ where
     /// Construct a decoder.
     #[inline]
     pub fn new(stream: S, encoding: ContentEncoding) -> Decoder<S> {
-        let decoder = match encoding {
-            #[cfg(feature = "brotli")]
-            ContentEncoding::Br => Some(ContentDecoder::Br(Box::new(
-                BrotliDecoder::new(Writer::new()),
-            ))),
-            #[cfg(any(feature = "flate2-zlib", feature = "flate2-rust"))]
-            ContentEncoding::Deflate => Some(ContentDecoder::Deflate(Box::new(
-                ZlibDecoder::new(Writer::new()),
-            ))),
-            #[cfg(any(feature = "flate2-zlib", feature = "flate2-rust"))]
-            ContentEncoding::Gzip => Some(ContentDecoder::Gzip(Box::new(
-                GzDecoder::new(Writer::new()),
-            ))),
-            _ => None,
-        };
+        Self::with_decoder(stream, ContentDecoder::new(encoding))
+    }
+
+    fn with_decoder(stream: S, decoder: Option<ContentDecoder>) -> Decoder<S> {
         Decoder {
             decoder,
             stream,
             fut: None,
             eof: false,
+            unsupported: false,
+        }
+    }
+
+    /// Set max size of decoded payload.
+    ///
+    /// Decoder fails with `PayloadError::Overflow` once decoded payload
+    /// exceeds the limit, limit is checked while payload is inflated.
+    /// Uncompressed payload is not limited.
+    pub fn max_size(mut self, size: usize) -> Self {
+        if let Some(ref mut decoder) = self.decoder {
+            decoder.set_limit(size);
         }
+        self
     }
 
     /// Construct decoder based on headers.
+    ///
+    /// Multiple encodings of `Content-Encoding` header are decoded in
+    /// reverse order, decoder fails with `PayloadError::EncodingCorrupted`
+    /// if one of them is not supported.
     #[inline]
     pub fn from_headers(stream: S, headers: &HeaderMap) -> Decoder<S> {
         // check content-encoding
-        let encoding = if let Some(enc) = headers.get(&CONTENT_ENCODING) {
-            if let Ok(enc) = enc.to_str() {
-                ContentEncoding::from(enc)
-            } else {
-                ContentEncoding::Identity
+        let value = match headers.get(&CONTENT_ENCODING).map(|enc| enc.to_str()) {
+            Some(Ok(value)) => value,
+            _ => return Self::new(stream, ContentEncoding::Identity),
+        };
+        if !value.contains(',') {
+            return Self::new(stream, ContentEncoding::from(value));
+        }
+
+        let mut decoders = Vec::new();
+        for enc in value.rsplit(',').map(|enc| enc.trim()) {
+            if enc.is_empty() || enc.eq_ignore_ascii_case("identity") {
+                continue;
+            }
+            match ContentDecoder::new(ContentEncoding::from(enc)) {
+                Some(decoder) => decoders.push(decoder),
+                None => {
+                    let mut decoder = Self::new(stream, ContentEncoding::Identity);
+                    decoder.unsupported = true;
+                    return decoder;
+                }
             }
+        }
+        let decoder = if decoders.len() > 1 {
+            Some(ContentDecoder::Chain(decoders))
         } else {
-            ContentEncoding::Identity
+            decoders.pop()
         };
-
-        Self::new(stream, encoding)
+        Self::with_decoder(stream, decoder)
     }
 }
 
@@ -77,9 +101,24 @@ where
     type Error = PayloadError;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.unsupported {
+            return Err(PayloadError::EncodingCorrupted);
+        }
+        self.poll_decode()
+    }
+}
+
+impl<S> Decoder<S>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+{
+    fn poll_decode(&mut self) -> Poll<Option<Bytes>, PayloadError> {
         loop {
             if let Some(ref mut fut) = self.fut {
-                let (chunk, decoder) = try_ready!(fut.poll());
+                let (chunk, decoder) = try_ready!(fut.poll().map_err(|e| match e {
+                    BlockingError::Error(e) => decode_error(e),
+                    e => PayloadError::from(e),
+                }));
                 self.decoder = Some(decoder);
                 self.fut.take();
                 if let Some(chunk) = chunk {
@@ -95,7 +134,7 @@ where
                 Async::Ready(Some(chunk)) => {
                     if let Some(mut decoder) = self.decoder.take() {
                         if chunk.len() < INPLACE {
-                            let chunk = decoder.feed_data(chunk)?;
+                            let chunk = decoder.feed_data(chunk).map_err(decode_error)?;
                             self.decoder = Some(decoder);
                             if let Some(chunk) = chunk {
                                 return Ok(Async::Ready(Some(chunk)));
@@ -114,7 +153,7 @@ where
                 Async::Ready(None) => {
                     self.eof = true;
                     return if let Some(mut decoder) = self.decoder.take() {
-                        Ok(Async::Ready(decoder.feed_eof()?))
+                        Ok(Async::Ready(decoder.feed_eof().map_err(decode_error)?))
                     } else {
                         Ok(Async::Ready(None))
                     };
@@ -126,6 +165,15 @@ where
     }
 }
 
+/// Decoder writer fails with `Overflow` error once size limit is reached
+fn decode_error(err: io::Error) -> PayloadError {
+    if Overflow::is_overflow(&err) {
+        PayloadError::Overflow
+    } else {
+        err.into()
+    }
+}
+
 enum ContentDecoder {
     #[cfg(any(feature = "flate2-zlib", feature = "flate2-rust"))]
     Deflate(Box<ZlibDecoder<Writer>>),
@@ -133,9 +181,49 @@ enum ContentDecoder {
     Gzip(Box<GzDecoder<Writer>>),
     #[cfg(feature = "brotli")]
     Br(Box<BrotliDecoder<Writer>>),
+    /// Decoders of multiple encodings, in order of decoding
+    Chain(Vec<ContentDecoder>),
 }
 
 impl ContentDecoder {
+    fn new(encoding: ContentEncoding) -> Option<ContentDecoder> {
+        match encoding {
+            #[cfg(feature = "brotli")]
+            ContentEncoding::Br => Some(ContentDecoder::Br(Box::new(
+                BrotliDecoder::new(Writer::new()),
+            ))),
+            #[cfg(any(feature = "flate2-zlib", feature = "flate2-rust"))]
+            ContentEncoding::Deflate => Some(ContentDecoder::Deflate(Box::new(
+                ZlibDecoder::new(Writer::new()),
+            ))),
+            #[cfg(any(feature = "flate2-zlib", feature = "flate2-rust"))]
+            ContentEncoding::Gzip => Some(ContentDecoder::Gzip(Box::new(
+                GzDecoder::new(Writer::new()),
+            ))),
+            _ => None,
+        }
+    }
+
+    #[allow(unreachable_patterns)]
+    fn set_limit(&mut self, limit: usize) {
+        match self {
+            #[cfg(feature = "brotli")]
+            ContentDecoder::Br(ref mut decoder) => decoder.get_mut().set_limit(limit),
+            #[cfg(any(feature = "flate2-zlib", feature = "flate2-rust"))]
+            ContentDecoder::Gzip(ref mut decoder) => decoder.get_mut().set_limit(limit),
+            #[cfg(any(feature = "flate2-zlib", feature = "flate2-rust"))]
+            ContentDecoder::Deflate(ref mut decoder) => {
+                decoder.get_mut().set_limit(limit)
+            }
+            ContentDecoder::Chain(ref mut decoders) => {
+                for decoder in decoders.iter_mut() {
+                    decoder.set_limit(limit);
+                }
+            }
+            _ => (),
+        }
+    }
+
     #[allow(unreachable_patterns)]
     fn feed_eof(&mut self) -> io::Result<Option<Bytes>> {
         match self {
@@ -175,6 +263,25 @@ impl ContentDecoder {
                 }
                 Err(e) => Err(e),
             },
+            ContentDecoder::Chain(ref mut decoders) => {
+                // finish each decoder after feeding it rest of previous one
+                let mut rest = None;
+                for decoder in decoders.iter_mut() {
+                    let mut buf = BytesMut::new();
+                    if let Some(data) = rest.take() {
+                        if let Some(b) = decoder.feed_data(data)? {
+                            buf.extend_from_slice(&b);
+                        }
+                    }
+                    if let Some(b) = decoder.feed_eof()? {
+                        buf.extend_from_slice(&b);
+                    }
+                    if !buf.is_empty() {
+                        rest = Some(buf.freeze());
+                    }
+                }
+                Ok(rest)
+            }
             _ => Ok(None),
         }
     }
@@ -221,6 +328,16 @@ impl ContentDecoder {
                 }
                 Err(e) => Err(e),
             },
+            ContentDecoder::Chain(ref mut decoders) => {
+                let mut data = data;
+                for decoder in decoders.iter_mut() {
+                    match decoder.feed_data(data)? {
+                        Some(b) => data = b,
+                        None => return Ok(None),
+                    }
+                }
+                Ok(Some(data))
+            }
             _ => Ok(Some(data)),
         }
     }
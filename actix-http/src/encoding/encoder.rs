@@ -55,7 +55,7 @@ impl<B: MessageBody> Encoder<B> {
 
         if can_encode {
             // Modify response body only if encoder is not None
-            if let Some(enc) = ContentEncoder::encoder(encoding) {
+            if let Some(enc) = ContentEncoder::encoder(encoding, None) {
                 update_head(encoding, head);
                 head.no_chunking(false);
                 return ResponseBody::Body(Encoder {
@@ -73,6 +73,38 @@ impl<B: MessageBody> Encoder<B> {
             encoder: None,
         })
     }
+
+    /// Compress message body with specified encoding and compression level.
+    ///
+    /// Level is `0-9` for gzip and deflate and `0-11` for brotli. Body is
+    /// not modified if encoding is not supported.
+    pub fn body(encoding: ContentEncoding, level: u32, body: B) -> Encoder<B> {
+        Encoder {
+            body: EncoderBody::Stream(body),
+            eof: false,
+            fut: None,
+            encoder: ContentEncoder::encoder(encoding, Some(level)),
+        }
+    }
+
+    /// Check if body gets compressed
+    pub fn is_compressed(&self) -> bool {
+        self.encoder.is_some()
+    }
+}
+
+impl Encoder<Body> {
+    /// Compress bytes with specified encoding and compression level.
+    ///
+    /// Returns `None` if encoding is not supported.
+    pub fn bytes(
+        encoding: ContentEncoding,
+        level: u32,
+        data: &[u8],
+    ) -> Option<Result<Bytes, io::Error>> {
+        let mut encoder = ContentEncoder::encoder(encoding, Some(level))?;
+        Some(encoder.write(data).and_then(|_| encoder.finish()))
+    }
 }
 
 enum EncoderBody<B> {
@@ -177,22 +209,27 @@ enum ContentEncoder {
 }
 
 impl ContentEncoder {
-    fn encoder(encoding: ContentEncoding) -> Option<Self> {
+    fn encoder(encoding: ContentEncoding, level: Option<u32>) -> Option<Self> {
         match encoding {
             #[cfg(any(feature = "flate2-zlib", feature = "flate2-rust"))]
             ContentEncoding::Deflate => Some(ContentEncoder::Deflate(ZlibEncoder::new(
                 Writer::new(),
-                flate2::Compression::fast(),
+                level
+                    .map(|l| flate2::Compression::new(std::cmp::min(l, 9)))
+                    .unwrap_or_else(flate2::Compression::fast),
             ))),
             #[cfg(any(feature = "flate2-zlib", feature = "flate2-rust"))]
             ContentEncoding::Gzip => Some(ContentEncoder::Gzip(GzEncoder::new(
                 Writer::new(),
-                flate2::Compression::fast(),
+                level
+                    .map(|l| flate2::Compression::new(std::cmp::min(l, 9)))
+                    .unwrap_or_else(flate2::Compression::fast),
             ))),
             #[cfg(feature = "brotli")]
-            ContentEncoding::Br => {
-                Some(ContentEncoder::Br(BrotliEncoder::new(Writer::new(), 3)))
-            }
+            ContentEncoding::Br => Some(ContentEncoder::Br(BrotliEncoder::new(
+                Writer::new(),
+                level.map(|l| std::cmp::min(l, 11)).unwrap_or(3),
+            ))),
             _ => None,
         }
     }
@@ -1,5 +1,5 @@
 //! Content-Encoding support
-use std::io;
+use std::{error, fmt, io};
 
 use bytes::{Bytes, BytesMut};
 
@@ -11,21 +11,32 @@ pub use self::encoder::Encoder;
 
 pub(self) struct Writer {
     buf: BytesMut,
+    written: usize,
+    limit: usize,
 }
 
 impl Writer {
     fn new() -> Writer {
         Writer {
             buf: BytesMut::with_capacity(8192),
+            written: 0,
+            limit: usize::MAX,
         }
     }
     fn take(&mut self) -> Bytes {
         self.buf.take().freeze()
     }
+    fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
 }
 
 impl io::Write for Writer {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written = self.written.saturating_add(buf.len());
+        if self.written > self.limit {
+            return Err(io::Error::new(io::ErrorKind::Other, Overflow));
+        }
         self.buf.extend_from_slice(buf);
         Ok(buf.len())
     }
@@ -33,3 +44,21 @@ impl io::Write for Writer {
         Ok(())
     }
 }
+
+/// Writer error, total size of written data exceeds the limit
+#[derive(Debug)]
+struct Overflow;
+
+impl Overflow {
+    fn is_overflow(err: &io::Error) -> bool {
+        err.get_ref().map(|err| err.is::<Overflow>()).unwrap_or(false)
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Payload size limit is reached")
+    }
+}
+
+impl error::Error for Overflow {}
@@ -8,7 +8,7 @@ use bytes::{BufMut, Bytes, BytesMut};
 use http::header::{
     HeaderValue, CONNECTION, CONTENT_LENGTH, DATE, TRANSFER_ENCODING, UPGRADE,
 };
-use http::{Method, Version};
+use http::{Method, StatusCode, Version};
 
 use super::decoder::{PayloadDecoder, PayloadItem, PayloadType};
 use super::{decoder, encoder, reserve_readbuf};
@@ -23,8 +23,10 @@ use crate::header::HeaderMap;
 bitflags! {
     struct Flags: u8 {
         const HEAD              = 0b0000_0001;
+        const HTTP10_KEEPALIVE  = 0b0000_0100;
         const KEEPALIVE_ENABLED = 0b0000_1000;
         const STREAM            = 0b0001_0000;
+        const NO_LAST_CHUNK     = 0b0010_0000;
     }
 }
 
@@ -42,7 +44,8 @@ pub struct ClientPayloadCodec {
 
 struct ClientCodecInner {
     config: ServiceConfig,
-    decoder: decoder::MessageDecoder<ResponseHead>,
+    max_head_size: usize,
+    max_headers: usize,
     payload: Option<PayloadDecoder>,
     version: Version,
     ctype: ConnectionType,
@@ -72,7 +75,8 @@ impl ClientCodec {
         ClientCodec {
             inner: ClientCodecInner {
                 config,
-                decoder: decoder::MessageDecoder::default(),
+                max_head_size: decoder::MAX_BUFFER_SIZE,
+                max_headers: decoder::MAX_HEADERS,
                 payload: None,
                 version: Version::HTTP_11,
                 ctype: ConnectionType::Close,
@@ -84,6 +88,44 @@ impl ClientCodec {
         }
     }
 
+    /// Set max size of response head.
+    ///
+    /// Decoder fails with `ParseError::TooLarge` if response head exceeds
+    /// this size. Default is 128Kb.
+    pub fn set_max_head_size(&mut self, size: usize) {
+        self.inner.max_head_size = size;
+    }
+
+    /// Set max number of response headers.
+    ///
+    /// Decoder fails with `ParseError::TooLarge` if response contains more
+    /// headers. Default is 96.
+    pub fn set_max_headers(&mut self, num: usize) {
+        self.inner.max_headers = num;
+    }
+
+    /// Response is for `HEAD` request.
+    ///
+    /// Set for pre-serialized requests, which are not encoded by the codec.
+    pub(crate) fn set_head_request(&mut self, val: bool) {
+        self.inner.flags.set(Flags::HEAD, val);
+    }
+
+    /// Request keep-alive for http/1.0 requests.
+    ///
+    /// Applies to requests without explicitly set connection type.
+    pub(crate) fn set_http10_keep_alive(&mut self, val: bool) {
+        self.inner.flags.set(Flags::HTTP10_KEEPALIVE, val);
+    }
+
+    /// Treat connection close as the end of chunked payload.
+    ///
+    /// By default decoder fails if server closes connection before
+    /// sending terminal chunk.
+    pub(crate) fn set_tolerate_missing_last_chunk(&mut self, val: bool) {
+        self.inner.flags.set(Flags::NO_LAST_CHUNK, val);
+    }
+
     /// Check if request is upgrade
     pub fn upgrade(&self) -> bool {
         self.inner.ctype == ConnectionType::Upgrade
@@ -130,17 +172,28 @@ impl Decoder for ClientCodec {
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         debug_assert!(!self.inner.payload.is_some(), "Payload decoder is set");
 
-        if let Some((req, payload)) = self.inner.decoder.decode(src)? {
-            if let Some(ctype) = req.ctype() {
+        if let Some((req, payload)) = decoder::decode_response(
+            src,
+            self.inner.max_head_size,
+            self.inner.max_headers,
+        )? {
+            match req.ctype() {
                 // do not use peer's keep-alive
-                self.inner.ctype = if ctype == ConnectionType::KeepAlive {
-                    self.inner.ctype
-                } else {
-                    ctype
-                };
+                Some(ConnectionType::KeepAlive) => (),
+                Some(ctype) => self.inner.ctype = ctype,
+                // http/1.0 server closes connection unless it confirms keep-alive
+                None if req.version < Version::HTTP_11 => {
+                    self.inner.ctype = ConnectionType::Close
+                }
+                None => (),
             }
 
-            if !self.inner.flags.contains(Flags::HEAD) {
+            // responses to HEAD requests and 204/304 responses never have a body
+            let bodyless = self.inner.flags.contains(Flags::HEAD)
+                || req.status == StatusCode::NO_CONTENT
+                || req.status == StatusCode::NOT_MODIFIED;
+
+            if !bodyless {
                 match payload {
                     PayloadType::None => self.inner.payload = None,
                     PayloadType::Payload(pl) => self.inner.payload = Some(pl),
@@ -182,6 +235,41 @@ impl Decoder for ClientPayloadCodec {
             None => None,
         })
     }
+
+    fn decode_eof(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(item) = self.decode(src)? {
+            return Ok(Some(item));
+        }
+        match self.inner.payload {
+            Some(ref pl) if pl.is_chunked() => {
+                if !self.inner.flags.contains(Flags::NO_LAST_CHUNK) {
+                    return Err(PayloadError::Incomplete(Some(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed before terminal chunk",
+                    ))));
+                }
+                // payload ends with connection, it can not be reused
+                self.inner.payload.take();
+                self.inner.ctype = ConnectionType::Close;
+                Ok(Some(None))
+            }
+            Some(ref pl) if pl.is_length() => {
+                Err(PayloadError::Incomplete(Some(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed before end of payload",
+                ))))
+            }
+            _ if src.is_empty() => Ok(None),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "bytes remaining on stream",
+            )
+            .into()),
+        }
+    }
 }
 
 impl Encoder for ClientCodec {
@@ -211,6 +299,13 @@ impl Encoder for ClientCodec {
                     ConnectionType::Upgrade => ConnectionType::Upgrade,
                     ConnectionType::Close => ConnectionType::Close,
                 };
+                if inner.version < Version::HTTP_11
+                    && inner.flags.contains(Flags::HTTP10_KEEPALIVE)
+                    && inner.flags.contains(Flags::KEEPALIVE_ENABLED)
+                    && head.as_ref().ctype().is_none()
+                {
+                    inner.ctype = ConnectionType::KeepAlive;
+                }
 
                 inner.encoder.encode(
                     dst,
@@ -14,8 +14,8 @@ use crate::header::HeaderMap;
 use crate::message::{ConnectionType, ResponseHead};
 use crate::request::Request;
 
-const MAX_BUFFER_SIZE: usize = 131_072;
-const MAX_HEADERS: usize = 96;
+pub(crate) const MAX_BUFFER_SIZE: usize = 131_072;
+pub(crate) const MAX_HEADERS: usize = 96;
 
 /// Incoming messagd decoder
 pub(crate) struct MessageDecoder<T: MessageType>(PhantomData<T>);
@@ -258,59 +258,115 @@ impl MessageType for ResponseHead {
     }
 
     fn decode(src: &mut BytesMut) -> Result<Option<(Self, PayloadType)>, ParseError> {
+        decode_response(src, MAX_BUFFER_SIZE, MAX_HEADERS)
+    }
+}
+
+/// Decode response head, head size and number of headers are limited
+pub(crate) fn decode_response(
+    src: &mut BytesMut,
+    max_size: usize,
+    max_headers: usize,
+) -> Result<Option<(ResponseHead, PayloadType)>, ParseError> {
+    if max_headers <= MAX_HEADERS {
         // Unsafe: we read only this data only after httparse parses headers into.
         // performance bump for pipeline benchmarks.
         let mut headers: [HeaderIndex; MAX_HEADERS] = unsafe { mem::uninitialized() };
+        let mut parsed: [httparse::Header; MAX_HEADERS] = unsafe { mem::uninitialized() };
+        let head = parse_response(
+            src,
+            max_size,
+            &mut parsed[..max_headers],
+            &mut headers[..max_headers],
+        )?;
+        match head {
+            Some(head) => decode_response_with(src, max_size, head, &headers).map(Some),
+            None => Ok(None),
+        }
+    } else {
+        let mut headers = vec![
+            HeaderIndex {
+                name: (0, 0),
+                value: (0, 0)
+            };
+            max_headers
+        ];
+        let mut parsed = vec![httparse::EMPTY_HEADER; max_headers];
+        match parse_response(src, max_size, &mut parsed, &mut headers)? {
+            Some(head) => decode_response_with(src, max_size, head, &headers).map(Some),
+            None => Ok(None),
+        }
+    }
+}
 
-        let (len, ver, status, h_len) = {
-            let mut parsed: [httparse::Header; MAX_HEADERS] =
-                unsafe { mem::uninitialized() };
-
-            let mut res = httparse::Response::new(&mut parsed);
-            match res.parse(src)? {
-                httparse::Status::Complete(len) => {
-                    let version = if res.version.unwrap() == 1 {
-                        Version::HTTP_11
-                    } else {
-                        Version::HTTP_10
-                    };
-                    let status = StatusCode::from_u16(res.code.unwrap())
-                        .map_err(|_| ParseError::Status)?;
-                    HeaderIndex::record(src, res.headers, &mut headers);
-
-                    (len, version, status, res.headers.len())
-                }
-                httparse::Status::Partial => return Ok(None),
+/// Parse response head and record header positions.
+///
+/// Returns head size, version, status and number of headers.
+fn parse_response<'a>(
+    src: &'a [u8],
+    max_size: usize,
+    parsed: &mut [httparse::Header<'a>],
+    headers: &mut [HeaderIndex],
+) -> Result<Option<(usize, Version, StatusCode, usize)>, ParseError> {
+    let mut res = httparse::Response::new(parsed);
+    match res.parse(src)? {
+        httparse::Status::Complete(len) => {
+            if len > max_size {
+                return Err(ParseError::TooLarge);
             }
-        };
-
-        let mut msg = ResponseHead::new(status);
-        msg.version = ver;
+            let version = if res.version.unwrap() == 1 {
+                Version::HTTP_11
+            } else {
+                Version::HTTP_10
+            };
+            let status = StatusCode::from_u16(res.code.unwrap())
+                .map_err(|_| ParseError::Status)?;
+            HeaderIndex::record(src, res.headers, headers);
 
-        // convert headers
-        let length = msg.set_headers(&src.split_to(len).freeze(), &headers[..h_len])?;
+            Ok(Some((len, version, status, res.headers.len())))
+        }
+        httparse::Status::Partial => {
+            if src.len() >= max_size {
+                trace!("Max response head size reached, closing");
+                return Err(ParseError::TooLarge);
+            }
+            Ok(None)
+        }
+    }
+}
 
-        // message payload
-        let decoder = if let PayloadLength::Payload(pl) = length {
-            pl
-        } else if status == StatusCode::SWITCHING_PROTOCOLS {
-            // switching protocol or connect
-            PayloadType::Stream(PayloadDecoder::eof())
-        } else if src.len() >= MAX_BUFFER_SIZE {
-            error!("MAX_BUFFER_SIZE unprocessed data reached, closing");
-            return Err(ParseError::TooLarge);
+fn decode_response_with(
+    src: &mut BytesMut,
+    max_size: usize,
+    (len, ver, status, h_len): (usize, Version, StatusCode, usize),
+    headers: &[HeaderIndex],
+) -> Result<(ResponseHead, PayloadType), ParseError> {
+    let mut msg = ResponseHead::new(status);
+    msg.version = ver;
+
+    // convert headers
+    let length = msg.set_headers(&src.split_to(len).freeze(), &headers[..h_len])?;
+
+    // message payload
+    let decoder = if let PayloadLength::Payload(pl) = length {
+        pl
+    } else if status == StatusCode::SWITCHING_PROTOCOLS {
+        // switching protocol or connect
+        PayloadType::Stream(PayloadDecoder::eof())
+    } else if src.len() >= max_size {
+        error!("Max response head size of unprocessed data reached, closing");
+        return Err(ParseError::TooLarge);
+    } else {
+        // for HTTP/1.0 read to eof and close connection
+        if msg.version == Version::HTTP_10 {
+            msg.set_connection_type(ConnectionType::Close);
+            PayloadType::Payload(PayloadDecoder::eof())
         } else {
-            // for HTTP/1.0 read to eof and close connection
-            if msg.version == Version::HTTP_10 {
-                msg.set_connection_type(ConnectionType::Close);
-                PayloadType::Payload(PayloadDecoder::eof())
-            } else {
-                PayloadType::None
-            }
-        };
+            PayloadType::None
+        }
+    };
 
-        Ok(Some((msg, decoder)))
-    }
+    Ok((msg, decoder))
 }
 
 #[derive(Clone, Copy)]
@@ -369,6 +425,22 @@ impl PayloadDecoder {
     pub fn eof() -> PayloadDecoder {
         PayloadDecoder { kind: Kind::Eof }
     }
+
+    /// Check if decoder reads chunked payload
+    pub(crate) fn is_chunked(&self) -> bool {
+        match self.kind {
+            Kind::Chunked(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Check if decoder reads payload of known length
+    pub(crate) fn is_length(&self) -> bool {
+        match self.kind {
+            Kind::Length(..) => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -817,8 +889,8 @@ mod tests {
             .get_all(SET_COOKIE)
             .map(|v| v.to_str().unwrap().to_owned())
             .collect();
-        assert_eq!(val[1], "c1=cookie1");
-        assert_eq!(val[0], "c2=cookie2");
+        assert_eq!(val[0], "c1=cookie1");
+        assert_eq!(val[1], "c2=cookie2");
     }
 
     #[test]
@@ -1209,4 +1281,18 @@ mod tests {
         let chunk = pl.decode(&mut buf).unwrap().unwrap();
         assert_eq!(chunk, PayloadItem::Chunk(Bytes::from_static(b"test data")));
     }
+
+    #[test]
+    fn test_response_unprocessed_data_max_size() {
+        let data = "HTTP/1.1 200 Ok\r\n\r\n0123456789abcdefghijklmnopq";
+
+        let mut buf = BytesMut::from(data);
+        assert!(decode_response(&mut buf, 64, MAX_HEADERS).unwrap().is_some());
+
+        let mut buf = BytesMut::from(data);
+        match decode_response(&mut buf, 24, MAX_HEADERS) {
+            Err(ParseError::TooLarge) => (),
+            _ => panic!("error"),
+        }
+    }
 }
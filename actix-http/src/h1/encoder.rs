@@ -1,4 +1,5 @@
 #![allow(unused_imports, unused_variables, dead_code)]
+use std::borrow::Cow;
 use std::fmt::Write as FmtWrite;
 use std::io::Write;
 use std::marker::PhantomData;
@@ -7,21 +8,36 @@ use std::{cmp, fmt, io, mem};
 use std::rc::Rc;
 
 use bytes::{BufMut, Bytes, BytesMut};
+use percent_encoding::{percent_encode, AsciiSet, CONTROLS};
 
 use crate::body::BodySize;
 use crate::config::ServiceConfig;
 use crate::header::{map, ContentEncoding};
 use crate::helpers;
 use crate::http::header::{
-    HeaderValue, ACCEPT_ENCODING, CONNECTION, CONTENT_LENGTH, DATE, TRANSFER_ENCODING,
+    HeaderValue, ACCEPT_ENCODING, CONNECTION, CONTENT_LENGTH, DATE, EXPECT, TE,
+    TRANSFER_ENCODING,
 };
-use crate::http::{HeaderMap, Method, StatusCode, Version};
+use crate::http::{HeaderMap, Method, StatusCode, Uri, Version};
 use crate::message::{ConnectionType, Head, RequestHead, ResponseHead, RequestHeadType};
 use crate::request::Request;
 use crate::response::Response;
 
 const AVERAGE_HEADER_SIZE: usize = 30;
 
+/// Characters that are encoded in request path
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
 #[derive(Debug)]
 pub(crate) struct MessageEncoder<T: MessageType> {
     pub length: BodySize,
@@ -50,6 +66,14 @@ pub(crate) trait MessageType: Sized {
         false
     }
 
+    fn accept_trailers(&self) -> bool {
+        false
+    }
+
+    fn skip_expect(&self) -> bool {
+        false
+    }
+
     fn chunked(&self) -> bool;
 
     fn encode_status(&mut self, dst: &mut BytesMut) -> io::Result<()>;
@@ -65,6 +89,7 @@ pub(crate) trait MessageType: Sized {
         let chunked = self.chunked();
         let mut skip_len = length != BodySize::Stream;
         let camel_case = self.camel_case();
+        let skip_expect = self.skip_expect();
 
         // Content length
         if let Some(status) = self.status() {
@@ -112,23 +137,47 @@ pub(crate) trait MessageType: Sized {
         }
 
         // Connection
-        match ctype {
-            ConnectionType::Upgrade => dst.put_slice(b"connection: upgrade\r\n"),
+        let has_connection = match ctype {
+            ConnectionType::Upgrade => {
+                dst.put_slice(b"connection: upgrade");
+                true
+            }
             ConnectionType::KeepAlive if version < Version::HTTP_11 => {
                 if camel_case {
-                    dst.put_slice(b"Connection: keep-alive\r\n")
+                    dst.put_slice(b"Connection: keep-alive")
                 } else {
-                    dst.put_slice(b"connection: keep-alive\r\n")
+                    dst.put_slice(b"connection: keep-alive")
                 }
+                true
             }
             ConnectionType::Close if version >= Version::HTTP_11 => {
                 if camel_case {
-                    dst.put_slice(b"Connection: close\r\n")
+                    dst.put_slice(b"Connection: close")
                 } else {
-                    dst.put_slice(b"connection: close\r\n")
+                    dst.put_slice(b"connection: close")
                 }
+                true
+            }
+            _ => false,
+        };
+
+        // TE is hop-by-hop header, it has to be listed in Connection header
+        let accept_trailers = self.accept_trailers();
+        if accept_trailers {
+            if has_connection {
+                dst.put_slice(b", te\r\n");
+            } else if camel_case {
+                dst.put_slice(b"Connection: te\r\n");
+            } else {
+                dst.put_slice(b"connection: te\r\n");
             }
-            _ => (),
+            if camel_case {
+                dst.put_slice(b"Te: trailers\r\n");
+            } else {
+                dst.put_slice(b"te: trailers\r\n");
+            }
+        } else if has_connection {
+            dst.put_slice(b"\r\n");
         }
 
         // merging headers from head and extra headers. HeaderMap::new() does not allocate.
@@ -148,7 +197,9 @@ pub(crate) trait MessageType: Sized {
         for (key, value) in headers {
             match *key {
                 CONNECTION => continue,
+                TE if accept_trailers => continue,
                 TRANSFER_ENCODING | CONTENT_LENGTH if skip_len => continue,
+                EXPECT if skip_expect => continue,
                 DATE => {
                     has_date = true;
                 }
@@ -276,6 +327,14 @@ impl MessageType for RequestHeadType {
         self.as_ref().camel_case_headers()
     }
 
+    fn accept_trailers(&self) -> bool {
+        self.as_ref().accept_trailers()
+    }
+
+    fn skip_expect(&self) -> bool {
+        self.as_ref().skip_expect()
+    }
+
     fn headers(&self) -> &HeaderMap {
         self.as_ref().headers()
     }
@@ -287,11 +346,16 @@ impl MessageType for RequestHeadType {
     fn encode_status(&mut self, dst: &mut BytesMut) -> io::Result<()> {
         let head = self.as_ref();
         dst.reserve(256 + head.headers.len() * AVERAGE_HEADER_SIZE);
+        let path = if head.encode_path() {
+            Cow::Owned(encode_path(&head.uri))
+        } else {
+            Cow::Borrowed(head.uri.path_and_query().map(|u| u.as_str()).unwrap_or("/"))
+        };
         write!(
             Writer(dst),
             "{} {} {}",
             head.method,
-            head.uri.path_and_query().map(|u| u.as_str()).unwrap_or("/"),
+            path,
             match head.version {
                 Version::HTTP_09 => "HTTP/0.9",
                 Version::HTTP_10 => "HTTP/1.0",
@@ -303,6 +367,45 @@ impl MessageType for RequestHeadType {
     }
 }
 
+/// Normalize percent-encoded path, query is kept as is
+///
+/// Escaped unreserved characters are decoded, other escapes are kept
+/// as they change meaning of the path, i.e. `%2F` is not a separator.
+fn encode_path(uri: &Uri) -> String {
+    let path = uri.path().as_bytes();
+    let mut res = String::with_capacity(path.len());
+    let mut idx = 0;
+    while idx < path.len() {
+        if path[idx] == b'%' {
+            if let Some(ch) = path.get(idx + 1..idx + 3).and_then(hex_byte) {
+                if ch.is_ascii_alphanumeric() || b"-._~".contains(&ch) {
+                    res.push(ch as char);
+                } else {
+                    let _ = write!(res, "%{:02X}", ch);
+                }
+                idx += 3;
+                continue;
+            }
+        }
+        res.extend(percent_encode(&path[idx..=idx], PATH));
+        idx += 1;
+    }
+    if res.is_empty() {
+        res.push('/');
+    }
+    if let Some(query) = uri.query() {
+        res.push('?');
+        res.push_str(query);
+    }
+    res
+}
+
+fn hex_byte(s: &[u8]) -> Option<u8> {
+    let hi = (s[0] as char).to_digit(16)?;
+    let lo = (s[1] as char).to_digit(16)?;
+    Some((hi * 16 + lo) as u8)
+}
+
 impl<T: MessageType> MessageEncoder<T> {
     /// Encode message
     pub fn encode_chunk(&mut self, msg: &[u8], buf: &mut BytesMut) -> io::Result<bool> {
@@ -529,6 +632,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_encode_path() {
+        let uri: Uri = "/a%2Fb%7e/%C3%A9%20c?q=%2F".parse().unwrap();
+        assert_eq!(encode_path(&uri), "/a%2Fb~/%C3%A9%20c?q=%2F");
+
+        let mut bytes = BytesMut::with_capacity(2048);
+        let mut head = RequestHead::default();
+        head.uri = uri;
+        let mut head = RequestHeadType::Owned(head);
+        let _ = head.encode_status(&mut bytes);
+        assert_eq!(
+            bytes.take().freeze(),
+            Bytes::from_static(b"GET /a%2Fb%7e/%C3%A9%20c?q=%2F HTTP/1.1")
+        );
+
+        let uri: Uri = "/%41%2f%zz".parse().unwrap();
+        assert_eq!(encode_path(&uri), "/A%2F%25zz");
+    }
+
+    #[test]
+    fn test_skip_expect() {
+        let mut bytes = BytesMut::with_capacity(2048);
+        let mut head = RequestHead::default();
+        head.headers.insert(DATE, HeaderValue::from_static("date"));
+        head.headers
+            .insert(EXPECT, HeaderValue::from_static("100-continue"));
+        head.set_skip_expect(true);
+
+        let mut head = RequestHeadType::Owned(head);
+        let _ = head.encode_headers(
+            &mut bytes,
+            Version::HTTP_11,
+            BodySize::Sized(5),
+            ConnectionType::KeepAlive,
+            &ServiceConfig::default(),
+        );
+        assert_eq!(
+            bytes.take().freeze(),
+            Bytes::from_static(b"\r\ncontent-length: 5\r\ndate: date\r\n\r\n")
+        );
+    }
+
     #[test]
     fn test_camel_case() {
         let mut bytes = BytesMut::with_capacity(2048);
@@ -595,7 +740,7 @@ mod tests {
         );
         assert_eq!(
             bytes.take().freeze(),
-            Bytes::from_static(b"\r\ntransfer-encoding: chunked\r\ndate: date\r\ncontent-type: xml\r\ncontent-type: plain/text\r\n\r\n")
+            Bytes::from_static(b"\r\ntransfer-encoding: chunked\r\ndate: date\r\ncontent-type: plain/text\r\ncontent-type: xml\r\n\r\n")
         );
     }
 
@@ -624,4 +769,40 @@ mod tests {
             Bytes::from_static(b"\r\ncontent-length: 0\r\nconnection: close\r\nauthorization: another authorization\r\ndate: date\r\n\r\n")
         );
     }
+
+    #[test]
+    fn test_accept_trailers() {
+        let mut bytes = BytesMut::with_capacity(2048);
+
+        let mut head = RequestHead::default();
+        head.set_accept_trailers(true);
+        head.headers.insert(DATE, HeaderValue::from_static("date"));
+        head.headers.insert(TE, HeaderValue::from_static("gzip"));
+
+        let mut head = RequestHeadType::Owned(head);
+
+        let _ = head.encode_headers(
+            &mut bytes,
+            Version::HTTP_11,
+            BodySize::Empty,
+            ConnectionType::KeepAlive,
+            &ServiceConfig::default(),
+        );
+        assert_eq!(
+            bytes.take().freeze(),
+            Bytes::from_static(b"\r\ncontent-length: 0\r\nconnection: te\r\nte: trailers\r\ndate: date\r\n\r\n")
+        );
+
+        let _ = head.encode_headers(
+            &mut bytes,
+            Version::HTTP_11,
+            BodySize::Empty,
+            ConnectionType::Close,
+            &ServiceConfig::default(),
+        );
+        assert_eq!(
+            bytes.take().freeze(),
+            Bytes::from_static(b"\r\ncontent-length: 0\r\nconnection: close, te\r\nte: trailers\r\ndate: date\r\n\r\n")
+        );
+    }
 }
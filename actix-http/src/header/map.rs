@@ -38,9 +38,9 @@ impl Value {
     fn append(&mut self, val: HeaderValue) {
         match self {
             Value::One(_) => {
-                let data = std::mem::replace(self, Value::Multi(vec![val]));
+                let data = std::mem::replace(self, Value::Multi(Vec::new()));
                 match data {
-                    Value::One(val) => self.append(val),
+                    Value::One(first) => *self = Value::Multi(vec![first, val]),
                     Value::Multi(_) => unreachable!(),
                 }
             }
@@ -382,3 +382,21 @@ impl<'a> Iterator for Iter<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header;
+
+    #[test]
+    fn test_append_keeps_insertion_order() {
+        let mut map = HeaderMap::new();
+        map.append(header::ACCEPT, HeaderValue::from_static("a"));
+        map.append(header::ACCEPT, HeaderValue::from_static("b"));
+        map.append(header::ACCEPT, HeaderValue::from_static("c"));
+
+        let values: Vec<_> = map.get_all(header::ACCEPT).collect();
+        assert_eq!(values, vec!["a", "b", "c"]);
+        assert_eq!(map.get(header::ACCEPT).unwrap(), "a");
+    }
+}
@@ -21,13 +21,16 @@ pub enum ConnectionType {
 }
 
 bitflags! {
-    pub(crate) struct Flags: u8 {
-        const CLOSE       = 0b0000_0001;
-        const KEEP_ALIVE  = 0b0000_0010;
-        const UPGRADE     = 0b0000_0100;
-        const EXPECT      = 0b0000_1000;
-        const NO_CHUNKING = 0b0001_0000;
-        const CAMEL_CASE  = 0b0010_0000;
+    pub(crate) struct Flags: u16 {
+        const CLOSE       = 0b0_0000_0001;
+        const KEEP_ALIVE  = 0b0_0000_0010;
+        const UPGRADE     = 0b0_0000_0100;
+        const EXPECT      = 0b0_0000_1000;
+        const NO_CHUNKING = 0b0_0001_0000;
+        const CAMEL_CASE  = 0b0_0010_0000;
+        const TRAILERS    = 0b0_0100_0000;
+        const ENCODE_PATH = 0b0_1000_0000;
+        const SKIP_EXPECT = 0b1_0000_0000;
     }
 }
 
@@ -115,6 +118,43 @@ impl RequestHead {
         }
     }
 
+    /// Is `TE: trailers` header sent with request.
+    /// Default is `false`
+    #[inline]
+    pub fn accept_trailers(&self) -> bool {
+        self.flags.contains(Flags::TRAILERS)
+    }
+
+    /// Set `true` to advertise support of trailers with `TE: trailers` header.
+    #[inline]
+    pub fn set_accept_trailers(&mut self, val: bool) {
+        if val {
+            self.flags.insert(Flags::TRAILERS);
+        } else {
+            self.flags.remove(Flags::TRAILERS);
+        }
+    }
+
+    /// Is request path re-encoded before sending.
+    #[inline]
+    pub fn encode_path(&self) -> bool {
+        self.flags.contains(Flags::ENCODE_PATH)
+    }
+
+    /// Set `true` to normalize percent-encoded request path before sending.
+    ///
+    /// Escaped unreserved characters are decoded, other escapes are kept.
+    ///
+    /// By default path is sent verbatim as it is stored in `Uri`.
+    #[inline]
+    pub fn set_encode_path(&mut self, val: bool) {
+        if val {
+            self.flags.insert(Flags::ENCODE_PATH);
+        } else {
+            self.flags.remove(Flags::ENCODE_PATH);
+        }
+    }
+
     #[inline]
     /// Set connection type of the message
     pub fn set_connection_type(&mut self, ctype: ConnectionType) {
@@ -141,6 +181,20 @@ impl RequestHead {
         }
     }
 
+    #[inline]
+    /// Connection type set explicitly with `set_connection_type()`
+    pub(crate) fn ctype(&self) -> Option<ConnectionType> {
+        if self.flags.contains(Flags::CLOSE) {
+            Some(ConnectionType::Close)
+        } else if self.flags.contains(Flags::KEEP_ALIVE) {
+            Some(ConnectionType::KeepAlive)
+        } else if self.flags.contains(Flags::UPGRADE) {
+            Some(ConnectionType::Upgrade)
+        } else {
+            None
+        }
+    }
+
     /// Connection upgrade status
     pub fn upgrade(&self) -> bool {
         if let Some(hdr) = self.headers().get(header::CONNECTION) {
@@ -179,6 +233,44 @@ impl RequestHead {
     pub(crate) fn set_expect(&mut self) {
         self.flags.insert(Flags::EXPECT);
     }
+
+    #[inline]
+    /// Is `Expect` header skipped while request head is encoded
+    pub(crate) fn skip_expect(&self) -> bool {
+        self.flags.contains(Flags::SKIP_EXPECT)
+    }
+
+    #[inline]
+    pub(crate) fn set_skip_expect(&mut self, val: bool) {
+        if val {
+            self.flags.insert(Flags::SKIP_EXPECT);
+        } else {
+            self.flags.remove(Flags::SKIP_EXPECT);
+        }
+    }
+
+    /// Copy of request head with extra headers merged in, extensions are
+    /// not copied
+    pub(crate) fn duplicate(&self, extra_headers: Option<&HeaderMap>) -> RequestHead {
+        let mut headers = self.headers.clone();
+        if let Some(extra) = extra_headers {
+            for name in extra.keys() {
+                headers.remove(name.clone());
+            }
+            for (name, value) in extra.iter() {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+        RequestHead {
+            uri: self.uri.clone(),
+            method: self.method.clone(),
+            version: self.version,
+            headers,
+            extensions: RefCell::new(Extensions::new()),
+            peer_addr: self.peer_addr,
+            flags: self.flags,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -1,9 +1,32 @@
-use actix_service::NewService;
-use bytes::Bytes;
-use futures::future::{self, ok};
+use std::cell::{Cell, RefCell};
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use actix_http::{http, HttpService, Request, Response};
-use actix_http_test::TestServer;
+use actix_codec::{AsyncRead, AsyncWrite, BytesCodec, Framed};
+use actix_connect::{default_connector, Connection as TcpConnection};
+use actix_server_config::Io;
+use actix_service::{apply_fn, service_fn, NewService, Service};
+use bytes::{Bytes, BytesMut};
+use futures::future::{self, ok, Either, Loop};
+use futures::stream;
+use futures::{Future, IntoFuture, Sink, Stream};
+use tokio_tcp::TcpStream;
+
+use actix_http::client::{
+    CircuitState, Connect, ConnectError, Connection, ConnectionBytes, ConnectionGroup,
+    Connector, EvictReason, HandleConnection, HostConflictPolicy, IoConnection,
+    PoolObserver, Priority, Protocol, QueuePolicy, SendRequestError, TunnelPermit,
+};
+use actix_http::body::{Body, BodyChunk, BodyStream, FlushStream, MessageBody};
+use actix_http::error::PayloadError;
+use actix_http::http::uri::{Authority, Uri};
+use actix_http::{
+    h1, http, HttpMessage, HttpService, Payload, Request, RequestHead, Response,
+    ResponseHead,
+};
+use actix_http_test::{TestServer, TestServerRuntime};
 
 const STR: &str = "Hello World Hello World Hello World Hello World Hello World \
                    Hello World Hello World Hello World Hello World Hello World \
@@ -81,3 +104,1846 @@ fn test_with_query_parameter() {
     let response = srv.block_on(request).unwrap();
     assert!(response.status().is_success());
 }
+
+#[test]
+fn test_connection_from_stream() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|req: Request| {
+            ok::<_, ()>(Response::Ok().body(format!("{:?}", req.version())))
+        })
+    });
+
+    for &(protocol, version) in &[
+        (Protocol::Http1, http::Version::HTTP_11),
+        (Protocol::Http2, http::Version::HTTP_2),
+    ] {
+        let addr = srv.addr();
+        let uri: Uri = srv.url("/").parse().unwrap();
+        let (head, body) = srv
+            .block_on_fn(move || {
+                let mut head = RequestHead::default();
+                head.uri = uri;
+                TcpStream::connect(&addr)
+                    .map_err(ConnectError::from)
+                    .and_then(move |io| IoConnection::from_stream(io, protocol))
+                    .from_err()
+                    .and_then(move |conn| conn.send_request(head, ()))
+                    .and_then(|(head, payload)| {
+                        payload
+                            .concat2()
+                            .map(move |body| (head, body))
+                            .map_err(|e| SendRequestError::Body(e.into()))
+                    })
+            })
+            .unwrap();
+        assert!(head.status.is_success());
+        assert_eq!(head.version, version);
+        assert_eq!(body, Bytes::from(format!("{:?}", version)));
+    }
+}
+
+/// Send request for `path` over connection of `connector`
+///
+/// `request` adjusts request head before it is sent. `response` handles
+/// response head and payload, it also receives byte counters of
+/// the connection before request is sent.
+fn fetch<T, B, Q, F, R>(
+    srv: &mut TestServerRuntime,
+    connector: &T,
+    path: &str,
+    body: B,
+    request: Q,
+    response: F,
+) -> Result<R::Item, SendRequestError>
+where
+    T: Service<Request = Connect, Error = ConnectError> + Clone + 'static,
+    T::Response: Connection,
+    B: MessageBody + 'static,
+    Q: FnOnce(&mut RequestHead),
+    F: FnOnce(ConnectionBytes, ResponseHead, Payload) -> R + 'static,
+    R: IntoFuture<Error = SendRequestError>,
+{
+    let mut connector = connector.clone();
+    let uri: Uri = srv.url(path).parse().unwrap();
+    let mut head = RequestHead::default();
+    head.uri = uri.clone();
+    request(&mut head);
+    srv.block_on_fn(move || {
+        connector
+            .call(Connect::new(uri))
+            .from_err()
+            .and_then(move |conn| {
+                let bytes = conn.bytes();
+                conn.send_request(head, body)
+                    .and_then(move |(head, payload)| response(bytes, head, payload))
+            })
+    })
+}
+
+/// Response handler of `fetch()`, payload is dropped unread
+fn head_only(
+    _: ConnectionBytes,
+    head: ResponseHead,
+    _: Payload,
+) -> Result<ResponseHead, SendRequestError> {
+    Ok(head)
+}
+
+/// Response handler of `fetch()`, payload is read to the end
+fn with_body(
+    _: ConnectionBytes,
+    head: ResponseHead,
+    payload: Payload,
+) -> impl Future<
+    Item = (ResponseHead, Result<Bytes, PayloadError>),
+    Error = SendRequestError,
+> {
+    payload.concat2().then(move |body| Ok((head, body)))
+}
+
+#[derive(Clone, Default)]
+struct Events(Rc<RefCell<Vec<EvictReason>>>);
+
+impl PoolObserver for Events {
+    fn on_evict(&self, _: &Authority, reason: EvictReason) {
+        self.0.borrow_mut().push(reason);
+    }
+}
+
+#[test]
+fn test_pool_evict_idle_timeout() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| ok::<_, ()>(Response::Ok().finish()))
+            .map(|_| ())
+    });
+
+    let events = Events::default();
+    let observer = events.clone();
+    let connector = srv.execute(move || {
+        Connector::new()
+            .conn_keep_alive(Duration::from_millis(50))
+            .pool_observer(observer)
+            .finish()
+    });
+
+    let head = fetch(&mut srv, &connector, "/", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+    assert!(events.0.borrow().is_empty());
+
+    // idle connection expires before next checkout
+    thread::sleep(Duration::from_millis(200));
+    let head = fetch(&mut srv, &connector, "/", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+    assert_eq!(&*events.0.borrow(), &[EvictReason::IdleTimeout]);
+}
+
+#[test]
+fn test_pool_evict_max_requests() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|req: Request| {
+                let port = req.peer_addr().unwrap().port().to_string();
+                ok::<_, ()>(Response::Ok().body(port))
+            })
+            .map(|_| ())
+    });
+
+    let events = Events::default();
+    let observer = events.clone();
+    let connector = srv.execute(move || {
+        Connector::new()
+            .pool_max_requests(2)
+            .pool_observer(observer)
+            .finish()
+    });
+
+    let (_, port1) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    assert!(events.0.borrow().is_empty());
+    let (_, port2) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    assert_eq!(&*events.0.borrow(), &[EvictReason::MaxRequests]);
+
+    // connection is closed after second request, next request dials new one
+    let (_, port3) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    let (port1, port2, port3) = (port1.unwrap(), port2.unwrap(), port3.unwrap());
+    assert_eq!(port1, port2);
+    assert_ne!(port2, port3);
+}
+
+#[derive(Clone, Default)]
+struct Closes(Rc<RefCell<Vec<(EvictReason, Duration, u64, u64)>>>);
+
+impl PoolObserver for Closes {
+    fn on_close(
+        &self,
+        _: &Authority,
+        reason: EvictReason,
+        lifetime: Duration,
+        sent: u64,
+        received: u64,
+    ) {
+        self.0.borrow_mut().push((reason, lifetime, sent, received));
+    }
+}
+
+#[test]
+fn test_pool_close_event() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| ok::<_, ()>(Response::Ok().finish()))
+            .map(|_| ())
+    });
+
+    let closes = Closes::default();
+    let observer = closes.clone();
+    let connector = srv.execute(move || {
+        Connector::new()
+            .conn_keep_alive(Duration::from_millis(50))
+            .pool_observer(observer)
+            .finish()
+    });
+
+    let head = fetch(&mut srv, &connector, "/", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+    assert!(closes.0.borrow().is_empty());
+
+    // idle connection gets closed on next checkout
+    thread::sleep(Duration::from_millis(200));
+    let head = fetch(&mut srv, &connector, "/", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+
+    let closes = closes.0.borrow();
+    assert_eq!(closes.len(), 1);
+    let (reason, lifetime, sent, received) = closes[0];
+    assert_eq!(reason, EvictReason::IdleTimeout);
+    assert!(lifetime >= Duration::from_millis(200));
+    assert!(lifetime < Duration::from_secs(5));
+    assert!(sent > 0);
+    assert!(received > 0);
+}
+
+#[test]
+fn test_pool_stats() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| ok::<_, ()>(Response::Ok().finish()))
+            .map(|_| ())
+    });
+
+    let (handle, connector) = srv.execute(move || {
+        // connects complete after 100 milliseconds
+        let tcp = apply_fn(default_connector(), |req, srv| {
+            let mut srv = srv.clone();
+            tokio_timer::sleep(Duration::from_millis(100)).then(move |_| srv.call(req))
+        });
+        let connector = Connector::new().connector(tcp).checkout_histogram(&[
+            Duration::from_millis(50),
+            Duration::from_millis(20),
+        ]);
+        (connector.pool_handle(), connector.finish())
+    });
+    assert_eq!(
+        handle.pool_stats().checkout_bounds(),
+        &[Duration::from_millis(20), Duration::from_millis(50)]
+    );
+
+    // one cold checkout, then pooled ones
+    for _ in 0..3 {
+        let head = fetch(&mut srv, &connector, "/", (), |_| (), head_only).unwrap();
+        assert!(head.status.is_success());
+    }
+    assert_eq!(handle.pool_stats().checkout_counts(), &[2, 0, 1]);
+}
+
+#[test]
+fn test_pool_drain_rate() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| ok::<_, ()>(Response::Ok().finish()))
+            .map(|_| ())
+    });
+
+    let closes = Closes::default();
+    let observer = closes.clone();
+    let (handle, connector) = srv.execute(move || {
+        let connector = Connector::new()
+            .drain_rate(2, Duration::from_millis(100))
+            .pool_observer(observer);
+        (connector.pool_handle(), connector.finish())
+    });
+
+    // open ten connections at once, then release all of them
+    let mut connector = connector.clone();
+    let uri: Uri = srv.url("/").parse().unwrap();
+    srv.block_on_fn(move || {
+        let conns: Vec<_> = (0..10)
+            .map(|_| {
+                connector.call(Connect::new(uri.clone()))
+            })
+            .collect();
+        future::join_all(conns).from_err().and_then(move |conns| {
+            future::join_all(conns.into_iter().map(move |conn| {
+                let mut head = RequestHead::default();
+                head.uri = uri.clone();
+                conn.send_request(head, ())
+            }))
+        })
+    })
+    .unwrap();
+    assert!(closes.0.borrow().is_empty());
+
+    // first batch is closed immediately
+    srv.block_on_fn(move || {
+        handle.drain();
+        ok::<_, ()>(())
+    })
+    .unwrap();
+    assert_eq!(closes.0.borrow().len(), 2);
+
+    // remaining connections are closed in batches of two
+    let mut closed = 2;
+    while closed < 10 {
+        let (prev, closes) = (closed, closes.clone());
+        closed = srv
+            .block_on(future::loop_fn((), move |_| {
+                let closed = closes.0.borrow().len();
+                if closed != prev {
+                    Either::A(ok(Loop::Break(closed)))
+                } else {
+                    let tick = tokio_timer::sleep(Duration::from_millis(10));
+                    Either::B(tick.map(|_| Loop::Continue(())))
+                }
+            }))
+            .unwrap();
+        assert_eq!(closed, prev + 2);
+    }
+    assert!(closes.0.borrow().iter().all(|c| c.0 == EvictReason::Drained));
+}
+
+#[derive(Clone, Default)]
+struct Releases(Rc<RefCell<Vec<(bool, Protocol)>>>);
+
+impl PoolObserver for Releases {
+    fn on_release(&self, reused: bool, _: &Authority, protocol: Protocol) {
+        self.0.borrow_mut().push((reused, protocol));
+    }
+}
+
+#[test]
+fn test_pool_release() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| ok::<_, ()>(Response::Ok().finish()))
+            .map(|_| ())
+    });
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let connector =
+        srv.execute(move || Connector::new().pool_observer(observer).finish());
+
+    let head = fetch(&mut srv, &connector, "/", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+    assert_eq!(&*releases.0.borrow(), &[(true, Protocol::Http1)]);
+
+    // pooled connection gets reused by next request
+    let head = fetch(&mut srv, &connector, "/", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+    assert_eq!(
+        &*releases.0.borrow(),
+        &[(true, Protocol::Http1), (true, Protocol::Http1)]
+    );
+}
+
+#[test]
+fn test_should_pool() {
+    // responds with client port, `/drain` responses carry `x-drain` header
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|req: Request| {
+                let port = req.peer_addr().unwrap().port().to_string();
+                let mut res = Response::Ok();
+                if req.path() == "/drain" {
+                    res.header("x-drain", "1");
+                }
+                ok::<_, ()>(res.body(port))
+            })
+            .map(|_| ())
+    });
+
+    let events = Events::default();
+    let observer = events.clone();
+    let connector = srv.execute(move || {
+        Connector::new()
+            .should_pool(|res: &ResponseHead| !res.headers.contains_key("x-drain"))
+            .pool_observer(observer)
+            .finish()
+    });
+
+    let (_, port1) =
+        fetch(&mut srv, &connector, "/drain", (), |_| (), with_body).unwrap();
+    let (_, port2) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    let (_, port3) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+
+    // drained connection is closed, next request dials new one
+    let (port1, port2, port3) = (port1.unwrap(), port2.unwrap(), port3.unwrap());
+    assert_ne!(port1, port2);
+    assert_eq!(port2, port3);
+    assert_eq!(&*events.0.borrow(), &[EvictReason::ErrorStatus]);
+}
+
+/// Server that answers requests with bytes returned by `respond`
+///
+/// `respond` also decides whether connection is kept open after the response,
+/// otherwise connection is closed.
+fn raw_server_with<F>(respond: F) -> TestServerRuntime
+where
+    F: Fn(&[u8]) -> (Bytes, bool) + Clone + Send + 'static,
+{
+    TestServer::new(move || {
+        let respond = respond.clone();
+        service_fn(move |io: Io<TcpStream>| {
+            let respond = respond.clone();
+            let framed = Framed::new(io.into_parts().0, BytesCodec);
+            future::loop_fn(framed, move |framed| {
+                let respond = respond.clone();
+                framed
+                    .into_future()
+                    .map_err(|(e, _)| e)
+                    .and_then(move |(req, framed)| match req {
+                        Some(req) => {
+                            let (res, keep_alive) = respond(&req);
+                            Either::A(framed.send(res).map(move |framed| {
+                                if keep_alive {
+                                    Loop::Continue(framed)
+                                } else {
+                                    Loop::Break(())
+                                }
+                            }))
+                        }
+                        None => Either::B(ok(Loop::Break(()))),
+                    })
+            })
+        })
+    })
+}
+
+/// Server that responds to every connection with `response` bytes
+fn raw_server(response: &'static [u8]) -> TestServerRuntime {
+    raw_server_with(move |_| (Bytes::from_static(response), false))
+}
+
+fn open_tunnel<T>(
+    srv: &mut TestServerRuntime,
+    connector: &T,
+    upgrade: Option<&'static str>,
+) -> Result<
+    (Framed<<T::Response as Connection>::Io, h1::ClientCodec>, TunnelPermit),
+    SendRequestError,
+>
+where
+    T: Service<Request = Connect, Error = ConnectError> + Clone + 'static,
+    T::Response: Connection,
+{
+    let mut connector = connector.clone();
+    let uri: Uri = srv.url("/").parse().unwrap();
+    srv.block_on_fn(move || {
+        let mut head = RequestHead::default();
+        head.uri = uri.clone();
+        if let Some(upgrade) = upgrade {
+            head.set_connection_type(http::ConnectionType::Upgrade);
+            head.headers.insert(
+                http::header::UPGRADE,
+                http::header::HeaderValue::from_static(upgrade),
+            );
+        }
+        connector
+            .call(Connect::new(uri))
+            .from_err()
+            .and_then(move |conn| conn.open_tunnel(head))
+            .map(|(_, framed, permit)| (framed, permit))
+    })
+}
+
+#[test]
+fn test_max_tunnels() {
+    let mut srv =
+        raw_server(b"HTTP/1.1 101 Switching Protocols\r\nupgrade: test\r\n\r\n");
+    let connector = srv.execute(|| Connector::new().max_tunnels(2).finish());
+
+    let (_first_io, first) = open_tunnel(&mut srv, &connector, None).unwrap();
+    let _second = open_tunnel(&mut srv, &connector, None).unwrap();
+    // connector is at max number of tunnels
+    match open_tunnel(&mut srv, &connector, None) {
+        Err(SendRequestError::TunnelLimit) => (),
+        _ => panic!("tunnel limit error is expected"),
+    }
+
+    // dropped tunnel permit frees its slot
+    drop(first);
+    assert!(open_tunnel(&mut srv, &connector, None).is_ok());
+}
+
+#[test]
+fn test_upgrade_mismatch() {
+    let mut srv = raw_server(
+        b"HTTP/1.1 101 Switching Protocols\r\n\
+          connection: upgrade\r\nupgrade: h2c\r\n\r\n",
+    );
+    let connector = srv.execute(|| Connector::new().finish());
+
+    // server switches to other protocol than requested
+    match open_tunnel(&mut srv, &connector, Some("websocket")) {
+        Err(SendRequestError::UpgradeMismatch) => (),
+        _ => panic!("upgrade mismatch error is expected"),
+    }
+    assert!(open_tunnel(&mut srv, &connector, Some("websocket, h2c")).is_ok());
+}
+
+/// Server that sends more body data than declared by `Content-Length`
+fn over_sending_server() -> TestServerRuntime {
+    raw_server(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhelloEXTRA")
+}
+
+#[test]
+fn test_extra_response_data() {
+    let mut srv = over_sending_server();
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let connector =
+        srv.execute(move || Connector::new().pool_observer(observer).finish());
+
+    let (head, body) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    assert!(head.status.is_success());
+    assert_eq!(body.unwrap(), Bytes::from_static(b"hello"));
+    // connection is not pooled
+    assert_eq!(&*releases.0.borrow(), &[(false, Protocol::Http1)]);
+}
+
+#[test]
+fn test_extra_response_data_strict() {
+    let mut srv = over_sending_server();
+
+    let connector = srv.execute(move || {
+        Connector::new().strict_content_length(true).finish()
+    });
+
+    let (head, body) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    assert!(head.status.is_success());
+    match body {
+        Err(PayloadError::Io(ref e)) if e.kind() == std::io::ErrorKind::InvalidData => (),
+        res => panic!("{:?}", res),
+    }
+}
+
+/// Server that closes connection before sending terminal chunk
+fn truncated_chunked_server() -> TestServerRuntime {
+    raw_server(b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n5\r\nhello\r\n")
+}
+
+#[test]
+fn test_missing_last_chunk() {
+    let mut srv = truncated_chunked_server();
+    let connector = srv.execute(|| Connector::new().finish());
+
+    let (head, body) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    assert!(head.status.is_success());
+    match body {
+        Err(PayloadError::Incomplete(Some(ref e)))
+            if e.kind() == std::io::ErrorKind::UnexpectedEof => {}
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn test_tolerate_missing_last_chunk() {
+    let mut srv = truncated_chunked_server();
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let connector = srv.execute(move || {
+        Connector::new()
+            .tolerate_missing_last_chunk(true)
+            .pool_observer(observer)
+            .finish()
+    });
+
+    let (head, body) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    assert!(head.status.is_success());
+    assert_eq!(body.unwrap(), Bytes::from_static(b"hello"));
+    // connection is closed, not pooled
+    assert_eq!(&*releases.0.borrow(), &[(false, Protocol::Http1)]);
+}
+
+#[test]
+fn test_declined_upgrade() {
+    let mut srv = raw_server(
+        b"HTTP/1.1 101 Switching Protocols\r\nconnection: upgrade\r\n\
+          upgrade: websocket\r\n\r\ntunneled data",
+    );
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let connector =
+        srv.execute(move || Connector::new().pool_observer(observer).finish());
+
+    let (head, body) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    assert_eq!(head.status, http::StatusCode::SWITCHING_PROTOCOLS);
+    match body {
+        Err(PayloadError::Io(ref e)) if e.kind() == std::io::ErrorKind::InvalidData => (),
+        res => panic!("{:?}", res),
+    }
+    // connection is closed, not pooled
+    assert_eq!(&*releases.0.borrow(), &[(false, Protocol::Http1)]);
+}
+
+#[test]
+fn test_write_timeout() {
+    // server never reads request
+    let mut srv = TestServer::new(move || {
+        service_fn(|io: Io<TcpStream>| {
+            future::empty::<(), ()>().then(move |res| {
+                drop(io);
+                res
+            })
+        })
+    });
+
+    let connector = srv.execute(move || {
+        Connector::new()
+            .write_timeout(Duration::from_millis(300))
+            .finish()
+    });
+
+    let size = 32 * 1024 * 1024;
+    let mut conn = connector.clone();
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let res = srv.block_on_fn(move || {
+        let mut head = RequestHead::default();
+        head.method = http::Method::PUT;
+        head.uri = uri.clone();
+        conn.call(Connect::new(uri))
+            .from_err()
+            .and_then(move |conn| {
+                conn.send_request(head, Body::from(Bytes::from(vec![0u8; size])))
+            })
+    });
+
+    // part of the body is buffered by the sockets
+    match res {
+        Err(SendRequestError::WriteTimeout(sent)) => {
+            assert!(sent > 0);
+            assert!(sent < size as u64);
+        }
+        _ => panic!("write timeout is expected"),
+    }
+}
+
+#[test]
+fn test_expect_continue_threshold() {
+    // server reports received `Expect` header once request body is read
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|mut req: Request| {
+                let expect = req.headers().contains_key(http::header::EXPECT);
+                req.take_payload().concat2().then(move |_| {
+                    let mut res = Response::Ok();
+                    res.header("x-expect", if expect { "yes" } else { "no" });
+                    ok::<_, ()>(res.finish())
+                })
+            })
+            .map(|_| ())
+    });
+
+    let connector = srv.execute(move || {
+        Connector::new().expect_continue_threshold(1024).finish()
+    });
+
+    let mut expect = |size: usize| {
+        let body = Body::from(Bytes::from(vec![0u8; size]));
+        let put = |head: &mut RequestHead| {
+            head.method = http::Method::PUT;
+            head.headers.insert(
+                http::header::EXPECT,
+                http::HeaderValue::from_static("100-continue"),
+            );
+        };
+        let head = fetch(&mut srv, &connector, "/", body, put, head_only).unwrap();
+        assert!(head.status.is_success());
+        head.headers.get("x-expect").unwrap().clone()
+    };
+
+    assert_eq!(expect(4096), "yes");
+    // explicitly set header is removed for small body
+    assert_eq!(expect(16), "no");
+}
+
+#[test]
+fn test_drain_on_drop() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|req: Request| {
+                if req.path() == "/large" {
+                    ok::<_, ()>(Response::Ok().body(vec![b'x'; 1024 * 1024]))
+                } else {
+                    ok::<_, ()>(Response::Ok().body(STR))
+                }
+            })
+            .map(|_| ())
+    });
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let connector = srv.execute(move || {
+        Connector::new()
+            .drain_on_drop(64 * 1024)
+            .pool_observer(observer)
+            .finish()
+    });
+
+    // payload is dropped unread, small rest gets drained in background
+    let head = fetch(&mut srv, &connector, "/small", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+    srv.block_on(tokio_timer::sleep(Duration::from_millis(100)))
+        .unwrap();
+    assert_eq!(&*releases.0.borrow(), &[(true, Protocol::Http1)]);
+
+    // too much data left, connection is closed
+    let head = fetch(&mut srv, &connector, "/large", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+    srv.block_on(tokio_timer::sleep(Duration::from_millis(100)))
+        .unwrap();
+    assert_eq!(
+        &*releases.0.borrow(),
+        &[(true, Protocol::Http1), (false, Protocol::Http1)]
+    );
+
+    // payload is dropped outside of executor, connection is closed right away
+    let payload = fetch(&mut srv, &connector, "/small", (), |_| (), |_, _, pl| {
+        Ok::<_, SendRequestError>(pl)
+    });
+    drop(payload);
+    assert_eq!(
+        &*releases.0.borrow(),
+        &[
+            (true, Protocol::Http1),
+            (false, Protocol::Http1),
+            (false, Protocol::Http1)
+        ]
+    );
+}
+
+#[test]
+fn test_drop_payload_close() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| ok::<_, ()>(Response::Ok().body(STR)))
+            .map(|_| ())
+    });
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let connector =
+        srv.execute(move || Connector::new().pool_observer(observer).finish());
+
+    // payload is dropped unread, connection is closed right away
+    let head = fetch(&mut srv, &connector, "/", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+    assert_eq!(&*releases.0.borrow(), &[(false, Protocol::Http1)]);
+}
+
+#[test]
+fn test_explicit_zero_content_length() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|req: Request| {
+                let len = req
+                    .headers()
+                    .get(http::header::CONTENT_LENGTH)
+                    .map(|v| v.to_str().unwrap().to_owned())
+                    .unwrap_or_else(|| "none".to_owned());
+                ok::<_, ()>(Response::Ok().header("x-content-length", len).finish())
+            })
+            .map(|_| ())
+    });
+
+    let post = |head: &mut RequestHead| head.method = http::Method::POST;
+    let put = |head: &mut RequestHead| head.method = http::Method::PUT;
+
+    let connector = srv.execute(|| {
+        Connector::new().explicit_zero_content_length(true).finish()
+    });
+    let head = fetch(&mut srv, &connector, "/", Body::None, post, head_only).unwrap();
+    assert_eq!(head.headers.get("x-content-length").unwrap(), "0");
+
+    let connector = srv.execute(|| {
+        Connector::new().explicit_zero_content_length(false).finish()
+    });
+    let head = fetch(&mut srv, &connector, "/", Body::None, post, head_only).unwrap();
+    assert_eq!(head.headers.get("x-content-length").unwrap(), "none");
+    let head = fetch(&mut srv, &connector, "/", Body::Empty, put, head_only).unwrap();
+    assert_eq!(head.headers.get("x-content-length").unwrap(), "none");
+}
+
+#[test]
+fn test_pool_max_idle_per_host() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| ok::<_, ()>(Response::Ok().finish()))
+            .map(|_| ())
+    });
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let connector = srv.execute(move || {
+        Connector::new()
+            .pool_max_idle_per_host(2)
+            .pool_observer(observer)
+            .finish()
+    });
+
+    // open five connections at once, then release all of them
+    let mut connector = connector.clone();
+    let uri: Uri = srv.url("/").parse().unwrap();
+    srv.block_on_fn(move || {
+        let conns: Vec<_> = (0..5)
+            .map(|_| {
+                connector.call(Connect::new(uri.clone()))
+            })
+            .collect();
+        future::join_all(conns).from_err().and_then(move |conns| {
+            future::join_all(conns.into_iter().map(move |conn| {
+                let mut head = RequestHead::default();
+                head.uri = uri.clone();
+                conn.send_request(head, ())
+            }))
+        })
+    })
+    .unwrap();
+
+    // only two connections are kept idle
+    let releases = releases.0.borrow();
+    assert_eq!(releases.len(), 5);
+    assert_eq!(releases.iter().filter(|(reused, _)| *reused).count(), 2);
+}
+
+#[test]
+fn test_max_interim_responses() {
+    let mut srv = raw_server(
+        b"HTTP/1.1 103 Early Hints\r\n\r\n\
+          HTTP/1.1 103 Early Hints\r\n\r\n\
+          HTTP/1.1 103 Early Hints\r\n\r\n\
+          HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n",
+    );
+
+    let connector = srv.execute(|| Connector::new().max_interim_responses(5).finish());
+    let head = fetch(&mut srv, &connector, "/", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+
+    let mut connector = srv.execute(|| Connector::new().max_interim_responses(2).finish());
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let res = srv.block_on_fn(move || {
+        let mut head = RequestHead::default();
+        head.uri = uri.clone();
+        connector
+            .call(Connect::new(uri))
+            .from_err()
+            .and_then(move |conn| conn.send_request(head, ()))
+    });
+    match res {
+        Err(SendRequestError::TooManyInterimResponses) => (),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_no_content_with_body() {
+    let mut srv = raw_server(b"HTTP/1.1 204 No Content\r\ncontent-length: 5\r\n\r\nhello");
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let connector =
+        srv.execute(move || Connector::new().pool_observer(observer).finish());
+
+    let (head, body) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    assert_eq!(head.status, http::StatusCode::NO_CONTENT);
+    assert!(body.unwrap().is_empty());
+    // spurious body, connection is not pooled
+    assert_eq!(&*releases.0.borrow(), &[(false, Protocol::Http1)]);
+}
+
+#[test]
+fn test_head_with_content_length() {
+    let mut srv = raw_server(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n");
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let mut connector =
+        srv.execute(move || Connector::new().pool_observer(observer).finish());
+
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let (head, body) = srv
+        .block_on_fn(move || {
+            let mut head = RequestHead::default();
+            head.method = http::Method::HEAD;
+            head.uri = uri.clone();
+            connector
+                .call(Connect::new(uri))
+                .from_err()
+                .and_then(move |conn| conn.send_request(head, ()))
+                .and_then(|(head, payload)| {
+                    payload
+                        .concat2()
+                        .then(move |body| Ok::<_, SendRequestError>((head, body)))
+                })
+        })
+        .unwrap();
+    assert!(head.status.is_success());
+    assert!(body.unwrap().is_empty());
+    assert_eq!(&*releases.0.borrow(), &[(true, Protocol::Http1)]);
+}
+
+fn fetch_with_priority<T>(
+    mut connector: T,
+    uri: Uri,
+    priority: u8,
+    order: Rc<RefCell<Vec<u8>>>,
+) -> impl Future<Item = (), Error = SendRequestError>
+where
+    T: Service<Request = Connect, Error = ConnectError>,
+    T::Response: Connection,
+{
+    let mut head = RequestHead::default();
+    head.uri = uri.clone();
+    connector
+        .call(Connect::new(uri).priority(Priority(priority)))
+        .from_err()
+        .and_then(move |conn| conn.send_request(head, ()))
+        .and_then(|(_, payload)| {
+            payload
+                .concat2()
+                .map_err(|e| SendRequestError::Body(e.into()))
+        })
+        .map(move |_| order.borrow_mut().push(priority))
+}
+
+/// Order in which queued requests with given priorities get connection
+fn queue_order(srv: &mut TestServerRuntime, policy: QueuePolicy) -> Vec<u8> {
+    let connector =
+        srv.execute(move || Connector::new().limit(1).queue_policy(policy).finish());
+    let order = Rc::new(RefCell::new(Vec::new()));
+
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let order2 = order.clone();
+    srv.block_on_fn(move || {
+        // pool is saturated, following requests wait in queue
+        let fetch = |priority| {
+            fetch_with_priority(connector.clone(), uri.clone(), priority, order2.clone())
+        };
+        let first = fetch(0);
+        let rest: Vec<_> = [1, 1, 2].iter().map(|p| fetch(*p)).collect();
+        first.join(future::join_all(rest))
+    })
+    .unwrap();
+
+    order.replace(Vec::new())
+}
+
+#[test]
+fn test_queue_policy() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|_| future::ok::<_, ()>(Response::Ok().body(STR)))
+    });
+    assert_eq!(queue_order(&mut srv, QueuePolicy::Fifo), vec![0, 1, 1, 2]);
+    assert_eq!(queue_order(&mut srv, QueuePolicy::Priority), vec![0, 2, 1, 1]);
+}
+
+#[test]
+fn test_priority_limit() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|_| future::ok::<_, ()>(Response::Ok().body(STR)))
+    });
+    let connector = srv.execute(move || {
+        Connector::new()
+            .limit(1)
+            .priority_limit(Priority(9), 1)
+            .finish()
+    });
+
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let connect = move |priority| Connect::new(uri.clone()).priority(Priority(priority));
+    let (connect2, connect3) = (connect.clone(), connect.clone());
+    let (mut c1, mut c2, mut c3) = (connector.clone(), connector.clone(), connector);
+    let (bulk, critical) = srv
+        .block_on_fn(move || {
+            // held connection saturates default segment
+            c1.call(connect(0)).and_then(move |held| {
+                let bulk = tokio_timer::Timeout::new(
+                    c2.call(connect2(0)),
+                    Duration::from_millis(300),
+                )
+                .then(|res| Ok::<_, ConnectError>(res.is_ok()));
+                let critical = c3.call(connect3(9)).map(|_| true);
+                bulk.join(critical).map(move |res| {
+                    drop(held);
+                    res
+                })
+            })
+        })
+        .unwrap();
+
+    // bulk request waits, critical one gets connection of reserved segment
+    assert!(!bulk);
+    assert!(critical);
+}
+
+#[test]
+fn test_health_check_reserve() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|_| future::ok::<_, ()>(Response::Ok().body(STR)))
+    });
+    let connector =
+        srv.execute(move || Connector::new().limit(1).health_check_reserve(1).finish());
+
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let connect = move |check| Connect::new(uri.clone()).health_check(check);
+    let (connect2, connect3) = (connect.clone(), connect.clone());
+    let (mut c1, mut c2, mut c3) = (connector.clone(), connector.clone(), connector);
+    let (normal, health_check) = srv
+        .block_on_fn(move || {
+            // held connection saturates the pool
+            c1.call(connect(false)).and_then(move |held| {
+                let normal = tokio_timer::Timeout::new(
+                    c2.call(connect2(false)),
+                    Duration::from_millis(300),
+                )
+                .then(|res| Ok::<_, ConnectError>(res.is_ok()));
+                let health_check = c3.call(connect3(true)).map(|_| true);
+                normal.join(health_check).map(move |res| {
+                    drop(held);
+                    res
+                })
+            })
+        })
+        .unwrap();
+
+    // normal request waits, health check gets reserved connection
+    assert!(!normal);
+    assert!(health_check);
+}
+
+#[test]
+fn test_limit_per_group() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|_| future::ok::<_, ()>(Response::Ok().body(STR)))
+    });
+    let connector = srv.execute(move || Connector::new().limit_per_group(1).finish());
+
+    // group limit spans hosts
+    let port = srv.addr().port();
+    let connect = move |host, group| {
+        let uri = format!("http://{}:{}/", host, port).parse().unwrap();
+        Connect::new(uri).group(Some(ConnectionGroup(group)))
+    };
+    let (connect2, connect3) = (connect.clone(), connect.clone());
+    let (mut c1, mut c2, mut c3) = (connector.clone(), connector.clone(), connector);
+    let (saturated, other) = srv
+        .block_on_fn(move || {
+            // held connection saturates group 1
+            c1.call(connect("localhost", 1)).and_then(move |held| {
+                let saturated = tokio_timer::Timeout::new(
+                    c2.call(connect2("127.0.0.1", 1)),
+                    Duration::from_millis(300),
+                )
+                .then(|res| Ok::<_, ConnectError>(res.is_ok()));
+                let other = c3.call(connect3("127.0.0.1", 2)).map(|_| true);
+                saturated.join(other).map(move |res| {
+                    drop(held);
+                    res
+                })
+            })
+        })
+        .unwrap();
+
+    // request of saturated group waits, other group is not affected
+    assert!(!saturated);
+    assert!(other);
+}
+
+#[test]
+fn test_deadline() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|_| future::ok::<_, ()>(Response::Ok().body(STR)))
+    });
+    let connector = srv.execute(move || {
+        // connects complete after 200 milliseconds
+        let tcp = apply_fn(default_connector(), |req, srv| {
+            let mut srv = srv.clone();
+            tokio_timer::sleep(Duration::from_millis(200)).then(move |_| srv.call(req))
+        });
+        Connector::new().connector(tcp).limit(1).finish()
+    });
+
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let connect = move |deadline| Connect::new(uri.clone()).deadline(deadline);
+    let connect2 = connect.clone();
+    let (mut c1, mut c2) = (connector.clone(), connector);
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let (events1, events2) = (events.clone(), events.clone());
+    let res = srv
+        .block_on_fn(move || {
+            // held connection saturates pool for 400 milliseconds
+            c1.call(connect(None)).and_then(move |held| {
+                let release = tokio_timer::sleep(Duration::from_millis(400));
+                let release = release.then(move |_| {
+                    drop(held);
+                    events1.borrow_mut().push("released");
+                    Ok::<_, ConnectError>(())
+                });
+                let deadline = Instant::now() + Duration::from_millis(500);
+                let waiting = c2.call(connect2(Some(deadline))).then(move |res| {
+                    events2.borrow_mut().push("failed");
+                    Ok(res.map(|_| ()))
+                });
+                release.join(waiting).map(|(_, res)| res)
+            })
+        })
+        .unwrap();
+
+    // pool wait used most of the budget, connect fails once it runs out
+    match res {
+        Err(ConnectError::Timeout) => (),
+        res => panic!("{:?}", res),
+    }
+    assert_eq!(&*events.borrow(), &["released", "failed"]);
+}
+
+#[test]
+fn test_peer_address_filter() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|_| future::ok::<_, ()>(Response::Ok().body(STR)))
+    });
+    let addr = srv.addr();
+
+    // resolved address is outside of allowed addresses
+    let mut connector = srv.execute(|| {
+        Connector::new()
+            .peer_address_filter(|_, addr| !addr.ip().is_loopback())
+            .finish()
+    });
+    let uri: Uri = format!("http://example.com:{}/", addr.port()).parse().unwrap();
+    let res = srv.block_on_fn(move || {
+        connector.call(Connect::new(uri).set_addr(Some(addr)))
+    });
+    match res {
+        Err(ConnectError::PeerAddressRejected(peer)) => assert_eq!(peer, addr),
+        _ => panic!(),
+    }
+
+    let connector = srv.execute(move || {
+        Connector::new()
+            .peer_address_filter(move |_, peer| *peer == addr)
+            .finish()
+    });
+    let head = fetch(&mut srv, &connector, "/", (), |_| (), head_only).unwrap();
+    assert!(head.status.is_success());
+}
+
+fn connect_to<T>(
+    srv: &mut TestServerRuntime,
+    connector: &T,
+    addr: std::net::SocketAddr,
+) -> Result<(), ConnectError>
+where
+    T: Service<Request = Connect, Error = ConnectError> + Clone + 'static,
+{
+    let mut connector = connector.clone();
+    let uri: Uri = format!("http://{}/", addr).parse().unwrap();
+    srv.block_on_fn(move || {
+        connector
+            .call(Connect::new(uri))
+            .map(|_| ())
+    })
+}
+
+#[test]
+fn test_circuit_breaker() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|_| future::ok::<_, ()>(Response::Ok().finish()))
+    });
+
+    // nothing listens on the address
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let host = addr.to_string();
+
+    let (handle, connector) = srv.execute(|| {
+        let connector = Connector::new().circuit_breaker(2, Duration::from_millis(300));
+        (connector.pool_handle(), connector.finish())
+    });
+
+    for _ in 0..2 {
+        match connect_to(&mut srv, &connector, addr) {
+            Err(ConnectError::CircuitOpen) | Ok(_) => panic!(),
+            Err(_) => (),
+        }
+    }
+
+    // circuit is open, connect is not attempted
+    match connect_to(&mut srv, &connector, addr) {
+        Err(ConnectError::CircuitOpen) => (),
+        _ => panic!(),
+    }
+
+    // other hosts are not affected
+    let srv_addr = srv.addr();
+    assert!(connect_to(&mut srv, &connector, srv_addr).is_ok());
+
+    // host recovers after cooldown
+    let _listener = std::net::TcpListener::bind(addr).unwrap();
+    match handle.circuit_state(&host) {
+        CircuitState::Open(until) => {
+            let now = Instant::now();
+            if until > now {
+                thread::sleep(until - now);
+            }
+        }
+        state => panic!("{:?}", state),
+    }
+    assert!(connect_to(&mut srv, &connector, addr).is_ok());
+}
+
+#[test]
+fn test_circuit_state() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|_| future::ok::<_, ()>(Response::Ok().finish()))
+    });
+
+    // nothing listens on the address
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let host = addr.to_string();
+
+    let (handle, connector) = srv.execute(|| {
+        let connector = Connector::new().circuit_breaker(2, Duration::from_millis(300));
+        (connector.pool_handle(), connector.finish())
+    });
+    assert_eq!(handle.circuit_state(&host), CircuitState::Closed);
+
+    let start = Instant::now();
+    for _ in 0..2 {
+        assert!(connect_to(&mut srv, &connector, addr).is_err());
+    }
+    match handle.circuit_state(&host) {
+        CircuitState::Open(until) => {
+            assert!(until > Instant::now());
+            assert!(until <= start + Duration::from_secs(1));
+        }
+        state => panic!("{:?}", state),
+    }
+
+    // cooldown elapsed, successful connect closes the circuit
+    let _listener = std::net::TcpListener::bind(addr).unwrap();
+    thread::sleep(Duration::from_millis(400));
+    assert_eq!(handle.circuit_state(&host), CircuitState::HalfOpen);
+    assert!(connect_to(&mut srv, &connector, addr).is_ok());
+    assert_eq!(handle.circuit_state(&host), CircuitState::Closed);
+}
+
+#[test]
+fn test_connect_refused() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|_| future::ok::<_, ()>(Response::Ok().finish()))
+    });
+    let connector = srv
+        .execute(|| Connector::new().timeout(Duration::from_millis(500)).finish());
+
+    // nothing listens on the address
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    match connect_to(&mut srv, &connector, closed) {
+        Err(ConnectError::Refused(e)) => {
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused)
+        }
+        _ => panic!("refused error is expected"),
+    }
+}
+
+#[test]
+fn test_default_accept() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|req: Request| {
+            let accept = req
+                .headers()
+                .get(http::header::ACCEPT)
+                .map(|v| Bytes::from(v.as_bytes()))
+                .unwrap_or_default();
+            future::ok::<_, ()>(Response::Ok().body(accept))
+        })
+    });
+    let connector = srv.execute(|| {
+        Connector::new()
+            .default_accept(http::header::HeaderValue::from_static("application/json"))
+            .finish()
+    });
+
+    let (_, body) = fetch(&mut srv, &connector, "/", (), |_| (), with_body).unwrap();
+    assert_eq!(body.unwrap(), Bytes::from_static(b"application/json"));
+
+    // request value takes precedence
+    let mut connector = connector.clone();
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let body = srv
+        .block_on_fn(move || {
+            let mut head = RequestHead::default();
+            head.uri = uri.clone();
+            head.headers.insert(
+                http::header::ACCEPT,
+                http::header::HeaderValue::from_static("text/plain"),
+            );
+            connector
+                .call(Connect::new(uri))
+                .from_err()
+                .and_then(move |conn| conn.send_request(head, ()))
+                .and_then(|(_, payload)| {
+                    payload
+                        .concat2()
+                        .map_err(|e| SendRequestError::Body(e.into()))
+                })
+        })
+        .unwrap();
+    assert_eq!(body, Bytes::from_static(b"text/plain"));
+}
+
+/// Send request with `Host: other.example` header, returns `Host` header
+/// received by the server
+fn send_conflicting_host(
+    srv: &mut TestServerRuntime,
+    policy: HostConflictPolicy,
+) -> Result<Bytes, SendRequestError> {
+    let connector =
+        srv.execute(move || Connector::new().host_conflict_policy(policy).finish());
+
+    let host = |head: &mut RequestHead| {
+        head.headers.insert(
+            http::header::HOST,
+            http::header::HeaderValue::from_static("other.example"),
+        );
+    };
+    let (_, body) = fetch(srv, &connector, "/", (), host, with_body)?;
+    Ok(body.unwrap())
+}
+
+#[test]
+fn test_host_conflict_policy() {
+    // responds with received `Host` header
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|req: Request| {
+            let host = req
+                .headers()
+                .get(http::header::HOST)
+                .map(|v| Bytes::from(v.as_bytes()))
+                .unwrap_or_default();
+            future::ok::<_, ()>(Response::Ok().body(host))
+        })
+    });
+
+    let body = send_conflicting_host(&mut srv, HostConflictPolicy::Header).unwrap();
+    assert_eq!(body, Bytes::from_static(b"other.example"));
+
+    let body = send_conflicting_host(&mut srv, HostConflictPolicy::Uri).unwrap();
+    assert!(body.starts_with(b"localhost:"));
+
+    match send_conflicting_host(&mut srv, HostConflictPolicy::Error) {
+        Err(SendRequestError::HostConflict) => (),
+        res => panic!("{:?}", res),
+    }
+}
+
+/// Response handler of `fetch()`, reads payload and returns byte counters
+/// of the connection before request is sent
+fn acquired_bytes(
+    bytes: ConnectionBytes,
+    head: ResponseHead,
+    payload: Payload,
+) -> impl Future<Item = ConnectionBytes, Error = SendRequestError> {
+    with_body(bytes, head, payload).map(move |_| bytes)
+}
+
+#[test]
+fn test_connection_bytes() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| future::ok::<_, ()>(Response::Ok().body(STR)))
+    });
+    let (handle, connector) = srv.execute(|| {
+        let connector = Connector::new();
+        (connector.pool_handle(), connector.finish())
+    });
+
+    // fresh connection
+    let first = fetch(&mut srv, &connector, "/", (), |_| (), acquired_bytes).unwrap();
+    assert_eq!(first, ConnectionBytes::default());
+
+    // connection is reused, counters include first request
+    let second = fetch(&mut srv, &connector, "/", (), |_| (), acquired_bytes).unwrap();
+    assert!(second.sent > 0);
+    assert!(second.received > STR.len() as u64);
+    assert_eq!(second.requests, 1);
+
+    // cumulative totals of both requests
+    let third = fetch(&mut srv, &connector, "/", (), |_| (), acquired_bytes).unwrap();
+    assert_eq!(third.sent, second.sent * 2);
+    assert!(third.received > second.received + STR.len() as u64);
+
+    // pool snapshot includes all three requests
+    let stats = handle.pool_stats();
+    let idle = stats.idle_connections();
+    assert_eq!(idle.len(), 1);
+    let uri: Uri = srv.url("/").parse().unwrap();
+    assert_eq!(idle[0].0, uri.authority_part().unwrap().as_str());
+    assert_eq!(idle[0].1.sent, second.sent * 3);
+    assert_eq!(idle[0].1.requests, 3);
+    assert!(idle[0].1.received > third.received + STR.len() as u64);
+}
+
+#[test]
+fn test_connection_handle() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|req: Request| {
+                future::ok::<_, ()>(Response::Ok().body(req.path().to_owned()))
+            })
+            .map(|_| ())
+    });
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let mut connector =
+        srv.execute(move || Connector::new().pool_observer(observer).finish());
+
+    let uri: Uri = srv.url("/first").parse().unwrap();
+    let uri2: Uri = srv.url("/second").parse().unwrap();
+    let (first, second) = srv
+        .block_on_fn(move || {
+            let mut head = RequestHead::default();
+            head.uri = uri.clone();
+            let mut head2 = RequestHead::default();
+            head2.uri = uri2;
+            connector
+                .call(Connect::new(uri))
+                .from_err()
+                .and_then(move |conn| conn.send_request_with_handle(head, ()))
+                .and_then(|(_, payload, handle)| {
+                    payload
+                        .concat2()
+                        .map_err(|e| SendRequestError::Body(e.into()))
+                        .and_then(move |body| handle.from_err().map(|conn| (body, conn)))
+                })
+                // next request on the same connection, no pool checkout
+                .and_then(move |(first, conn)| {
+                    conn.send_request(head2, ()).and_then(|(_, payload)| {
+                        payload
+                            .concat2()
+                            .map_err(|e| SendRequestError::Body(e.into()))
+                            .map(move |second| (first, second))
+                    })
+                })
+        })
+        .unwrap();
+    assert_eq!(first, Bytes::from_static(b"/first"));
+    assert_eq!(second, Bytes::from_static(b"/second"));
+
+    // connection is returned to the pool only once, after second request
+    assert_eq!(&*releases.0.borrow(), &[(true, Protocol::Http1)]);
+}
+
+/// Stream that records size of every write
+///
+/// With `backpressure` every write is refused with `WouldBlock` once before
+/// it is accepted, so writer sees full socket after each write.
+#[derive(Debug)]
+struct Recorder {
+    io: TcpStream,
+    writes: Rc<RefCell<Vec<usize>>>,
+    backpressure: bool,
+    stalled: bool,
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl AsyncRead for Recorder {}
+
+impl Write for Recorder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.backpressure && !self.stalled {
+            self.stalled = true;
+            futures::task::current().notify();
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.stalled = false;
+        let n = self.io.write(buf)?;
+        self.writes.borrow_mut().push(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl AsyncWrite for Recorder {
+    fn shutdown(&mut self) -> futures::Poll<(), io::Error> {
+        AsyncWrite::shutdown(&mut self.io)
+    }
+}
+
+fn post_recorded(
+    srv: &mut TestServerRuntime,
+    coalesce: usize,
+    flush_each: bool,
+    backpressure: bool,
+    body: Body,
+) -> Vec<usize> {
+    let writes = Rc::new(RefCell::new(Vec::new()));
+    let writes2 = writes.clone();
+    let mut connector = srv.execute(move || {
+        let tcp = apply_fn(default_connector(), move |req, srv| {
+            let writes = writes2.clone();
+            srv.call(req).map(move |conn| {
+                let (io, uri) = conn.into_parts();
+                let io = Recorder {
+                    io,
+                    writes,
+                    backpressure,
+                    stalled: false,
+                };
+                TcpConnection::new(io, uri)
+            })
+        });
+        Connector::new()
+            .flush_each_request(flush_each)
+            .connector(tcp)
+            .coalesce_small_body(coalesce)
+            .finish()
+    });
+
+    let uri: Uri = srv.url("/").parse().unwrap();
+    srv.block_on_fn(move || {
+        let mut head = RequestHead::default();
+        head.method = http::Method::POST;
+        head.uri = uri.clone();
+        connector
+            .call(Connect::new(uri))
+            .from_err()
+            .and_then(move |conn| conn.send_request(head, body))
+            .and_then(|(_, payload)| {
+                payload
+                    .concat2()
+                    .map_err(|e| SendRequestError::Body(e.into()))
+            })
+    })
+    .unwrap();
+    writes.replace(Vec::new())
+}
+
+#[test]
+fn test_coalesce_small_body() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| future::ok::<_, ()>(Response::Ok().finish()))
+            .map(|_| ())
+    });
+
+    // head and body are written separately by default
+    let hello = || Body::from("hello");
+    assert_eq!(post_recorded(&mut srv, 0, false, false, hello()).len(), 2);
+
+    // single write
+    assert_eq!(post_recorded(&mut srv, 1024, false, false, hello()).len(), 1);
+}
+
+#[test]
+fn test_body_flush_points() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| future::ok::<_, ()>(Response::Ok().finish()))
+            .map(|_| ())
+    });
+
+    let data = |chunk: &'static [u8]| BodyChunk::Data(Bytes::from_static(chunk));
+    let chunks = vec![
+        data(b"a"),
+        data(b"b"),
+        BodyChunk::Flush,
+        data(b"c"),
+        BodyChunk::Flush,
+        data(b"d"),
+    ];
+    let body = FlushStream::new(stream::iter_ok::<_, actix_http::Error>(chunks));
+    let writes = post_recorded(&mut srv, 0, false, false, Body::from_message(body));
+
+    // head, then merged chunks "a" and "b", chunk "c", chunk "d", terminal chunk
+    assert_eq!(&writes[1..], &[7, 6, 6, 5]);
+}
+
+#[test]
+fn test_flush_each_request() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build()
+            .finish(|_| future::ok::<_, ()>(Response::Ok().finish()))
+            .map(|_| ())
+    });
+
+    let body = || {
+        let chunks = vec![Bytes::from_static(b"a"), Bytes::from_static(b"b")];
+        Body::from_message(BodyStream::new(stream::iter_ok::<_, actix_http::Error>(
+            chunks,
+        )))
+    };
+
+    // chunks that are ready while socket is full are batched by default
+    let writes = post_recorded(&mut srv, 0, false, true, body());
+    assert_eq!(&writes[1..], &[12, 5]);
+
+    // every chunk is written separately
+    let writes = post_recorded(&mut srv, 0, true, true, body());
+    assert_eq!(&writes[1..], &[6, 6, 5]);
+
+    // head is not coalesced with body
+    let hello = || Body::from("hello");
+    assert_eq!(post_recorded(&mut srv, 1024, false, false, hello()).len(), 1);
+    assert_eq!(post_recorded(&mut srv, 1024, true, false, hello()).len(), 2);
+}
+
+/// Opens `CONNECT` tunnel to `example.com` through proxy that responds
+/// with `response`, returns data received after proxy response head
+fn proxy_connect(response: &'static [u8]) -> Result<BytesMut, SendRequestError> {
+    let mut proxy = raw_server(response);
+    let proxy_uri: Uri = proxy.url("/").parse().unwrap();
+    let mut connector = proxy.execute(|| Connector::new().finish());
+    proxy.block_on_fn(move || {
+        let mut head = RequestHead::default();
+        head.method = http::Method::CONNECT;
+        head.uri = Uri::from_static("example.com:443");
+        connector
+            .call(Connect::new(proxy_uri))
+            .from_err()
+            .and_then(move |conn| conn.open_tunnel(head))
+            .map(|(_, framed, _)| framed.into_parts().read_buf)
+    })
+}
+
+#[test]
+fn test_proxy_auth_required() {
+    let res = proxy_connect(
+        b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+          proxy-authenticate: Basic realm=\"proxy\"\r\n\
+          content-length: 12\r\n\r\nauth needed!",
+    );
+    match res {
+        Err(SendRequestError::Connect(ConnectError::ProxyStatus(err))) => {
+            assert_eq!(err.status(), http::StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+            assert_eq!(err.body(), &Bytes::from_static(b"auth needed!"));
+        }
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_proxy_forbidden() {
+    let res = proxy_connect(b"HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\n\r\n");
+    match res {
+        Err(SendRequestError::Connect(ConnectError::ProxyStatus(err))) => {
+            assert_eq!(err.status(), http::StatusCode::FORBIDDEN);
+            assert!(err.body().is_empty());
+        }
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_proxy_tunnel_early_data() {
+    // tunneled response is received together with proxy response head
+    let data = proxy_connect(
+        b"HTTP/1.1 200 Connection established\r\n\r\n\
+          HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello",
+    )
+    .unwrap();
+    assert_eq!(
+        &data[..],
+        &b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello"[..]
+    );
+}
+
+#[test]
+fn test_send_raw_request() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|req: Request| {
+            let body = format!("{} {}", req.method(), req.uri());
+            future::ok::<_, ()>(Response::Ok().header("x-raw", "1").body(body))
+        })
+    });
+
+    let mut connector = srv.execute(|| Connector::new().finish());
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let (head, body) = srv
+        .block_on_fn(move || {
+            connector
+                .call(Connect::new(uri))
+                .from_err()
+                .and_then(|conn| {
+                    conn.send_raw_request(Bytes::from_static(
+                        b"PUT /raw?q=1 HTTP/1.1\r\nhost: localhost\r\n\
+                          content-length: 0\r\n\r\n",
+                    ))
+                })
+                .and_then(|(head, payload)| {
+                    payload
+                        .concat2()
+                        .map(move |body| (head, body))
+                        .map_err(|e| SendRequestError::Body(e.into()))
+                })
+        })
+        .unwrap();
+
+    assert_eq!(head.status, http::StatusCode::OK);
+    assert_eq!(head.headers.get("x-raw").unwrap(), "1");
+    assert_eq!(body, Bytes::from_static(b"PUT /raw?q=1"));
+
+    // response to raw `HEAD` request has no payload
+    let mut connector = srv.execute(|| Connector::new().finish());
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let (head, body) = srv
+        .block_on_fn(move || {
+            connector
+                .call(Connect::new(uri))
+                .from_err()
+                .and_then(|conn| {
+                    conn.send_raw_request(Bytes::from_static(
+                        b"HEAD /raw HTTP/1.1\r\nhost: localhost\r\n\r\n",
+                    ))
+                })
+                .and_then(|(head, payload)| {
+                    payload
+                        .concat2()
+                        .map(move |body| (head, body))
+                        .map_err(|e| SendRequestError::Body(e.into()))
+                })
+        })
+        .unwrap();
+    assert_eq!(head.status, http::StatusCode::OK);
+    assert!(body.is_empty());
+}
+
+/// Http/1.0 server, keeps connection alive if request asks for it
+fn http10_server() -> TestServerRuntime {
+    raw_server_with(|req| {
+        if req.windows(10).any(|w| w.eq_ignore_ascii_case(b"keep-alive")) {
+            let res = b"HTTP/1.0 200 OK\r\nconnection: keep-alive\r\n\
+                        content-length: 2\r\n\r\nok";
+            (Bytes::from_static(res), true)
+        } else {
+            let res = b"HTTP/1.0 200 OK\r\ncontent-length: 2\r\n\r\nok";
+            (Bytes::from_static(res), false)
+        }
+    })
+}
+
+fn send_http10<T>(srv: &mut TestServerRuntime, connector: &T) -> Bytes
+where
+    T: Service<Request = Connect, Error = ConnectError> + Clone + 'static,
+    T::Response: Connection,
+{
+    let http10 = |head: &mut RequestHead| head.version = http::Version::HTTP_10;
+    let (_, body) = fetch(srv, connector, "/", (), http10, with_body).unwrap();
+    body.unwrap()
+}
+
+#[test]
+fn test_http10_keep_alive() {
+    let mut srv = http10_server();
+
+    // http/1.0 connection is closed by default
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let (handle, connector) = srv.execute(move || {
+        let connector = Connector::new().pool_observer(observer);
+        (connector.pool_handle(), connector.finish())
+    });
+    assert_eq!(send_http10(&mut srv, &connector), Bytes::from_static(b"ok"));
+    assert_eq!(&*releases.0.borrow(), &[(false, Protocol::Http1)]);
+    assert!(handle.pool_stats().idle_connections().is_empty());
+
+    let releases = Releases::default();
+    let observer = releases.clone();
+    let (handle, connector) = srv.execute(move || {
+        let connector = Connector::new()
+            .http10_keep_alive(true)
+            .pool_observer(observer);
+        (connector.pool_handle(), connector.finish())
+    });
+    assert_eq!(send_http10(&mut srv, &connector), Bytes::from_static(b"ok"));
+    assert_eq!(send_http10(&mut srv, &connector), Bytes::from_static(b"ok"));
+    assert_eq!(
+        &*releases.0.borrow(),
+        &[(true, Protocol::Http1), (true, Protocol::Http1)]
+    );
+    // second request is sent over pooled connection
+    let stats = handle.pool_stats();
+    assert_eq!(stats.idle_connections().len(), 1);
+    assert_eq!(stats.idle_connections()[0].1.requests, 2);
+}
+
+#[test]
+fn test_wait_idle() {
+    let mut srv = TestServer::new(move || {
+        HttpService::build().finish(|_| ok::<_, ()>(Response::Ok().body(STR)))
+    });
+
+    let (wait_idle, mut connector) = srv.execute(|| {
+        let connector = Connector::new();
+        (connector.wait_idle(), connector.finish())
+    });
+
+    let uri: Uri = srv.url("/").parse().unwrap();
+    let done = Rc::new(Cell::new(false));
+    let done2 = done.clone();
+    let idle_after_request = srv
+        .block_on_fn(move || {
+            let mut head = RequestHead::default();
+            head.uri = uri.clone();
+            let request = connector
+                .call(Connect::new(uri))
+                .from_err()
+                .and_then(move |conn| conn.send_request(head, ()))
+                .and_then(|(_, payload)| {
+                    payload
+                        .concat2()
+                        .map_err(|e| SendRequestError::Body(e.into()))
+                })
+                .then(move |res| {
+                    assert!(res.is_ok());
+                    done2.set(true);
+                    Ok(())
+                });
+            request.join(wait_idle.map(move |_| done.get()))
+        })
+        .unwrap()
+        .1;
+
+    // pool is idle only once response payload is read
+    assert!(idle_after_request);
+}
@@ -326,7 +326,7 @@ fn test_content_length() {
     let value = HeaderValue::from_static("0");
 
     {
-        for i in 0..4 {
+        for i in &[0, 2] {
             let req = srv
                 .request(http::Method::GET, srv.url(&format!("/{}", i)))
                 .send();
@@ -340,6 +340,20 @@ fn test_content_length() {
             assert_eq!(response.headers().get(&header), None);
         }
 
+        // client skips interim responses, read them from socket
+        for i in &[1, 3] {
+            for method in &["GET", "HEAD"] {
+                let mut stream = net::TcpStream::connect(srv.addr()).unwrap();
+                let req = format!("{} /{} HTTP/1.1\r\n\r\n", method, i);
+                let _ = stream.write_all(req.as_bytes());
+                let mut data = vec![0; 1024];
+                let n = stream.read(&mut data).unwrap();
+                let head = String::from_utf8_lossy(&data[..n]).to_lowercase();
+                assert!(head.starts_with("http/1.1 1"));
+                assert!(!head.contains("content-length"));
+            }
+        }
+
         for i in 4..6 {
             let req = srv
                 .request(http::Method::GET, srv.url(&format!("/{}", i)))
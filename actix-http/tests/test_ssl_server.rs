@@ -1,16 +1,21 @@
 #![cfg(feature = "ssl")]
-use actix_codec::{AsyncRead, AsyncWrite};
+use actix_codec::{AsyncRead, AsyncWrite, BytesCodec, Framed};
 use actix_http_test::TestServer;
 use actix_server::ssl::OpensslAcceptor;
-use actix_server_config::ServerConfig;
-use actix_service::{new_service_cfg, NewService};
+use actix_server_config::{Io, ServerConfig};
+use actix_service::{new_service_cfg, service_fn, NewService, Service};
 
 use bytes::{Bytes, BytesMut};
 use futures::future::{ok, Future};
+use futures::sink::Sink;
 use futures::stream::{once, Stream};
-use openssl::ssl::{AlpnError, SslAcceptor, SslFiletype, SslMethod};
+use openssl::ssl::{
+    AlpnError, SslAcceptor, SslConnector, SslFiletype, SslMethod, SslVerifyMode,
+};
 use std::io::Result;
+use std::time::Duration;
 
+use actix_http::client::{Connect, Connection, Connector, SendRequestError};
 use actix_http::error::{ErrorBadRequest, PayloadError};
 use actix_http::http::header::{self, HeaderName, HeaderValue};
 use actix_http::http::{Method, StatusCode, Version};
@@ -478,3 +483,158 @@ fn test_h2_on_connect() {
     let response = srv.block_on(srv.sget("/").send()).unwrap();
     assert!(response.status().is_success());
 }
+
+/// Http/2 server that allows single stream, each response takes 300 milliseconds
+fn h2_single_stream<T>(io: Io<T>) -> impl Future<Item = (), Error = ()>
+where
+    T: AsyncRead + AsyncWrite + 'static,
+{
+    h2::server::Builder::new()
+        .max_concurrent_streams(1)
+        .handshake::<_, Bytes>(io.into_parts().0)
+        .and_then(|conn| {
+            conn.for_each(|(_, mut respond)| {
+                actix_rt::spawn(tokio_timer::sleep(Duration::from_millis(300)).then(
+                    move |_| {
+                        let res = http::Response::new(());
+                        let _ = respond.send_response(res, true);
+                        Ok(())
+                    },
+                ));
+                Ok(())
+            })
+        })
+        .map_err(|_| ())
+}
+
+#[test]
+fn test_h2_try_send_request() {
+    let openssl = ssl_acceptor().unwrap();
+
+    let mut srv = TestServer::new(move || {
+        openssl
+            .clone()
+            .map_err(|e| println!("Openssl error: {}", e))
+            .and_then(service_fn(h2_single_stream))
+    });
+
+    // disable ssl verification
+    let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+    builder.set_verify(SslVerifyMode::NONE);
+    let _ = builder.set_alpn_protos(b"\x02h2\x08http/1.1");
+    let connector = srv.execute(move || Connector::new().ssl(builder.build()).finish());
+
+    let uri: actix_http::http::Uri = srv.surl("/").parse().unwrap();
+    let head = {
+        let uri = uri.clone();
+        move || {
+            let mut head = actix_http::RequestHead::default();
+            head.uri = uri.clone();
+            head
+        }
+    };
+    let (head2, head3) = (head.clone(), head.clone());
+    let connect = move || Connect::new(uri.clone());
+    let (connect2, connect3) = (connect.clone(), connect.clone());
+    let (mut c1, mut c2, mut c3) = (connector.clone(), connector.clone(), connector);
+    let (first, (at_capacity, second)) = srv
+        .block_on_fn(move || {
+            c1.call(connect()).from_err().and_then(move |conn| {
+                // first request takes the only stream
+                let first = conn.send_request(head(), ());
+                let second = tokio_timer::sleep(Duration::from_millis(100))
+                    .then(move |_| c2.call(connect2()))
+                    .from_err()
+                    .and_then(move |conn| {
+                        conn.try_send_request(head2(), ()).then(|res| match res {
+                            Err(SendRequestError::StreamCapacity) => Ok(true),
+                            _ => Ok(false),
+                        })
+                    })
+                    .and_then(move |at_capacity| {
+                        // waits until first stream is done
+                        c3.call(connect3())
+                            .from_err()
+                            .and_then(move |conn| conn.send_request(head3(), ()))
+                            .map(move |(head, _)| (at_capacity, head))
+                    });
+                first.map(|(head, _)| head).join(second)
+            })
+        })
+        .unwrap();
+
+    assert!(first.status.is_success());
+    assert!(at_capacity);
+    assert!(second.status.is_success());
+}
+
+/// Write `data` instead of server connection preface, close connection
+/// after `linger`
+fn write_and_close<T>(
+    io: Io<T>,
+    data: &'static [u8],
+    linger: Duration,
+) -> impl Future<Item = (), Error = ()>
+where
+    T: AsyncRead + AsyncWrite + 'static,
+{
+    Framed::new(io.into_parts().0, BytesCodec)
+        .send(Bytes::from_static(data))
+        .and_then(move |framed| {
+            tokio_timer::sleep(linger).then(move |_| Ok(drop(framed)))
+        })
+        .map_err(|_| ())
+}
+
+/// Send http/2 request to server that negotiates h2 and writes `data`
+/// instead of connection preface
+fn h2_invalid_preface(
+    data: &'static [u8],
+    linger: Duration,
+) -> std::result::Result<(), SendRequestError> {
+    let openssl = ssl_acceptor().unwrap();
+
+    let mut srv = TestServer::new(move || {
+        openssl
+            .clone()
+            .map_err(|e| println!("Openssl error: {}", e))
+            .and_then(service_fn(move |io| write_and_close(io, data, linger)))
+    });
+
+    // disable ssl verification
+    let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+    builder.set_verify(SslVerifyMode::NONE);
+    let _ = builder.set_alpn_protos(b"\x02h2\x08http/1.1");
+    let mut connector =
+        srv.execute(move || Connector::new().ssl(builder.build()).finish());
+
+    let uri: actix_http::http::Uri = srv.surl("/").parse().unwrap();
+    let mut head = actix_http::RequestHead::default();
+    head.uri = uri.clone();
+    srv.block_on_fn(move || {
+        connector
+            .call(Connect::new(uri))
+            .from_err()
+            .and_then(move |conn| conn.send_request(head, ()))
+            .map(|_| ())
+    })
+}
+
+#[test]
+fn test_h2_invalid_preface() {
+    // server responds with http/1 data
+    let data = b"HTTP/1.1 400 Bad Request\r\n\r\n";
+    match h2_invalid_preface(data, Duration::from_millis(500)) {
+        Err(SendRequestError::H2Preface) => (),
+        _ => panic!("expected preface error"),
+    }
+}
+
+#[test]
+fn test_h2_partial_preface() {
+    // server closes connection after partial frame header
+    match h2_invalid_preface(&[0, 0, 0, 0x4], Duration::from_millis(0)) {
+        Err(SendRequestError::H2Preface) => (),
+        _ => panic!("expected preface error"),
+    }
+}
@@ -0,0 +1,169 @@
+//! Blocking http client
+//!
+//! Blocking client runs requests on its own single threaded runtime,
+//! so it can be used from code that does not run an async runtime.
+//!
+//! ```rust,no_run
+//! fn main() {
+//!     let mut client = awc::blocking::Client::new();
+//!
+//!     let request = client.get("http://www.rust-lang.org") // <- Create request builder
+//!         .header("User-Agent", "Actix-web");
+//!     let response = client.send_blocking(request).unwrap(); // <- Send http request
+//!
+//!     println!("Response: {:?}", response.body());
+//! }
+//! ```
+use actix_http::http::{HeaderMap, HttpTryFrom, Method, StatusCode, Uri, Version};
+use actix_http::ResponseHead;
+use actix_rt::{System, SystemRunner};
+use bytes::Bytes;
+use futures::future::{lazy, Future};
+
+use crate::error::BlockingError;
+use crate::request::ClientRequest;
+
+/// Blocking HTTP client
+///
+/// It wraps regular `Client` and drives requests to completion on
+/// a private runtime. Response body is loaded to memory.
+pub struct Client {
+    rt: SystemRunner,
+    client: crate::Client,
+    limit: usize,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client::new()
+    }
+}
+
+impl Client {
+    /// Create new blocking client with default settings.
+    pub fn new() -> Client {
+        Client::with(crate::Client::default)
+    }
+
+    /// Create new blocking client, regular client is constructed
+    /// by the provided function within client's runtime.
+    pub fn with<F>(f: F) -> Client
+    where
+        F: FnOnce() -> crate::Client,
+    {
+        let mut rt = System::new("awc-blocking");
+        let client = rt.block_on(lazy(|| Ok::<_, ()>(f()))).unwrap();
+        Client {
+            rt,
+            client,
+            limit: 262_144,
+        }
+    }
+
+    /// Change max size of response payload. By default max size is 256Kb
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Construct HTTP request.
+    pub fn request<U>(&self, method: Method, url: U) -> ClientRequest
+    where
+        Uri: HttpTryFrom<U>,
+    {
+        self.client.request(method, url)
+    }
+
+    /// Construct HTTP *GET* request.
+    pub fn get<U>(&self, url: U) -> ClientRequest
+    where
+        Uri: HttpTryFrom<U>,
+    {
+        self.client.get(url)
+    }
+
+    /// Construct HTTP *HEAD* request.
+    pub fn head<U>(&self, url: U) -> ClientRequest
+    where
+        Uri: HttpTryFrom<U>,
+    {
+        self.client.head(url)
+    }
+
+    /// Construct HTTP *PUT* request.
+    pub fn put<U>(&self, url: U) -> ClientRequest
+    where
+        Uri: HttpTryFrom<U>,
+    {
+        self.client.put(url)
+    }
+
+    /// Construct HTTP *POST* request.
+    pub fn post<U>(&self, url: U) -> ClientRequest
+    where
+        Uri: HttpTryFrom<U>,
+    {
+        self.client.post(url)
+    }
+
+    /// Construct HTTP *DELETE* request.
+    pub fn delete<U>(&self, url: U) -> ClientRequest
+    where
+        Uri: HttpTryFrom<U>,
+    {
+        self.client.delete(url)
+    }
+
+    /// Send request with an empty body and wait for the complete response.
+    pub fn send_blocking(
+        &mut self,
+        request: ClientRequest,
+    ) -> Result<Response, BlockingError> {
+        let limit = self.limit;
+        self.rt.block_on(lazy(move || {
+            request
+                .send()
+                .from_err::<BlockingError>()
+                .and_then(move |mut res| {
+                    res.body().limit(limit).from_err().map(move |body| Response {
+                        head: res.head,
+                        body,
+                    })
+                })
+        }))
+    }
+}
+
+/// Response of the blocking client with loaded body
+#[derive(Debug)]
+pub struct Response {
+    head: ResponseHead,
+    body: Bytes,
+}
+
+impl Response {
+    /// Read the Response Version.
+    pub fn version(&self) -> Version {
+        self.head.version
+    }
+
+    /// Get the status from the server.
+    pub fn status(&self) -> StatusCode {
+        self.head.status
+    }
+
+    /// Returns response's headers.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.head.headers
+    }
+
+    /// Returns response's body.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// Consume response and return its body.
+    pub fn into_body(self) -> Bytes {
+        self.body
+    }
+}
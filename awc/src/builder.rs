@@ -8,7 +8,7 @@ use actix_http::http::{header, HeaderMap, HeaderName, HttpTryFrom};
 use actix_service::Service;
 
 use crate::connect::ConnectorWrapper;
-use crate::{Client, ClientConfig};
+use crate::{Client, ClientConfig, RetryPolicy};
 
 /// An HTTP Client builder
 ///
@@ -36,6 +36,9 @@ impl ClientBuilder {
             config: ClientConfig {
                 headers: HeaderMap::new(),
                 timeout: Some(Duration::from_secs(5)),
+                max_redirects: 10,
+                strict_redirects: false,
+                retry_policy: None,
                 connector: RefCell::new(Box::new(ConnectorWrapper(
                     Connector::new().finish(),
                 ))),
@@ -86,6 +89,23 @@ impl ClientBuilder {
         self
     }
 
+    /// Fail redirect responses without `Location` header.
+    ///
+    /// By default such response is returned to the caller, in strict mode
+    /// request fails with `SendRequestError::RedirectNoLocation` error.
+    pub fn strict_redirects(mut self, val: bool) -> Self {
+        self.config.strict_redirects = val;
+        self
+    }
+
+    /// Set retry policy for failed requests.
+    ///
+    /// Requests are not retried by default.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.config.retry_policy = Some(policy);
+        self
+    }
+
     /// Do not add default request headers.
     /// By default `Date` and `User-Agent` headers are set.
     pub fn no_default_headers(mut self) -> Self {
@@ -138,7 +158,12 @@ impl ClientBuilder {
     }
 
     /// Finish build process and create `Client` instance.
-    pub fn finish(self) -> Client {
+    pub fn finish(mut self) -> Client {
+        self.config.max_redirects = if self.allow_redirects {
+            self.max_redirects
+        } else {
+            0
+        };
         Client(Rc::new(self.config))
     }
 }
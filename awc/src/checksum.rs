@@ -0,0 +1,126 @@
+//! Request body checksums
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use actix_http::body::{Body, BodySize, MessageBody};
+use actix_http::Error;
+use bytes::Bytes;
+use futures::{Async, Poll};
+use sha2::{Digest, Sha256};
+
+use crate::error::ChecksumError;
+use crate::response::ClientResponse;
+
+struct State {
+    hasher: Sha256,
+    size: u64,
+    done: bool,
+}
+
+/// Request body that computes SHA-256 digest of sent data.
+///
+/// ```rust,ignore
+/// let (body, checksum) = ChecksumBody::sha256(data);
+/// client
+///     .put("http://example.com/object")
+///     .send_body(body)
+///     .map_err(ChecksumError::from)
+///     .and_then(move |res| checksum.verify(&res, "x-checksum-sha256").map(|_| res))
+/// ```
+pub struct ChecksumBody {
+    body: Body,
+    state: Rc<RefCell<State>>,
+}
+
+impl ChecksumBody {
+    /// Wrap request body, returns handle for verifying the checksum
+    pub fn sha256<B: Into<Body>>(body: B) -> (ChecksumBody, Checksum) {
+        let state = Rc::new(RefCell::new(State {
+            hasher: Sha256::new(),
+            size: 0,
+            done: false,
+        }));
+        let body = ChecksumBody {
+            body: body.into(),
+            state: state.clone(),
+        };
+        (body, Checksum(state))
+    }
+}
+
+impl MessageBody for ChecksumBody {
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(&mut self) -> Poll<Option<Bytes>, Error> {
+        let mut state = self.state.borrow_mut();
+        match self.body.poll_next()? {
+            Async::Ready(Some(chunk)) => {
+                state.hasher.input(&chunk);
+                state.size += chunk.len() as u64;
+                Ok(Async::Ready(Some(chunk)))
+            }
+            Async::Ready(None) => {
+                state.done = true;
+                Ok(Async::Ready(None))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl From<ChecksumBody> for Body {
+    fn from(body: ChecksumBody) -> Body {
+        Body::from_message(body)
+    }
+}
+
+/// Checksum of sent request body
+pub struct Checksum(Rc<RefCell<State>>);
+
+impl Checksum {
+    /// Hex encoded digest, available once whole body is sent
+    pub fn hex(&self) -> Option<String> {
+        let state = self.0.borrow();
+        if !state.done {
+            return None;
+        }
+        let mut hex = String::with_capacity(64);
+        for b in state.hasher.clone().result().iter() {
+            let _ = write!(hex, "{:02x}", b);
+        }
+        Some(hex)
+    }
+
+    /// Compare digest with value of response header.
+    ///
+    /// Body must be fully sent and all of it must be accounted by
+    /// `ClientResponse::request_bytes_sent()`. Header value is compared
+    /// case insensitive.
+    pub fn verify<S>(
+        &self,
+        res: &ClientResponse<S>,
+        header: &str,
+    ) -> Result<(), ChecksumError> {
+        let actual = match self.hex() {
+            Some(hex) if self.0.borrow().size == res.request_bytes_sent() => hex,
+            _ => return Err(ChecksumError::Incomplete),
+        };
+        let expected = res
+            .headers()
+            .get(header)
+            .and_then(|val| val.to_str().ok())
+            .ok_or(ChecksumError::MissingHeader)?;
+
+        if expected.trim().eq_ignore_ascii_case(&actual) {
+            Ok(())
+        } else {
+            Err(ChecksumError::Mismatch {
+                expected: expected.to_owned(),
+                actual,
+            })
+        }
+    }
+}
@@ -1,10 +1,16 @@
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::cell::Cell;
 use std::{fmt, io, net};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use actix_codec::{AsyncRead, AsyncWrite, Framed};
 use actix_http::body::Body;
 use actix_http::client::{
-    Connect as ClientConnect, ConnectError, Connection, SendRequestError,
+    Connect as ClientConnect, ConnectError, ConnectTimeout, Connection,
+    ConnectionGroup, Deadline, HealthCheck, Priority, Protocol, SendRequestError,
+    TunnelPermit,
 };
 use actix_http::h1::ClientCodec;
 use actix_http::{RequestHead, RequestHeadType, ResponseHead};
@@ -12,7 +18,7 @@ use actix_http::http::HeaderMap;
 use actix_service::Service;
 use futures::{Future, Poll};
 
-use crate::response::ClientResponse;
+use crate::response::{ClientResponse, Timings};
 
 pub(crate) struct ConnectorWrapper<T>(pub T);
 
@@ -32,19 +38,20 @@ pub(crate) trait Connect {
         addr: Option<net::SocketAddr>,
     ) -> Box<dyn Future<Item = ClientResponse, Error = SendRequestError>>;
 
-    /// Send request, returns Response and Framed
+    /// Send request, returns Response, Framed and negotiated protocol
     fn open_tunnel(
         &mut self,
         head: RequestHead,
         addr: Option<net::SocketAddr>,
     ) -> Box<
         dyn Future<
-            Item = (ResponseHead, Framed<BoxedSocket, ClientCodec>),
+            Item = (ResponseHead, Framed<BoxedSocket, ClientCodec>, Protocol),
             Error = SendRequestError,
         >,
     >;
 
-    /// Send request and extra headers, returns Response and Framed
+    /// Send request and extra headers, returns Response, Framed and
+    /// negotiated protocol
     fn open_tunnel_extra(
         &mut self,
         head: Rc<RequestHead>,
@@ -52,7 +59,7 @@ pub(crate) trait Connect {
         addr: Option<net::SocketAddr>,
     ) -> Box<
         dyn Future<
-            Item = (ResponseHead, Framed<BoxedSocket, ClientCodec>),
+            Item = (ResponseHead, Framed<BoxedSocket, ClientCodec>, Protocol),
             Error = SendRequestError,
         >,
     >;
@@ -73,17 +80,27 @@ where
         body: Body,
         addr: Option<net::SocketAddr>,
     ) -> Box<dyn Future<Item = ClientResponse, Error = SendRequestError>> {
+        let start = Instant::now();
         Box::new(
             self.0
                 // connect to the host
-                .call(ClientConnect {
-                    uri: head.uri.clone(),
-                    addr,
-                })
+                .call(connect_request(&head, addr))
                 .from_err()
                 // send request
-                .and_then(move |connection| connection.send_request(RequestHeadType::from(head), body))
-                .map(|(head, payload)| ClientResponse::new(head, payload)),
+                .and_then(move |connection| {
+                    let acquired = Instant::now();
+                    let protocol = connection.protocol();
+                    connection
+                        .send_request(RequestHeadType::from(head), body)
+                        .map(move |(head, payload)| {
+                            head.extensions_mut().insert(protocol);
+                            head.extensions_mut().insert(Timings {
+                                acquire: acquired - start,
+                                send: acquired.elapsed(),
+                            });
+                            ClientResponse::new(head, payload)
+                        })
+                }),
         )
     }
 
@@ -94,17 +111,27 @@ where
         body: Body,
         addr: Option<net::SocketAddr>,
     ) -> Box<dyn Future<Item = ClientResponse, Error = SendRequestError>> {
+        let start = Instant::now();
         Box::new(
             self.0
                 // connect to the host
-                .call(ClientConnect {
-                    uri: head.uri.clone(),
-                    addr,
-                })
+                .call(connect_request(&head, addr))
                 .from_err()
                 // send request
-                .and_then(move |connection| connection.send_request(RequestHeadType::Rc(head, extra_headers), body))
-                .map(|(head, payload)| ClientResponse::new(head, payload)),
+                .and_then(move |connection| {
+                    let acquired = Instant::now();
+                    let protocol = connection.protocol();
+                    connection
+                        .send_request(RequestHeadType::Rc(head, extra_headers), body)
+                        .map(move |(head, payload)| {
+                            head.extensions_mut().insert(protocol);
+                            head.extensions_mut().insert(Timings {
+                                acquire: acquired - start,
+                                send: acquired.elapsed(),
+                            });
+                            ClientResponse::new(head, payload)
+                        })
+                }),
         )
     }
 
@@ -114,23 +141,27 @@ where
         addr: Option<net::SocketAddr>,
     ) -> Box<
         dyn Future<
-            Item = (ResponseHead, Framed<BoxedSocket, ClientCodec>),
+            Item = (ResponseHead, Framed<BoxedSocket, ClientCodec>, Protocol),
             Error = SendRequestError,
         >,
     > {
         Box::new(
             self.0
                 // connect to the host
-                .call(ClientConnect {
-                    uri: head.uri.clone(),
-                    addr,
-                })
+                .call(connect_request(&head, addr))
                 .from_err()
                 // send request
-                .and_then(move |connection| connection.open_tunnel(RequestHeadType::from(head)))
-                .map(|(head, framed)| {
-                    let framed = framed.map_io(|io| BoxedSocket(Box::new(Socket(io))));
-                    (head, framed)
+                .and_then(move |connection| {
+                    let protocol = connection.protocol();
+                    let fd = raw_fd(&connection);
+                    connection
+                        .open_tunnel(RequestHeadType::from(head))
+                        .map(move |(head, framed, permit)| {
+                            (head, framed, permit, protocol, fd)
+                        })
+                })
+                .map(|(head, framed, permit, protocol, fd)| {
+                    (head, boxed_tunnel(framed, fd, permit), protocol)
                 }),
         )
     }
@@ -142,50 +173,145 @@ where
         addr: Option<net::SocketAddr>,
     ) -> Box<
         dyn Future<
-            Item = (ResponseHead, Framed<BoxedSocket, ClientCodec>),
+            Item = (ResponseHead, Framed<BoxedSocket, ClientCodec>, Protocol),
             Error = SendRequestError,
         >,
     > {
         Box::new(
             self.0
                 // connect to the host
-                .call(ClientConnect {
-                    uri: head.uri.clone(),
-                    addr,
-                })
+                .call(connect_request(&head, addr))
                 .from_err()
                 // send request
-                .and_then(move |connection| connection.open_tunnel(RequestHeadType::Rc(head, extra_headers)))
-                .map(|(head, framed)| {
-                    let framed = framed.map_io(|io| BoxedSocket(Box::new(Socket(io))));
-                    (head, framed)
+                .and_then(move |connection| {
+                    let protocol = connection.protocol();
+                    let fd = raw_fd(&connection);
+                    connection
+                        .open_tunnel(RequestHeadType::Rc(head, extra_headers))
+                        .map(move |(head, framed, permit)| {
+                            (head, framed, permit, protocol, fd)
+                        })
+                })
+                .map(|(head, framed, permit, protocol, fd)| {
+                    (head, boxed_tunnel(framed, fd, permit), protocol)
                 }),
         )
     }
 }
 
+/// Connect request to the host of request head
+fn connect_request(head: &RequestHead, addr: Option<net::SocketAddr>) -> ClientConnect {
+    ClientConnect::new(head.uri.clone())
+        .set_addr(addr)
+        .priority(request_priority(head))
+        .group(request_group(head))
+        .timeout(request_timeout(head))
+        .deadline(request_deadline(head))
+        .health_check(is_health_check(head))
+}
+
+/// Connection pool priority stored in request extensions
+fn request_priority(head: &RequestHead) -> Priority {
+    head.extensions().get::<Priority>().cloned().unwrap_or_default()
+}
+
+/// Connection group stored in request extensions
+fn request_group(head: &RequestHead) -> Option<ConnectionGroup> {
+    head.extensions().get::<ConnectionGroup>().cloned()
+}
+
+/// Connect timeout stored in request extensions
+fn request_timeout(head: &RequestHead) -> Option<Duration> {
+    head.extensions().get::<ConnectTimeout>().map(|timeout| timeout.0)
+}
+
+/// Request deadline stored in request extensions
+fn request_deadline(head: &RequestHead) -> Option<Instant> {
+    head.extensions().get::<Deadline>().map(|deadline| deadline.0)
+}
+
+/// Health check mark stored in request extensions
+fn is_health_check(head: &RequestHead) -> bool {
+    head.extensions().contains::<HealthCheck>()
+}
+
+/// Box io of tunnel, socket keeps tunnel permit while it is used
+fn boxed_tunnel<T>(
+    framed: Framed<T, ClientCodec>,
+    fd: Option<RawFd>,
+    permit: TunnelPermit,
+) -> Framed<BoxedSocket, ClientCodec>
+where
+    T: AsyncRead + AsyncWrite + 'static,
+{
+    let permit = Cell::new(permit);
+    framed.map_io(|io| {
+        BoxedSocket(Box::new(Socket {
+            io,
+            fd,
+            _permit: permit.take(),
+        }))
+    })
+}
+
+/// Raw file descriptor of connection socket
+#[cfg(unix)]
+fn raw_fd<C: Connection>(conn: &C) -> Option<RawFd> {
+    conn.as_raw_fd()
+}
+
+#[cfg(not(unix))]
+type RawFd = ();
+
+#[cfg(not(unix))]
+fn raw_fd<C: Connection>(_: &C) -> Option<RawFd> {
+    None
+}
+
 trait AsyncSocket {
     fn as_read(&self) -> &dyn AsyncRead;
     fn as_read_mut(&mut self) -> &mut dyn AsyncRead;
     fn as_write(&mut self) -> &mut dyn AsyncWrite;
+    fn raw_fd(&self) -> Option<RawFd>;
 }
 
-struct Socket<T: AsyncRead + AsyncWrite>(T);
+/// Tunnel socket, keeps tunnel permit of the connector
+struct Socket<T: AsyncRead + AsyncWrite> {
+    io: T,
+    fd: Option<RawFd>,
+    /// releases tunnel slot of the connector on drop
+    _permit: TunnelPermit,
+}
 
 impl<T: AsyncRead + AsyncWrite> AsyncSocket for Socket<T> {
     fn as_read(&self) -> &dyn AsyncRead {
-        &self.0
+        &self.io
     }
     fn as_read_mut(&mut self) -> &mut dyn AsyncRead {
-        &mut self.0
+        &mut self.io
     }
     fn as_write(&mut self) -> &mut dyn AsyncWrite {
-        &mut self.0
+        &mut self.io
+    }
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.fd
     }
 }
 
 pub struct BoxedSocket(Box<dyn AsyncSocket>);
 
+impl BoxedSocket {
+    /// Raw file descriptor of the socket.
+    ///
+    /// Descriptor is available for sockets of tcp and tls connections
+    /// opened by default connector. Socket keeps ownership of descriptor,
+    /// it must not be closed.
+    #[cfg(unix)]
+    pub fn as_raw_fd(&self) -> Option<RawFd> {
+        self.0.raw_fd()
+    }
+}
+
 impl fmt::Debug for BoxedSocket {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "BoxedSocket")
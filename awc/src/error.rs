@@ -1,10 +1,11 @@
 //! Http client errors
 pub use actix_http::client::{ConnectError, InvalidUrl, SendRequestError, FreezeRequestError};
-pub use actix_http::error::PayloadError;
+pub use actix_http::error::{ContentTypeError, PayloadError};
 pub use actix_http::ws::HandshakeError as WsHandshakeError;
 pub use actix_http::ws::ProtocolError as WsProtocolError;
 
 use actix_http::{Response, ResponseError};
+use bytes::Bytes;
 use serde_json::error::Error as JsonError;
 
 use actix_http::http::{header::HeaderValue, Error as HttpError, StatusCode};
@@ -65,9 +66,66 @@ pub enum JsonPayloadError {
     Payload(PayloadError),
 }
 
+/// A set of errors that can occur during decoding text payloads
+#[derive(Debug, Display, From)]
+pub enum TextError {
+    /// Content type error, charset is unknown
+    #[display(fmt = "Content type error: {}", _0)]
+    ContentType(ContentTypeError),
+    /// Body is not valid text in declared charset
+    #[display(fmt = "Body can not be decoded with declared charset")]
+    Decode,
+    /// Payload error
+    #[display(fmt = "Error that occur during reading payload: {}", _0)]
+    Payload(PayloadError),
+}
+
 /// Return `InternalServerError` for `JsonPayloadError`
 impl ResponseError for JsonPayloadError {
     fn error_response(&self) -> Response {
         Response::new(StatusCode::INTERNAL_SERVER_ERROR)
     }
 }
+
+/// A set of errors that can occur during blocking request execution
+#[derive(Debug, Display, From)]
+pub enum BlockingError {
+    /// Send request error
+    #[display(fmt = "{}", _0)]
+    SendRequest(SendRequestError),
+    /// Payload error
+    #[display(fmt = "{}", _0)]
+    Payload(PayloadError),
+}
+
+/// A set of errors that can occur during typed json request execution
+#[derive(Debug, Display, From)]
+pub enum FetchJsonError {
+    /// Send request error
+    #[display(fmt = "{}", _0)]
+    SendRequest(SendRequestError),
+    /// Server responded with non-success status, contains response body
+    #[display(fmt = "Unexpected response status: {}", _0)]
+    Status(StatusCode, Bytes),
+    /// Json payload error
+    #[display(fmt = "{}", _0)]
+    Json(JsonPayloadError),
+}
+
+/// A set of errors that can occur during request body checksum verification
+#[cfg(feature = "checksum")]
+#[derive(Debug, Display, From)]
+pub enum ChecksumError {
+    /// Send request error
+    #[display(fmt = "{}", _0)]
+    SendRequest(SendRequestError),
+    /// Request body is not fully sent
+    #[display(fmt = "Request body is not fully sent")]
+    Incomplete,
+    /// Response does not contain checksum header
+    #[display(fmt = "Checksum header is missing")]
+    MissingHeader,
+    /// Checksum in response header does not match sent body
+    #[display(fmt = "Checksum mismatch, expected {} got {}", expected, actual)]
+    Mismatch { expected: String, actual: String },
+}
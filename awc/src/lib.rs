@@ -25,23 +25,37 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use std::time::Duration;
 
-pub use actix_http::{client::Connector, cookie, http};
+pub use actix_http::client::{
+    Connector, HostConflictPolicy, QueuePolicy, TransferEncoding,
+};
+pub use actix_http::{cookie, http};
 
 use actix_http::http::{HeaderMap, HttpTryFrom, Method, Uri};
 use actix_http::RequestHead;
 
+pub mod blocking;
 mod builder;
+#[cfg(feature = "checksum")]
+mod checksum;
 mod connect;
 pub mod error;
+mod redirect;
 mod request;
 mod response;
+mod retry;
 pub mod test;
 pub mod ws;
 
 pub use self::builder::ClientBuilder;
+#[cfg(feature = "checksum")]
+pub use self::checksum::{Checksum, ChecksumBody};
 pub use self::connect::BoxedSocket;
-pub use self::request::ClientRequest;
-pub use self::response::{ClientResponse, JsonBody, MessageBody};
+pub use self::request::{ClientRequest, RequestSnapshot};
+pub use self::response::{
+    BodyFraming, ClientResponse, JsonBody, MessageBody, NdJson, PartialBody, TailBody,
+    TextBody, Timings,
+};
+pub use self::retry::RetryPolicy;
 
 use self::connect::{Connect, ConnectorWrapper};
 
@@ -74,6 +88,9 @@ pub(crate) struct ClientConfig {
     pub(crate) connector: RefCell<Box<dyn Connect>>,
     pub(crate) headers: HeaderMap,
     pub(crate) timeout: Option<Duration>,
+    pub(crate) max_redirects: usize,
+    pub(crate) strict_redirects: bool,
+    pub(crate) retry_policy: Option<RetryPolicy>,
 }
 
 impl Default for Client {
@@ -84,6 +101,9 @@ impl Default for Client {
             ))),
             headers: HeaderMap::new(),
             timeout: Some(Duration::from_secs(5)),
+            max_redirects: 10,
+            strict_redirects: false,
+            retry_policy: None,
         }))
     }
 }
@@ -127,6 +147,15 @@ impl Client {
         req
     }
 
+    /// Create `ClientRequest` from `RequestSnapshot`
+    ///
+    /// Method, uri, version and headers are taken from the snapshot,
+    /// client default headers are not applied.
+    pub fn request_from_snapshot(&self, snapshot: &RequestSnapshot) -> ClientRequest {
+        ClientRequest::new(Method::GET, snapshot.uri.as_str(), self.0.clone())
+            .restore(snapshot)
+    }
+
     /// Construct HTTP *GET* request.
     pub fn get<U>(&self, url: U) -> ClientRequest
     where
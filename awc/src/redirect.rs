@@ -0,0 +1,339 @@
+use std::net;
+use std::rc::Rc;
+
+use actix_http::body::Body;
+use actix_http::client::{
+    ConnectTimeout, ConnectionGroup, Deadline, HealthCheck, Priority, SendRequestError,
+};
+use actix_http::http::{header, HeaderMap, Method, StatusCode, Uri};
+use actix_http::RequestHead;
+use futures::{Async, Future, Poll};
+
+use crate::response::ClientResponse;
+use crate::{ClientConfig, TransferEncoding};
+
+/// Future that follows redirect responses up to `remaining` times.
+///
+/// Only requests with a body that can be sent again (empty or in-memory
+/// bytes) are followed, streaming requests are never redirected.
+pub(crate) struct FollowRedirects {
+    fut: Box<dyn Future<Item = ClientResponse, Error = SendRequestError>>,
+    head: Rc<RequestHead>,
+    extra_headers: Option<HeaderMap>,
+    body: Body,
+    addr: Option<net::SocketAddr>,
+    remaining: usize,
+    config: Rc<ClientConfig>,
+}
+
+impl FollowRedirects {
+    pub(crate) fn new(
+        fut: Box<dyn Future<Item = ClientResponse, Error = SendRequestError>>,
+        head: Rc<RequestHead>,
+        extra_headers: Option<HeaderMap>,
+        body: Body,
+        addr: Option<net::SocketAddr>,
+        remaining: usize,
+        config: Rc<ClientConfig>,
+    ) -> Self {
+        FollowRedirects {
+            fut,
+            head,
+            extra_headers,
+            body,
+            addr,
+            remaining,
+            config,
+        }
+    }
+
+    /// Copy body if it can be sent more than once.
+    pub(crate) fn clone_body(body: &Body) -> Option<Body> {
+        match body {
+            Body::None => Some(Body::None),
+            Body::Empty => Some(Body::Empty),
+            Body::Bytes(ref bytes) => Some(Body::Bytes(bytes.clone())),
+            Body::Message(_) => None,
+        }
+    }
+
+    /// Build request for redirect response, if response is a redirect.
+    fn redirect(&mut self, res: &ClientResponse) -> Option<(Rc<RequestHead>, Body)> {
+        let method = match res.status() {
+            StatusCode::SEE_OTHER if self.head.method != Method::HEAD => Method::GET,
+            StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND
+                if self.head.method == Method::POST =>
+            {
+                Method::GET
+            }
+            StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT => self.head.method.clone(),
+            _ => return None,
+        };
+        let uri = res
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|loc| loc.to_str().ok())
+            .and_then(|loc| resolve_location(&self.head.uri, loc))?;
+
+        let mut head = RequestHead::default();
+        head.method = method;
+        head.version = self.head.version;
+        head.headers = self.head.headers.clone();
+        head.set_camel_case_headers(self.head.camel_case_headers());
+        head.set_accept_trailers(self.head.accept_trailers());
+        if let Some(te) = self.head.extensions().get::<TransferEncoding>() {
+            head.extensions_mut().insert(*te);
+        }
+        if let Some(priority) = self.head.extensions().get::<Priority>() {
+            head.extensions_mut().insert(*priority);
+        }
+        if let Some(group) = self.head.extensions().get::<ConnectionGroup>() {
+            head.extensions_mut().insert(*group);
+        }
+        if let Some(timeout) = self.head.extensions().get::<ConnectTimeout>() {
+            head.extensions_mut().insert(*timeout);
+        }
+        if let Some(deadline) = self.head.extensions().get::<Deadline>() {
+            head.extensions_mut().insert(*deadline);
+        }
+        if self.head.extensions().contains::<HealthCheck>() {
+            head.extensions_mut().insert(HealthCheck);
+        }
+        head.set_connection_type(self.head.connection_type());
+        if let Some(extra_headers) = self.extra_headers.take() {
+            for (key, value) in extra_headers.iter() {
+                head.headers.insert(key.clone(), value.clone());
+            }
+        }
+
+        let body = if head.method != self.head.method {
+            head.headers.remove(header::CONTENT_TYPE);
+            head.headers.remove(header::CONTENT_LENGTH);
+            Body::Empty
+        } else {
+            FollowRedirects::clone_body(&self.body)?
+        };
+        self.body = FollowRedirects::clone_body(&body)?;
+
+        // do not leak credentials to other hosts
+        if uri.scheme_part() != self.head.uri.scheme_part()
+            || uri.authority_part() != self.head.uri.authority_part()
+        {
+            head.headers.remove(header::AUTHORIZATION);
+            head.headers.remove(header::COOKIE);
+            head.headers.remove(header::HOST);
+            self.addr = None;
+        }
+        head.uri = uri;
+
+        let head = Rc::new(head);
+        self.head = head.clone();
+        Some((head, body))
+    }
+}
+
+impl Future for FollowRedirects {
+    type Item = ClientResponse;
+    type Error = SendRequestError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let res = futures::try_ready!(self.fut.poll());
+            if self.remaining == 0 {
+                return Ok(Async::Ready(res));
+            }
+
+            match self.redirect(&res) {
+                Some((head, body)) => {
+                    self.remaining -= 1;
+                    self.fut = self.config.connector.borrow_mut().send_request_extra(
+                        head, None, body, self.addr,
+                    );
+                }
+                None => return Ok(Async::Ready(res)),
+            }
+        }
+    }
+}
+
+/// Fail redirect response without `Location` header, used in strict mode.
+///
+/// Applied to final response, so it covers requests that are not
+/// followed, i.e. streaming requests or requests with redirects disabled.
+pub(crate) fn check_location(
+    res: ClientResponse,
+) -> Result<ClientResponse, SendRequestError> {
+    if is_redirect(res.status()) && !res.headers().contains_key(header::LOCATION) {
+        Err(SendRequestError::RedirectNoLocation)
+    } else {
+        Ok(res)
+    }
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    match status {
+        StatusCode::MOVED_PERMANENTLY
+        | StatusCode::FOUND
+        | StatusCode::SEE_OTHER
+        | StatusCode::TEMPORARY_REDIRECT
+        | StatusCode::PERMANENT_REDIRECT => true,
+        _ => false,
+    }
+}
+
+/// Resolve `Location` header value against request uri.
+///
+/// Reference resolution follows RFC 3986 section 5.2, fragment is dropped.
+fn resolve_location(base: &Uri, location: &str) -> Option<Uri> {
+    let location = location.splitn(2, '#').next().unwrap_or("");
+    let (reference, query) = match location.find('?') {
+        Some(idx) => (&location[..idx], Some(&location[idx..])),
+        None => (location, None),
+    };
+    let base_scheme = base.scheme_part()?.as_str();
+    let base_authority = base.authority_part()?.as_str();
+
+    let (scheme, authority, path, query) = if let Some(len) = scheme_len(reference) {
+        let rest = &reference[len + 1..];
+        if !rest.starts_with("//") {
+            return None;
+        }
+        let (authority, path) = split_authority(&rest[2..]);
+        (&reference[..len], authority, remove_dot_segments(path), query)
+    } else if reference.starts_with("//") {
+        let (authority, path) = split_authority(&reference[2..]);
+        (base_scheme, authority, remove_dot_segments(path), query)
+    } else if reference.is_empty() {
+        let base_query = base
+            .path_and_query()
+            .map(|pq| &pq.as_str()[pq.path().len()..]);
+        let path = base.path().to_owned();
+        (base_scheme, base_authority, path, query.or(base_query))
+    } else if reference.starts_with('/') {
+        let path = remove_dot_segments(reference);
+        (base_scheme, base_authority, path, query)
+    } else {
+        let base_path = base.path();
+        let idx = base_path.rfind('/').map(|idx| idx + 1).unwrap_or(0);
+        let path = remove_dot_segments(&format!("{}{}", &base_path[..idx], reference));
+        (base_scheme, base_authority, path, query)
+    };
+    format!("{}://{}{}{}", scheme, authority, path, query.unwrap_or(""))
+        .parse()
+        .ok()
+}
+
+/// Length of uri reference scheme, if reference has one
+fn scheme_len(reference: &str) -> Option<usize> {
+    let len = reference.find(':')?;
+    let scheme = &reference[..len];
+    let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+    if valid {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Split authority and path of network-path reference
+fn split_authority(reference: &str) -> (&str, &str) {
+    let idx = reference.find('/').unwrap_or_else(|| reference.len());
+    (&reference[..idx], &reference[idx..])
+}
+
+/// Remove `.` and `..` segments of absolute path, RFC 3986 section 5.2.4
+fn remove_dot_segments(path: &str) -> String {
+    let mut segments = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path.split('/').skip(1) {
+        trailing_slash = segment == "." || segment == "..";
+        match segment {
+            "." => (),
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut result = String::with_capacity(path.len() + 1);
+    for segment in segments {
+        result.push('/');
+        result.push_str(segment);
+    }
+    if trailing_slash || result.is_empty() {
+        result.push('/');
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_location() {
+        let base = Uri::from_static("http://localhost:8080/a/b?q=1");
+        assert_eq!(
+            resolve_location(&base, "/c").unwrap(),
+            Uri::from_static("http://localhost:8080/c")
+        );
+        assert_eq!(
+            resolve_location(&base, "c?x=2").unwrap(),
+            Uri::from_static("http://localhost:8080/a/c?x=2")
+        );
+        assert_eq!(
+            resolve_location(&base, "https://example.com/").unwrap(),
+            Uri::from_static("https://example.com/")
+        );
+        assert_eq!(
+            resolve_location(&base, "?x=2#frag").unwrap(),
+            Uri::from_static("http://localhost:8080/a/b?x=2")
+        );
+        assert_eq!(
+            resolve_location(&base, "#frag").unwrap(),
+            Uri::from_static("http://localhost:8080/a/b?q=1")
+        );
+    }
+
+    #[test]
+    fn test_resolve_network_path() {
+        let base = Uri::from_static("https://localhost:8080/a/b");
+        assert_eq!(
+            resolve_location(&base, "//example.com/c").unwrap(),
+            Uri::from_static("https://example.com/c")
+        );
+        assert_eq!(
+            resolve_location(&base, "//example.com:8443").unwrap(),
+            Uri::from_static("https://example.com:8443/")
+        );
+        assert_eq!(
+            resolve_location(&base, "//example.com/c/../d?x=1").unwrap(),
+            Uri::from_static("https://example.com/d?x=1")
+        );
+    }
+
+    #[test]
+    fn test_resolve_dot_segments() {
+        let base = Uri::from_static("http://localhost/a/b/c");
+        let resolve = |location| resolve_location(&base, location).unwrap();
+        assert_eq!(resolve("./d"), Uri::from_static("http://localhost/a/b/d"));
+        assert_eq!(resolve("../d"), Uri::from_static("http://localhost/a/d"));
+        assert_eq!(resolve("../../d"), Uri::from_static("http://localhost/d"));
+        assert_eq!(resolve("../../../d"), Uri::from_static("http://localhost/d"));
+        assert_eq!(resolve("."), Uri::from_static("http://localhost/a/b/"));
+        assert_eq!(resolve(".."), Uri::from_static("http://localhost/a/"));
+        assert_eq!(resolve("/d/./e/../f"), Uri::from_static("http://localhost/d/f"));
+        assert_eq!(
+            resolve("http://example.com/x/../y"),
+            Uri::from_static("http://example.com/y")
+        );
+    }
+}
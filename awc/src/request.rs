@@ -2,19 +2,26 @@ use std::fmt::Write as FmtWrite;
 use std::io::Write;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
-use std::{fmt, net};
+use std::{cmp, fmt, net};
 
 use bytes::{BufMut, Bytes, BytesMut};
+use futures::future::Either;
 use futures::{Async, Future, Poll, Stream, try_ready};
 use percent_encoding::percent_encode;
-use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json;
 use tokio_timer::Delay;
 use derive_more::From;
 
 use actix_http::body::{Body, BodyStream};
+use actix_http::client::{
+    CaptureSentHeaders, ConnectTimeout, ConnectionGroup, Deadline, DecompressLimit,
+    HealthCheck, MaxResponseHeaderSize, MaxResponseHeaders, PerReadTimeout, Priority,
+    ReadIdleTimeout,
+};
 use actix_http::cookie::{Cookie, CookieJar, USERINFO};
-use actix_http::encoding::Decoder;
+use actix_http::encoding::{Decoder, Encoder};
 use actix_http::http::header::{self, ContentEncoding, Header, IntoHeaderValue};
 use actix_http::http::{
     uri, ConnectionType, Error as HttpError, HeaderMap, HeaderName, HeaderValue,
@@ -22,9 +29,11 @@ use actix_http::http::{
 };
 use actix_http::{Error, Payload, PayloadStream, RequestHead};
 
-use crate::error::{InvalidUrl, SendRequestError, FreezeRequestError};
+use crate::error::{FetchJsonError, InvalidUrl, SendRequestError, FreezeRequestError};
+use crate::redirect::{check_location, FollowRedirects};
+use crate::retry::RetryRequest;
 use crate::response::ClientResponse;
-use crate::ClientConfig;
+use crate::{ClientConfig, TransferEncoding};
 
 #[cfg(any(feature = "brotli", feature = "flate2-zlib", feature = "flate2-rust"))]
 const HTTPS_ENCODING: &str = "br, gzip, deflate";
@@ -64,6 +73,7 @@ pub struct ClientRequest {
     cookies: Option<CookieJar>,
     response_decompress: bool,
     timeout: Option<Duration>,
+    max_redirects: Option<usize>,
     config: Rc<ClientConfig>,
 }
 
@@ -80,6 +90,7 @@ impl ClientRequest {
             addr: None,
             cookies: None,
             timeout: None,
+            max_redirects: None,
             response_decompress: true,
         }
         .method(method)
@@ -246,6 +257,160 @@ impl ClientRequest {
         self
     }
 
+    /// Advertise support of response trailers with `TE: trailers` header.
+    /// `TE` is added to `Connection` header as well.
+    /// This setting affect only http/1 connections.
+    #[inline]
+    pub fn accept_trailers(mut self, val: bool) -> Self {
+        self.head.set_accept_trailers(val);
+        self
+    }
+
+    /// Re-encode request path before sending.
+    ///
+    /// By default path is sent verbatim, already encoded sequences
+    /// are passed through unchanged. If set to `true` escaped unreserved
+    /// characters are decoded, i.e. `%7E` is sent as `~`, while reserved
+    /// ones like `%2F` are kept.
+    /// This setting affect only http/1 connections.
+    #[inline]
+    pub fn encode_path(mut self, val: bool) -> Self {
+        self.head.set_encode_path(val);
+        self
+    }
+
+    /// Override request body framing.
+    ///
+    /// By default framing is selected by the body type, `Content-Length`
+    /// for bodies of known size and chunked encoding for streams.
+    /// Sending stream with `TransferEncoding::ContentLength` fails with
+    /// `SendRequestError::UnknownBodyLength` error.
+    /// This setting affect only http/1 connections.
+    pub fn transfer_encoding(self, te: TransferEncoding) -> Self {
+        self.head.extensions_mut().insert(te);
+        self
+    }
+
+    /// Set connection pool priority of the request.
+    ///
+    /// Priority is used when request waits for a connection and connector
+    /// is configured with `QueuePolicy::Priority`, requests with higher
+    /// priority get connection first. Default priority is 0.
+    pub fn priority(self, priority: u8) -> Self {
+        self.head.extensions_mut().insert(Priority(priority));
+        self
+    }
+
+    /// Set connection group of the request.
+    ///
+    /// Connections of a group are limited across all hosts if connector
+    /// is configured with `limit_per_group()`.
+    pub fn connection_group(self, group: u64) -> Self {
+        self.head.extensions_mut().insert(ConnectionGroup(group));
+        self
+    }
+
+    /// Set connect timeout for this request.
+    ///
+    /// Overrides connector default, applies only if request opens new
+    /// connection to the host.
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        self.head.extensions_mut().insert(ConnectTimeout(timeout));
+        self
+    }
+
+    /// Mark this request as health check.
+    ///
+    /// Health check could use connections reserved with
+    /// `Connector::health_check_reserve()` if connections limit is reached.
+    pub fn health_check(self) -> Self {
+        self.head.extensions_mut().insert(HealthCheck);
+        self
+    }
+
+    /// Capture request headers as they are sent to the server.
+    ///
+    /// Captured headers are available with `ClientResponse::sent_headers()`.
+    pub fn capture_sent_headers(self) -> Self {
+        self.head.extensions_mut().insert(CaptureSentHeaders);
+        self
+    }
+
+    /// Set absolute deadline for this request.
+    ///
+    /// Waiting for connection pool slot, connecting and receiving response
+    /// share the deadline, time spent in the pool queue shortens connect
+    /// timeout. Request fails with `SendRequestError::Timeout` once the
+    /// deadline passes, request timeout still applies if it expires earlier.
+    pub fn deadline(self, deadline: Instant) -> Self {
+        self.head.extensions_mut().insert(Deadline(deadline));
+        self
+    }
+
+    /// Set max size of response head for this request.
+    ///
+    /// Overrides connector default. Request fails if response head
+    /// exceeds this size. This setting affect only http/1 connections.
+    pub fn max_response_header_size(self, size: usize) -> Self {
+        self.head
+            .extensions_mut()
+            .insert(MaxResponseHeaderSize(size));
+        self
+    }
+
+    /// Set max number of response headers for this request.
+    ///
+    /// Overrides connector default. Request fails if response contains
+    /// more headers. This setting affect only http/1 connections.
+    pub fn max_response_headers(self, num: usize) -> Self {
+        self.head.extensions_mut().insert(MaxResponseHeaders(num));
+        self
+    }
+
+    /// Set max period without response payload data for this request.
+    ///
+    /// Overrides connector default. Period restarts after each payload
+    /// chunk. This setting affect only http/1 connections.
+    pub fn read_idle_timeout(self, timeout: Duration) -> Self {
+        self.head.extensions_mut().insert(ReadIdleTimeout(timeout));
+        self
+    }
+
+    /// Set max period of single response payload read for this request.
+    ///
+    /// Overrides connector default. Period starts when payload is polled
+    /// and no data is available. This setting affect only http/1 connections.
+    pub fn per_read_timeout(self, timeout: Duration) -> Self {
+        self.head.extensions_mut().insert(PerReadTimeout(timeout));
+        self
+    }
+
+    /// Never retry this request.
+    ///
+    /// Disables client retry policy for this request, failed request is
+    /// not sent again even if it is idempotent. Redirects are still followed.
+    pub fn no_retry(self) -> Self {
+        self.head.extensions_mut().insert(NoRetry);
+        self
+    }
+
+    /// Make request conditional on validators of previously received response.
+    ///
+    /// `ETag` is sent back as `If-None-Match` and `Last-Modified` as
+    /// `If-Modified-Since` header. Use `ClientResponse::is_not_modified()`
+    /// to check if cached response is still valid.
+    pub fn conditional_on<S>(mut self, res: &ClientResponse<S>) -> Self {
+        if let Some(etag) = res.headers().get(header::ETAG) {
+            self.head.headers.insert(header::IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(modified) = res.headers().get(header::LAST_MODIFIED) {
+            self.head
+                .headers
+                .insert(header::IF_MODIFIED_SINCE, modified.clone());
+        }
+        self
+    }
+
     /// Force close connection instead of returning it back to connections pool.
     /// This setting affect only http/1 connections.
     #[inline]
@@ -340,6 +505,20 @@ impl ClientRequest {
         self
     }
 
+    /// Compress request body with specified encoding and compression level.
+    ///
+    /// Level is `0-9` for gzip and deflate and `0-11` for brotli,
+    /// `Content-Encoding` header is set if encoding is supported.
+    /// In-memory body is compressed before sending, stream body is
+    /// compressed on the fly and sent with chunked transfer encoding.
+    /// Stream body can not be redirected or retried.
+    pub fn compress(self, encoding: ContentEncoding, level: u32) -> Self {
+        self.head
+            .extensions_mut()
+            .insert(RequestCompression(encoding, level));
+        self
+    }
+
     /// Set request timeout. Overrides client wide timeout setting.
     ///
     /// Request timeout is the total time before a response must be received.
@@ -349,6 +528,15 @@ impl ClientRequest {
         self
     }
 
+    /// Set max number of redirects to follow for this request.
+    /// Overrides client wide setting.
+    ///
+    /// Setting it to 0 disables following redirects for this request.
+    pub fn max_redirects(mut self, num: usize) -> Self {
+        self.max_redirects = Some(num);
+        self
+    }
+
     /// This method calls provided closure with builder reference if
     /// value is `true`.
     pub fn if_true<F>(self, value: bool, f: F) -> Self
@@ -375,6 +563,60 @@ impl ClientRequest {
         }
     }
 
+    /// Capture method, uri, version and headers of the request.
+    ///
+    /// Cookies are captured as `Cookie` header, request body and
+    /// settings like timeout are not captured. Request could be
+    /// reconstructed with `Client::request_from_snapshot()`.
+    pub fn snapshot(&self) -> RequestSnapshot {
+        let mut headers: Vec<_> = self
+            .head
+            .headers
+            .iter()
+            .filter(|(key, _)| self.cookies.is_none() || *key != header::COOKIE)
+            .map(|(key, value)| {
+                (
+                    key.as_str().to_owned(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect();
+        if let Some(ref jar) = self.cookies {
+            let cookie = cookie_header(jar, &self.head.headers);
+            headers.push((
+                header::COOKIE.as_str().to_owned(),
+                String::from_utf8_lossy(cookie.as_bytes()).into_owned(),
+            ));
+        }
+
+        RequestSnapshot {
+            method: self.head.method.as_str().to_owned(),
+            uri: self.head.uri.to_string(),
+            version: format!("{:?}", self.head.version),
+            headers,
+        }
+    }
+
+    /// Restore request head from snapshot, existing headers are replaced.
+    pub(crate) fn restore(mut self, snapshot: &RequestSnapshot) -> Self {
+        match Method::from_bytes(snapshot.method.as_bytes()) {
+            Ok(method) => self.head.method = method,
+            Err(e) => self.err = Some(e.into()),
+        }
+        self.head.version = match snapshot.version.as_str() {
+            "HTTP/0.9" => Version::HTTP_09,
+            "HTTP/1.0" => Version::HTTP_10,
+            "HTTP/2.0" => Version::HTTP_2,
+            _ => Version::HTTP_11,
+        };
+        self.head.headers.clear();
+
+        for (key, value) in &snapshot.headers {
+            self = self.header(key.as_str(), value.as_str());
+        }
+        self
+    }
+
     pub fn freeze(self) -> Result<FrozenClientRequest, FreezeRequestError> {
         let slf = match self.prep_for_sending() {
             Ok(slf) => slf,
@@ -386,6 +628,7 @@ impl ClientRequest {
             addr: slf.addr,
             response_decompress: slf.response_decompress,
             timeout: slf.timeout,
+            max_redirects: slf.max_redirects,
             config: slf.config,
         };
 
@@ -406,7 +649,7 @@ impl ClientRequest {
         };
 
         RequestSender::Owned(slf.head)
-            .send_body(slf.addr, slf.response_decompress, slf.timeout, slf.config.as_ref(), body)
+            .send_body(slf.addr, slf.response_decompress, slf.timeout, slf.max_redirects, &slf.config, body)
     }
 
     /// Set a JSON body and generate `ClientRequest`
@@ -421,7 +664,7 @@ impl ClientRequest {
         };
 
         RequestSender::Owned(slf.head)
-            .send_json(slf.addr, slf.response_decompress, slf.timeout, slf.config.as_ref(), value)
+            .send_json(slf.addr, slf.response_decompress, slf.timeout, slf.max_redirects, &slf.config, value)
     }
 
     /// Set a urlencoded body and generate `ClientRequest`
@@ -438,7 +681,7 @@ impl ClientRequest {
         };
 
         RequestSender::Owned(slf.head)
-            .send_form(slf.addr, slf.response_decompress, slf.timeout, slf.config.as_ref(), value)
+            .send_form(slf.addr, slf.response_decompress, slf.timeout, slf.max_redirects, &slf.config, value)
     }
 
     /// Set an streaming body and generate `ClientRequest`.
@@ -456,7 +699,7 @@ impl ClientRequest {
         };
 
         RequestSender::Owned(slf.head)
-            .send_stream(slf.addr, slf.response_decompress, slf.timeout, slf.config.as_ref(), stream)
+            .send_stream(slf.addr, slf.response_decompress, slf.timeout, slf.max_redirects, &slf.config, stream)
     }
 
     /// Set an empty body and generate `ClientRequest`.
@@ -470,10 +713,39 @@ impl ClientRequest {
         };
 
         RequestSender::Owned(slf.head)
-            .send(slf.addr, slf.response_decompress, slf.timeout, slf.config.as_ref())
+            .send(slf.addr, slf.response_decompress, slf.timeout, slf.max_redirects, &slf.config)
+    }
+
+    /// Send request and deserialize `application/json` response body.
+    ///
+    /// Response with non-success status fails with `FetchJsonError::Status`
+    /// error, it contains response status and body. Response body size is
+    /// limited to 64Kb.
+    pub fn fetch_json<T>(self) -> impl Future<Item = T, Error = FetchJsonError>
+    where
+        T: DeserializeOwned + 'static,
+    {
+        self.send().from_err().and_then(|mut res| {
+            if res.status().is_success() {
+                Either::A(res.json::<T>().from_err())
+            } else {
+                let status = res.status();
+                Either::B(res.body().limit(65536).then(move |body| {
+                    Err(FetchJsonError::Status(status, body.unwrap_or_default()))
+                }))
+            }
+        })
+    }
+
+    /// Check request without sending it.
+    ///
+    /// Performs the same checks as `send()` and `freeze()` and fails with
+    /// the same errors. Request is returned back if it can be sent.
+    pub fn validate(self) -> Result<Self, FreezeRequestError> {
+        self.check().map_err(|e| e.into())
     }
 
-    fn prep_for_sending(mut self) -> Result<Self, PrepForSendingError> {
+    fn check(self) -> Result<Self, PrepForSendingError> {
         if let Some(e) = self.err {
             return Err(e.into());
         }
@@ -492,23 +764,18 @@ impl ClientRequest {
         } else {
             return Err(InvalidUrl::UnknownScheme.into());
         }
+        Ok(self)
+    }
+
+    fn prep_for_sending(self) -> Result<Self, PrepForSendingError> {
+        let mut slf = self.check()?;
 
         // set cookies
-        if let Some(ref mut jar) = self.cookies {
-            let mut cookie = String::new();
-            for c in jar.delta() {
-                let name = percent_encode(c.name().as_bytes(), USERINFO);
-                let value = percent_encode(c.value().as_bytes(), USERINFO);
-                let _ = write!(&mut cookie, "; {}={}", name, value);
-            }
-            self.head.headers.insert(
-                header::COOKIE,
-                HeaderValue::from_str(&cookie.as_str()[2..]).unwrap(),
-            );
+        if let Some(ref jar) = slf.cookies {
+            let cookie = cookie_header(jar, &slf.head.headers);
+            slf.head.headers.insert(header::COOKIE, cookie);
         }
 
-        let mut slf = self;
-
         // enable br only for https
         #[cfg(any(
             feature = "brotli",
@@ -540,6 +807,35 @@ impl ClientRequest {
     }
 }
 
+/// Single `Cookie` header with jar cookies merged into cookies of `headers`.
+///
+/// Cookies of `Cookie` headers take precedence over jar cookies with
+/// the same name. Jar cookies are sent first, ordered by name.
+pub(crate) fn cookie_header(jar: &CookieJar, headers: &HeaderMap) -> HeaderValue {
+    let mut user = Vec::new();
+    for value in headers.get_all(header::COOKIE) {
+        if let Ok(value) = value.to_str() {
+            user.extend(value.split(';').map(str::trim).filter(|c| !c.is_empty()));
+        }
+    }
+    let mut cookies: Vec<_> = jar.delta().collect();
+    cookies.sort_by(|a, b| a.name().cmp(b.name()));
+
+    let mut cookie = String::new();
+    for c in cookies {
+        let name = percent_encode(c.name().as_bytes(), USERINFO).to_string();
+        if user.iter().any(|u| u.split('=').next() == Some(name.as_str())) {
+            continue;
+        }
+        let value = percent_encode(c.value().as_bytes(), USERINFO);
+        let _ = write!(&mut cookie, "; {}={}", name, value);
+    }
+    for c in user {
+        let _ = write!(&mut cookie, "; {}", c);
+    }
+    HeaderValue::from_str(&cookie.as_str()[2..]).unwrap()
+}
+
 impl fmt::Debug for ClientRequest {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(
@@ -555,12 +851,29 @@ impl fmt::Debug for ClientRequest {
     }
 }
 
+/// Serializable head of a client request.
+///
+/// Captured with `ClientRequest::snapshot()`, used for recording
+/// requests in tests and replaying them later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestSnapshot {
+    /// Request method
+    pub method: String,
+    /// Request uri
+    pub uri: String,
+    /// Http version, i.e. `HTTP/1.1`
+    pub version: String,
+    /// Request headers in order
+    pub headers: Vec<(String, String)>,
+}
+
 #[derive(Clone)]
 pub struct FrozenClientRequest {
     pub(crate) head: Rc<RequestHead>,
     pub(crate) addr: Option<net::SocketAddr>,
     pub(crate) response_decompress: bool,
     pub(crate) timeout: Option<Duration>,
+    pub(crate) max_redirects: Option<usize>,
     pub(crate) config: Rc<ClientConfig>,
 }
 
@@ -589,7 +902,7 @@ impl FrozenClientRequest {
         B: Into<Body>,
     {
         RequestSender::Rc(self.head.clone(), None)
-            .send_body(self.addr, self.response_decompress, self.timeout, self.config.as_ref(), body)
+            .send_body(self.addr, self.response_decompress, self.timeout, self.max_redirects, &self.config, body)
     }
 
     /// Send a json body.
@@ -599,7 +912,7 @@ impl FrozenClientRequest {
     ) -> SendBody
     {
         RequestSender::Rc(self.head.clone(), None)
-            .send_json(self.addr, self.response_decompress, self.timeout, self.config.as_ref(), value)
+            .send_json(self.addr, self.response_decompress, self.timeout, self.max_redirects, &self.config, value)
     }
 
     /// Send an urlencoded body.
@@ -609,7 +922,7 @@ impl FrozenClientRequest {
     ) -> SendBody
     {
         RequestSender::Rc(self.head.clone(), None)
-            .send_form(self.addr, self.response_decompress, self.timeout, self.config.as_ref(), value)
+            .send_form(self.addr, self.response_decompress, self.timeout, self.max_redirects, &self.config, value)
     }
 
     /// Send a streaming body.
@@ -622,7 +935,7 @@ impl FrozenClientRequest {
         E: Into<Error> + 'static,
     {
         RequestSender::Rc(self.head.clone(), None)
-            .send_stream(self.addr, self.response_decompress, self.timeout, self.config.as_ref(), stream)
+            .send_stream(self.addr, self.response_decompress, self.timeout, self.max_redirects, &self.config, stream)
     }
 
     /// Send an empty body.
@@ -631,7 +944,7 @@ impl FrozenClientRequest {
     ) -> SendBody
     {
         RequestSender::Rc(self.head.clone(), None)
-            .send(self.addr, self.response_decompress, self.timeout, self.config.as_ref())
+            .send(self.addr, self.response_decompress, self.timeout, self.max_redirects, &self.config)
     }
 
     /// Create a `FrozenSendBuilder` with extra headers
@@ -693,7 +1006,7 @@ impl FrozenSendBuilder {
         }
 
         RequestSender::Rc(self.req.head, Some(self.extra_headers))
-            .send_body(self.req.addr, self.req.response_decompress, self.req.timeout, self.req.config.as_ref(), body)
+            .send_body(self.req.addr, self.req.response_decompress, self.req.timeout, self.req.max_redirects, &self.req.config, body)
     }
 
     /// Complete request construction and send a json body.
@@ -707,7 +1020,7 @@ impl FrozenSendBuilder {
         }
 
         RequestSender::Rc(self.req.head, Some(self.extra_headers))
-            .send_json(self.req.addr, self.req.response_decompress, self.req.timeout, self.req.config.as_ref(), value)
+            .send_json(self.req.addr, self.req.response_decompress, self.req.timeout, self.req.max_redirects, &self.req.config, value)
     }
 
     /// Complete request construction and send an urlencoded body.
@@ -721,7 +1034,7 @@ impl FrozenSendBuilder {
         }
 
         RequestSender::Rc(self.req.head, Some(self.extra_headers))
-            .send_form(self.req.addr, self.req.response_decompress, self.req.timeout, self.req.config.as_ref(), value)
+            .send_form(self.req.addr, self.req.response_decompress, self.req.timeout, self.req.max_redirects, &self.req.config, value)
     }
 
     /// Complete request construction and send a streaming body.
@@ -738,7 +1051,7 @@ impl FrozenSendBuilder {
         }
 
         RequestSender::Rc(self.req.head, Some(self.extra_headers))
-            .send_stream(self.req.addr, self.req.response_decompress, self.req.timeout, self.req.config.as_ref(), stream)
+            .send_stream(self.req.addr, self.req.response_decompress, self.req.timeout, self.req.max_redirects, &self.req.config, stream)
     }
 
     /// Complete request construction and send an empty body.
@@ -751,7 +1064,7 @@ impl FrozenSendBuilder {
         }
 
         RequestSender::Rc(self.req.head, Some(self.extra_headers))
-            .send(self.req.addr, self.req.response_decompress, self.req.timeout, self.req.config.as_ref())
+            .send(self.req.addr, self.req.response_decompress, self.req.timeout, self.req.max_redirects, &self.req.config)
     }
 }
 
@@ -791,9 +1104,14 @@ impl SendBody
         send: Box<dyn Future<Item = ClientResponse, Error = SendRequestError>>,
         response_decompress: bool,
         timeout: Option<Duration>,
+        deadline: Option<Instant>,
     ) -> SendBody
     {
-        let delay = timeout.map(|t| Delay::new(Instant::now() + t));
+        let mut expires = timeout.map(|t| Instant::now() + t);
+        if let Some(deadline) = deadline {
+            expires = Some(expires.map_or(deadline, |t| cmp::min(t, deadline)));
+        }
+        let delay = expires.map(Delay::new);
         SendBody::Fut(send, delay, response_decompress)
     }
 }
@@ -816,7 +1134,12 @@ impl Future for SendBody
                 let res = try_ready!(send.poll())
                     .map_body(|head, payload| {
                         if *response_decompress {
-                            Payload::Stream(Decoder::from_headers(payload, &head.headers))
+                            let limit = head.extensions().get::<DecompressLimit>().cloned();
+                            let decoder = Decoder::from_headers(payload, &head.headers);
+                            Payload::Stream(match limit {
+                                Some(DecompressLimit(size)) => decoder.max_size(size),
+                                None => decoder,
+                            })
                         } else {
                             Payload::Stream(Decoder::new(payload, ContentEncoding::Identity))
                         }
@@ -863,6 +1186,13 @@ impl From<PrepForSendingError> for SendBody
     }
 }
 
+/// Retries are disabled, set by `ClientRequest::no_retry()`
+struct NoRetry;
+
+/// Request body compression, set by `ClientRequest::compress()`
+#[derive(Clone, Copy)]
+struct RequestCompression(ContentEncoding, u32);
+
 #[derive(Debug)]
 enum RequestSender {
     Owned(RequestHead),
@@ -875,20 +1205,139 @@ impl RequestSender {
         addr: Option<net::SocketAddr>,
         response_decompress: bool,
         timeout: Option<Duration>,
-        config: &ClientConfig,
+        max_redirects: Option<usize>,
+        config: &Rc<ClientConfig>,
         body: B,
     ) -> SendBody
     where
         B: Into<Body>,
     {
-        let mut connector = config.connector.borrow_mut();
+        let (slf, body) = self.compress(body.into());
+        let max_redirects = max_redirects.unwrap_or(config.max_redirects);
+        let no_retry = match slf {
+            RequestSender::Owned(ref head) => head.extensions().contains::<NoRetry>(),
+            RequestSender::Rc(ref head, _) => head.extensions().contains::<NoRetry>(),
+        };
+        let deadline = match slf {
+            RequestSender::Owned(ref head) => {
+                head.extensions().get::<Deadline>().map(|deadline| deadline.0)
+            }
+            RequestSender::Rc(ref head, _) => {
+                head.extensions().get::<Deadline>().map(|deadline| deadline.0)
+            }
+        };
+        let retry_policy = if no_retry {
+            None
+        } else {
+            config.retry_policy.as_ref()
+        };
+
+        let fut: Box<dyn Future<Item = ClientResponse, Error = SendRequestError>> =
+            match FollowRedirects::clone_body(&body) {
+                // keep request around, redirected or retried request is built from it
+                Some(next_body) if max_redirects > 0 || retry_policy.is_some() => {
+                    let (head, extra_headers) = match slf {
+                        RequestSender::Owned(head) => (Rc::new(head), None),
+                        RequestSender::Rc(head, extra_headers) => (head, extra_headers),
+                    };
+                    let fut: Box<dyn Future<Item = ClientResponse, Error = SendRequestError>> =
+                        match retry_policy {
+                            Some(policy) => Box::new(RetryRequest::new(
+                                head.clone(),
+                                extra_headers.clone(),
+                                body,
+                                addr,
+                                policy.clone(),
+                                config.clone(),
+                            )),
+                            None => config.connector.borrow_mut().send_request_extra(
+                                head.clone(),
+                                extra_headers.clone(),
+                                body,
+                                addr,
+                            ),
+                        };
+                    if max_redirects > 0 {
+                        Box::new(FollowRedirects::new(
+                            fut,
+                            head,
+                            extra_headers,
+                            next_body,
+                            addr,
+                            max_redirects,
+                            config.clone(),
+                        ))
+                    } else {
+                        fut
+                    }
+                }
+                _ => {
+                    let mut connector = config.connector.borrow_mut();
+                    match slf {
+                        RequestSender::Owned(head) => connector.send_request(head, body, addr),
+                        RequestSender::Rc(head, extra_headers) => connector.send_request_extra(head, extra_headers, body, addr),
+                    }
+                }
+            };
+        let fut = if config.strict_redirects {
+            Box::new(fut.and_then(check_location))
+        } else {
+            fut
+        };
+
+        SendBody::new(
+            fut,
+            response_decompress,
+            timeout.or_else(|| config.timeout.clone()),
+            deadline,
+        )
+    }
 
-        let fut = match self {
-            RequestSender::Owned(head) => connector.send_request(head, body.into(), addr),
-            RequestSender::Rc(head, extra_headers) => connector.send_request_extra(head, extra_headers, body.into(), addr),
+    // compress body if compression is set for the request
+    fn compress(self, body: Body) -> (Self, Body) {
+        let compression = match self {
+            RequestSender::Owned(ref head) => {
+                head.extensions().get::<RequestCompression>().cloned()
+            }
+            RequestSender::Rc(ref head, _) => {
+                head.extensions().get::<RequestCompression>().cloned()
+            }
+        };
+        let (encoding, body) = match (compression, body) {
+            (None, body) => return (self, body),
+            // compress in-memory body eagerly, so it can be redirected or retried
+            (Some(RequestCompression(encoding, level)), Body::Bytes(bytes)) => {
+                match Encoder::bytes(encoding, level, &bytes) {
+                    Some(Ok(bytes)) => (encoding, Body::Bytes(bytes)),
+                    Some(Err(_)) => {
+                        let encoder = Encoder::body(encoding, level, Body::Bytes(bytes));
+                        (encoding, Body::from_message(encoder))
+                    }
+                    None => return (self, Body::Bytes(bytes)),
+                }
+            }
+            (Some(RequestCompression(encoding, level)), body) => {
+                let encoder = Encoder::body(encoding, level, body);
+                if !encoder.is_compressed() {
+                    return (self, Body::from_message(encoder));
+                }
+                (encoding, Body::from_message(encoder))
+            }
         };
 
-        SendBody::new(fut, response_decompress, timeout.or_else(|| config.timeout.clone()))
+        let value = HeaderValue::from_static(encoding.as_str());
+        let slf = match self {
+            RequestSender::Owned(mut head) => {
+                head.headers.insert(header::CONTENT_ENCODING, value);
+                RequestSender::Owned(head)
+            }
+            RequestSender::Rc(head, extra_headers) => {
+                let mut extra_headers = extra_headers.unwrap_or_else(HeaderMap::new);
+                extra_headers.insert(header::CONTENT_ENCODING, value);
+                RequestSender::Rc(head, Some(extra_headers))
+            }
+        };
+        (slf, body)
     }
 
     pub fn send_json<T: Serialize>(
@@ -896,7 +1345,8 @@ impl RequestSender {
         addr: Option<net::SocketAddr>,
         response_decompress: bool,
         timeout: Option<Duration>,
-        config: &ClientConfig,
+        max_redirects: Option<usize>,
+        config: &Rc<ClientConfig>,
         value: &T,
     ) -> SendBody
     {
@@ -909,7 +1359,7 @@ impl RequestSender {
             return e.into();
         }
 
-        self.send_body(addr, response_decompress, timeout, config, Body::Bytes(Bytes::from(body)))
+        self.send_body(addr, response_decompress, timeout, max_redirects, config, Body::Bytes(Bytes::from(body)))
     }
 
     pub fn send_form<T: Serialize>(
@@ -917,7 +1367,8 @@ impl RequestSender {
         addr: Option<net::SocketAddr>,
         response_decompress: bool,
         timeout: Option<Duration>,
-        config: &ClientConfig,
+        max_redirects: Option<usize>,
+        config: &Rc<ClientConfig>,
         value: &T,
     ) -> SendBody
     {
@@ -931,7 +1382,7 @@ impl RequestSender {
             return e.into();
         }
 
-        self.send_body(addr, response_decompress, timeout, config, Body::Bytes(Bytes::from(body)))
+        self.send_body(addr, response_decompress, timeout, max_redirects, config, Body::Bytes(Bytes::from(body)))
     }
 
     pub fn send_stream<S, E>(
@@ -939,14 +1390,15 @@ impl RequestSender {
         addr: Option<net::SocketAddr>,
         response_decompress: bool,
         timeout: Option<Duration>,
-        config: &ClientConfig,
+        max_redirects: Option<usize>,
+        config: &Rc<ClientConfig>,
         stream: S,
     ) -> SendBody
     where
         S: Stream<Item = Bytes, Error = E> + 'static,
         E: Into<Error> + 'static,
     {
-        self.send_body(addr, response_decompress, timeout, config, Body::from_message(BodyStream::new(stream)))
+        self.send_body(addr, response_decompress, timeout, max_redirects, config, Body::from_message(BodyStream::new(stream)))
     }
 
     pub fn send(
@@ -954,10 +1406,11 @@ impl RequestSender {
         addr: Option<net::SocketAddr>,
         response_decompress: bool,
         timeout: Option<Duration>,
-        config: &ClientConfig,
+        max_redirects: Option<usize>,
+        config: &Rc<ClientConfig>,
     ) -> SendBody
     {
-        self.send_body(addr, response_decompress, timeout, config, Body::Empty)
+        self.send_body(addr, response_decompress, timeout, max_redirects, config, Body::Empty)
     }
 
     fn set_header_if_none<V>(&mut self, key: HeaderName, value: V) -> Result<(), HttpError>
@@ -1032,6 +1485,45 @@ mod tests {
         let _ = req.send_body("");
     }
 
+    #[test]
+    fn test_validate() {
+        let client = Client::new();
+        let req = client.get("http://localhost/").header("x-test", "111");
+        assert!(req.validate().is_ok());
+
+        let req = client.get("http://localhost/").header("x-test", "1\n1");
+        match req.validate() {
+            Err(FreezeRequestError::Http(_)) => (),
+            _ => panic!("expected http error"),
+        }
+
+        let req = client.get("/path");
+        match req.validate() {
+            Err(FreezeRequestError::Url(InvalidUrl::MissingHost)) => (),
+            _ => panic!("expected invalid url error"),
+        }
+
+        let req = client.get("ftp://localhost/");
+        match req.validate() {
+            Err(FreezeRequestError::Url(InvalidUrl::UnknownScheme)) => (),
+            _ => panic!("expected invalid url error"),
+        }
+    }
+
+    #[test]
+    fn test_cookie_merge() {
+        let req = Client::new()
+            .get("http://localhost/")
+            .cookie(Cookie::new("a", "jar"))
+            .cookie(Cookie::new("c", "3"))
+            .header(header::COOKIE, "a=user; b=2");
+        let req = req.prep_for_sending().unwrap();
+
+        // single header, cookie of `Cookie` header wins
+        let cookies: Vec<_> = req.head.headers.get_all(header::COOKIE).collect();
+        assert_eq!(cookies, vec!["c=3; a=user; b=2"]);
+    }
+
     #[test]
     fn test_client_header() {
         let req = Client::build()
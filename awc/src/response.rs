@@ -1,23 +1,52 @@
 use std::cell::{Ref, RefMut};
-use std::fmt;
 use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use std::{fmt, io};
 
 use bytes::{Bytes, BytesMut};
+use encoding_rs::{Encoding, UTF_8};
 use futures::{Async, Future, Poll, Stream};
 
+use actix_http::client::{Protocol, RequestBytesSent, SentHeaders};
 use actix_http::cookie::Cookie;
 use actix_http::error::{CookieParseError, PayloadError};
-use actix_http::http::header::{CONTENT_LENGTH, SET_COOKIE};
+use actix_http::http::header::{CONTENT_LENGTH, SET_COOKIE, TRANSFER_ENCODING};
 use actix_http::http::{HeaderMap, StatusCode, Version};
 use actix_http::{Extensions, HttpMessage, Payload, PayloadStream, ResponseHead};
 use serde::de::DeserializeOwned;
+use tokio_timer::Delay;
+
+use crate::error::{JsonPayloadError, TextError};
+
+/// Request timings
+#[derive(Debug, Clone, Copy)]
+pub struct Timings {
+    /// Time spent acquiring a connection, includes waiting in connection
+    /// pool queue and establishing new connection. Near zero for pooled
+    /// connections.
+    pub acquire: Duration,
+    /// Time spent sending request and receiving response head
+    pub send: Duration,
+}
 
-use crate::error::JsonPayloadError;
+/// Response body framing declared by the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyFraming {
+    /// Body size is declared with `Content-Length` header
+    ContentLength(u64),
+    /// Body is sent with chunked transfer encoding
+    Chunked,
+    /// Body is delimited by the end of the stream
+    Eof,
+    /// Response status does not allow body
+    None,
+}
 
 /// Client Response
 pub struct ClientResponse<S = PayloadStream> {
     pub(crate) head: ResponseHead,
     pub(crate) payload: Payload<S>,
+    pub(crate) sent_headers: HeaderMap,
 }
 
 impl<S> HttpMessage for ClientResponse<S> {
@@ -62,7 +91,16 @@ impl<S> HttpMessage for ClientResponse<S> {
 impl<S> ClientResponse<S> {
     /// Create new Request instance
     pub(crate) fn new(head: ResponseHead, payload: Payload<S>) -> Self {
-        ClientResponse { head, payload }
+        let sent_headers = head
+            .extensions_mut()
+            .remove::<SentHeaders>()
+            .map(|headers| headers.0)
+            .unwrap_or_else(HeaderMap::new);
+        ClientResponse {
+            head,
+            payload,
+            sent_headers,
+        }
     }
 
     #[inline]
@@ -88,6 +126,84 @@ impl<S> ClientResponse<S> {
         &self.head().headers
     }
 
+    /// Request headers as they were sent to the server.
+    ///
+    /// Includes headers added by the client and connector, i.e. `Host`
+    /// and `Content-Length`. Empty unless request is sent with
+    /// `ClientRequest::capture_sent_headers()`.
+    pub fn sent_headers(&self) -> &HeaderMap {
+        &self.sent_headers
+    }
+
+    /// Number of request body bytes sent to the server.
+    ///
+    /// Only body bytes are counted, chunked transfer encoding
+    /// framing is not included.
+    pub fn request_bytes_sent(&self) -> u64 {
+        self.head
+            .extensions()
+            .get::<RequestBytesSent>()
+            .map(|sent| sent.0)
+            .unwrap_or(0)
+    }
+
+    /// Request timings, available for responses received from a connector.
+    pub fn timings(&self) -> Option<Timings> {
+        self.head.extensions().get::<Timings>().cloned()
+    }
+
+    /// Protocol of the connection, available for responses received
+    /// from a connector, including websocket handshake response.
+    pub fn protocol(&self) -> Option<Protocol> {
+        self.head.extensions().get::<Protocol>().cloned()
+    }
+
+    /// Check if server responded with `304 Not Modified`,
+    /// previously cached response could be used.
+    #[inline]
+    pub fn is_not_modified(&self) -> bool {
+        self.status() == StatusCode::NOT_MODIFIED
+    }
+
+    /// Body framing declared by response headers.
+    ///
+    /// Framing is determined from `Transfer-Encoding` and `Content-Length`
+    /// headers and response status only, so for responses without body,
+    /// like responses to `HEAD` requests, it reports framing the server
+    /// would use for the body.
+    pub fn body_framing(&self) -> BodyFraming {
+        let chunked = self
+            .headers()
+            .get_all(&TRANSFER_ENCODING)
+            .last()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .map(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+            .unwrap_or(false);
+        if chunked {
+            return BodyFraming::Chunked;
+        }
+
+        let len = self
+            .headers()
+            .get(&CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok());
+        if let Some(len) = len {
+            return BodyFraming::ContentLength(len);
+        }
+
+        let status = self.status();
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            BodyFraming::None
+        } else {
+            BodyFraming::Eof
+        }
+    }
+
     /// Set a body and return previous body value
     pub fn map_body<F, U>(mut self, f: F) -> ClientResponse<U>
     where
@@ -98,6 +214,7 @@ impl<S> ClientResponse<S> {
         ClientResponse {
             payload,
             head: self.head,
+            sent_headers: self.sent_headers,
         }
     }
 }
@@ -111,6 +228,49 @@ where
         MessageBody::new(self)
     }
 
+    /// Loads http response's body, reading must complete before `deadline`.
+    ///
+    /// Returns `PayloadError::Io` error with `TimedOut` kind if the body
+    /// is not fully received when the deadline passes.
+    pub fn body_with_deadline(&mut self, deadline: Instant) -> MessageBody<S> {
+        let mut body = MessageBody::new(self);
+        body.deadline = Some(Delay::new(deadline));
+        body
+    }
+
+    /// Loads http response's body, returns part of the body if it is
+    /// not fully received within `timeout`.
+    ///
+    /// Future resolves to received bytes and a flag that is `true` if
+    /// the body is truncated by the timeout.
+    pub fn body_or_partial(&mut self, timeout: Duration) -> PartialBody<S> {
+        PartialBody::new(self, timeout)
+    }
+
+    /// Loads http response's body, `progress` callback is called
+    /// for every received chunk.
+    ///
+    /// Callback receives number of bytes received so far and total
+    /// body size, if server sent `Content-Length` header.
+    pub fn body_with_progress<F>(&mut self, progress: F) -> MessageBody<S>
+    where
+        F: Fn(u64, Option<u64>) + 'static,
+    {
+        let mut body = MessageBody::new(self);
+        let total = body.length.map(|len| len as u64);
+        if let Some(ref mut fut) = body.fut {
+            fut.progress = Some((Box::new(progress), total));
+        }
+        body
+    }
+
+    /// Reads the whole response body, keeps only its last `size` bytes.
+    ///
+    /// Body size is not limited, memory usage is bounded by `size`.
+    pub fn tail(&mut self, size: usize) -> TailBody<S> {
+        TailBody::new(self, size)
+    }
+
     /// Loads and parse `application/json` encoded body.
     /// Return `JsonBody<T>` future. It resolves to a `T` value.
     ///
@@ -121,6 +281,27 @@ where
     pub fn json<T: DeserializeOwned>(&mut self) -> JsonBody<S, T> {
         JsonBody::new(self)
     }
+
+    /// Loads response body and decodes it to a `String`.
+    ///
+    /// Body is decoded with charset of `Content-Type` header, utf-8 is
+    /// used if charset is not specified. Returns error:
+    ///
+    /// * charset is unknown
+    /// * body is not valid text in declared charset
+    /// * content length is greater than 256k
+    pub fn text(&mut self) -> TextBody<S> {
+        TextBody::new(self)
+    }
+
+    /// Stream of newline-delimited json values.
+    ///
+    /// Every non-empty line of response body is deserialized into `T`.
+    /// Line that can not be deserialized is returned as stream error,
+    /// by default stream ends after first error.
+    pub fn ndjson<T: DeserializeOwned>(&mut self) -> NdJson<S, T> {
+        NdJson::new(self)
+    }
 }
 
 impl<S> Stream for ClientResponse<S>
@@ -151,6 +332,7 @@ pub struct MessageBody<S> {
     length: Option<usize>,
     err: Option<PayloadError>,
     fut: Option<ReadBody<S>>,
+    deadline: Option<Delay>,
 }
 
 impl<S> MessageBody<S>
@@ -176,6 +358,7 @@ where
             length: len,
             err: None,
             fut: Some(ReadBody::new(res.take_payload(), 262_144)),
+            deadline: None,
         }
     }
 
@@ -192,6 +375,7 @@ where
             fut: None,
             err: Some(e),
             length: None,
+            deadline: None,
         }
     }
 }
@@ -209,12 +393,134 @@ where
         }
 
         if let Some(len) = self.length.take() {
-            if len > self.fut.as_ref().unwrap().limit {
+            let fut = self.fut.as_mut().unwrap();
+            if len > fut.limit {
                 return Err(PayloadError::Overflow);
             }
+            // body is buffered without reallocations
+            fut.capacity = Some(len);
         }
 
-        self.fut.as_mut().unwrap().poll()
+        match self.fut.as_mut().unwrap().poll()? {
+            Async::Ready(body) => Ok(Async::Ready(body)),
+            Async::NotReady => {
+                if let Some(ref mut deadline) = self.deadline {
+                    match deadline.poll() {
+                        Ok(Async::NotReady) => (),
+                        Ok(Async::Ready(_)) => {
+                            return Err(PayloadError::Io(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "Response body deadline passed",
+                            )))
+                        }
+                        Err(e) => {
+                            return Err(PayloadError::Io(io::Error::new(
+                                io::ErrorKind::Other,
+                                e,
+                            )))
+                        }
+                    }
+                }
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+/// Future that resolves to http message body received before timeout,
+/// and a flag that is `true` if the body is truncated.
+pub struct PartialBody<S> {
+    fut: ReadBody<S>,
+    timeout: Delay,
+}
+
+impl<S> PartialBody<S>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+{
+    fn new(res: &mut ClientResponse<S>, timeout: Duration) -> Self {
+        PartialBody {
+            fut: ReadBody::new(res.take_payload(), 262_144),
+            timeout: Delay::new(Instant::now() + timeout),
+        }
+    }
+
+    /// Change max size of payload. By default max size is 256Kb
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.fut.limit = limit;
+        self
+    }
+}
+
+impl<S> Future for PartialBody<S>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+{
+    type Item = (Bytes, bool);
+    type Error = PayloadError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(body) = self.fut.poll()? {
+            return Ok(Async::Ready((body, false)));
+        }
+
+        match self.timeout.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(_)) => {
+                Ok(Async::Ready((self.fut.buf.take().freeze(), true)))
+            }
+            Err(e) => Err(PayloadError::Io(io::Error::new(io::ErrorKind::Other, e))),
+        }
+    }
+}
+
+/// Future that reads http message body and resolves to its last bytes
+pub struct TailBody<S> {
+    stream: Payload<S>,
+    buf: BytesMut,
+    size: usize,
+}
+
+impl<S> TailBody<S>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+{
+    fn new(res: &mut ClientResponse<S>, size: usize) -> Self {
+        TailBody {
+            stream: res.take_payload(),
+            buf: BytesMut::with_capacity(size),
+            size,
+        }
+    }
+}
+
+impl<S> Future for TailBody<S>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+{
+    type Item = Bytes;
+    type Error = PayloadError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.stream.poll()? {
+                Async::Ready(Some(chunk)) => {
+                    if chunk.len() >= self.size {
+                        self.buf.clear();
+                        self.buf.extend_from_slice(&chunk[chunk.len() - self.size..]);
+                    } else {
+                        // drop head of the buffer, space is reclaimed on reserve
+                        let overflow = (self.buf.len() + chunk.len())
+                            .saturating_sub(self.size);
+                        let _ = self.buf.split_to(overflow);
+                        self.buf.reserve(chunk.len());
+                        self.buf.extend_from_slice(&chunk);
+                    }
+                }
+                Async::Ready(None) => return Ok(Async::Ready(self.buf.take().freeze())),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
     }
 }
 
@@ -293,9 +599,11 @@ where
         }
 
         if let Some(len) = self.length.take() {
-            if len > self.fut.as_ref().unwrap().limit {
+            let fut = self.fut.as_mut().unwrap();
+            if len > fut.limit {
                 return Err(JsonPayloadError::Payload(PayloadError::Overflow));
             }
+            fut.capacity = Some(len);
         }
 
         let body = futures::try_ready!(self.fut.as_mut().unwrap().poll());
@@ -303,18 +611,209 @@ where
     }
 }
 
+/// Response body decoded to a `String`
+pub struct TextBody<S> {
+    length: Option<usize>,
+    err: Option<TextError>,
+    encoding: &'static Encoding,
+    fut: Option<ReadBody<S>>,
+}
+
+impl<S> TextBody<S>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+{
+    /// Create `TextBody` for response.
+    pub fn new(res: &mut ClientResponse<S>) -> Self {
+        let encoding = match res.encoding() {
+            Ok(encoding) => encoding,
+            Err(e) => {
+                return TextBody {
+                    length: None,
+                    err: Some(e.into()),
+                    encoding: UTF_8,
+                    fut: None,
+                }
+            }
+        };
+
+        let mut len = None;
+        if let Some(l) = res.headers().get(&CONTENT_LENGTH) {
+            if let Ok(s) = l.to_str() {
+                if let Ok(l) = s.parse::<usize>() {
+                    len = Some(l)
+                }
+            }
+        }
+
+        TextBody {
+            length: len,
+            err: None,
+            encoding,
+            fut: Some(ReadBody::new(res.take_payload(), 262_144)),
+        }
+    }
+
+    /// Change max size of payload. By default max size is 256Kb
+    pub fn limit(mut self, limit: usize) -> Self {
+        if let Some(ref mut fut) = self.fut {
+            fut.limit = limit;
+        }
+        self
+    }
+}
+
+impl<S> Future for TextBody<S>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+{
+    type Item = String;
+    type Error = TextError;
+
+    fn poll(&mut self) -> Poll<String, TextError> {
+        if let Some(err) = self.err.take() {
+            return Err(err);
+        }
+
+        if let Some(len) = self.length.take() {
+            let fut = self.fut.as_mut().unwrap();
+            if len > fut.limit {
+                return Err(TextError::Payload(PayloadError::Overflow));
+            }
+            fut.capacity = Some(len);
+        }
+
+        let body = futures::try_ready!(self.fut.as_mut().unwrap().poll());
+        match self
+            .encoding
+            .decode_without_bom_handling_and_without_replacement(&body)
+        {
+            Some(text) => Ok(Async::Ready(text.into_owned())),
+            None => Err(TextError::Decode),
+        }
+    }
+}
+
+/// Stream of newline-delimited json values of response body
+pub struct NdJson<S, U> {
+    stream: Payload<S>,
+    buf: BytesMut,
+    limit: usize,
+    stop_on_error: bool,
+    eof: bool,
+    done: bool,
+    _t: PhantomData<U>,
+}
+
+impl<S, U> NdJson<S, U>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+    U: DeserializeOwned,
+{
+    /// Create `NdJson` stream for response.
+    pub fn new(res: &mut ClientResponse<S>) -> Self {
+        NdJson {
+            stream: res.take_payload(),
+            buf: BytesMut::new(),
+            limit: 65536,
+            stop_on_error: true,
+            eof: false,
+            done: false,
+            _t: PhantomData,
+        }
+    }
+
+    /// Change max size of a line. By default max size is 64Kb
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Continue with next line after a line fails to deserialize.
+    ///
+    /// Payload errors always end the stream.
+    pub fn continue_on_error(mut self, val: bool) -> Self {
+        self.stop_on_error = !val;
+        self
+    }
+
+    fn parse(&mut self, line: &[u8]) -> Poll<Option<U>, JsonPayloadError> {
+        match serde_json::from_slice::<U>(line) {
+            Ok(item) => Ok(Async::Ready(Some(item))),
+            Err(e) => {
+                self.done = self.stop_on_error;
+                Err(JsonPayloadError::Deserialize(e))
+            }
+        }
+    }
+}
+
+impl<S, U> Stream for NdJson<S, U>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+    U: DeserializeOwned,
+{
+    type Item = U;
+    type Error = JsonPayloadError;
+
+    fn poll(&mut self) -> Poll<Option<U>, JsonPayloadError> {
+        loop {
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            if let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+                let line = self.buf.split_to(pos + 1);
+                if line.iter().all(|b| b.is_ascii_whitespace()) {
+                    continue;
+                }
+                return self.parse(&line);
+            }
+
+            if self.eof {
+                self.done = true;
+                let line = self.buf.take();
+                if line.iter().all(|b| b.is_ascii_whitespace()) {
+                    return Ok(Async::Ready(None));
+                }
+                return self.parse(&line);
+            }
+
+            if self.buf.len() > self.limit {
+                self.done = true;
+                return Err(JsonPayloadError::Payload(PayloadError::Overflow));
+            }
+
+            match self.stream.poll() {
+                Ok(Async::Ready(Some(chunk))) => self.buf.extend_from_slice(&chunk),
+                Ok(Async::Ready(None)) => self.eof = true,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    self.done = true;
+                    return Err(JsonPayloadError::Payload(e));
+                }
+            }
+        }
+    }
+}
+
 struct ReadBody<S> {
     stream: Payload<S>,
     buf: BytesMut,
     limit: usize,
+    progress: Option<(Box<dyn Fn(u64, Option<u64>)>, Option<u64>)>,
+    /// Buffer capacity, allocated with the first chunk
+    capacity: Option<usize>,
 }
 
 impl<S> ReadBody<S> {
     fn new(stream: Payload<S>, limit: usize) -> Self {
         Self {
             stream,
-            buf: BytesMut::with_capacity(std::cmp::min(limit, 32768)),
+            buf: BytesMut::new(),
             limit,
+            progress: None,
+            capacity: Some(32768),
         }
     }
 }
@@ -333,7 +832,13 @@ where
                     if (self.buf.len() + chunk.len()) > self.limit {
                         Err(PayloadError::Overflow)
                     } else {
+                        if let Some(capacity) = self.capacity.take() {
+                            self.buf.reserve(std::cmp::min(capacity, self.limit));
+                        }
                         self.buf.extend_from_slice(&chunk);
+                        if let Some((ref progress, total)) = self.progress {
+                            progress(self.buf.len() as u64, total);
+                        }
                         continue;
                     }
                 }
@@ -385,6 +890,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_body_capacity_hint() {
+        let data = Bytes::from(vec![b'x'; 100_000]);
+        let mut req = TestResponse::with_header(header::CONTENT_LENGTH, "100000")
+            .set_payload(data.clone())
+            .finish();
+        let body = block_on(req.body()).unwrap();
+        assert_eq!(body, data);
+        // single allocation of declared size
+        assert_eq!(body.try_mut().unwrap().capacity(), 100_000);
+    }
+
     #[derive(Serialize, Deserialize, PartialEq, Debug)]
     struct MyObject {
         name: String,
@@ -456,4 +973,36 @@ mod tests {
             }
         );
     }
+
+    fn ndjson_items(stream: &mut NdJson<PayloadStream, MyObject>) -> Vec<Option<String>> {
+        let mut items = Vec::new();
+        loop {
+            match block_on(futures::future::poll_fn(|| stream.poll())) {
+                Ok(Some(item)) => items.push(Some(item.name)),
+                Ok(None) => return items,
+                Err(JsonPayloadError::Deserialize(_)) => items.push(None),
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ndjson() {
+        const BODY: &[u8] = b"{\"name\": \"a\"}\n{\"name\": \n\n{\"name\": \"c\"}";
+
+        let mut req = TestResponse::default()
+            .set_payload(Bytes::from_static(BODY))
+            .finish();
+        let mut stream = req.ndjson::<MyObject>();
+        assert_eq!(ndjson_items(&mut stream), vec![Some("a".to_owned()), None]);
+
+        let mut req = TestResponse::default()
+            .set_payload(Bytes::from_static(BODY))
+            .finish();
+        let mut stream = req.ndjson::<MyObject>().continue_on_error(true);
+        assert_eq!(
+            ndjson_items(&mut stream),
+            vec![Some("a".to_owned()), None, Some("c".to_owned())]
+        );
+    }
 }
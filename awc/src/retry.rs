@@ -0,0 +1,308 @@
+use std::net;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix_http::body::Body;
+use actix_http::client::{ConnectError, SendRequestError};
+use actix_http::error::ParseError;
+use actix_http::http::header::{HeaderName, RETRY_AFTER};
+use actix_http::http::{HeaderMap, Method, StatusCode};
+use actix_http::RequestHead;
+use futures::{Async, Future, Poll};
+use tokio_timer::Delay;
+
+use crate::redirect::FollowRedirects;
+use crate::response::ClientResponse;
+use crate::ClientConfig;
+
+/// Retry policy for failed requests.
+///
+/// Only failures that happen before response head is received are retried.
+/// Errors while reading response body are never retried, part of the
+/// response is already delivered at that point. Requests with streaming
+/// body are never retried. Requests with non-idempotent method are retried
+/// only if they fail to connect, unless enabled with `retry_non_idempotent()`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_retries: usize,
+    backoff: Duration,
+    max_total_delay: Option<Duration>,
+    rate_limit: Option<(HeaderName, HeaderName)>,
+    tls_handshake: bool,
+    non_idempotent: bool,
+}
+
+/// Values of reset header above this are unix timestamps, lower values
+/// are number of seconds until reset
+const RESET_TIMESTAMP_MIN: u64 = 1_000_000_000;
+
+impl RetryPolicy {
+    /// Create retry policy with max number of retries.
+    pub fn new(max_retries: usize) -> Self {
+        RetryPolicy {
+            max_retries,
+            backoff: Duration::from_secs(0),
+            max_total_delay: None,
+            rate_limit: None,
+            tls_handshake: false,
+            non_idempotent: false,
+        }
+    }
+
+    /// Set delay before each retry.
+    ///
+    /// By default failed request is retried immediately.
+    pub fn backoff(mut self, delay: Duration) -> Self {
+        self.backoff = delay;
+        self
+    }
+
+    /// Set limit for the sum of all backoff delays.
+    ///
+    /// Request is not retried if next delay would exceed the limit,
+    /// the last error is returned even if retries remain.
+    /// By default total delay is not limited.
+    pub fn max_total_delay(mut self, limit: Duration) -> Self {
+        self.max_total_delay = Some(limit);
+        self
+    }
+
+    /// Retry `429 Too Many Requests` responses after rate limit reset.
+    ///
+    /// `reset` header contains time of rate limit reset, either unix
+    /// timestamp or number of seconds until reset. Request is retried
+    /// once reset time is reached if `remaining` header is missing or
+    /// is zero. `Retry-After` header in seconds is used if response
+    /// does not contain `reset` header, otherwise backoff delay is used.
+    /// Retries of rate limited requests count towards max retries and
+    /// total delay limit.
+    pub fn rate_limit_headers(
+        mut self,
+        reset: HeaderName,
+        remaining: HeaderName,
+    ) -> Self {
+        self.rate_limit = Some((reset, remaining));
+        self
+    }
+
+    /// Retry requests that failed because of tls handshake error.
+    ///
+    /// Handshake failures are usually caused by misconfiguration and
+    /// are not retried by default. Each retry opens new connection.
+    pub fn retry_tls_handshake(mut self, val: bool) -> Self {
+        self.tls_handshake = val;
+        self
+    }
+
+    /// Retry requests with non-idempotent method that failed after
+    /// request is sent.
+    ///
+    /// Server could have processed such request already, by default
+    /// `POST`, `PATCH` and `CONNECT` requests are retried only if they
+    /// fail to connect.
+    pub fn retry_non_idempotent(mut self, val: bool) -> Self {
+        self.non_idempotent = val;
+        self
+    }
+
+    /// Max number of retries
+    pub fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    /// Check if request with `method` that failed with this error could
+    /// be retried.
+    ///
+    /// Transient connect errors (io errors, timeouts, dns resolution errors)
+    /// are retryable, connects refused by peer, connector filters, proxy or
+    /// circuit breaker are not. Io errors while sending request or reading
+    /// response head are retryable for idempotent methods. Tls handshake
+    /// errors are retryable only if enabled with `retry_tls_handshake()`.
+    pub fn is_retryable(&self, method: &Method, err: &SendRequestError) -> bool {
+        match err {
+            SendRequestError::Connect(ref e) if is_tls_handshake(e) => {
+                self.tls_handshake
+            }
+            SendRequestError::Connect(ConnectError::Io(_))
+            | SendRequestError::Connect(ConnectError::Timeout)
+            | SendRequestError::Connect(ConnectError::Disconnected)
+            | SendRequestError::Connect(ConnectError::Resolver(_)) => true,
+            SendRequestError::Send(_)
+            | SendRequestError::H2(_)
+            | SendRequestError::Response(ParseError::Io(_)) => {
+                self.non_idempotent || is_idempotent(method)
+            }
+            _ => false,
+        }
+    }
+
+    /// Delay before retry of rate limited response, `None` if response
+    /// should not be retried
+    fn rate_limit_delay(&self, res: &ClientResponse) -> Option<Duration> {
+        let (reset, remaining) = match self.rate_limit {
+            Some(ref headers) if res.status() == StatusCode::TOO_MANY_REQUESTS => {
+                headers
+            }
+            _ => return None,
+        };
+        let headers = res.headers();
+        if let Some(remaining) = header_u64(headers, remaining) {
+            if remaining > 0 {
+                return None;
+            }
+        }
+
+        let delay = match header_u64(headers, reset) {
+            Some(reset) if reset >= RESET_TIMESTAMP_MIN => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                Duration::from_secs(reset)
+                    .checked_sub(now)
+                    .unwrap_or_default()
+            }
+            Some(reset) => Duration::from_secs(reset),
+            None => header_u64(headers, &RETRY_AFTER)
+                .map(Duration::from_secs)
+                .unwrap_or(self.backoff),
+        };
+        Some(delay)
+    }
+
+    /// Check if one more delay fits into total delay limit
+    fn within_total_delay(&self, delayed: Duration, delay: Duration) -> bool {
+        match self.max_total_delay {
+            Some(limit) => delayed + delay <= limit,
+            None => true,
+        }
+    }
+}
+
+fn is_tls_handshake(err: &ConnectError) -> bool {
+    match err {
+        #[cfg(feature = "ssl")]
+        ConnectError::SslError(_) => true,
+        #[cfg(feature = "rust-tls")]
+        ConnectError::TlsHandshake(_) => true,
+        _ => false,
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    match *method {
+        Method::POST | Method::PATCH | Method::CONNECT => false,
+        _ => true,
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &HeaderName) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Future that sends request again if it fails before response head
+/// is received.
+pub(crate) struct RetryRequest {
+    fut: Box<dyn Future<Item = ClientResponse, Error = SendRequestError>>,
+    delay: Option<Delay>,
+    head: Rc<RequestHead>,
+    extra_headers: Option<HeaderMap>,
+    body: Body,
+    addr: Option<net::SocketAddr>,
+    remaining: usize,
+    delayed: Duration,
+    policy: RetryPolicy,
+    config: Rc<ClientConfig>,
+}
+
+impl RetryRequest {
+    /// Send request, body must be clonable with `FollowRedirects::clone_body()`
+    pub(crate) fn new(
+        head: Rc<RequestHead>,
+        extra_headers: Option<HeaderMap>,
+        body: Body,
+        addr: Option<net::SocketAddr>,
+        policy: RetryPolicy,
+        config: Rc<ClientConfig>,
+    ) -> Self {
+        let fut = RetryRequest::send(&config, &head, &extra_headers, &body, addr);
+        RetryRequest {
+            fut,
+            delay: None,
+            head,
+            extra_headers,
+            body,
+            addr,
+            remaining: policy.max_retries,
+            delayed: Duration::from_secs(0),
+            policy,
+            config,
+        }
+    }
+
+    fn send(
+        config: &ClientConfig,
+        head: &Rc<RequestHead>,
+        extra_headers: &Option<HeaderMap>,
+        body: &Body,
+        addr: Option<net::SocketAddr>,
+    ) -> Box<dyn Future<Item = ClientResponse, Error = SendRequestError>> {
+        config.connector.borrow_mut().send_request_extra(
+            head.clone(),
+            extra_headers.clone(),
+            FollowRedirects::clone_body(body).unwrap_or(Body::None),
+            addr,
+        )
+    }
+}
+
+impl Future for RetryRequest {
+    type Item = ClientResponse;
+    type Error = SendRequestError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if let Some(ref mut delay) = self.delay {
+                match delay.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    _ => {
+                        self.fut = RetryRequest::send(
+                            &self.config,
+                            &self.head,
+                            &self.extra_headers,
+                            &self.body,
+                            self.addr,
+                        );
+                    }
+                }
+            }
+            self.delay = None;
+
+            let delay = match self.fut.poll() {
+                Err(ref e)
+                    if self.remaining > 0
+                        && self.policy.is_retryable(&self.head.method, e)
+                        && self
+                            .policy
+                            .within_total_delay(self.delayed, self.policy.backoff) =>
+                {
+                    log::trace!("Request failed, retrying: {:?}", e);
+                    self.policy.backoff
+                }
+                Ok(Async::Ready(res)) => match self.policy.rate_limit_delay(&res) {
+                    Some(delay)
+                        if self.remaining > 0
+                            && self.policy.within_total_delay(self.delayed, delay) =>
+                    {
+                        log::trace!("Request is rate limited, retrying in {:?}", delay);
+                        delay
+                    }
+                    _ => return Ok(Async::Ready(res)),
+                },
+                res => return res,
+            };
+            self.remaining -= 1;
+            self.delayed += delay;
+            self.delay = Some(Delay::new(Instant::now() + delay));
+        }
+    }
+}
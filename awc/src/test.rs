@@ -1,14 +1,27 @@
 //! Test helpers for actix http client to use during testing.
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::fmt::Write as FmtWrite;
+use std::io;
+use std::rc::Rc;
 
+use actix_codec::Framed;
+use actix_http::body::MessageBody;
+use actix_http::client::{
+    Connect, ConnectError, Connection, Protocol, SendRequestError, TunnelPermit,
+};
 use actix_http::cookie::{Cookie, CookieJar, USERINFO};
+use actix_http::h1::ClientCodec;
 use actix_http::http::header::{self, Header, HeaderValue, IntoHeaderValue};
 use actix_http::http::{HeaderName, HttpTryFrom, StatusCode, Version};
-use actix_http::{h1, Payload, ResponseHead};
+use actix_http::{h1, Payload, RequestHeadType, ResponseHead};
+use actix_service::Service;
 use bytes::Bytes;
+use futures::future::{err, ok, FutureResult};
+use futures::{Async, Poll};
 use percent_encoding::percent_encode;
 
-use crate::ClientResponse;
+use crate::{BoxedSocket, ClientResponse};
 
 /// Test `ClientResponse` builder
 pub struct TestResponse {
@@ -83,6 +96,11 @@ impl TestResponse {
 
     /// Complete response creation and generate `ClientResponse` instance
     pub fn finish(self) -> ClientResponse {
+        let (head, payload) = self.into_parts();
+        ClientResponse::new(head, payload)
+    }
+
+    fn into_parts(self) -> (ResponseHead, Payload) {
         let mut head = self.head;
 
         let mut cookie = String::new();
@@ -99,13 +117,124 @@ impl TestResponse {
         }
 
         if let Some(pl) = self.payload {
-            ClientResponse::new(head, pl)
+            (head, pl)
         } else {
-            ClientResponse::new(head, h1::Payload::empty().into())
+            (head, h1::Payload::empty().into())
         }
     }
 }
 
+enum TestStep {
+    Refuse,
+    Fail(ConnectError),
+    Respond(TestResponse),
+}
+
+/// Scripted connector for testing client without network access
+///
+/// Every connect attempt takes the next step of the script. Connector
+/// fails with `ConnectError::Disconnected` once the script is exhausted.
+///
+/// ```rust
+/// use awc::test::{TestConnector, TestResponse};
+///
+/// let connector = TestConnector::new()
+///     .refuse()
+///     .respond(TestResponse::default().set_payload("ok"));
+/// let client = awc::Client::build().connector(connector.clone()).finish();
+/// ```
+#[derive(Clone, Default)]
+pub struct TestConnector {
+    steps: Rc<RefCell<VecDeque<TestStep>>>,
+    attempts: Rc<Cell<usize>>,
+}
+
+impl TestConnector {
+    /// Create connector with empty script
+    pub fn new() -> Self {
+        TestConnector::default()
+    }
+
+    /// Refuse next connect attempt
+    pub fn refuse(self) -> Self {
+        self.steps.borrow_mut().push_back(TestStep::Refuse);
+        self
+    }
+
+    /// Fail next connect attempt with specified error
+    pub fn fail(self, err: ConnectError) -> Self {
+        self.steps.borrow_mut().push_back(TestStep::Fail(err));
+        self
+    }
+
+    /// Accept next connect attempt, request on the connection
+    /// gets this response
+    pub fn respond(self, res: TestResponse) -> Self {
+        self.steps.borrow_mut().push_back(TestStep::Respond(res));
+        self
+    }
+
+    /// Number of connect attempts made so far
+    pub fn attempts(&self) -> usize {
+        self.attempts.get()
+    }
+}
+
+impl Service for TestConnector {
+    type Request = Connect;
+    type Response = TestConnection;
+    type Error = ConnectError;
+    type Future = FutureResult<TestConnection, ConnectError>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, _: Connect) -> Self::Future {
+        self.attempts.set(self.attempts.get() + 1);
+        match self.steps.borrow_mut().pop_front() {
+            Some(TestStep::Respond(res)) => ok(TestConnection(res)),
+            Some(TestStep::Refuse) => err(ConnectError::Refused(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "Connection refused",
+            ))),
+            Some(TestStep::Fail(e)) => err(e),
+            None => err(ConnectError::Disconnected),
+        }
+    }
+}
+
+/// Connection produced by `TestConnector`
+///
+/// Request body is ignored, tunnels and raw requests are not supported.
+pub struct TestConnection(TestResponse);
+
+impl Connection for TestConnection {
+    type Io = BoxedSocket;
+    type Future = FutureResult<(ResponseHead, Payload), SendRequestError>;
+
+    fn protocol(&self) -> Protocol {
+        Protocol::Http1
+    }
+
+    fn send_request<B: MessageBody + 'static, H: Into<RequestHeadType>>(
+        self,
+        _: H,
+        _: B,
+    ) -> Self::Future {
+        ok(self.0.into_parts())
+    }
+
+    type TunnelFuture = FutureResult<
+        (ResponseHead, Framed<Self::Io, ClientCodec>, TunnelPermit),
+        SendRequestError,
+    >;
+
+    fn open_tunnel<H: Into<RequestHeadType>>(self, _: H) -> Self::TunnelFuture {
+        err(SendRequestError::TunnelNotSupported)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::SystemTime;
@@ -124,4 +253,13 @@ mod tests {
         assert!(res.headers().contains_key(header::DATE));
         assert_eq!(res.version(), Version::HTTP_2);
     }
+
+    #[test]
+    fn test_raw_request_not_supported() {
+        let conn = TestConnection(TestResponse::default());
+        match conn.send_raw_request(Bytes::new()).poll() {
+            Err(SendRequestError::RawRequestNotSupported) => (),
+            _ => panic!("raw request is not supported"),
+        }
+    }
 }
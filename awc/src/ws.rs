@@ -1,5 +1,4 @@
 //! Websockets client
-use std::fmt::Write as FmtWrite;
 use std::net::SocketAddr;
 use std::rc::Rc;
 use std::{fmt, str};
@@ -8,10 +7,8 @@ use actix_codec::Framed;
 use actix_http::cookie::{Cookie, CookieJar};
 use actix_http::{ws, Payload, RequestHead};
 use futures::future::{err, Either, Future};
-use percent_encoding::percent_encode;
 use tokio_timer::Timeout;
 
-use actix_http::cookie::USERINFO;
 pub use actix_http::ws::{CloseCode, CloseReason, Codec, Frame, Message};
 
 use crate::connect::BoxedSocket;
@@ -22,6 +19,7 @@ use crate::http::header::{
 use crate::http::{
     ConnectionType, Error as HttpError, HttpTryFrom, Method, StatusCode, Uri, Version,
 };
+use crate::request::cookie_header;
 use crate::response::ClientResponse;
 use crate::ClientConfig;
 
@@ -238,17 +236,9 @@ impl WebsocketsRequest {
         }
 
         // set cookies
-        if let Some(ref mut jar) = self.cookies {
-            let mut cookie = String::new();
-            for c in jar.delta() {
-                let name = percent_encode(c.name().as_bytes(), USERINFO);
-                let value = percent_encode(c.value().as_bytes(), USERINFO);
-                let _ = write!(&mut cookie, "; {}={}", name, value);
-            }
-            self.head.headers.insert(
-                header::COOKIE,
-                HeaderValue::from_str(&cookie.as_str()[2..]).unwrap(),
-            );
+        if let Some(ref jar) = self.cookies {
+            let cookie = cookie_header(jar, &self.head.headers);
+            self.head.headers.insert(header::COOKIE, cookie);
         }
 
         // origin
@@ -293,7 +283,7 @@ impl WebsocketsRequest {
             .borrow_mut()
             .open_tunnel(head, self.addr)
             .from_err()
-            .and_then(move |(head, framed)| {
+            .and_then(move |(head, framed, protocol)| {
                 // verify response
                 if head.status != StatusCode::SWITCHING_PROTOCOLS {
                     return Err(WsClientError::InvalidResponseStatus(head.status));
@@ -351,6 +341,7 @@ impl WebsocketsRequest {
                 };
 
                 // response and ws framed
+                head.extensions_mut().insert(protocol);
                 Ok((
                     ClientResponse::new(head, Payload::None),
                     framed.map_codec(|_| {
@@ -1,24 +1,33 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use std::{net, thread};
 
 use brotli2::write::BrotliEncoder;
 use bytes::Bytes;
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use futures::Future;
+use futures::{Future, Stream};
 use rand::Rng;
 
+use actix_http::body::Body;
+use actix_http::error::PayloadError;
 use actix_http::HttpService;
 use actix_http_test::TestServer;
-use actix_service::{service_fn, NewService};
-use actix_web::http::Cookie;
+use actix_service::{apply_fn, service_fn, NewService, Service};
+use actix_web::http::{Cookie, StatusCode, Uri};
 use actix_web::middleware::{BodyEncoding, Compress};
 use actix_web::{http::header, web, App, Error, HttpMessage, HttpRequest, HttpResponse};
-use awc::error::SendRequestError;
+use awc::error::{
+    ConnectError, FetchJsonError, JsonPayloadError, SendRequestError, TextError,
+};
+use awc::test::{TestConnector, TestResponse};
+use awc::{BodyFraming, RetryPolicy};
 
 const STR: &str = "Hello World Hello World Hello World Hello World Hello World \
                    Hello World Hello World Hello World Hello World Hello World \
@@ -126,6 +135,241 @@ fn test_timeout() {
     }
 }
 
+#[test]
+fn test_body_with_deadline() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").route(web::to(|| {
+            // drip body in small chunks, the whole transfer takes about 1 second
+            let body = futures::stream::iter_ok::<_, Error>(0..10).and_then(|_| {
+                tokio_timer::sleep(Duration::from_millis(100))
+                    .then(|_| Ok::<_, Error>(Bytes::from_static(b"data")))
+            });
+            HttpResponse::Ok().streaming(body)
+        }))))
+    });
+
+    let mut response = srv.block_on(awc::Client::new().get(srv.url("/")).send()).unwrap();
+    assert!(response.status().is_success());
+
+    let deadline = Instant::now() + Duration::from_millis(250);
+    match srv.block_on(response.body_with_deadline(deadline)) {
+        Err(PayloadError::Io(ref e)) if e.kind() == io::ErrorKind::TimedOut => (),
+        res => panic!("{:?}", res),
+    }
+}
+
+fn stalled_body_server() -> actix_http_test::TestServerRuntime {
+    TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").route(web::to(|| {
+            // second chunk is sent after 300 milliseconds
+            let body = futures::stream::iter_ok::<_, Error>(0..2).and_then(|i| {
+                tokio_timer::sleep(Duration::from_millis(i * 300))
+                    .then(|_| Ok::<_, Error>(Bytes::from_static(b"data")))
+            });
+            HttpResponse::Ok().streaming(body)
+        }))))
+    })
+}
+
+#[test]
+fn test_read_idle_timeout() {
+    let mut srv = stalled_body_server();
+    let client = srv.execute(|| {
+        awc::Client::build()
+            .connector(
+                awc::Connector::new()
+                    .read_idle_timeout(Duration::from_millis(100))
+                    .finish(),
+            )
+            .finish()
+    });
+
+    let mut res = srv.block_on(client.get(srv.url("/")).send()).unwrap();
+    match srv.block_on(res.body()) {
+        Err(PayloadError::Io(ref e)) if e.kind() == io::ErrorKind::TimedOut => (),
+        res => panic!("{:?}", res),
+    }
+
+    // request overrides connector default
+    let mut res = srv
+        .block_on(
+            client
+                .get(srv.url("/"))
+                .read_idle_timeout(Duration::from_secs(5))
+                .send(),
+        )
+        .unwrap();
+    let bytes = srv.block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(b"datadata"));
+}
+
+#[test]
+fn test_after_receive() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(
+            App::new().service(web::resource("/").to(|| HttpResponse::Ok().finish())),
+        )
+    });
+    let client = srv.execute(|| {
+        awc::Client::build()
+            .connector(
+                awc::Connector::new()
+                    .after_receive(|head| {
+                        let name = header::HeaderName::from_static("x-status");
+                        let status = header::HeaderValue::from(head.status.as_u16());
+                        head.headers.insert(name, status);
+                    })
+                    .finish(),
+            )
+            .finish()
+    });
+
+    let res = srv.block_on(client.get(srv.url("/")).send()).unwrap();
+    assert!(res.status().is_success());
+    assert_eq!(res.headers().get("x-status").unwrap(), "200");
+}
+
+#[test]
+fn test_frozen_expect_continue_threshold() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").to(
+            |req: HttpRequest, _: Bytes| {
+                let expect = req.headers().contains_key(header::EXPECT);
+                HttpResponse::Ok()
+                    .header("x-expect", if expect { "yes" } else { "no" })
+                    .finish()
+            },
+        )))
+    });
+    let client = srv.execute(|| {
+        awc::Client::build()
+            .connector(awc::Connector::new().expect_continue_threshold(1024).finish())
+            .finish()
+    });
+
+    // shared head keeps its `Expect` header for the next request
+    let req = client
+        .put(srv.url("/"))
+        .header(header::EXPECT, "100-continue")
+        .freeze()
+        .unwrap();
+    for &(size, expect) in &[(10, "no"), (4096, "yes"), (10, "no")] {
+        let res = srv.block_on_fn(|| req.send_body(vec![0u8; size])).unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(res.headers().get("x-expect").unwrap(), expect);
+    }
+}
+
+#[test]
+fn test_per_read_timeout() {
+    let mut srv = stalled_body_server();
+    let client = srv.execute(|| {
+        awc::Client::build()
+            .connector(
+                awc::Connector::new()
+                    .per_read_timeout(Duration::from_secs(5))
+                    .finish(),
+            )
+            .finish()
+    });
+
+    let mut res = srv.block_on(client.get(srv.url("/")).send()).unwrap();
+    let bytes = srv.block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(b"datadata"));
+
+    // request overrides connector default, pooled connection is used
+    let req = client
+        .get(srv.url("/"))
+        .per_read_timeout(Duration::from_millis(100));
+    let mut res = srv.block_on_fn(move || req.send()).unwrap();
+    match srv.block_on_fn(move || res.body()) {
+        Err(PayloadError::Io(ref e)) if e.kind() == io::ErrorKind::TimedOut => (),
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn test_connect_timeout_override() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(
+            App::new().service(web::resource("/").to(|| HttpResponse::Ok().finish())),
+        )
+    });
+    let client = srv.execute(|| {
+        // connects complete after 300 milliseconds
+        let tcp = apply_fn(actix_connect::default_connector(), |req, srv| {
+            let mut srv = srv.clone();
+            tokio_timer::sleep(Duration::from_millis(300)).then(move |_| srv.call(req))
+        });
+        awc::Client::build()
+            .connector(
+                awc::Connector::new()
+                    .connector(tcp)
+                    .timeout(Duration::from_millis(100))
+                    .finish(),
+            )
+            .finish()
+    });
+
+    match srv.block_on(client.get(srv.url("/")).send()) {
+        Err(SendRequestError::Connect(ConnectError::Timeout)) => (),
+        res => panic!("{:?}", res.map(|res| res.status())),
+    }
+
+    // request overrides connector default
+    let res = srv
+        .block_on(
+            client
+                .get(srv.url("/"))
+                .connect_timeout(Duration::from_secs(5))
+                .send(),
+        )
+        .unwrap();
+    assert!(res.status().is_success());
+}
+
+#[test]
+fn test_body_with_progress() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(
+            web::resource("/").route(web::to(|| HttpResponse::Ok().body(STR))),
+        ))
+    });
+
+    let mut response = srv.block_on(awc::Client::new().get(srv.url("/")).send()).unwrap();
+    assert!(response.status().is_success());
+
+    let progress = Rc::new(RefCell::new(Vec::new()));
+    let progress2 = progress.clone();
+    let bytes = srv
+        .block_on(response.body_with_progress(move |received, total| {
+            progress2.borrow_mut().push((received, total))
+        }))
+        .unwrap();
+    assert_eq!(bytes, Bytes::from_static(STR.as_ref()));
+
+    let total = STR.len() as u64;
+    assert_eq!(progress.borrow().last(), Some(&(total, Some(total))));
+}
+
+#[test]
+fn test_body_tail() {
+    let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    let expected = Bytes::from(&data[data.len() - 1000..]);
+    let mut srv = TestServer::new(move || {
+        let data = data.clone();
+        HttpService::new(App::new().service(web::resource("/").route(web::to(
+            move || HttpResponse::Ok().body(data.clone()),
+        ))))
+    });
+
+    let mut response = srv.block_on(awc::Client::new().get(srv.url("/")).send()).unwrap();
+    assert!(response.status().is_success());
+
+    let bytes = srv.block_on(response.tail(1000)).unwrap();
+    assert_eq!(bytes, expected);
+}
+
 #[test]
 fn test_timeout_override() {
     let mut srv = TestServer::new(|| {
@@ -342,6 +586,802 @@ fn test_connection_wait_queue_force_close() {
     assert_eq!(num.load(Ordering::Relaxed), 2);
 }
 
+#[test]
+fn test_blocking_client() {
+    let srv = TestServer::new(|| {
+        HttpService::new(App::new().service(
+            web::resource("/").route(web::to(|| HttpResponse::Ok().body(STR))),
+        ))
+    });
+
+    // plain thread, no runtime is running
+    let mut client = awc::blocking::Client::new();
+    let request = client.get(format!("http://{}/", srv.addr()));
+    let response = client.send_blocking(request).unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.body(), &Bytes::from_static(STR.as_ref()));
+}
+
+#[test]
+fn test_max_redirects_override() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/redirect/{n}").to(
+            |n: web::Path<(usize,)>| {
+                if n.0 == 0 {
+                    HttpResponse::Ok().finish()
+                } else {
+                    HttpResponse::Found()
+                        .header(header::LOCATION, format!("/redirect/{}", n.0 - 1))
+                        .finish()
+                }
+            },
+        )))
+    });
+
+    let client = srv.execute(|| awc::Client::build().max_redirects(1).finish());
+
+    // client wide limit is too low for this chain
+    let req = client.get(srv.url("/redirect/3"));
+    let res = srv.block_on_fn(move || req.send()).unwrap();
+    assert_eq!(res.status(), actix_web::http::StatusCode::FOUND);
+
+    let req = client.get(srv.url("/redirect/3")).max_redirects(5);
+    let res = srv.block_on_fn(move || req.send()).unwrap();
+    assert!(res.status().is_success());
+
+    // following is disabled for this request only
+    let req = client.get(srv.url("/redirect/1")).max_redirects(0);
+    let res = srv.block_on_fn(move || req.send()).unwrap();
+    assert_eq!(res.status(), actix_web::http::StatusCode::FOUND);
+}
+
+#[test]
+fn test_redirect_without_location() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(
+            App::new().service(web::resource("/").to(|| HttpResponse::Found().finish())),
+        )
+    });
+
+    // response is returned as-is by default
+    let client = awc::Client::default();
+    let res = srv.block_on(client.get(srv.url("/")).send()).unwrap();
+    assert_eq!(res.status(), StatusCode::FOUND);
+
+    let client = awc::Client::build().strict_redirects(true).finish();
+    match srv.block_on(client.get(srv.url("/")).send()) {
+        Err(SendRequestError::RedirectNoLocation) => (),
+        _ => panic!("expected RedirectNoLocation error"),
+    }
+
+    // redirects are not followed
+    let req = client.get(srv.url("/")).max_redirects(0);
+    match srv.block_on_fn(move || req.send()) {
+        Err(SendRequestError::RedirectNoLocation) => (),
+        _ => panic!("expected RedirectNoLocation error"),
+    }
+
+    // streaming request
+    let body = futures::stream::once::<_, Error>(Ok(Bytes::from_static(b"data")));
+    let req = client.post(srv.url("/"));
+    match srv.block_on_fn(move || req.send_stream(body)) {
+        Err(SendRequestError::RedirectNoLocation) => (),
+        _ => panic!("expected RedirectNoLocation error"),
+    }
+}
+
+#[test]
+fn test_accept_trailers() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").to(
+            |req: HttpRequest| {
+                let te = req.headers().get(header::TE).unwrap();
+                let conn = req.headers().get(header::CONNECTION).unwrap();
+                if te == "trailers" && conn.to_str().unwrap().contains("te") {
+                    HttpResponse::Ok()
+                } else {
+                    HttpResponse::BadRequest()
+                }
+            },
+        )))
+    });
+
+    let res = srv
+        .block_on(awc::Client::new().get(srv.url("/")).accept_trailers(true).send())
+        .unwrap();
+    assert!(res.status().is_success());
+}
+
+#[test]
+fn test_encode_path() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().default_service(web::to(|req: HttpRequest| {
+            HttpResponse::Ok().body(req.uri().path().to_owned())
+        })))
+    });
+
+    // passed through unchanged by default
+    let mut res = srv.block_on(srv.get("/a%2Fb%7E").send()).unwrap();
+    let bytes = srv.block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(b"/a%2Fb%7E"));
+
+    let mut res = srv
+        .block_on(srv.get("/a%2Fb%7E").encode_path(true).send())
+        .unwrap();
+    let bytes = srv.block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(b"/a%2Fb~"));
+}
+
+#[test]
+fn test_max_response_headers() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").to(|| {
+            let mut res = HttpResponse::Ok();
+            for i in 0..30 {
+                res.header(format!("x-header-{}", i).as_str(), "value");
+            }
+            res.finish()
+        })))
+    });
+
+    let client = awc::Client::build()
+        .connector(awc::Connector::new().max_response_headers(10).finish())
+        .finish();
+
+    // connector limit applies by default
+    let res = srv.block_on(client.get(srv.url("/")).send());
+    assert!(res.is_err());
+
+    let res = srv
+        .block_on(client.get(srv.url("/")).max_response_headers(50).send())
+        .unwrap();
+    assert!(res.status().is_success());
+    assert!(res.headers().contains_key("x-header-29"));
+}
+
+#[test]
+fn test_request_snapshot() {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().take(2) {
+            let mut stream = stream.unwrap();
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).unwrap();
+            let _ = tx.send(buf[..n].to_vec());
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            );
+        }
+    });
+
+    let client = awc::Client::new();
+    let req = client
+        .put(format!("http://{}/path?query=1", addr))
+        .header("x-test", "111")
+        .header("x-test", "222")
+        .cookie(Cookie::new("name", "value"));
+
+    let json = serde_json::to_string(&req.snapshot()).unwrap();
+    let snapshot: awc::RequestSnapshot = serde_json::from_str(&json).unwrap();
+    assert_eq!(snapshot, req.snapshot());
+
+    let res = actix_http_test::block_fn(move || req.send()).unwrap();
+    assert!(res.status().is_success());
+    let original = rx.recv().unwrap();
+
+    let req = client.request_from_snapshot(&snapshot);
+    assert_eq!(req.get_method(), &actix_web::http::Method::PUT);
+    let res = actix_http_test::block_fn(move || req.send()).unwrap();
+    assert!(res.status().is_success());
+    let replayed = rx.recv().unwrap();
+
+    // date header is set when request is sent
+    let without_date = |req: Vec<u8>| {
+        String::from_utf8(req)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with("date:"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    assert_eq!(without_date(original), without_date(replayed));
+}
+
+#[test]
+fn test_empty_body_with_content_type() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").to(
+            |req: HttpRequest, body: Bytes| {
+                let header = |name| {
+                    req.headers()
+                        .get(name)
+                        .map(|v| v.to_str().unwrap().to_owned())
+                        .unwrap_or_else(|| "none".to_owned())
+                };
+                HttpResponse::Ok()
+                    .header("x-content-type", header(header::CONTENT_TYPE))
+                    .header("x-content-length", header(header::CONTENT_LENGTH))
+                    .body(format!("{}", body.len()))
+            },
+        )))
+    });
+
+    // explicitly empty body
+    let request = srv.post("/").content_type("application/json").send();
+    let mut res = srv.block_on(request).unwrap();
+    assert_eq!(
+        res.headers().get("x-content-type").unwrap(),
+        "application/json"
+    );
+    assert_eq!(res.headers().get("x-content-length").unwrap(), "0");
+    let bytes = srv.block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(b"0"));
+
+    // bodyless get request is sent as is
+    let request = srv
+        .get("/")
+        .content_type("application/json")
+        .send_body(Body::None);
+    let mut res = srv.block_on(request).unwrap();
+    assert_eq!(
+        res.headers().get("x-content-type").unwrap(),
+        "application/json"
+    );
+    assert_eq!(res.headers().get("x-content-length").unwrap(), "none");
+    let bytes = srv.block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(b"0"));
+}
+
+#[test]
+fn test_conditional_request() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").to(
+            |req: HttpRequest| {
+                if req.headers().get(header::IF_NONE_MATCH).map(|v| v == "\"v1\"")
+                    == Some(true)
+                    && req.headers().contains_key(header::IF_MODIFIED_SINCE)
+                {
+                    HttpResponse::NotModified().finish()
+                } else {
+                    HttpResponse::Ok()
+                        .header(header::ETAG, "\"v1\"")
+                        .header(header::LAST_MODIFIED, "Wed, 21 Oct 2015 07:28:00 GMT")
+                        .body(STR)
+                }
+            },
+        )))
+    });
+
+    let client = awc::Client::new();
+    let res = srv.block_on(client.get(srv.url("/")).send()).unwrap();
+    assert!(res.status().is_success());
+    assert!(!res.is_not_modified());
+
+    let res = srv
+        .block_on(client.get(srv.url("/")).conditional_on(&res).send())
+        .unwrap();
+    assert!(res.is_not_modified());
+}
+
+#[test]
+fn test_transfer_encoding_override() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").to(
+            |req: HttpRequest, _: Bytes| {
+                let chunked = req.headers().get(header::TRANSFER_ENCODING)
+                    .map(|v| v == "chunked")
+                    .unwrap_or(false);
+                let len = req.headers().get(header::CONTENT_LENGTH)
+                    .map(|v| v.to_str().unwrap().to_owned())
+                    .unwrap_or_default();
+                HttpResponse::Ok().body(format!("{}:{}", chunked, len))
+            },
+        )))
+    });
+
+    let client = awc::Client::new();
+
+    // sized body is sent chunked
+    let mut res = srv
+        .block_on(
+            client
+                .post(srv.url("/"))
+                .transfer_encoding(awc::TransferEncoding::Chunked)
+                .send_body(STR),
+        )
+        .unwrap();
+    let bytes = srv.block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(b"true:"));
+
+    // sized body is sent with content-length
+    let req = client
+        .post(srv.url("/"))
+        .transfer_encoding(awc::TransferEncoding::ContentLength);
+    let mut res = srv.block_on_fn(move || req.send_body(STR)).unwrap();
+    let bytes = srv.block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from(format!("false:{}", STR.len())));
+
+    // stream size is not known
+    let body = futures::stream::once::<_, Error>(Ok(Bytes::from_static(STR.as_ref())));
+    let req = client
+        .post(srv.url("/"))
+        .transfer_encoding(awc::TransferEncoding::ContentLength);
+    let res = srv.block_on_fn(move || req.send_stream(body));
+    match res {
+        Err(SendRequestError::UnknownBodyLength) => (),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_request_bytes_sent() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(
+            web::resource("/").route(web::to(|body: Bytes| HttpResponse::Ok().body(body))),
+        ))
+    });
+
+    // chunked stream of known total size
+    let body = futures::stream::iter_ok::<_, Error>(vec![
+        Bytes::from_static(STR.as_ref()),
+        Bytes::from_static(STR.as_ref()),
+        Bytes::from_static(STR.as_ref()),
+    ]);
+    let res = srv
+        .block_on(awc::Client::new().post(srv.url("/")).send_stream(body))
+        .unwrap();
+    assert!(res.status().is_success());
+    assert_eq!(res.request_bytes_sent(), (STR.len() * 3) as u64);
+}
+
+#[cfg(feature = "checksum")]
+#[test]
+fn test_checksum() {
+    use awc::error::ChecksumError;
+    use sha2::{Digest, Sha256};
+
+    // server echoes body checksum, `/bad` responds with wrong one
+    let mut srv = TestServer::new(|| {
+        HttpService::new(
+            App::new()
+                .service(web::resource("/").to(|body: Bytes| {
+                    let digest = Sha256::digest(&body);
+                    let hex: String =
+                        digest.iter().map(|b| format!("{:02x}", b)).collect();
+                    HttpResponse::Ok().header("x-checksum", hex).finish()
+                }))
+                .service(web::resource("/bad").to(|_: Bytes| {
+                    HttpResponse::Ok().header("x-checksum", "0123abcd").finish()
+                })),
+        )
+    });
+
+    let (body, checksum) = awc::ChecksumBody::sha256(STR);
+    let res = srv
+        .block_on(awc::Client::new().put(srv.url("/")).send_body(body))
+        .unwrap();
+    assert!(res.status().is_success());
+    assert!(checksum.verify(&res, "x-checksum").is_ok());
+
+    let (body, checksum) = awc::ChecksumBody::sha256(STR);
+    let res = srv
+        .block_on(awc::Client::new().put(srv.url("/bad")).send_body(body))
+        .unwrap();
+    match checksum.verify(&res, "x-checksum") {
+        Err(ChecksumError::Mismatch { expected, actual }) => {
+            assert_eq!(expected, "0123abcd");
+            assert_eq!(Some(actual), checksum.hex());
+        }
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn test_sent_headers() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(
+            web::resource("/").route(web::to(|body: Bytes| HttpResponse::Ok().body(body))),
+        ))
+    });
+
+    // headers are not captured by default
+    let res = srv
+        .block_on(awc::Client::new().post(srv.url("/")).send_body(STR))
+        .unwrap();
+    assert!(res.status().is_success());
+    assert!(res.sent_headers().is_empty());
+
+    let res = srv
+        .block_on(
+            awc::Client::new()
+                .post(srv.url("/"))
+                .header("x-test", "111")
+                .capture_sent_headers()
+                .send_body(STR),
+        )
+        .unwrap();
+    assert!(res.status().is_success());
+
+    let headers = res.sent_headers();
+    // user-set header
+    assert_eq!(headers.get("x-test").unwrap(), "111");
+    // headers inserted by client
+    let url: Uri = srv.url("/").parse().unwrap();
+    assert_eq!(
+        headers.get(header::HOST).unwrap().to_str().unwrap(),
+        url.authority_part().unwrap().as_str()
+    );
+    assert_eq!(
+        headers.get(header::CONTENT_LENGTH).unwrap().to_str().unwrap(),
+        STR.len().to_string()
+    );
+}
+
+#[test]
+fn test_fetch_json() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(
+            App::new()
+                .service(web::resource("/ok").to(|| {
+                    HttpResponse::Ok()
+                        .content_type("application/json")
+                        .body(r#"{"name": "test"}"#)
+                }))
+                .service(web::resource("/missing").to(|| {
+                    HttpResponse::NotFound().body("not here")
+                }))
+                .service(web::resource("/malformed").to(|| {
+                    HttpResponse::Ok()
+                        .content_type("application/json")
+                        .body(r#"{"name": "#)
+                })),
+        )
+    });
+
+    let client = awc::Client::new();
+
+    // successful deserialize
+    let value: HashMap<String, String> = srv
+        .block_on(client.get(srv.url("/ok")).fetch_json())
+        .unwrap();
+    assert_eq!(value.get("name").map(|s| s.as_str()), Some("test"));
+
+    // non-2xx status
+    let req = client.get(srv.url("/missing"));
+    let res = srv.block_on_fn(move || req.fetch_json::<HashMap<String, String>>());
+    match res {
+        Err(FetchJsonError::Status(status, body)) => {
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body, Bytes::from_static(b"not here"));
+        }
+        _ => panic!("expected status error"),
+    }
+
+    // malformed json
+    let req = client.get(srv.url("/malformed"));
+    let res = srv.block_on_fn(move || req.fetch_json::<HashMap<String, String>>());
+    match res {
+        Err(FetchJsonError::Json(JsonPayloadError::Deserialize(_))) => (),
+        _ => panic!("expected json error"),
+    }
+}
+
+#[test]
+fn test_shared_body() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/{path}").route(
+            web::to(|body: Bytes| HttpResponse::Ok().body(body)),
+        )))
+    });
+
+    // every request gets a clone of the same buffer
+    let body = Bytes::from(STR.repeat(10));
+    let client = awc::Client::new();
+
+    for path in &["/first", "/second"] {
+        let (client, url, body2) = (client.clone(), srv.url(path), body.clone());
+        let mut res = srv
+            .block_on_fn(move || client.post(url).send_body(body2))
+            .unwrap();
+        let bytes = srv.block_on(res.body().limit(1024 * 1024)).unwrap();
+        assert_eq!(bytes, body);
+    }
+}
+
+/// Raw http/1 server, every accepted connection gets next scripted response.
+/// Connection is closed after response is written.
+fn scripted_server(
+    responses: &'static [&'static [u8]],
+) -> (net::SocketAddr, Arc<AtomicUsize>) {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let conns = Arc::new(AtomicUsize::new(0));
+    let conns2 = conns.clone();
+
+    thread::spawn(move || {
+        for (stream, response) in listener.incoming().zip(responses) {
+            let mut stream = stream.unwrap();
+            conns2.fetch_add(1, Ordering::Relaxed);
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(response);
+        }
+    });
+    (addr, conns)
+}
+
+#[test]
+fn test_retry_before_head() {
+    // first connection is closed before response head
+    let (addr, conns) =
+        scripted_server(&[b"", b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok"]);
+
+    let client = awc::Client::build()
+        .retry_policy(RetryPolicy::new(1))
+        .finish();
+    let url = format!("http://{}/", addr);
+    let mut res = actix_http_test::block_fn(move || client.get(url).send()).unwrap();
+    assert!(res.status().is_success());
+    let bytes = actix_http_test::block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(b"ok"));
+    assert_eq!(conns.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn test_retry_server_close_twice() {
+    // first two connections are closed before response head
+    let (addr, conns) = scripted_server(&[
+        b"",
+        b"",
+        b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok",
+    ]);
+
+    let client = awc::Client::build()
+        .retry_policy(RetryPolicy::new(2))
+        .finish();
+    let url = format!("http://{}/", addr);
+    let mut res = actix_http_test::block_fn(move || client.get(url).send()).unwrap();
+    assert!(res.status().is_success());
+    let bytes = actix_http_test::block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(b"ok"));
+    assert_eq!(conns.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn test_no_retry() {
+    // first connection is closed before response head
+    let (addr, conns) =
+        scripted_server(&[b"", b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok"]);
+
+    let client = awc::Client::build()
+        .retry_policy(RetryPolicy::new(1))
+        .finish();
+    let url = format!("http://{}/", addr);
+    let res = actix_http_test::block_fn(move || client.get(url).no_retry().send());
+    assert!(res.is_err());
+    assert_eq!(conns.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn test_retry_rate_limit_reset() {
+    // first request is rate limited for about two seconds, server records
+    // whether retried request arrives after reset time
+    let requests = Arc::new(AtomicUsize::new(0));
+    let after_reset = Arc::new(AtomicBool::new(false));
+    let (requests2, after_reset2) = (requests.clone(), after_reset.clone());
+    let reset = Arc::new(AtomicUsize::new(0));
+    let mut srv = TestServer::new(move || {
+        let reset = reset.clone();
+        let (requests, after_reset) = (requests2.clone(), after_reset2.clone());
+        HttpService::new(App::new().service(web::resource("/").to(move || {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap();
+            if requests.fetch_add(1, Ordering::Relaxed) == 0 {
+                let secs = now.as_secs() as usize + 2;
+                reset.store(secs, Ordering::Relaxed);
+                HttpResponse::TooManyRequests()
+                    .header("x-ratelimit-reset", secs.to_string())
+                    .header("x-ratelimit-remaining", "0")
+                    .finish()
+            } else {
+                let secs = reset.load(Ordering::Relaxed) as u64;
+                after_reset.store(now >= Duration::from_secs(secs), Ordering::Relaxed);
+                HttpResponse::Ok().finish()
+            }
+        })))
+    });
+
+    let client = awc::Client::build()
+        .retry_policy(RetryPolicy::new(1).rate_limit_headers(
+            header::HeaderName::from_static("x-ratelimit-reset"),
+            header::HeaderName::from_static("x-ratelimit-remaining"),
+        ))
+        .finish();
+
+    let res = srv.block_on(client.get(srv.url("/")).send()).unwrap();
+    assert!(res.status().is_success());
+    // retry waits until reset time
+    assert_eq!(requests.load(Ordering::Relaxed), 2);
+    assert!(after_reset.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_retry_max_total_delay() {
+    // every connection is closed before response head
+    static CLOSED: [&[u8]; 10] = [b""; 10];
+    let (addr, conns) = scripted_server(&CLOSED);
+
+    let client = awc::Client::build()
+        .retry_policy(
+            RetryPolicy::new(10)
+                .backoff(Duration::from_millis(50))
+                .max_total_delay(Duration::from_millis(120)),
+        )
+        .finish();
+    let url = format!("http://{}/", addr);
+    let res = actix_http_test::block_fn(move || client.get(url).send());
+    assert!(res.is_err());
+    // two delays fit into the limit
+    assert_eq!(conns.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn test_retry_scripted_connector() {
+    let connector = TestConnector::new()
+        .fail(ConnectError::Timeout)
+        .fail(ConnectError::Disconnected)
+        .respond(TestResponse::default().set_payload("ok"));
+    let client = awc::Client::build()
+        .connector(connector.clone())
+        .retry_policy(RetryPolicy::new(2))
+        .finish();
+
+    let mut res =
+        actix_http_test::block_fn(move || client.get("http://localhost/").send())
+            .unwrap();
+    assert!(res.status().is_success());
+    let bytes = actix_http_test::block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(b"ok"));
+    assert_eq!(connector.attempts(), 3);
+
+    // retries are exhausted
+    let connector = TestConnector::new()
+        .fail(ConnectError::Timeout)
+        .fail(ConnectError::Timeout)
+        .respond(TestResponse::default());
+    let client = awc::Client::build()
+        .connector(connector.clone())
+        .retry_policy(RetryPolicy::new(1))
+        .finish();
+    let res = actix_http_test::block_fn(move || client.get("http://localhost/").send());
+    match res {
+        Err(SendRequestError::Connect(_)) => (),
+        res => panic!("unexpected result: {:?}", res.map(|_| ())),
+    }
+    assert_eq!(connector.attempts(), 2);
+
+    // refused connect is not retried
+    let connector = TestConnector::new()
+        .refuse()
+        .respond(TestResponse::default());
+    let client = awc::Client::build()
+        .connector(connector.clone())
+        .retry_policy(RetryPolicy::new(1))
+        .finish();
+    let res = actix_http_test::block_fn(move || client.get("http://localhost/").send());
+    match res {
+        Err(SendRequestError::Connect(ConnectError::Refused(_))) => (),
+        res => panic!("unexpected result: {:?}", res.map(|_| ())),
+    }
+    assert_eq!(connector.attempts(), 1);
+}
+
+#[test]
+fn test_retry_non_idempotent() {
+    // post request is sent, connection is closed before response head
+    static RESPONSES: [&[u8]; 2] =
+        [b"", b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok"];
+    let (addr, conns) = scripted_server(&RESPONSES);
+    let client = awc::Client::build()
+        .retry_policy(RetryPolicy::new(1))
+        .finish();
+    let url = format!("http://{}/", addr);
+    let res = actix_http_test::block_fn(move || client.post(url).send_body("data"));
+    assert!(res.is_err());
+    assert_eq!(conns.load(Ordering::Relaxed), 1);
+
+    // retry is enabled explicitly
+    let (addr, conns) = scripted_server(&RESPONSES);
+    let client = awc::Client::build()
+        .retry_policy(RetryPolicy::new(1).retry_non_idempotent(true))
+        .finish();
+    let url = format!("http://{}/", addr);
+    let res = actix_http_test::block_fn(move || client.post(url).send_body("data"));
+    assert!(res.unwrap().status().is_success());
+    assert_eq!(conns.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn test_no_retry_during_body() {
+    // first connection is closed in the middle of response body
+    let (addr, conns) = scripted_server(&[
+        b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nok",
+        b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok",
+    ]);
+
+    let client = awc::Client::build()
+        .retry_policy(RetryPolicy::new(1))
+        .finish();
+    let url = format!("http://{}/", addr);
+    let mut res = actix_http_test::block_fn(move || client.get(url).send()).unwrap();
+    assert!(res.status().is_success());
+    assert!(actix_http_test::block_on(res.body()).is_err());
+    assert_eq!(conns.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn test_body_or_partial() {
+    // server sends first part of the body and stalls
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 1024];
+        let _ = stream.read(&mut buf);
+        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nhello");
+        thread::sleep(Duration::from_secs(1));
+        let _ = stream.write_all(b"world");
+    });
+
+    let client = awc::Client::default();
+    let url = format!("http://{}/", addr);
+    let (body, truncated) = actix_http_test::block_fn(move || {
+        client.get(url).send().and_then(|mut res| {
+            res.body_or_partial(Duration::from_millis(200))
+                .map_err(|e| SendRequestError::Body(e.into()))
+        })
+    })
+    .unwrap();
+    assert_eq!(body, Bytes::from_static(b"hello"));
+    assert!(truncated);
+}
+
+#[test]
+fn test_timings() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(
+            web::resource("/").route(web::to(|| HttpResponse::Ok().body(STR))),
+        ))
+    });
+
+    let client = srv.execute(|| {
+        // connects complete after 200 milliseconds
+        let tcp = apply_fn(actix_connect::default_connector(), |req, srv| {
+            let mut srv = srv.clone();
+            tokio_timer::sleep(Duration::from_millis(200)).then(move |_| srv.call(req))
+        });
+        awc::Client::build()
+            .connector(awc::Connector::new().connector(tcp).finish())
+            .finish()
+    });
+
+    // cold request opens new connection
+    let mut res = srv.block_on(client.get(srv.url("/")).send()).unwrap();
+    let _ = srv.block_on(res.body());
+    let cold = res.timings().unwrap();
+    assert!(cold.acquire >= Duration::from_millis(200));
+
+    // pooled connection does not wait for connect
+    let req = client.get(srv.url("/"));
+    let mut res = srv.block_on_fn(move || req.send()).unwrap();
+    let _ = srv.block_on(res.body());
+    let pooled = res.timings().unwrap();
+    assert!(pooled.acquire < Duration::from_millis(200));
+}
+
 #[test]
 fn test_with_query_parameter() {
     let mut srv = TestServer::new(|| {
@@ -400,6 +1440,115 @@ fn test_no_decompress() {
     assert_eq!(Bytes::from(dec), Bytes::from_static(STR.as_ref()));
 }
 
+#[test]
+fn test_client_compress_level() {
+    // server echoes decompressed request body
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").to(
+            |req: HttpRequest, body: Bytes| {
+                let encoding = req.headers().get(header::CONTENT_ENCODING).cloned();
+                HttpResponse::Ok()
+                    .header("x-encoding", encoding.unwrap())
+                    .body(body)
+            },
+        )))
+    });
+
+    let data: String = rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(16_384)
+        .collect::<String>()
+        .repeat(4);
+
+    let mut sent = Vec::new();
+    for level in &[1, 9] {
+        let req = srv
+            .post("/")
+            .compress(header::ContentEncoding::Gzip, *level)
+            .send_body(data.clone());
+        let mut res = srv.block_on(req).unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(res.headers().get("x-encoding").unwrap(), "gzip");
+        sent.push(res.request_bytes_sent());
+
+        let bytes = srv.block_on(res.body().limit(1_048_576)).unwrap();
+        assert_eq!(bytes, Bytes::from(data.clone()));
+    }
+    assert!(sent[0] < data.len() as u64);
+    assert!(sent[0] >= sent[1]);
+}
+
+#[test]
+fn test_client_compress_redirect() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(
+            App::new()
+                .service(web::resource("/").to(|req: HttpRequest, body: Bytes| {
+                    let encoding = req.headers().get(header::CONTENT_ENCODING).cloned();
+                    HttpResponse::Ok()
+                        .header("x-encoding", encoding.unwrap())
+                        .body(body)
+                }))
+                .service(web::resource("/old").to(|_: Bytes| {
+                    HttpResponse::TemporaryRedirect()
+                        .header(header::LOCATION, "/")
+                        .finish()
+                })),
+        )
+    });
+
+    // in-memory body is compressed eagerly and resent on redirect
+    let req = srv
+        .post("/old")
+        .compress(header::ContentEncoding::Gzip, 6)
+        .send_body(STR);
+    let mut res = srv.block_on(req).unwrap();
+    assert!(res.status().is_success());
+    assert_eq!(res.headers().get("x-encoding").unwrap(), "gzip");
+
+    let bytes = srv.block_on(res.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(STR.as_ref()));
+}
+
+#[test]
+fn test_text_charset() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(
+            App::new()
+                .service(web::resource("/utf8").to(|| {
+                    HttpResponse::Ok()
+                        .content_type("text/plain; charset=utf-8")
+                        .body("h\u{e9}llo w\u{f6}rld")
+                }))
+                .service(web::resource("/latin1").to(|| {
+                    HttpResponse::Ok()
+                        .content_type("text/plain; charset=iso-8859-1")
+                        .body(&b"h\xe9llo w\xf6rld"[..])
+                }))
+                .service(web::resource("/invalid").to(|| {
+                    HttpResponse::Ok()
+                        .content_type("text/plain")
+                        .body(&b"h\xe9llo"[..])
+                })),
+        )
+    });
+
+    let mut res = srv.block_on(srv.get("/utf8").send()).unwrap();
+    let text = srv.block_on(res.text()).unwrap();
+    assert_eq!(text, "h\u{e9}llo w\u{f6}rld");
+
+    let mut res = srv.block_on(srv.get("/latin1").send()).unwrap();
+    let text = srv.block_on(res.text()).unwrap();
+    assert_eq!(text, "h\u{e9}llo w\u{f6}rld");
+
+    // body is decoded as utf-8 without charset
+    let mut res = srv.block_on(srv.get("/invalid").send()).unwrap();
+    match srv.block_on(res.text()) {
+        Err(TextError::Decode) => (),
+        res => panic!("{:?}", res),
+    }
+}
+
 #[test]
 fn test_client_gzip_encoding() {
     let mut srv = TestServer::new(|| {
@@ -446,6 +1595,58 @@ fn test_client_gzip_encoding_large() {
     assert_eq!(bytes, Bytes::from(STR.repeat(10)));
 }
 
+#[test]
+fn test_client_max_decompressed_size() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").route(web::to(|| {
+            // about 1Kb compressed
+            let mut e = GzEncoder::new(Vec::new(), Compression::best());
+            e.write_all(&[0; 1024 * 1024]).unwrap();
+            let data = e.finish().unwrap();
+
+            HttpResponse::Ok()
+                .header("content-encoding", "gzip")
+                .body(data)
+        }))))
+    });
+
+    let client = srv.execute(|| {
+        awc::Client::build()
+            .connector(awc::Connector::new().max_decompressed_size(64 * 1024).finish())
+            .finish()
+    });
+
+    let mut response = srv.block_on(client.get(srv.url("/")).send()).unwrap();
+    assert!(response.status().is_success());
+    match srv.block_on(response.body().limit(2 * 1024 * 1024)) {
+        Err(PayloadError::Overflow) => (),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn test_client_default_max_decompressed_size() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").route(web::to(|| {
+            // inflates past default limit of 25Mb
+            let mut e = GzEncoder::new(Vec::new(), Compression::fast());
+            e.write_all(&[0; 26 * 1024 * 1024]).unwrap();
+            let data = e.finish().unwrap();
+
+            HttpResponse::Ok()
+                .header("content-encoding", "gzip")
+                .body(data)
+        }))))
+    });
+
+    let mut response = srv.block_on(srv.get("/").send()).unwrap();
+    assert!(response.status().is_success());
+    match srv.block_on(response.body().limit(64 * 1024 * 1024)) {
+        Err(PayloadError::Overflow) => (),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
 #[test]
 fn test_client_gzip_encoding_large_random() {
     let data = rand::thread_rng()
@@ -499,6 +1700,58 @@ fn test_client_brotli_encoding() {
     assert_eq!(bytes, Bytes::from_static(STR.as_ref()));
 }
 
+#[test]
+fn test_client_multiple_encodings() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(App::new().service(web::resource("/").route(web::to(
+            |req: HttpRequest| {
+                // unknown encodings, payload is sent as is
+                match req.query_string() {
+                    "single" => {
+                        return HttpResponse::Ok()
+                            .header("content-encoding", "zz")
+                            .body(STR);
+                    }
+                    "list" => {
+                        return HttpResponse::Ok()
+                            .header("content-encoding", "gzip, zz")
+                            .body(STR);
+                    }
+                    _ => (),
+                }
+
+                // gzip is applied first, then brotli
+                let mut e = GzEncoder::new(Vec::new(), Compression::default());
+                e.write_all(STR.as_ref()).unwrap();
+                let data = e.finish().unwrap();
+                let mut e = BrotliEncoder::new(Vec::new(), 5);
+                e.write_all(&data).unwrap();
+                let data = e.finish().unwrap();
+                HttpResponse::Ok()
+                    .header("content-encoding", "gzip, br")
+                    .body(data)
+            },
+        ))))
+    });
+
+    let mut response = srv.block_on(srv.get("/").send()).unwrap();
+    assert!(response.status().is_success());
+    let bytes = srv.block_on(response.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(STR.as_ref()));
+
+    // single unknown encoding is passed through
+    let mut response = srv.block_on(srv.get("/?single").send()).unwrap();
+    let bytes = srv.block_on(response.body()).unwrap();
+    assert_eq!(bytes, Bytes::from_static(STR.as_ref()));
+
+    // unknown encoding in the chain
+    let mut response = srv.block_on(srv.get("/?list").send()).unwrap();
+    match srv.block_on(response.body()) {
+        Err(PayloadError::EncodingCorrupted) => (),
+        res => panic!("{:?}", res),
+    }
+}
+
 // #[test]
 // fn test_client_brotli_encoding_large_random() {
 //     let data = rand::thread_rng()
@@ -790,3 +2043,31 @@ fn client_bearer_auth() {
     let response = srv.block_on(request.send()).unwrap();
     assert!(response.status().is_success());
 }
+
+#[test]
+fn test_head_body_framing() {
+    let mut srv = TestServer::new(|| {
+        HttpService::new(
+            App::new()
+                .service(web::resource("/").to(|| HttpResponse::Ok().body(STR)))
+                .service(web::resource("/stream").to(|| {
+                    HttpResponse::Ok().streaming(futures::stream::once::<_, Error>(Ok(
+                        Bytes::from_static(STR.as_ref()),
+                    )))
+                })),
+        )
+    });
+
+    // head response has no body, framing is reported from headers
+    let mut res = srv.block_on(srv.head("/").send()).unwrap();
+    assert!(res.status().is_success());
+    assert_eq!(res.body_framing(), BodyFraming::ContentLength(STR.len() as u64));
+    let bytes = srv.block_on(res.body()).unwrap();
+    assert!(bytes.is_empty());
+
+    let res = srv.block_on(srv.head("/stream").send()).unwrap();
+    assert_eq!(res.body_framing(), BodyFraming::Chunked);
+
+    let res = srv.block_on(srv.get("/").send()).unwrap();
+    assert_eq!(res.body_framing(), BodyFraming::ContentLength(STR.len() as u64));
+}
@@ -10,12 +10,14 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use actix_codec::{AsyncRead, AsyncWrite};
+use actix_http::client::{ConnectError, SendRequestError};
 use actix_http::HttpService;
 use actix_http_test::TestServer;
 use actix_server::ssl::RustlsAcceptor;
 use actix_service::{service_fn, NewService};
 use actix_web::http::Version;
 use actix_web::{web, App, HttpResponse};
+use awc::RetryPolicy;
 
 fn ssl_acceptor<T: AsyncRead + AsyncWrite>() -> Result<RustlsAcceptor<T, ()>> {
     use rustls::ServerConfig;
@@ -94,3 +96,60 @@ fn test_connection_reuse_h2() {
     // one connection
     assert_eq!(num.load(Ordering::Relaxed), 1);
 }
+
+#[test]
+fn test_retry_tls_handshake() {
+    let rustls = ssl_acceptor().unwrap();
+    let num = Arc::new(AtomicUsize::new(0));
+    let num2 = num.clone();
+
+    let mut srv = TestServer::new(move || {
+        let num2 = num2.clone();
+        // first two connections are closed before handshake
+        service_fn(move |io| {
+            if num2.fetch_add(1, Ordering::Relaxed) < 2 {
+                Err(())
+            } else {
+                Ok(io)
+            }
+        })
+        .and_then(rustls.clone().map_err(|e| println!("Rustls error: {}", e)))
+        .and_then(
+            HttpService::build()
+                .h2(App::new()
+                    .service(web::resource("/").route(web::to(|| HttpResponse::Ok()))))
+                .map_err(|_| ()),
+        )
+    });
+
+    // disable ssl verification
+    let no_verify = || {
+        let mut config = ClientConfig::new();
+        let protos = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        config.set_protocols(&protos);
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(danger::NoCertificateVerification {}));
+        Arc::new(config)
+    };
+
+    // handshake errors are not retried by default
+    let client = awc::Client::build()
+        .connector(awc::Connector::new().rustls(no_verify()).finish())
+        .retry_policy(RetryPolicy::new(1))
+        .finish();
+    let response = srv.block_on(client.get(srv.surl("/")).send());
+    match response {
+        Err(SendRequestError::Connect(ConnectError::TlsHandshake(_))) => (),
+        _ => panic!("tls handshake error expected"),
+    }
+    assert_eq!(num.load(Ordering::Relaxed), 1);
+
+    let client = awc::Client::build()
+        .connector(awc::Connector::new().rustls(no_verify()).finish())
+        .retry_policy(RetryPolicy::new(1).retry_tls_handshake(true))
+        .finish();
+    let response = srv.block_on(client.get(srv.surl("/")).send()).unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(num.load(Ordering::Relaxed), 3);
+}
@@ -4,14 +4,19 @@ use openssl::ssl::{SslAcceptor, SslConnector, SslFiletype, SslMethod, SslVerifyM
 use std::io::Result;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use actix_codec::{AsyncRead, AsyncWrite};
+use actix_http::client::{ConnectError, Protocol, SendRequestError, TlsConfig};
 use actix_http::HttpService;
 use actix_http_test::TestServer;
 use actix_server::ssl::OpensslAcceptor;
 use actix_service::{service_fn, NewService};
 use actix_web::http::Version;
 use actix_web::{web, App, HttpResponse};
+use awc::RetryPolicy;
+use futures::{future, Future};
+use tokio_timer::Delay;
 
 fn ssl_acceptor<T: AsyncRead + AsyncWrite>() -> Result<OpensslAcceptor<T, ()>> {
     // load ssl keys
@@ -34,6 +39,48 @@ fn ssl_acceptor<T: AsyncRead + AsyncWrite>() -> Result<OpensslAcceptor<T, ()>> {
     Ok(actix_server::ssl::OpensslAcceptor::new(builder.build()))
 }
 
+#[test]
+fn test_response_protocol() {
+    // server selects http/1.1 with alpn
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    builder
+        .set_private_key_file("../tests/key.pem", SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("../tests/cert.pem")
+        .unwrap();
+    builder.set_alpn_select_callback(|_, protos| {
+        const H1: &[u8] = b"\x08http/1.1";
+        if protos.windows(9).any(|window| window == H1) {
+            Ok(b"http/1.1")
+        } else {
+            Err(openssl::ssl::AlpnError::NOACK)
+        }
+    });
+    let openssl = OpensslAcceptor::new(builder.build());
+
+    let mut srv = TestServer::new(move || {
+        openssl.clone().map_err(|_| ()).and_then(
+            HttpService::build()
+                .h1(App::new().service(web::resource("/").to(|| HttpResponse::Ok())))
+                .map_err(|_| ()),
+        )
+    });
+
+    // disable ssl verification
+    let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+    builder.set_verify(SslVerifyMode::NONE);
+    let _ = builder.set_alpn_protos(b"\x02h2\x08http/1.1");
+
+    let client = awc::Client::build()
+        .connector(awc::Connector::new().ssl(builder.build()).finish())
+        .finish();
+
+    let response = srv.block_on(client.get(srv.surl("/")).send()).unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.protocol(), Some(Protocol::Http1));
+}
+
 #[test]
 fn test_connection_reuse_h2() {
     let openssl = ssl_acceptor().unwrap();
@@ -84,3 +131,228 @@ fn test_connection_reuse_h2() {
     // one connection
     assert_eq!(num.load(Ordering::Relaxed), 1);
 }
+
+#[test]
+fn test_tls_config_swap() {
+    let openssl = ssl_acceptor().unwrap();
+    let num = Arc::new(AtomicUsize::new(0));
+    let num2 = num.clone();
+
+    let mut srv = TestServer::new(move || {
+        let num2 = num2.clone();
+        service_fn(move |io| {
+            num2.fetch_add(1, Ordering::Relaxed);
+            Ok(io)
+        })
+        .and_then(
+            openssl
+                .clone()
+                .map_err(|e| println!("Openssl error: {}", e)),
+        )
+        .and_then(
+            HttpService::build()
+                .h2(App::new()
+                    .service(web::resource("/").route(web::to(|| HttpResponse::Ok()))))
+                .map_err(|_| ()),
+        )
+    });
+
+    // disable ssl verification
+    let no_verify = || {
+        let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+        builder.set_verify(SslVerifyMode::NONE);
+        let _ = builder.set_alpn_protos(b"\x02h2\x08http/1.1");
+        builder.build()
+    };
+
+    let connector = awc::Connector::new().ssl(no_verify());
+    let tls = connector.tls_config_handle();
+    let client = awc::Client::build().connector(connector.finish()).finish();
+
+    let response = srv.block_on(client.get(srv.surl("/")).send()).unwrap();
+    assert!(response.status().is_success());
+
+    // self-signed server certificate is not trusted by default config
+    let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+    let _ = builder.set_alpn_protos(b"\x02h2\x08http/1.1");
+    tls.set_tls_config(TlsConfig::Openssl(builder.build()));
+
+    // pooled connection keeps working
+    let response = srv.block_on(client.get(srv.surl("/")).send()).unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(num.load(Ordering::Relaxed), 1);
+
+    // new connection uses new config
+    let url = format!("https://127.0.0.1:{}/", srv.addr().port());
+    assert!(srv.block_on(client.get(url.as_str()).send()).is_err());
+
+    tls.set_tls_config(TlsConfig::Openssl(no_verify()));
+    let response = srv.block_on(client.get(url.as_str()).send()).unwrap();
+    assert!(response.status().is_success());
+
+    // drained pool opens new connection
+    let opened = num.load(Ordering::Relaxed);
+    srv.block_on_fn(|| {
+        tls.set_tls_config_and_drain(TlsConfig::Openssl(no_verify()));
+        future::ok::<_, ()>(())
+    })
+    .unwrap();
+    let response = srv.block_on(client.get(srv.surl("/")).send()).unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(num.load(Ordering::Relaxed), opened + 1);
+}
+
+#[test]
+fn test_host_tls_config() {
+    let openssl = ssl_acceptor().unwrap();
+
+    let mut srv = TestServer::new(move || {
+        openssl
+            .clone()
+            .map_err(|e| println!("Openssl error: {}", e))
+            .and_then(
+                HttpService::build()
+                    .h2(App::new()
+                        .service(web::resource("/").route(web::to(|| HttpResponse::Ok()))))
+                    .map_err(|_| ()),
+            )
+    });
+
+    // self-signed certificate is accepted only for internal host
+    let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+    builder.set_verify(SslVerifyMode::NONE);
+    let _ = builder.set_alpn_protos(b"\x02h2\x08http/1.1");
+
+    let connector = awc::Connector::new()
+        .host_tls_config("internal.test", TlsConfig::Openssl(builder.build()));
+    let client = awc::Client::build().connector(connector.finish()).finish();
+
+    let url = format!("https://internal.test:{}/", srv.addr().port());
+    let response = srv
+        .block_on(client.get(url.as_str()).address(srv.addr()).send())
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let url = format!("https://external.test:{}/", srv.addr().port());
+    let response = srv.block_on(client.get(url.as_str()).address(srv.addr()).send());
+    assert!(response.is_err());
+}
+
+#[test]
+fn test_h2_max_connection_age() {
+    let openssl = ssl_acceptor().unwrap();
+    let num = Arc::new(AtomicUsize::new(0));
+    let num2 = num.clone();
+
+    let mut srv = TestServer::new(move || {
+        let num2 = num2.clone();
+        service_fn(move |io| {
+            num2.fetch_add(1, Ordering::Relaxed);
+            Ok(io)
+        })
+        .and_then(
+            openssl
+                .clone()
+                .map_err(|e| println!("Openssl error: {}", e)),
+        )
+        .and_then(
+            HttpService::build()
+                .h2(App::new()
+                    .service(web::resource("/").route(web::to(|| HttpResponse::Ok())))
+                    .service(web::resource("/slow").route(web::to_async(|| {
+                        Delay::new(Instant::now() + Duration::from_millis(500))
+                            .then(|_| Ok::<_, actix_web::Error>(HttpResponse::Ok()))
+                    }))))
+                .map_err(|_| ()),
+        )
+    });
+
+    // disable ssl verification
+    let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+    builder.set_verify(SslVerifyMode::NONE);
+    let _ = builder.set_alpn_protos(b"\x02h2\x08http/1.1");
+
+    let client = awc::Client::build()
+        .connector(
+            awc::Connector::new()
+                .ssl(builder.build())
+                .h2_max_connection_age(Duration::from_millis(200))
+                .finish(),
+        )
+        .finish();
+
+    // slow stream is in-flight while connection expires
+    let slow = client.get(srv.surl("/slow"));
+    let fresh = client.get(srv.surl("/"));
+    let (slow, fresh) = srv
+        .block_on_fn(move || {
+            let fresh = Delay::new(Instant::now() + Duration::from_millis(300))
+                .then(move |_| fresh.send());
+            slow.send().join(fresh)
+        })
+        .unwrap();
+    assert!(slow.status().is_success());
+    assert!(fresh.status().is_success());
+    assert_eq!(fresh.version(), Version::HTTP_2);
+
+    // expired connection is not reused
+    assert_eq!(num.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn test_retry_tls_handshake() {
+    let openssl = ssl_acceptor().unwrap();
+    let num = Arc::new(AtomicUsize::new(0));
+    let num2 = num.clone();
+
+    let mut srv = TestServer::new(move || {
+        let num2 = num2.clone();
+        // first two connections are closed before handshake
+        service_fn(move |io| {
+            if num2.fetch_add(1, Ordering::Relaxed) < 2 {
+                Err(())
+            } else {
+                Ok(io)
+            }
+        })
+        .and_then(
+            openssl
+                .clone()
+                .map_err(|e| println!("Openssl error: {}", e)),
+        )
+        .and_then(
+            HttpService::build()
+                .h2(App::new()
+                    .service(web::resource("/").route(web::to(|| HttpResponse::Ok()))))
+                .map_err(|_| ()),
+        )
+    });
+
+    // disable ssl verification
+    let no_verify = || {
+        let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+        builder.set_verify(SslVerifyMode::NONE);
+        let _ = builder.set_alpn_protos(b"\x02h2\x08http/1.1");
+        builder.build()
+    };
+
+    // handshake errors are not retried by default
+    let client = awc::Client::build()
+        .connector(awc::Connector::new().ssl(no_verify()).finish())
+        .retry_policy(RetryPolicy::new(1))
+        .finish();
+    let response = srv.block_on(client.get(srv.surl("/")).send());
+    match response {
+        Err(SendRequestError::Connect(ConnectError::SslError(_))) => (),
+        _ => panic!("tls handshake error expected"),
+    }
+    assert_eq!(num.load(Ordering::Relaxed), 1);
+
+    let client = awc::Client::build()
+        .connector(awc::Connector::new().ssl(no_verify()).finish())
+        .retry_policy(RetryPolicy::new(1).retry_tls_handshake(true))
+        .finish();
+    let response = srv.block_on(client.get(srv.surl("/")).send()).unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(num.load(Ordering::Relaxed), 3);
+}
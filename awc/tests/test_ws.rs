@@ -1,8 +1,9 @@
 use std::io;
 
 use actix_codec::Framed;
+use actix_http::client::Protocol;
 use actix_http::{body::BodySize, h1, ws, Error, HttpService, Request, Response};
-use actix_http_test::TestServer;
+use actix_http_test::{TestServer, TestServerRuntime};
 use bytes::{Bytes, BytesMut};
 use futures::future::ok;
 use futures::{Future, Sink, Stream};
@@ -28,9 +29,9 @@ fn ws_service(req: ws::Frame) -> impl Future<Item = ws::Message, Error = io::Err
     }
 }
 
-#[test]
-fn test_simple() {
-    let mut srv = TestServer::new(|| {
+/// Server that upgrades every request to websocket echo service
+fn ws_server() -> TestServerRuntime {
+    TestServer::new(|| {
         HttpService::build()
             .upgrade(|(req, framed): (Request, Framed<_, _>)| {
                 let res = ws::handshake_response(req.head()).finish();
@@ -45,7 +46,12 @@ fn test_simple() {
                     })
             })
             .finish(|_| ok::<_, Error>(Response::NotFound()))
-    });
+    })
+}
+
+#[test]
+fn test_simple() {
+    let mut srv = ws_server();
 
     // client service
     let framed = srv.ws().unwrap();
@@ -80,3 +86,32 @@ fn test_simple() {
         Some(ws::Frame::Close(Some(ws::CloseCode::Normal.into())))
     );
 }
+
+#[test]
+fn test_response_protocol() {
+    let mut srv = ws_server();
+
+    let (res, _) = srv
+        .block_on(awc::Client::new().ws(srv.url("/")).connect())
+        .unwrap();
+    assert_eq!(res.protocol(), Some(Protocol::Http1));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_socket_raw_fd() {
+    use std::os::unix::io::FromRawFd;
+
+    let mut srv = ws_server();
+
+    let (_, framed) = srv
+        .block_on(awc::Client::new().ws(srv.url("/")).connect())
+        .unwrap();
+    let fd = framed.get_ref().as_raw_fd().unwrap();
+    assert!(fd >= 0);
+
+    // descriptor belongs to connected tcp socket
+    let sock = unsafe { std::net::TcpStream::from_raw_fd(fd) };
+    assert_eq!(sock.peer_addr().unwrap().port(), srv.addr().port());
+    std::mem::forget(sock);
+}